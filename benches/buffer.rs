@@ -0,0 +1,199 @@
+//! Compares the sliding `AsyncBufReader` with the previous implementation,
+//! which only rewound when everything was consumed and allocated a new buffer
+//! to make room, under a workload of pipelined frames arriving in chunks that
+//! split frames, so a partial frame is always pending.
+//!
+//! Run with `cargo bench --bench buffer`.
+
+extern crate deuter;
+
+use std::cmp;
+use std::io::{self, Read, BufRead};
+use std::time::{Duration, Instant};
+use deuter::buffer::AsyncBufReader;
+
+const FRAME_SIZES: &'static [usize] = &[9, 17, 13, 109, 1033, 9, 4105, 21];
+const CHUNK_SIZE: usize = 1500;
+const TOTAL: usize = 64 * 1024 * 1024;
+const ROUNDS: usize = 5;
+
+/// A socket delivering pipelined frames in chunks of `CHUNK_SIZE` bytes,
+/// returning `WouldBlock` after each chunk.
+struct Pipeline<'a> {
+    data: &'a [u8],
+    pos: usize,
+    chunk_left: usize,
+}
+
+/// Generates `TOTAL` bytes of frames with the sizes of `FRAME_SIZES`.
+fn frames() -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut i = 0;
+    while data.len() < TOTAL {
+        let payload_len = FRAME_SIZES[i % FRAME_SIZES.len()] - 9;
+        data.extend_from_slice(&[(payload_len >> 16) as u8,
+                                 (payload_len >> 8) as u8,
+                                 payload_len as u8,
+                                 0,
+                                 0,
+                                 0,
+                                 0,
+                                 0,
+                                 1]);
+        data.extend(::std::iter::repeat(0xAB).take(payload_len));
+        i += 1;
+    }
+    data
+}
+
+impl<'a> Pipeline<'a> {
+    fn new(data: &'a [u8]) -> Pipeline<'a> {
+        Pipeline {
+            data: data,
+            pos: 0,
+            chunk_left: CHUNK_SIZE,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    /// Allow the next chunk to be read, like a readable event.
+    fn ready(&mut self) {
+        self.chunk_left = CHUNK_SIZE;
+    }
+}
+
+impl<'a> Read for Pipeline<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunk_left == 0 {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+        }
+        let n = cmp::min(cmp::min(buf.len(), self.chunk_left), self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        self.chunk_left -= n;
+        Ok(n)
+    }
+}
+
+/// The reader as it was before the sliding buffer.
+struct PreviousBufReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    cap: usize,
+}
+
+impl<R: Read> PreviousBufReader<R> {
+    fn new(inner: R) -> PreviousBufReader<R> {
+        PreviousBufReader {
+            inner: inner,
+            buf: vec![0; 64],
+            pos: 0,
+            cap: 0,
+        }
+    }
+}
+
+impl<R: Read> BufRead for PreviousBufReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        loop {
+            if self.cap == self.buf.len() {
+                let len = self.cap - self.pos;
+                let new_len = len + cmp::max(cmp::min(len, 8 * 1024), 64);
+                let mut new_buf = vec![0; new_len];
+                new_buf[..len].copy_from_slice(&self.buf[self.pos..self.cap]);
+                self.buf = new_buf;
+                self.pos = 0;
+                self.cap = len;
+            }
+            let remaining = self.buf.len() - self.cap;
+            let nread = match self.inner.read(&mut self.buf[self.cap..]) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => 0,
+                Err(e) => return Err(e),
+            };
+            self.cap += nread;
+            if nread != remaining {
+                break;
+            }
+        }
+        Ok(&self.buf[self.pos..self.cap])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.cap);
+        if self.pos == self.cap {
+            self.pos = 0;
+            self.cap = 0;
+        }
+    }
+}
+
+impl<R: Read> Read for PreviousBufReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = cmp::min(buf.len(), self.cap - self.pos);
+        buf[..len].copy_from_slice(&self.buf[self.pos..self.pos + len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+/// Reads all frames, consuming complete frames after each readable event.
+fn run<'a, B, F, G>(mut reader: B, pipeline: F, pipeline_mut: G) -> usize
+    where B: BufRead,
+          F: Fn(&B) -> &Pipeline<'a>,
+          G: Fn(&mut B) -> &mut Pipeline<'a>
+{
+    let mut frames = 0;
+    while !pipeline(&reader).is_done() {
+        pipeline_mut(&mut reader).ready();
+        let mut consumed = 0;
+        {
+            let mut buf = reader.fill_buf().unwrap();
+            while buf.len() >= 9 {
+                let size = 9 + ((buf[0] as usize) << 16 | (buf[1] as usize) << 8 | buf[2] as usize);
+                if buf.len() < size {
+                    break;
+                }
+                buf = &buf[size..];
+                consumed += size;
+                frames += 1;
+            }
+        }
+        reader.consume(consumed);
+    }
+    frames
+}
+
+fn bench<F: FnMut() -> usize>(name: &str, mut f: F) {
+    let mut best = Duration::from_secs(3600);
+    let mut frames = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        frames = f();
+        best = cmp::min(best, start.elapsed());
+    }
+    let secs = best.as_secs() as f64 + best.subsec_nanos() as f64 * 1e-9;
+    println!("{:<12} {:>9} frames  {:>8.2} ms  {:>8.1} MB/s",
+             name,
+             frames,
+             secs * 1e3,
+             TOTAL as f64 / secs / 1e6);
+}
+
+fn main() {
+    let data = frames();
+    bench("previous", || {
+        run(PreviousBufReader::new(Pipeline::new(&data)),
+            |r| &r.inner,
+            |r| &mut r.inner)
+    });
+    bench("sliding", || {
+        run(AsyncBufReader::new(Pipeline::new(&data)),
+            |r| r.get_ref(),
+            |r| r.get_mut())
+    });
+}
@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate deuter;
+
+fuzz_target!(|data: &[u8]| {
+    deuter::fuzz::frame_reader(data);
+});
@@ -0,0 +1,499 @@
+//! Streamed message bodies.
+//!
+//! A `Body` yields the data of a stream as it arrives. The flow control
+//! windows of the stream are only given back to the peer as the data is
+//! consumed, so a slow reader stops the peer instead of growing buffers. A
+//! `BodySender` takes data as long as the flow control windows of the peer
+//! have room.
+//!
+//! Both share their state with the `Bodies` of the connection they belong to,
+//! which moves data between them and the connection on every `ready`.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::rc::Rc;
+use StreamId;
+use connection::{Connection, Transport};
+use error::{Error, ErrorKind, Result};
+use hpack::Header;
+use message::{self, Request, Response};
+
+#[derive(Default)]
+struct Inbound {
+    chunks: VecDeque<Vec<u8>>,
+    end: bool,
+    trailers: Option<Vec<Header>>,
+    error: Option<ErrorKind>,
+    // octets consumed and not yet released to the connection
+    consumed: usize,
+}
+
+/// The body of a received message, yields DATA as it arrives.
+///
+/// `Read` returns `WouldBlock` while the peer has not sent more data, call
+/// `ready` on the connection and try again.
+pub struct Body {
+    inbound: Rc<RefCell<Inbound>>,
+}
+
+impl Body {
+    /// Takes the next chunk of data, `None` if nothing is buffered.
+    pub fn poll_chunk(&mut self) -> Option<Vec<u8>> {
+        let mut inbound = self.inbound.borrow_mut();
+        let chunk = inbound.chunks.pop_front();
+        if let Some(ref chunk) = chunk {
+            inbound.consumed += chunk.len();
+        }
+        chunk
+    }
+
+    /// Octets buffered and not consumed yet.
+    pub fn buffered(&self) -> usize {
+        self.inbound.borrow().chunks.iter().map(|c| c.len()).sum()
+    }
+
+    /// Returns true once the stream ended and all data is consumed.
+    pub fn is_end(&self) -> bool {
+        let inbound = self.inbound.borrow();
+        inbound.end && inbound.chunks.is_empty()
+    }
+
+    /// The trailers, once they arrived.
+    pub fn trailers(&self) -> Option<Vec<Header>> {
+        self.inbound.borrow().trailers.clone()
+    }
+
+    /// The error code the stream was reset with.
+    pub fn error(&self) -> Option<ErrorKind> {
+        self.inbound.borrow().error
+    }
+}
+
+impl io::Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut inbound = self.inbound.borrow_mut();
+        if let Some(error) = inbound.error {
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset,
+                                      format!("stream reset: {:?}", error)));
+        }
+        let end = inbound.end;
+        let n = match inbound.chunks.front_mut() {
+            Some(chunk) => {
+                let n = ::std::cmp::min(buf.len(), chunk.len());
+                buf[..n].copy_from_slice(&chunk[..n]);
+                chunk.drain(..n);
+                n
+            }
+            None if end => return Ok(0),
+            None => return Err(io::Error::new(io::ErrorKind::WouldBlock, "waiting for data")),
+        };
+        if inbound.chunks.front().map_or(false, |c| c.is_empty()) {
+            inbound.chunks.pop_front();
+        }
+        inbound.consumed += n;
+        Ok(n)
+    }
+}
+
+#[derive(Default)]
+struct Outbound {
+    // promised requests and their responses, sent before anything else
+    promises: Vec<(Vec<Header>, Rc<RefCell<Outbound>>)>,
+    // streams which may still be promised when last synced
+    push_room: usize,
+    // informational (1xx) header blocks, sent before the head
+    interim: Vec<Vec<Header>>,
+    // the header block of a response, not sent yet
+    head: Option<Vec<Header>>,
+    // the header block went out, see `Responder`
+    started: bool,
+    buf: Vec<u8>,
+    // room in the flow control windows when last synced
+    capacity: usize,
+    end: bool,
+    trailers: Option<Vec<Header>>,
+    error: Option<ErrorKind>,
+}
+
+/// Writes the body of a message incrementally.
+///
+/// `Write` takes as much as the flow control windows have room for and
+/// returns `WouldBlock` when they are full, call `ready` on the connection
+/// and try again. A sender dropped before `finish` resets the stream.
+pub struct BodySender {
+    outbound: Rc<RefCell<Outbound>>,
+}
+
+impl BodySender {
+    /// Octets that can be written right now.
+    pub fn capacity(&self) -> usize {
+        let outbound = self.outbound.borrow();
+        outbound.capacity.saturating_sub(outbound.buf.len())
+    }
+
+    /// Ends the body.
+    pub fn finish(self) {
+        self.outbound.borrow_mut().end = true;
+    }
+
+    /// Ends the body with trailers, which must not contain pseudo-header
+    /// fields.
+    pub fn finish_with_trailers(self, trailers: Vec<Header>) -> Result<()> {
+        try!(message::check_trailers(&trailers));
+        let mut outbound = self.outbound.borrow_mut();
+        outbound.trailers = Some(trailers);
+        outbound.end = true;
+        Ok(())
+    }
+
+    /// The error code the stream was reset with.
+    pub fn error(&self) -> Option<ErrorKind> {
+        self.outbound.borrow().error
+    }
+}
+
+impl io::Write for BodySender {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if let Some(error) = self.error() {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe,
+                                      format!("stream reset: {:?}", error)));
+        }
+        let n = ::std::cmp::min(data.len(), self.capacity());
+        if n == 0 && !data.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "flow control window is full"));
+        }
+        self.outbound.borrow_mut().buf.extend_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Answers a request whose body is streamed. A responder dropped without
+/// answering resets the stream.
+pub struct Responder {
+    outbound: Rc<RefCell<Outbound>>,
+}
+
+impl Responder {
+    /// Sends an informational (1xx) response ahead of the final one, such as
+    /// 103 (Early Hints). It carries neither body nor trailers.
+    pub fn inform(&mut self, response: Response) -> Result<()> {
+        if !response.is_informational() || response.status == 101 {
+            return Err(Error::new(ErrorKind::Internal, "not an informational status"));
+        }
+        if !response.body.is_empty() || !response.trailers.is_empty() {
+            return Err(Error::new(ErrorKind::Internal, "informational response with a body"));
+        }
+        self.outbound.borrow_mut().interim.push(response.header_block());
+        Ok(())
+    }
+
+    /// Promises a response to `request` and returns the responder for it, a
+    /// client usually asks for the pushed resource next otherwise.
+    ///
+    /// The request must be a GET or HEAD without body (rfc 8.2). Fails if the
+    /// client disabled push or the client's stream limit is reached.
+    pub fn push(&mut self, request: Request) -> Result<Responder> {
+        try!(message::check_request(&request.headers));
+        match request.method() {
+            Some(b"GET") | Some(b"HEAD") => {}
+            _ => return Err(Error::new(ErrorKind::Internal, "only GET and HEAD can be pushed")),
+        }
+        if !request.body.is_empty() || !request.trailers.is_empty() {
+            return Err(Error::new(ErrorKind::Internal, "pushed request with a body"));
+        }
+        let mut outbound = self.outbound.borrow_mut();
+        if outbound.push_room == 0 {
+            return Err(Error::new(ErrorKind::RefusedStream, "push is not allowed"));
+        }
+        outbound.push_room -= 1;
+        let pushed = Rc::new(RefCell::new(Outbound::default()));
+        outbound.promises.push((request.headers, pushed.clone()));
+        Ok(Responder { outbound: pushed })
+    }
+
+    /// Sends a complete response.
+    pub fn respond(self, response: Response) -> Result<()> {
+        try!(message::check_trailers(&response.trailers));
+        let mut outbound = self.outbound.borrow_mut();
+        outbound.head = Some(response.header_block());
+        outbound.buf = response.body;
+        if !response.trailers.is_empty() {
+            outbound.trailers = Some(response.trailers);
+        }
+        outbound.end = true;
+        Ok(())
+    }
+
+    /// Sends the status and headers of the response, its body and trailers
+    /// follow through the returned sender.
+    pub fn send(self, response: Response) -> BodySender {
+        {
+            let mut outbound = self.outbound.borrow_mut();
+            outbound.head = Some(response.header_block());
+            outbound.buf = response.body;
+        }
+        BodySender { outbound: self.outbound }
+    }
+}
+
+/// The streamed bodies of a connection.
+#[derive(Default)]
+pub struct Bodies {
+    inbound: HashMap<StreamId, Rc<RefCell<Inbound>>>,
+    outbound: HashMap<StreamId, Rc<RefCell<Outbound>>>,
+}
+
+impl Bodies {
+    pub fn new() -> Bodies {
+        Bodies::default()
+    }
+
+    /// Streams the data received on the stream from now on.
+    pub fn receive(&mut self, id: StreamId) -> Body {
+        let inbound = Rc::new(RefCell::new(Inbound::default()));
+        self.inbound.insert(id, inbound.clone());
+        Body { inbound: inbound }
+    }
+
+    /// A sender for a stream whose header block is sent already, with room
+    /// for `capacity` octets until the next sync.
+    pub fn send(&mut self, id: StreamId, capacity: usize) -> BodySender {
+        let outbound = Rc::new(RefCell::new(Outbound {
+            started: true,
+            capacity: capacity,
+            ..Outbound::default()
+        }));
+        self.outbound.insert(id, outbound.clone());
+        BodySender { outbound: outbound }
+    }
+
+    /// A responder for the stream, see `send`, which may promise up to
+    /// `push_room` pushed responses until the next sync.
+    pub fn respond(&mut self, id: StreamId, capacity: usize, push_room: usize) -> Responder {
+        let outbound = Rc::new(RefCell::new(Outbound {
+            capacity: capacity,
+            push_room: push_room,
+            ..Outbound::default()
+        }));
+        self.outbound.insert(id, outbound.clone());
+        Responder { outbound: outbound }
+    }
+
+    /// Sends 100 (Continue) on the stream, unless the response or a 100 of
+    /// its own is queued already.
+    pub fn expect_continue(&mut self, id: StreamId) {
+        if let Some(outbound) = self.outbound.get(&id) {
+            let mut outbound = outbound.borrow_mut();
+            let head = Response::new(100).header_block();
+            if outbound.head.is_none() && !outbound.started && !outbound.interim.contains(&head) {
+                outbound.interim.push(head);
+            }
+        }
+    }
+
+    /// Stops streaming the stream in both directions.
+    pub fn forget(&mut self, id: StreamId) {
+        self.inbound.remove(&id);
+        self.outbound.remove(&id);
+    }
+
+    pub fn recv_data(&mut self, id: StreamId, data: Vec<u8>, end_stream: bool) {
+        if let Some(inbound) = self.inbound.get(&id) {
+            let mut inbound = inbound.borrow_mut();
+            if !data.is_empty() {
+                inbound.chunks.push_back(data);
+            }
+            inbound.end |= end_stream;
+        }
+    }
+
+    pub fn recv_trailers(&mut self, id: StreamId, trailers: Vec<Header>) {
+        if let Some(inbound) = self.inbound.get(&id) {
+            let mut inbound = inbound.borrow_mut();
+            inbound.trailers = Some(trailers);
+            inbound.end = true;
+        }
+    }
+
+    /// The stream was reset, both directions fail with `error`.
+    pub fn reset(&mut self, id: StreamId, error: ErrorKind) {
+        if let Some(inbound) = self.inbound.remove(&id) {
+            inbound.borrow_mut().error = Some(error);
+        }
+        if let Some(outbound) = self.outbound.remove(&id) {
+            outbound.borrow_mut().error = Some(error);
+        }
+    }
+
+    /// Every stream fails with `error`, the connection is closed.
+    pub fn reset_all(&mut self, error: ErrorKind) {
+        let ids: Vec<StreamId> = self.inbound.keys().chain(self.outbound.keys()).cloned().collect();
+        for id in ids {
+            self.reset(id, error);
+        }
+    }
+
+    /// Releases consumed data and hands written data to the connection.
+    pub fn sync<S: Transport>(&mut self, conn: &mut Connection<S>) {
+        let mut done = Vec::new();
+        for (&id, inbound) in &self.inbound {
+            // data of dropped bodies is consumed by no one
+            let dropped = Rc::strong_count(inbound) == 1;
+            let mut inbound = inbound.borrow_mut();
+            if dropped {
+                let len: usize = inbound.chunks.drain(..).map(|c| c.len()).sum();
+                inbound.consumed += len;
+            }
+            conn.release_capacity(id, inbound.consumed);
+            inbound.consumed = 0;
+            if inbound.end && (dropped || inbound.chunks.is_empty()) {
+                done.push(id);
+            }
+        }
+        for id in done.drain(..) {
+            self.inbound.remove(&id);
+        }
+
+        let mut pushed = Vec::new();
+        for (&id, outbound) in &self.outbound {
+            sync_outbound(conn, id, outbound, &mut pushed, &mut done);
+        }
+        // pushed responses may be complete already
+        for &(id, ref outbound) in &pushed {
+            sync_outbound(conn, id, outbound, &mut Vec::new(), &mut done);
+        }
+        self.outbound.extend(pushed);
+        for id in done {
+            self.outbound.remove(&id);
+        }
+    }
+}
+
+/// Flushes an outbound body, collects the streams it promised and whether it
+/// is done.
+fn sync_outbound<S: Transport>(conn: &mut Connection<S>,
+                               id: StreamId,
+                               outbound: &Rc<RefCell<Outbound>>,
+                               pushed: &mut Vec<(StreamId, Rc<RefCell<Outbound>>)>,
+                               done: &mut Vec<StreamId>) {
+    let dropped = Rc::strong_count(outbound) == 1;
+    let mut outbound = outbound.borrow_mut();
+    if let Err(e) = flush(conn, id, &mut outbound, dropped, pushed) {
+        debug!("failed to send body on {:?}: {}", id, e);
+        outbound.error = Some(e.kind());
+    }
+    outbound.capacity = conn.send_capacity(id);
+    outbound.push_room = conn.push_capacity(id);
+    if outbound.error.is_some() || outbound.end && outbound.buf.is_empty() {
+        done.push(id);
+    }
+}
+
+/// Sends what the application wrote since the last sync.
+fn flush<S: Transport>(conn: &mut Connection<S>,
+                       id: StreamId,
+                       outbound: &mut Outbound,
+                       dropped: bool,
+                       pushed: &mut Vec<(StreamId, Rc<RefCell<Outbound>>)>)
+                       -> Result<()> {
+    for (request, promised) in outbound.promises.drain(..) {
+        match conn.send_push_promise(id, &request) {
+            Ok(promised_id) => pushed.push((promised_id, promised)),
+            Err(e) => {
+                debug!("failed to promise on {:?}: {}", id, e);
+                promised.borrow_mut().error = Some(e.kind());
+            }
+        }
+    }
+    if dropped && !outbound.end {
+        // nobody is going to finish the message
+        let error = if outbound.started || outbound.head.is_some() {
+            ErrorKind::Cancel
+        } else {
+            ErrorKind::Internal
+        };
+        conn.reset(id, error);
+        return Err(Error::new(error, "body dropped before it was finished"));
+    }
+    for block in outbound.interim.drain(..) {
+        try!(conn.send_headers(id, &block, false));
+    }
+    let trailers = outbound.trailers.take();
+    let end_data = outbound.end && trailers.is_none();
+    if let Some(head) = outbound.head.take() {
+        outbound.started = true;
+        let end_stream = end_data && outbound.buf.is_empty();
+        try!(conn.send_headers(id, &head, end_stream));
+        if end_stream {
+            return Ok(());
+        }
+    }
+    if !outbound.started {
+        return Ok(());
+    }
+    if !outbound.buf.is_empty() || end_data {
+        try!(conn.send_data(id, &outbound.buf, end_data));
+        outbound.buf.clear();
+    }
+    if let Some(trailers) = trailers {
+        try!(conn.send_trailers(id, &trailers));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use StreamId;
+    use error::ErrorKind;
+    use super::Bodies;
+
+    #[test]
+    fn test_body() {
+        let mut bodies = Bodies::new();
+        let mut body = bodies.receive(StreamId(1));
+        let mut buf = [0; 3];
+        assert_eq!(body.read(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        bodies.recv_data(StreamId(1), b"hello".to_vec(), false);
+        assert_eq!(body.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+        assert_eq!(body.buffered(), 2);
+        assert_eq!(body.poll_chunk(), Some(b"lo".to_vec()));
+        assert_eq!(bodies.inbound[&StreamId(1)].borrow().consumed, 5);
+
+        bodies.recv_trailers(StreamId(1), vec![(b"a".to_vec(), b"b".to_vec())]);
+        assert!(body.is_end());
+        assert_eq!(body.read(&mut buf).unwrap(), 0);
+        assert_eq!(body.trailers(), Some(vec![(b"a".to_vec(), b"b".to_vec())]));
+    }
+
+    #[test]
+    fn test_body_reset() {
+        let mut bodies = Bodies::new();
+        let mut body = bodies.receive(StreamId(1));
+        let mut sender = bodies.send(StreamId(1), 10);
+        bodies.reset(StreamId(1), ErrorKind::Cancel);
+        assert_eq!(body.error(), Some(ErrorKind::Cancel));
+        assert!(body.read(&mut [0; 1]).is_err());
+        assert_eq!(sender.write(b"x").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_sender_capacity() {
+        let mut bodies = Bodies::new();
+        let mut sender = bodies.send(StreamId(1), 4);
+        assert_eq!(sender.write(b"abc").unwrap(), 3);
+        assert_eq!(sender.capacity(), 1);
+        assert_eq!(sender.write(b"def").unwrap(), 1);
+        assert_eq!(sender.write(b"gh").unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(bodies.outbound[&StreamId(1)].borrow().buf, b"abcd".to_vec());
+        assert!(sender.finish_with_trailers(vec![(b":status".to_vec(), b"200".to_vec())])
+            .is_err());
+    }
+}
@@ -1,19 +1,34 @@
 //! Buffer and Reader for Asynchronous / non-blocking IO
 
 use std::io;
-use std::io::{Read, BufRead, ErrorKind};
+use std::io::{Read, Write, BufRead, ErrorKind};
 use std::cmp;
+use std::mem;
 use std::ops::{Index, Range, RangeTo, RangeFrom, RangeFull};
 
-use frame::FrameIter;
-use error::Result;
+use frame::{Frame, FrameIter, FrameKind, FrameRef, HEADER_SIZE};
+use frame::encoder::FrameEncoder;
+use pool::BufferPool;
+use error::{Error, Result};
 
 const INITIAL_BUF_SIZE: usize = 64;
 const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+/// Soft limit for queued output, a bit more than a default sized DATA frame
+const DEFAULT_MAX_WRITE_BUF_SIZE: usize = 64 * 1024;
+/// Room for frames following a maximum sized frame, so a full frame does not
+/// stop the reader from reading ahead.
+const BUF_HEADROOM: usize = DEFAULT_BUF_SIZE;
+
+/// The buffer size needed to hold a frame of `max_payload` octets plus headroom.
+pub fn max_buffer_size(max_payload: usize) -> usize {
+    max_payload.saturating_add(HEADER_SIZE + BUF_HEADROOM)
+}
 
 /// The `AsyncBufReader` adds asynchronous buffering to any reader.
 ///
-/// contiguous growable, sliding buffer
+/// contiguous growable, sliding buffer: unconsumed data always starts at the
+/// read position and is moved back to the front of the buffer in place when
+/// the end is reached, so partial frames never wrap around.
 ///
 /// ```
 /// use std::net::{TcpListener, TcpStream};
@@ -37,26 +52,204 @@ const DEFAULT_BUF_SIZE: usize = 8 * 1024;
 /// ```
 ///
 /// `AsyncBufReader` implements `Index` to peek into the buffer.
+///
+/// The buffer grows up to a maximum size, `fill_buf` fails if it is called
+/// while the buffer is completely filled with unconsumed data. Once all data
+/// is consumed the buffer shrinks back to its initial size.
+///
+/// A reader created `with_pool` takes its buffer from a `BufferPool` when
+/// data arrives and gives it back as soon as all data is consumed.
 pub struct AsyncBufReader<R> {
     inner: R,
     buf: Vec<u8>,
     pos: usize,
     cap: usize,
+    initial_size: usize,
+    max_size: usize,
+    pool: Option<BufferPool>,
+    eof: bool,
 }
 
 impl<R: Read> AsyncBufReader<R> {
     pub fn new(inner: R) -> AsyncBufReader<R> {
+        Self::with_max_size(inner, usize::max_value())
+    }
+
+    pub fn with_max_size(inner: R, max_size: usize) -> AsyncBufReader<R> {
+        let initial_size = cmp::min(INITIAL_BUF_SIZE, max_size);
+        AsyncBufReader {
+            inner: inner,
+            buf: vec![0; initial_size],
+            pos: 0,
+            cap: 0,
+            initial_size: initial_size,
+            max_size: max_size,
+            pool: None,
+            eof: false,
+        }
+    }
+
+    /// Creates a reader which holds a chunk of the `pool` only while it has
+    /// unconsumed data.
+    pub fn with_pool(inner: R, pool: BufferPool, max_size: usize) -> AsyncBufReader<R> {
         AsyncBufReader {
             inner: inner,
-            buf: vec![0; INITIAL_BUF_SIZE],
+            buf: Vec::new(),
             pos: 0,
             cap: 0,
+            initial_size: 0,
+            max_size: max_size,
+            pool: Some(pool),
+            eof: false,
         }
     }
 
     pub fn len(&self) -> usize {
         self.cap - self.pos
     }
+
+    /// The number of bytes allocated for the buffer.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    #[inline]
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Set the maximum size, a larger buffer is shrunk with the next reset.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = cmp::max(max_size, self.initial_size);
+    }
+
+    /// Returns true if the buffer holds as much unconsumed data as allowed.
+    ///
+    /// There may be more data available to read from the inner reader.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.max_size
+    }
+
+    /// Returns true once the inner reader reached its end, i.e. the peer
+    /// closed the connection. Buffered data may still be unconsumed.
+    #[inline]
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Returns the first `n` unconsumed bytes, if available.
+    ///
+    /// The unconsumed data is always contiguous, e.g. a frame header can be
+    /// inspected without copying it out of the buffer.
+    #[inline]
+    pub fn peek(&self, n: usize) -> Option<&[u8]> {
+        if n > self.len() {
+            return None;
+        }
+        Some(&self.buf[self.pos..self.pos + n])
+    }
+
+    /// Moves the unconsumed data into a reader of its own, e.g. to parse it
+    /// while the owner of this reader is borrowed. Give it back with `attach`.
+    pub fn detach(&mut self) -> AsyncBufReader<io::Empty> {
+        let detached = AsyncBufReader {
+            inner: io::empty(),
+            buf: mem::replace(&mut self.buf, Vec::new()),
+            pos: self.pos,
+            cap: self.cap,
+            initial_size: self.initial_size,
+            max_size: self.max_size,
+            pool: self.pool.clone(),
+            eof: self.eof,
+        };
+        self.pos = 0;
+        self.cap = 0;
+        detached
+    }
+
+    /// Takes back the data left in a reader created by `detach`.
+    pub fn attach(&mut self, mut other: AsyncBufReader<io::Empty>) {
+        self.buf = mem::replace(&mut other.buf, Vec::new());
+        self.pos = other.pos;
+        self.cap = other.cap;
+    }
+
+    /// Take a chunk from the pool, if the reader does not hold one.
+    fn acquire(&mut self) {
+        if let Some(ref pool) = self.pool {
+            if self.buf.is_empty() {
+                let mut chunk = pool.acquire();
+                let len = cmp::min(pool.chunk_size(), self.max_size);
+                chunk.resize(len, 0);
+                self.buf = chunk;
+            }
+        }
+    }
+
+    /// Reset the empty buffer, either giving it back to the pool or shrinking
+    /// it to its initial size.
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.cap = 0;
+        match self.pool {
+            Some(ref pool) => {
+                if !self.buf.is_empty() {
+                    pool.release(mem::replace(&mut self.buf, Vec::new()));
+                }
+            }
+            // release memory held after a burst
+            None => {
+                if self.buf.len() > self.initial_size {
+                    self.buf = vec![0; self.initial_size];
+                }
+            }
+        }
+    }
+
+    /// Make room at the end of the buffer.
+    ///
+    /// Unconsumed data is slid to the front of the buffer in place. The buffer
+    /// only grows if the unconsumed data still occupies more than half of it,
+    /// so compacting stays amortized linear.
+    fn make_room(&mut self) {
+        let len = self.len();
+        if self.pos > 0 {
+            self.buf.copy_within(self.pos..self.cap, 0);
+            self.pos = 0;
+            self.cap = len;
+            if len <= self.buf.len() / 2 {
+                return;
+            }
+        }
+        // double the allocated space, for small sizes,
+        // else allocated extra DEFAULT_BUF_SIZE
+        let new_len = cmp::min(self.buf.len() + cmp::min(self.buf.len(), DEFAULT_BUF_SIZE),
+                               self.max_size);
+        if new_len > self.buf.len() {
+            self.buf.resize(new_len, 0);
+        }
+    }
+}
+
+/// Gives a pooled chunk holding unconsumed data back to the pool.
+impl<R> Drop for AsyncBufReader<R> {
+    fn drop(&mut self) {
+        if let Some(ref pool) = self.pool {
+            if !self.buf.is_empty() {
+                pool.release(mem::replace(&mut self.buf, Vec::new()));
+            }
+        }
+    }
 }
 
 impl<R: Read> Read for AsyncBufReader<R> {
@@ -71,29 +264,44 @@ impl<R: Read> Read for AsyncBufReader<R> {
 // TODO check other `BufRead` trait functions to work with our `fill_buf()`
 impl<R: Read> BufRead for AsyncBufReader<R> {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.is_full() {
+            // there was no room left after the last fill and nothing was consumed
+            return Err(io::Error::new(ErrorKind::Other, "buffer size limit exceeded"));
+        }
+        self.acquire();
         loop {
             if self.cap == self.buf.len() {
-                // double the allocated space, for small sizes,
-                // else allocated extra DEFAULT_BUF_SIZE
-                let new_len = self.len() + cmp::min(self.len(), DEFAULT_BUF_SIZE);
-                let mut new_buf = vec![0; new_len];
-                new_buf.copy_from_slice(&self[..]);
-                self.buf = new_buf;
-            }
-            let remaining = self.buf.len() - self.cap;
-            let nread = try!(self.inner.read(&mut self.buf[self.cap..]).or_else(|e| {
-                match e.kind() {
-                    ErrorKind::WouldBlock => Ok(0),
-                    _ => Err(e),
+                if self.is_full() {
+                    // stop here, the caller has to consume before reading further
+                    break;
                 }
-            }));
+                self.make_room();
+            }
+            let nread = match self.inner.read(&mut self.buf[self.cap..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    0
+                }
+                Ok(n) => n,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => 0,
+                Err(e) => {
+                    if self.cap == 0 {
+                        self.reset();
+                    }
+                    return Err(e);
+                }
+            };
             self.cap += nread;
-            // if we read exactly until our buffer is full, there could be more data
-            // else break here
-            if nread != remaining {
+            // a short read does not mean the reader is drained, with edge
+            // triggered events keep reading until it would block
+            if nread == 0 {
                 break;
             }
         }
+        if self.cap == 0 {
+            // nothing to read, don't hold on to a pooled chunk
+            self.reset();
+        }
         Ok(&self.buf[self.pos..self.cap])
     }
 
@@ -101,8 +309,7 @@ impl<R: Read> BufRead for AsyncBufReader<R> {
         self.pos = cmp::min(self.pos + amt, self.cap);
         // if we consumed everything until the end, reset buffer to beginning
         if self.pos == self.cap {
-            self.pos = 0;
-            self.cap = 0;
+            self.reset();
         }
     }
 }
@@ -147,35 +354,368 @@ impl<R: Read> Index<RangeFull> for AsyncBufReader<R> {
     }
 }
 
+/// The `AsyncBufWriter` adds asynchronous buffering to any writer.
+///
+/// Frames and bytes are queued without blocking and written with as few
+/// syscalls as possible once the writer is ready, see `flush_buf`.
+///
+/// The writer has a soft size limit: frames are always accepted, so control
+/// frames can be answered while the socket is congested, but byte slices
+/// written through `io::Write` are only accepted up to the limit. Callers
+/// producing stream data check `is_congested` or `available` and stop
+/// pulling from stream bodies until the queued data is written.
+///
+/// ```
+/// use std::io::Write;
+/// use deuter::buffer::AsyncBufWriter;
+///
+/// let mut w = AsyncBufWriter::with_max_size(Vec::new(), 4);
+/// assert_eq!(w.write(&[1, 2, 3, 4, 5, 6]).unwrap(), 4);
+/// assert!(w.is_congested());
+/// assert_eq!(w.pending(), 4);
+/// w.flush_buf().unwrap();
+/// assert_eq!(w.pending(), 0);
+/// assert_eq!(w.get_ref(), &[1, 2, 3, 4]);
+/// ```
+pub struct AsyncBufWriter<W> {
+    inner: W,
+    encoder: FrameEncoder,
+    max_size: usize,
+}
+
+impl<W: Write> AsyncBufWriter<W> {
+    pub fn new(inner: W) -> AsyncBufWriter<W> {
+        Self::with_max_size(inner, DEFAULT_MAX_WRITE_BUF_SIZE)
+    }
+
+    pub fn with_max_size(inner: W, max_size: usize) -> AsyncBufWriter<W> {
+        AsyncBufWriter {
+            inner: inner,
+            encoder: FrameEncoder::new(),
+            max_size: max_size,
+        }
+    }
+
+    /// Creates a writer queueing into chunks of the `pool`, which are given
+    /// back once they are written or the writer is dropped.
+    pub fn with_pool(inner: W, pool: BufferPool, max_size: usize) -> AsyncBufWriter<W> {
+        AsyncBufWriter {
+            inner: inner,
+            encoder: FrameEncoder::with_pool(pool),
+            max_size: max_size,
+        }
+    }
+
+    /// Number of queued bytes not yet written to the inner writer.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.encoder.pending()
+    }
+
+    /// Number of bytes which can be queued before the writer is congested.
+    #[inline]
+    pub fn available(&self) -> usize {
+        self.max_size.saturating_sub(self.pending())
+    }
+
+    /// Returns true if at least `max_size` bytes are waiting to be written.
+    #[inline]
+    pub fn is_congested(&self) -> bool {
+        self.available() == 0
+    }
+
+    #[inline]
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Queues the frame, regardless of the size limit.
+    pub fn write_frame<F: Frame>(&mut self, frame: F) {
+        self.encoder.encode(frame);
+    }
+
+    /// Writes as much of the queued data as the inner writer accepts without
+    /// blocking, call this on writable events.
+    ///
+    /// Returns the number of bytes written.
+    pub fn flush_buf(&mut self) -> io::Result<usize> {
+        self.encoder.flush_to(&mut self.inner)
+    }
+}
+
+impl<W: Write> Write for AsyncBufWriter<W> {
+    /// Queues as many bytes as allowed by the size limit.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = cmp::min(buf.len(), self.available());
+        if len == 0 && !buf.is_empty() {
+            return Err(io::Error::new(ErrorKind::WouldBlock, "write buffer is full"));
+        }
+        self.encoder.encode_bytes(&buf[..len]);
+        Ok(len)
+    }
+
+    /// Fails with `WouldBlock` if not all queued data could be written.
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.flush_buf());
+        if self.pending() > 0 {
+            return Err(io::Error::new(ErrorKind::WouldBlock, "write buffer not flushed"));
+        }
+        self.inner.flush()
+    }
+}
+
 // #############
 
 pub struct FrameReader<R> {
     inner: AsyncBufReader<R>,
     max_payload: usize,
+    // bytes of the last frame returned by `next_frame_ref`, not consumed yet
+    parsed: usize,
 }
 
 impl<'a, R: Read> FrameReader<R> {
-    fn new(inner: R, max_payload: usize) -> FrameReader<R> {
+    /// The buffer is limited to hold a frame of `max_payload` octets,
+    /// i.e. `Settings::max_frame_size`, plus headroom.
+    pub fn new(inner: R, max_payload: usize) -> FrameReader<R> {
         FrameReader {
-            inner: AsyncBufReader::new(inner),
+            inner: AsyncBufReader::with_max_size(inner, max_buffer_size(max_payload)),
             max_payload: max_payload,
+            parsed: 0,
         }
     }
 
-    fn frames(&'a mut self) -> Result<FrameIter<'a>> {
-        let buf = try!(self.inner.fill_buf());
-        Ok(FrameIter::new(buf, self.max_payload))
+    /// Like `new`, the buffer is taken from the `pool` while data is pending.
+    pub fn with_pool(inner: R, pool: BufferPool, max_payload: usize) -> FrameReader<R> {
+        FrameReader {
+            inner: AsyncBufReader::with_pool(inner, pool, max_buffer_size(max_payload)),
+            max_payload: max_payload,
+            parsed: 0,
+        }
+    }
+
+    /// Returns true once the peer closed the connection.
+    pub fn is_eof(&self) -> bool {
+        self.inner.is_eof()
+    }
+
+    /// Returns true if the bytes of a partial frame are buffered.
+    pub fn has_partial_frame(&self) -> bool {
+        self.inner.len() > self.parsed
+    }
+
+    pub fn get_ref(&self) -> &R {
+        self.inner.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        self.inner.get_mut()
+    }
+
+    /// The buffered bytes, e.g. to read an HTTP/1.1 request instead of the
+    /// preface.
+    pub fn buffered(&self) -> &[u8] {
+        &self.inner[self.parsed..]
+    }
+
+    /// Consumes `amt` buffered bytes which were read through `buffered`.
+    pub fn consume(&mut self, amt: usize) {
+        self.settle();
+        self.inner.consume(amt);
+    }
+
+    /// Moves the buffered bytes into a reader of their own, so frames
+    /// borrowing from them can be handled while the owner of this reader is
+    /// borrowed mutably. Give the rest back with `attach`.
+    pub fn detach(&mut self) -> FrameReader<io::Empty> {
+        self.settle();
+        FrameReader {
+            inner: self.inner.detach(),
+            max_payload: self.max_payload,
+            parsed: 0,
+        }
+    }
+
+    /// Takes back the unconsumed bytes of a reader created by `detach`.
+    pub fn attach(&mut self, mut other: FrameReader<io::Empty>) {
+        other.settle();
+        self.inner.attach(other.inner);
+    }
+
+    /// Consumes the frame returned by the last call to `next_frame_ref`.
+    fn settle(&mut self) {
+        if self.parsed > 0 {
+            self.inner.consume(self.parsed);
+            self.parsed = 0;
+        }
+    }
+
+    #[inline]
+    pub fn max_payload(&self) -> usize {
+        self.max_payload
+    }
+
+    /// Update the maximum payload after a change of `Settings::max_frame_size`.
+    pub fn set_max_payload(&mut self, max_payload: usize) {
+        self.max_payload = max_payload;
+        self.inner.set_max_size(max_buffer_size(max_payload));
+    }
+
+    /// Reads available data and iterates over the complete frames.
+    ///
+    /// Every yielded frame is consumed from the buffer, the bytes of a partial
+    /// frame stay buffered until the rest arrives with a later readiness
+    /// event. If the buffer filled up, the iterator reads again once the
+    /// buffered frames are consumed, so the inner reader is always drained
+    /// until it would block.
+    pub fn frames(&'a mut self) -> Result<Frames<'a, R>> {
+        let more = try!(self.fill());
+        Ok(Frames {
+            reader: self,
+            more: more,
+        })
+    }
+
+    /// Reads available data into the buffer.
+    ///
+    /// Returns true if reading stopped because the buffer is full, call
+    /// `fill` again once the buffered frames are consumed.
+    pub fn fill(&mut self) -> io::Result<bool> {
+        self.settle();
+        try!(self.inner.fill_buf());
+        Ok(self.inner.is_full())
+    }
+
+    /// Checks and consumes the connection preface sent by clients (rfc 3.5).
+    ///
+    /// Returns false while the preface is incomplete, fails as soon as the
+    /// buffered bytes don't match.
+    pub fn read_preface(&mut self, preface: &[u8]) -> Result<bool> {
+        self.settle();
+        let len = cmp::min(self.inner.len(), preface.len());
+        if self.inner[..len] != preface[..len] {
+            return Err(Error::protocol("invalid connection preface"));
+        }
+        if len < preface.len() {
+            return Ok(false);
+        }
+        self.inner.consume(len);
+        Ok(true)
+    }
+
+    /// Parses the next complete frame from the buffer and consumes its bytes.
+    pub fn next_frame(&mut self) -> Option<Result<FrameKind>> {
+        self.settle();
+        let (frame, consumed) = {
+            let mut iter = FrameIter::new(&self.inner[..], self.max_payload);
+            match iter.next() {
+                Some(frame) => (frame, iter.position()),
+                None => return None,
+            }
+        };
+        self.inner.consume(consumed);
+        Some(frame)
+    }
+
+    /// Like `next_frame`, but DATA frames and header block fragments borrow
+    /// their payload from the buffer instead of copying it.
+    ///
+    /// The bytes of the frame are consumed with the next call to the reader.
+    pub fn next_frame_ref(&mut self) -> Option<Result<FrameRef>> {
+        self.settle();
+        let mut iter = FrameIter::new(&self.inner[..], self.max_payload);
+        let frame = iter.next_ref();
+        self.parsed = iter.position();
+        frame
+    }
+}
+
+/// Iterator over the complete frames of a `FrameReader`, see `frames`.
+pub struct Frames<'a, R: 'a> {
+    reader: &'a mut FrameReader<R>,
+    // the last fill stopped because the buffer was full
+    more: bool,
+}
+
+impl<'a, R: Read> Iterator for Frames<'a, R> {
+    type Item = Result<FrameKind>;
+
+    fn next(&mut self) -> Option<Result<FrameKind>> {
+        loop {
+            if let Some(frame) = self.reader.next_frame() {
+                return Some(frame);
+            }
+            if !self.more {
+                return None;
+            }
+            match self.reader.fill() {
+                Ok(more) => self.more = more,
+                Err(e) => {
+                    self.more = false;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::io::{Read, Write, BufRead, Cursor};
+    use std::io::{Read, Write, BufRead, Cursor, ErrorKind};
     use std::net::{TcpListener, TcpStream};
-    use super::{AsyncBufReader, FrameReader};
+    use std::io;
+    use std::cmp;
+    use super::{AsyncBufReader, AsyncBufWriter, FrameReader};
+    use pool::BufferPool;
+    use mock::MockStream;
     use StreamId;
-    use frame::{Frame, WriteFrame, FrameKind};
+    use frame::{Frame, WriteFrame, FrameKind, FrameRef};
     use frame::headers::HeadersFrame;
+    use frame::data::DataFrame;
+    use frame::ping::PingFrame;
+    use frame::settings::SettingsFrame;
+
+    /// Non-blocking reader delivering at most `available` bytes of `data`,
+    /// at most `chunk` bytes per read.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        available: usize,
+        chunk: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let end = cmp::min(self.available, self.data.len());
+            if self.pos == end {
+                return Err(io::Error::new(ErrorKind::WouldBlock, "would block"));
+            }
+            let len = cmp::min(cmp::min(buf.len(), self.chunk), end - self.pos);
+            buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+            self.pos += len;
+            Ok(len)
+        }
+    }
+
+    fn frame_stream() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.write_frame(SettingsFrame::ack()).unwrap();
+        data.write_frame(HeadersFrame::new(StreamId(1))).unwrap();
+        data.write_frame(DataFrame::new(StreamId(1)).data(vec![7; 300]).end_stream()).unwrap();
+        data.write_frame(PingFrame::new([1; 8])).unwrap();
+        data
+    }
 
     #[test]
     fn test_tcpstream_fillbuf() {
@@ -213,6 +753,385 @@ mod test {
         assert_eq!(r[1..], [4, 5, 6]);
     }
 
+    #[test]
+    fn test_grow_buffer() {
+        let data: Vec<u8> = (0..200).collect();
+        let mut r = AsyncBufReader::new(Cursor::new(data.clone()));
+        r.fill_buf().unwrap();
+        assert_eq!(r.len(), 200);
+        assert_eq!(r[..], data[..]);
+        let mut d = [0; 10];
+        r.read(&mut d).unwrap();
+        assert_eq!(r[0], 10);
+    }
+
+    #[test]
+    fn test_max_size() {
+        let data: Vec<u8> = (0..200).collect();
+        let mut r = AsyncBufReader::with_max_size(Cursor::new(data), 100);
+        assert_eq!(r.fill_buf().unwrap().len(), 100);
+        assert!(r.is_full());
+        assert_eq!(r.capacity(), 100);
+        // nothing consumed, no room to read into
+        assert_eq!(r.fill_buf().unwrap_err().kind(), ErrorKind::Other);
+        r.consume(50);
+        assert_eq!(r.fill_buf().unwrap().len(), 100);
+        assert_eq!(r[0], 50);
+        r.consume(100);
+        assert_eq!(r.fill_buf().unwrap().len(), 50);
+        assert_eq!(r[0], 150);
+    }
+
+    #[test]
+    fn test_compact_in_place() {
+        let data: Vec<u8> = (0..200).collect();
+        let mut r = AsyncBufReader::with_max_size(Cursor::new(data), 128);
+        r.fill_buf().unwrap();
+        assert_eq!(r.capacity(), 128);
+        // keep a partial "frame" of 8 bytes pending
+        r.consume(120);
+        let ptr = r.buf.as_ptr();
+        r.fill_buf().unwrap();
+        // the pending bytes were moved to the front, no new buffer allocated
+        assert_eq!(r.buf.as_ptr(), ptr);
+        assert_eq!(r.capacity(), 128);
+        assert_eq!(r.len(), 80);
+        assert_eq!(r[..8], [120, 121, 122, 123, 124, 125, 126, 127]);
+        assert_eq!(r[79], 199);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut r = AsyncBufReader::new(Cursor::new(vec![1, 2, 3, 4]));
+        assert!(r.peek(1).is_none());
+        r.fill_buf().unwrap();
+        assert_eq!(r.peek(3).unwrap(), [1, 2, 3]);
+        assert!(r.peek(5).is_none());
+        r.consume(2);
+        assert_eq!(r.peek(2).unwrap(), [3, 4]);
+    }
+
+    #[test]
+    fn test_shrink_after_burst() {
+        let mut r = AsyncBufReader::new(Cursor::new(vec![0; 100000]));
+        r.fill_buf().unwrap();
+        assert!(r.capacity() >= 100000);
+        r.consume(99999);
+        assert!(r.capacity() >= 100000);
+        r.consume(1);
+        assert_eq!(r.capacity(), 64);
+    }
+
+    #[test]
+    fn test_frame_reader_buffer_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tx = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let conn = listener.accept().unwrap().0;
+        let mut r = FrameReader::new(conn, 16384);
+        assert_eq!(r.inner.max_size(), 16384 + 9 + 8192);
+        r.set_max_payload(100000);
+        assert_eq!(r.inner.max_size(), 100000 + 9 + 8192);
+        drop(tx);
+    }
+
+    #[test]
+    fn test_pooled_reader_releases_when_idle() {
+        let pool = BufferPool::with_chunk_size(64, 4);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut tx = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let conn = listener.accept().unwrap().0;
+        conn.set_nonblocking(true).unwrap();
+
+        let mut r = AsyncBufReader::with_pool(conn, pool.clone(), 1024);
+        assert_eq!(r.capacity(), 0);
+        // would block, no chunk is held
+        assert_eq!(r.fill_buf().unwrap().len(), 0);
+        assert_eq!(pool.stats().in_use, 0);
+
+        tx.write_all(&[1; 10]).unwrap();
+        while r.len() < 10 {
+            r.fill_buf().unwrap();
+        }
+        assert_eq!(r.capacity(), 64);
+        assert_eq!(pool.stats().in_use, 1);
+        r.consume(4);
+        assert_eq!(pool.stats().in_use, 1);
+        r.consume(6);
+        assert_eq!(r.capacity(), 0);
+        assert_eq!(pool.stats().in_use, 0);
+        assert_eq!(pool.stats().idle, 1);
+    }
+
+    #[test]
+    fn test_pooled_reader_grows_beyond_chunk() {
+        let pool = BufferPool::with_chunk_size(64, 4);
+        let data: Vec<u8> = (0..200).collect();
+        let mut r = AsyncBufReader::with_pool(Cursor::new(data.clone()), pool.clone(), 1024);
+        assert_eq!(r.fill_buf().unwrap(), &data[..]);
+        r.consume(200);
+        // the grown chunk is not kept by the pool
+        assert_eq!(pool.stats().in_use, 0);
+        assert_eq!(pool.stats().idle, 0);
+    }
+
+    #[test]
+    fn test_pooled_reader_releases_on_drop() {
+        let pool = BufferPool::with_chunk_size(64, 4);
+        let mut r = AsyncBufReader::with_pool(Cursor::new(vec![1; 10]), pool.clone(), 1024);
+        r.fill_buf().unwrap();
+        assert_eq!(pool.stats().in_use, 1);
+        drop(r);
+        assert_eq!(pool.stats().in_use, 0);
+    }
+
+    #[test]
+    fn test_pooled_writer_releases_when_flushed() {
+        let pool = BufferPool::with_chunk_size(64, 4);
+        let mut w = AsyncBufWriter::with_pool(Vec::new(), pool.clone(), 4096);
+        w.write_frame(HeadersFrame::new(StreamId(1)));
+        w.write_all(&[2; 2000]).unwrap();
+        assert_eq!(pool.stats().in_use, 2);
+        w.flush_buf().unwrap();
+        assert_eq!(w.get_ref().len(), 2009);
+        assert_eq!(pool.stats().in_use, 0);
+        assert_eq!(pool.stats().idle, 1);
+    }
+
+    #[test]
+    fn test_write_frames_nonblocking() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tx = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        tx.set_nonblocking(true).unwrap();
+        let mut rx = listener.accept().unwrap().0;
+
+        let mut w = AsyncBufWriter::with_max_size(tx, 1024);
+        w.write_frame(HeadersFrame::new(StreamId(1)));
+        w.write_frame(HeadersFrame::new(StreamId(3)));
+        assert_eq!(w.pending(), 18);
+        assert_eq!(w.available(), 1024 - 18);
+        assert_eq!(w.flush_buf().unwrap(), 18);
+        assert_eq!(w.pending(), 0);
+        let mut buf = [0; 18];
+        rx.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..9], [0, 0, 0, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(buf[9..], [0, 0, 0, 1, 0, 0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn test_write_congested_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tx = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        tx.set_nonblocking(true).unwrap();
+        let mut rx = listener.accept().unwrap().0;
+
+        let mut w = AsyncBufWriter::with_max_size(tx, 64 * 1024);
+        let chunk = [7; 4096];
+        let mut queued = 0;
+        // nobody reads, fill the socket buffers until the writer is congested
+        loop {
+            while !w.is_congested() {
+                queued += w.write(&chunk).unwrap();
+            }
+            if w.flush_buf().unwrap() == 0 {
+                break;
+            }
+        }
+        assert!(w.is_congested());
+        assert_eq!(w.write(&chunk).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(w.flush().unwrap_err().kind(), ErrorKind::WouldBlock);
+        // control frames are still accepted
+        w.write_frame(HeadersFrame::new(StreamId(1)));
+        assert!(w.pending() > 64 * 1024);
+
+        // drain the receiving side, the writer makes progress again
+        let mut received = 0;
+        let mut buf = [0; 4096];
+        while received < queued + 9 {
+            received += rx.read(&mut buf).unwrap();
+            w.flush_buf().unwrap();
+        }
+        assert_eq!(w.pending(), 0);
+        assert_eq!(received, queued + 9);
+    }
+
+    #[test]
+    fn test_frames_one_byte_per_event() {
+        let data = frame_stream();
+        let len = data.len();
+        let trickle = Trickle { data: data, pos: 0, available: 0, chunk: 1 };
+        let mut r = FrameReader::new(trickle, 16384);
+        let mut received = Vec::new();
+        for i in 1..len + 1 {
+            // every readiness event delivers a single byte
+            r.get_mut().available = i;
+            for frame in r.frames().unwrap() {
+                received.push((i, frame.unwrap()));
+            }
+        }
+        assert_eq!(received.len(), 4);
+        // every frame is yielded exactly once, on the event completing it
+        assert_eq!(received[0].0, 9);
+        assert_eq!(received[1].0, 18);
+        assert_eq!(received[2].0, 18 + 309);
+        assert_eq!(received[3].0, len);
+        match received[2].1 {
+            FrameKind::Data(ref frame) => {
+                assert_eq!(frame.payload(), &[7; 300][..]);
+                assert!(frame.is_end_stream());
+            }
+            _ => panic!("Wrong frame"),
+        }
+        match received[3].1 {
+            FrameKind::Ping(ref frame) => assert_eq!(frame.data(), [1; 8]),
+            _ => panic!("Wrong frame"),
+        }
+    }
+
+    #[test]
+    fn test_frames_byte_by_byte_single_event() {
+        // all bytes available, but every read returns a single byte
+        let data = frame_stream();
+        let len = data.len();
+        let trickle = Trickle { data: data, pos: 0, available: len, chunk: 1 };
+        let mut r = FrameReader::new(trickle, 16384);
+        assert_eq!(r.frames().unwrap().count(), 4);
+        assert!(r.frames().unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_frames_partial_frame_kept() {
+        let data = frame_stream();
+        let trickle = Trickle { data: data, pos: 0, available: 13, chunk: 1024 };
+        let mut r = FrameReader::new(trickle, 16384);
+        assert_eq!(r.frames().unwrap().count(), 1);
+        // the first 4 bytes of the HEADERS frame stay buffered
+        assert_eq!(r.inner.len(), 4);
+        assert!(r.frames().unwrap().next().is_none());
+        r.get_mut().available = 18;
+        let mut frames = r.frames().unwrap();
+        match frames.next().unwrap().unwrap() {
+            FrameKind::Headers(frame) => assert_eq!(frame.stream_id(), 1),
+            _ => panic!("Wrong frame"),
+        }
+        assert!(frames.next().is_none());
+    }
+
+    #[test]
+    fn test_frames_refill_full_buffer() {
+        // more data than fits into the buffer, read it within one event
+        let mut data = Vec::new();
+        for i in 0..2000 {
+            data.write_frame(HeadersFrame::new(StreamId(2 * i + 1))).unwrap();
+        }
+        let len = data.len();
+        let trickle = Trickle { data: data, pos: 0, available: len, chunk: 4096 };
+        let mut r = FrameReader::new(trickle, 100);
+        assert!(len > r.inner.max_size());
+        let ids: Vec<u32> = r.frames()
+            .unwrap()
+            .map(|frame| match frame.unwrap() {
+                FrameKind::Headers(frame) => frame.stream_id().into(),
+                _ => panic!("Wrong frame"),
+            })
+            .collect();
+        assert_eq!(ids.len(), 2000);
+        assert_eq!(ids[1999], 3999);
+        assert_eq!(r.inner.len(), 0);
+    }
+
+    #[test]
+    fn test_read_preface() {
+        let preface = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+        let (mut peer, stream) = MockStream::new();
+        let mut r = FrameReader::new(stream, 16384);
+        peer.write_all(&preface[..10]).unwrap();
+        r.fill().unwrap();
+        assert!(!r.read_preface(preface).unwrap());
+        peer.write_all(&preface[10..]).unwrap();
+        peer.write_frame(SettingsFrame::ack()).unwrap();
+        r.fill().unwrap();
+        assert!(r.read_preface(preface).unwrap());
+        match r.next_frame().unwrap().unwrap() {
+            FrameKind::Settings(frame) => assert!(frame.is_ack()),
+            _ => panic!("Wrong frame"),
+        }
+
+        let (mut peer, stream) = MockStream::new();
+        let mut r = FrameReader::new(stream, 16384);
+        peer.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        r.fill().unwrap();
+        assert_eq!(r.read_preface(preface).unwrap_err().kind(), ::error::ErrorKind::Protocol);
+    }
+
+    #[test]
+    fn test_next_frame_ref() {
+        let (mut peer, stream) = MockStream::new();
+        let mut r = FrameReader::with_pool(stream, BufferPool::new(), 16384);
+        peer.write_frame(DataFrame::new(StreamId(1)).data(vec![1, 2, 3])).unwrap();
+        peer.write_frame(PingFrame::new([0; 8])).unwrap();
+        peer.write_all(&[0, 0]).unwrap();
+        r.fill().unwrap();
+        match r.next_frame_ref().unwrap().unwrap() {
+            FrameRef::Data(frame) => assert_eq!(frame.payload(), [1, 2, 3]),
+            _ => panic!("Wrong frame"),
+        }
+        match r.next_frame_ref().unwrap().unwrap() {
+            FrameRef::Control(FrameKind::Ping(_)) => {}
+            _ => panic!("Wrong frame"),
+        }
+        assert!(r.has_partial_frame());
+        assert!(r.next_frame_ref().is_none());
+        assert_eq!(r.buffered(), [0, 0]);
+    }
+
+    #[test]
+    fn test_detach() {
+        let (mut peer, stream) = MockStream::new();
+        let pool = BufferPool::new();
+        let mut r = FrameReader::with_pool(stream, pool.clone(), 16384);
+        peer.write_frame(PingFrame::new([0; 8])).unwrap();
+        peer.write_all(&[0, 0]).unwrap();
+        r.fill().unwrap();
+        let mut frames = r.detach();
+        assert!(!r.has_partial_frame());
+        assert!(frames.next_frame_ref().is_some());
+        assert!(frames.next_frame_ref().is_none());
+        r.attach(frames);
+        assert_eq!(r.buffered(), [0, 0]);
+        r.consume(2);
+        assert_eq!(pool.stats().in_use, 0);
+    }
+
+    #[test]
+    fn test_eof() {
+        let mut r = AsyncBufReader::new(Cursor::new(vec![1, 2, 3]));
+        assert!(!r.is_eof());
+        assert_eq!(r.fill_buf().unwrap().len(), 3);
+        assert!(r.is_eof());
+        assert_eq!(r.len(), 3);
+    }
+
+    #[test]
+    fn test_frames_over_mock_stream() {
+        let (mut peer, stream) = MockStream::new();
+        stream.set_read_chunk(5);
+        stream.block_reads(1);
+        peer.hold();
+        peer.write_all(&frame_stream()).unwrap();
+        let mut r = FrameReader::new(stream, 16384);
+        assert!(r.frames().unwrap().next().is_none());
+        // deliver the SETTINGS ACK and half of the HEADERS frame
+        peer.deliver_some(13);
+        assert_eq!(r.frames().unwrap().count(), 1);
+        assert!(r.has_partial_frame());
+        peer.deliver();
+        assert_eq!(r.frames().unwrap().count(), 3);
+        assert!(!r.is_eof());
+        peer.close();
+        assert!(r.frames().unwrap().next().is_none());
+        assert!(r.is_eof());
+    }
+
     #[test]
     fn test_iter_frames() {
         let listener = TcpListener::bind("127.0.0.1:0").unwrap();
@@ -1,88 +1,754 @@
-use connection::Connection;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use mio::{EventSet, Poll, PollOpt, Token};
+use mio::tcp::TcpStream;
+use {Settings, StreamId};
+use body::Bodies;
+use connection::{Connection, Event, Role, Transport};
 use error::{Error, ErrorKind, Result};
-use frame::settings::{SettingsFrame, Setting};
-use frame::{FrameKind, WriteFrame, ReadFrame};
-use {Settings, WindowSize};
-
-pub struct Client<C> {
-    conn: C,
-    settings: Settings,
-    window_in: WindowSize,
-    window_out: WindowSize,
+use hpack::Header;
+use message::{self, Body, BodyLength, BodySender, Request, Response};
+use pool::BufferPool;
+
+/// How long a blocking request waits for the server, in milliseconds.
+const REQUEST_TIMEOUT: usize = 30000;
+
+/// How often a blocking request is replayed after it was refused.
+const MAX_REPLAYS: usize = 3;
+
+/// Pushes kept until they are taken, promised ones included. Further
+/// promises are cancelled.
+const MAX_PUSHED: usize = 32;
+
+/// How long a request expecting 100 (Continue) holds back its body, in
+/// milliseconds. Servers may not know the expectation (rfc 7231 5.1.1).
+const CONTINUE_TIMEOUT: u64 = 1000;
+
+/// The settings a client announces, pushes are refused unless `enable_push`
+/// is set.
+pub fn default_settings() -> Settings {
+    Settings { enable_push: false, ..Settings::default() }
 }
 
-impl<C: Connection> Client<C> {
-    fn new(conn: C) -> Client<C>
-        where C: Connection
-    {
+/// A response waiting for its headers or the end of its body.
+struct Pending {
+    // responses to HEAD requests never have a body (rfc 7230 3.3)
+    head: bool,
+    // the body is streamed, see `send_streaming`
+    streaming: bool,
+    // body and trailers waiting for 100 (Continue), and since when
+    held: Option<(Vec<u8>, Vec<Header>, Instant)>,
+    // a held body was dropped, the stream is reset once the response ends
+    abandoned: bool,
+    response: Option<Response>,
+    length: BodyLength,
+    // the request, kept to be replayed if it is refused
+    request: Option<Request>,
+}
+
+/// The client side of a connection, sends requests and collects their
+/// responses.
+pub struct Client<S> {
+    conn: Connection<S>,
+    responses: HashMap<StreamId, Pending>,
+    done: VecDeque<(StreamId, Result<Response>)>,
+    streamed: VecDeque<(StreamId, Result<(Response, Body)>)>,
+    bodies: Bodies,
+    informational: Option<Box<FnMut(StreamId, &Response)>>,
+    // decides on pushes, all are accepted without it
+    push_filter: Option<Box<FnMut(StreamId, &Request) -> bool>>,
+    // the requests of accepted pushes until their response is complete
+    promised: HashMap<StreamId, Request>,
+    pushed: Vec<(Request, Response)>,
+    // keep idempotent requests, see `set_replay`
+    replay: bool,
+    refused: HashMap<StreamId, Request>,
+    // requests `request` and `reconnect` sent again
+    replays: u64,
+    // requests replayed by `reconnect`, by their new stream and the one
+    // they were sent on first
+    replayed: HashMap<StreamId, StreamId>,
+    // the server `connect` connected to
+    addr: Option<SocketAddr>,
+    // the server answered the upgrade request over HTTP/1.1
+    declined: bool,
+}
+
+impl<S: Transport> Client<S> {
+    pub fn new(socket: S, settings: Settings, pool: &BufferPool) -> io::Result<Client<S>> {
+        let conn = try!(Connection::new(socket, Role::Client, settings, pool));
+        Ok(Client::with_connection(conn))
+    }
+
+    /// Starts with `request` over HTTP/1.1, asking the server to upgrade to
+    /// HTTP/2 over cleartext TCP (h2c) with `settings`.
+    ///
+    /// The request is stream 1, its response is polled like any other
+    /// whether the server switched protocols or declined and answered over
+    /// HTTP/1.1, see `is_declined`. Other requests can be sent once the
+    /// server switched, see `is_upgrading`.
+    pub fn upgrade(socket: S, settings: Settings, pool: &BufferPool, request: Request)
+                   -> Result<Client<S>> {
+        let conn = try!(Connection::upgrade(socket, settings, pool, &request));
+        let mut client = Client::with_connection(conn);
+        let head = request.method() == Some(b"HEAD");
+        client.insert(StreamId(1), head, false, None);
+        Ok(client)
+    }
+
+    fn with_connection(conn: Connection<S>) -> Client<S> {
         Client {
             conn: conn,
-            settings: Settings::default(),
-            window_in: WindowSize::default(),
-            window_out: WindowSize::default(),
+            responses: HashMap::new(),
+            done: VecDeque::new(),
+            streamed: VecDeque::new(),
+            bodies: Bodies::new(),
+            informational: None,
+            push_filter: None,
+            promised: HashMap::new(),
+            pushed: Vec::new(),
+            replay: false,
+            refused: HashMap::new(),
+            replays: 0,
+            replayed: HashMap::new(),
+            addr: None,
+            declined: false,
+        }
+    }
+
+    pub fn socket(&self) -> &S {
+        self.conn.socket()
+    }
+
+    pub fn connection(&mut self) -> &mut Connection<S> {
+        &mut self.conn
+    }
+
+    /// Calls `f` with every informational (1xx) response received before a
+    /// final response, e.g. 103 (Early Hints).
+    pub fn on_informational<F>(&mut self, f: F)
+        where F: FnMut(StreamId, &Response) + 'static
+    {
+        self.informational = Some(Box::new(f));
+    }
+
+    /// Calls `f` with the parent stream and the request of every promised
+    /// push, a push is cancelled if `f` returns false. Pushes are only
+    /// received if the client's settings enable them, and while fewer than
+    /// 32 of them wait to be taken.
+    pub fn on_push<F>(&mut self, f: F)
+        where F: FnMut(StreamId, &Request) -> bool + 'static
+    {
+        self.push_filter = Some(Box::new(f));
+    }
+
+    /// Takes the pushed response to a request for the same resource, if one
+    /// was received completely.
+    pub fn take_pushed(&mut self, request: &Request) -> Option<Response> {
+        let same = |pushed: &Request| {
+            [":method", ":scheme", ":authority", ":path"]
+                .iter()
+                .all(|name| pushed.get(name) == request.get(name))
+        };
+        self.pushed
+            .iter()
+            .position(|&(ref pushed, _)| same(pushed))
+            .map(|i| self.pushed.remove(i).1)
+    }
+
+    /// Keeps idempotent requests until their response arrives. Those the
+    /// server did not process, see `Error::is_retryable`, can be taken back
+    /// with `take_refused` and are replayed by `request` on a new connection.
+    pub fn set_replay(&mut self, replay: bool) {
+        self.replay = replay;
+    }
+
+    /// Takes back the request of a stream which failed with a retryable
+    /// error, if replay is on and the request is idempotent.
+    pub fn take_refused(&mut self, id: StreamId) -> Option<Request> {
+        self.refused.remove(&id)
+    }
+
+    /// Total number of requests sent again by `request`, on this connection
+    /// or a new one.
+    pub fn replayed(&self) -> u64 {
+        self.replays
+    }
+
+    /// Returns true once no more requests can be sent, after GOAWAY or when
+    /// the connection is closed.
+    pub fn is_going_away(&self) -> bool {
+        self.conn.is_going_away()
+    }
+
+    /// Returns true while the server did not answer the upgrade request, see
+    /// `upgrade`.
+    pub fn is_upgrading(&self) -> bool {
+        self.conn.is_upgrading()
+    }
+
+    /// Returns true if the server declined the upgrade to h2c. Its HTTP/1.1
+    /// response is the response of stream 1 and the connection is closed.
+    pub fn is_declined(&self) -> bool {
+        self.declined
+    }
+
+    /// Returns true if a request can be sent right away, without exceeding
+    /// the server's MAX_CONCURRENT_STREAMS.
+    pub fn has_capacity(&self) -> bool {
+        self.conn.can_open_stream()
+    }
+
+    /// Number of requests waiting for their response to complete.
+    pub fn pending(&self) -> usize {
+        self.responses.len()
+    }
+
+    /// Queues the request on a new stream, call `ready` to send it.
+    ///
+    /// The body of a request with `expect: 100-continue` is held back until
+    /// the server answers with 100 (Continue), or a second has passed.
+    pub fn send(&mut self, request: Request) -> Result<StreamId> {
+        try!(message::check_trailers(&request.trailers));
+        let id = try!(self.conn.open_stream());
+        let head = request.method() == Some(b"HEAD");
+        let kept = if self.replay && request.is_idempotent() {
+            Some(request.clone())
+        } else {
+            None
+        };
+        if request.expects_continue() && !request.body.is_empty() {
+            try!(self.conn.send_headers(id, &request.headers, false));
+            self.insert(id, head, false, kept);
+            let held = (request.body, request.trailers, Instant::now());
+            self.responses.get_mut(&id).unwrap().held = Some(held);
+            return Ok(id);
+        }
+        try!(self.conn.send_message(id, &request.headers, &request.body, &request.trailers));
+        self.insert(id, head, false, kept);
+        Ok(id)
+    }
+
+    /// Queues the headers of the request on a new stream, followed by its
+    /// body, the rest of the body is written through the returned sender.
+    ///
+    /// The response is streamed as well, see `poll_streaming_response`.
+    pub fn send_streaming(&mut self, request: Request) -> Result<(StreamId, BodySender)> {
+        let id = try!(self.conn.open_stream());
+        let head = request.method() == Some(b"HEAD");
+        try!(self.conn.send_headers(id, &request.headers, false));
+        if !request.body.is_empty() {
+            try!(self.conn.send_data(id, &request.body, false));
+        }
+        self.insert(id, head, true, None);
+        let capacity = self.conn.send_capacity(id);
+        Ok((id, self.bodies.send(id, capacity)))
+    }
+
+    fn insert(&mut self, id: StreamId, head: bool, streaming: bool, request: Option<Request>) {
+        self.responses.insert(id,
+                              Pending {
+                                  head: head,
+                                  streaming: streaming,
+                                  held: None,
+                                  abandoned: false,
+                                  response: None,
+                                  length: BodyLength::new(None),
+                                  request: request,
+                              });
+    }
+
+    /// Reads available frames and writes as much as the transport takes.
+    pub fn ready(&mut self) -> Result<()> {
+        let read = self.conn.read();
+        while let Some(event) = self.conn.poll() {
+            self.handle_event(event);
+        }
+        self.release_held();
+        self.bodies.sync(&mut self.conn);
+        try!(self.conn.write());
+        if self.conn.is_closed() {
+            self.fail_all(ErrorKind::Cancel);
+            self.bodies.reset_all(ErrorKind::Cancel);
+            self.pushed.clear();
+        }
+        read
+    }
+
+    /// Takes the next completed response, or the error which ended its
+    /// stream.
+    pub fn poll_response(&mut self) -> Option<(StreamId, Result<Response>)> {
+        self.done.pop_front()
+    }
+
+    /// Takes the next response to a request sent with `send_streaming`, as
+    /// soon as its headers arrived. The body of the response is empty, its
+    /// data and trailers come through the `Body`.
+    pub fn poll_streaming_response(&mut self) -> Option<(StreamId, Result<(Response, Body)>)> {
+        self.streamed.pop_front()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.conn.is_closed()
+    }
+
+    /// Returns true while a request body waits for 100 (Continue).
+    pub fn is_awaiting_continue(&self) -> bool {
+        self.responses.values().any(|p| p.held.is_some())
+    }
+
+    /// Sends request bodies held back for 100 (Continue) which waited long
+    /// enough.
+    fn release_held(&mut self) {
+        let timeout = Duration::from_millis(CONTINUE_TIMEOUT);
+        let mut ids: Vec<StreamId> = self.responses
+            .iter()
+            .filter(|&(_, p)| p.response.is_none())
+            .filter(|&(_, p)| p.held.as_ref().map_or(false, |h| h.2.elapsed() >= timeout))
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort();
+        for id in ids {
+            self.send_held(id);
         }
     }
 
-    /// send preface string and settings frame
-    // TODO: add settings
-    fn send_preface(&mut self) -> Result<()> {
-        let preface = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
-        try!(self.conn.write(preface));
+    fn send_held(&mut self, id: StreamId) {
+        let held = self.responses.get_mut(&id).and_then(|p| p.held.take());
+        if let Some((body, trailers, _)) = held {
+            let mut result = self.conn.send_data(id, &body, trailers.is_empty());
+            if result.is_ok() && !trailers.is_empty() {
+                result = self.conn.send_trailers(id, &trailers);
+            }
+            if let Err(e) = result {
+                debug!("failed to send held body on {:?}: {}", id, e);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        let (id, result) = match event {
+            Event::Headers { stream_id, headers, end_stream } => {
+                (stream_id, self.recv_headers(stream_id, headers, end_stream).map(|_| end_stream))
+            }
+            Event::Data { stream_id, data, end_stream } => {
+                (stream_id, self.recv_data(stream_id, data, end_stream).map(|_| end_stream))
+            }
+            Event::Reset { stream_id, error } => {
+                self.finish(stream_id, Err(Error::new(error, "stream reset")));
+                return;
+            }
+            Event::PushPromise { stream_id, promised_stream_id, headers } => {
+                self.recv_push_promise(stream_id, promised_stream_id, headers);
+                return;
+            }
+            Event::Declined(response) => {
+                self.declined = true;
+                let id = StreamId(1);
+                if let Some(pending) = self.responses.get_mut(&id) {
+                    pending.response = Some(response);
+                }
+                self.complete(id);
+                return;
+            }
+            Event::GoAway { last_stream_id, .. } => {
+                // streams above last_stream_id were never processed, pushes
+                // are not requests of ours
+                let mut refused: Vec<StreamId> = self.responses
+                    .keys()
+                    .filter(|&&id| id > last_stream_id && !self.promised.contains_key(&id))
+                    .cloned()
+                    .collect();
+                refused.sort();
+                for id in refused {
+                    let error = Error::new(ErrorKind::RefusedStream, "refused by GOAWAY");
+                    self.finish(id, Err(error));
+                }
+                return;
+            }
+            _ => return,
+        };
+        match result {
+            Ok(true) => self.complete(id),
+            Ok(false) => {}
+            Err(e) => {
+                // malformed responses are stream errors (rfc 8.1.2.6)
+                self.conn.reset(id, ErrorKind::Protocol);
+                self.finish(id, Err(e));
+            }
+        }
+    }
 
-        // write settings frame
-        let frame = SettingsFrame::default();
-        try!(self.conn.write_frame(frame));
+    /// Accepts a promised push, unless its request is not a valid GET or HEAD
+    /// (rfc 8.2) or the push filter declines it.
+    fn recv_push_promise(&mut self, parent: StreamId, id: StreamId, headers: Vec<Header>) {
+        let request = match Request::from_headers(headers) {
+            Ok(ref request) if request.method() != Some(b"GET") &&
+                               request.method() != Some(b"HEAD") => {
+                debug!("promised {:?} is not a safe request", id);
+                return self.conn.reset(id, ErrorKind::Protocol);
+            }
+            Ok(request) => request,
+            Err(e) => {
+                debug!("malformed promise of {:?}: {}", id, e);
+                return self.conn.reset(id, ErrorKind::Protocol);
+            }
+        };
+        let accept = match self.push_filter {
+            Some(ref mut f) => f(parent, &request),
+            None => true,
+        };
+        if !accept {
+            return self.conn.reset(id, ErrorKind::Cancel);
+        }
+        if self.promised.len() + self.pushed.len() >= MAX_PUSHED {
+            debug!("too many pushes waiting, cancelling {:?}", id);
+            return self.conn.reset(id, ErrorKind::Cancel);
+        }
+        self.insert(id, request.method() == Some(b"HEAD"), false, None);
+        self.promised.insert(id, request);
+    }
 
+    fn recv_headers(&mut self, id: StreamId, headers: Vec<Header>, end_stream: bool)
+                    -> Result<()> {
+        let pending = match self.responses.get_mut(&id) {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        if let Some(ref mut response) = pending.response {
+            if !end_stream {
+                return Err(Error::protocol("trailers without END_STREAM"));
+            }
+            try!(message::check_trailers(&headers));
+            try!(pending.length.end());
+            if pending.streaming {
+                self.bodies.recv_trailers(id, headers);
+            } else {
+                response.trailers = headers;
+            }
+            return Ok(());
+        }
+        let response = try!(Response::from_headers(headers));
+        if response.is_informational() {
+            return self.recv_informational(id, response, end_stream);
+        }
+        let length = match response.status {
+            // no body whatever the content-length says
+            204 | 304 => Some(0),
+            _ if pending.head => Some(0),
+            _ => try!(message::content_length(&response.headers)),
+        };
+        pending.length = BodyLength::new(length);
+        if end_stream {
+            try!(pending.length.end());
+        }
+        let accepted = response.status < 300 && !end_stream;
+        if response.status >= 300 && pending.held.take().is_some() {
+            // the request was refused, its body is never sent
+            pending.abandoned = true;
+        }
+        if pending.streaming {
+            let body = self.bodies.receive(id);
+            self.bodies.recv_data(id, Vec::new(), end_stream);
+            self.streamed.push_back((id, Ok((response.clone(), body))));
+        }
+        pending.response = Some(response);
+        if accepted {
+            // a final response instead of 100 (Continue) still takes the body
+            self.send_held(id);
+        }
         Ok(())
     }
 
-    /// check preface
-    /// first received frame must be a valid settings frame
-    fn receive_preface(&mut self) -> Result<()> {
-        // read settings frame
-        if let FrameKind::Settings(frame) = try!(self.conn.read_frame(100)) {
-            if frame.is_ack() {
-                return Err(Error::new(ErrorKind::Protocol, "Invalid Preface, received ACK"));
+    /// Handles a 1xx header block, any number of them precede the final
+    /// response (rfc 8.1).
+    fn recv_informational(&mut self, id: StreamId, response: Response, end_stream: bool)
+                          -> Result<()> {
+        if end_stream {
+            return Err(Error::protocol("informational response with END_STREAM"));
+        }
+        if response.status == 101 {
+            // there is no upgrade in HTTP/2 (rfc 8.1.1)
+            return Err(Error::protocol("101 (Switching Protocols) response"));
+        }
+        if response.status == 100 {
+            self.send_held(id);
+        }
+        if let Some(ref mut f) = self.informational {
+            f(id, &response);
+        }
+        Ok(())
+    }
+
+    fn recv_data(&mut self, id: StreamId, data: Vec<u8>, end_stream: bool) -> Result<()> {
+        let pending = match self.responses.get_mut(&id) {
+            Some(pending) => pending,
+            None => {
+                self.conn.release_capacity(id, data.len());
+                return Ok(());
             }
+        };
+        if !pending.streaming {
+            // buffered bodies are consumed right away
+            self.conn.release_capacity(id, data.len());
+        }
+        let response = match pending.response {
+            Some(ref mut response) => response,
+            None => return Err(Error::protocol("DATA before response headers")),
+        };
+        try!(pending.length.add(data.len()));
+        if end_stream {
+            try!(pending.length.end());
+        }
+        if pending.streaming {
+            self.bodies.recv_data(id, data, end_stream);
         } else {
-            return Err(Error::new(ErrorKind::Protocol, "Invalid Preface, bad frame"));
+            response.body.extend(data);
         }
         Ok(())
     }
 
-    fn handle_next(&mut self) -> Result<()> {
-        match try!(self.conn.read_frame(100)) {
-            FrameKind::Settings(frame) => println!("Settings"),
-            // Unknown
-            _ => return Ok(()),
+    fn complete(&mut self, id: StreamId) {
+        if self.responses.get(&id).map_or(false, |p| p.held.is_some() || p.abandoned) {
+            // the final response came first, the body is not wanted
+            self.conn.reset(id, ErrorKind::Cancel);
+        }
+        let response = match self.responses.remove(&id) {
+            // the body has seen the end of the stream already
+            Some(Pending { streaming: true, response: Some(_), .. }) => return,
+            Some(Pending { response: Some(response), .. }) => response,
+            Some(pending) => {
+                self.responses.insert(id, pending);
+                return self.finish(id, Err(Error::protocol("response without headers")));
+            }
+            None => return,
+        };
+        match self.promised.remove(&id) {
+            Some(request) => self.pushed.push((request, response)),
+            None => self.report(id, Ok(response)),
+        }
+    }
+
+    /// Queues the result of a buffered response, under the stream the
+    /// request was first sent on.
+    fn report(&mut self, id: StreamId, result: Result<Response>) {
+        let id = self.replayed.remove(&id).unwrap_or(id);
+        self.done.push_back((id, result));
+    }
+
+    /// Ends the stream with an error, given to the response or its body.
+    fn finish(&mut self, id: StreamId, result: Result<Response>) {
+        if self.promised.remove(&id).is_some() {
+            // nobody waits for a failed push
+            self.responses.remove(&id);
+            return;
+        }
+        let pending = match self.responses.remove(&id) {
+            Some(pending) => pending,
+            None => {
+                // the response is complete, the request body may not be
+                if let Err(e) = result {
+                    self.bodies.reset(id, e.kind());
+                }
+                return;
+            }
+        };
+        if let (&Err(ref e), Some(request)) = (&result, pending.request) {
+            if e.is_retryable() {
+                let first = self.replayed.get(&id).cloned().unwrap_or(id);
+                self.refused.insert(first, request);
+            }
+        }
+        if !pending.streaming {
+            self.report(id, result);
+            return;
+        }
+        let error = match result {
+            Err(error) => error,
+            Ok(_) => return,
+        };
+        self.bodies.reset(id, error.kind());
+        if pending.response.is_none() {
+            self.streamed.push_back((id, Err(error)));
+        }
+    }
+
+    fn fail_all(&mut self, kind: ErrorKind) {
+        let mut ids: Vec<StreamId> = self.responses.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            self.finish(id, Err(Error::new(kind, "connection closed")));
+        }
+    }
+}
+
+impl Client<TcpStream> {
+    pub fn connect(addr: &SocketAddr) -> Result<Client<TcpStream>> {
+        let socket = try!(TcpStream::connect(addr));
+        let mut client = try!(Client::new(socket, default_settings(), &BufferPool::new()));
+        client.addr = Some(*addr);
+        Ok(client)
+    }
+
+    /// Connects with an upgrade to h2c, see `upgrade`, and blocks until the
+    /// response to `request` is complete. The client is closed if the server
+    /// declined the upgrade.
+    pub fn connect_upgrade(addr: &SocketAddr, request: Request)
+                           -> Result<(Client<TcpStream>, Response)> {
+        let socket = try!(TcpStream::connect(addr));
+        let mut client = try!(Client::upgrade(socket, default_settings(), &BufferPool::new(),
+                                              request));
+        client.addr = Some(*addr);
+        let response = try!(client.wait(StreamId(1)));
+        Ok((client, response))
+    }
+
+    /// Sends the request and blocks until its response is complete.
+    ///
+    /// With replay on, see `set_replay`, an idempotent request the server
+    /// refused is sent again, on a new connection if the old one is going
+    /// away.
+    pub fn request(&mut self, mut request: Request) -> Result<Response> {
+        let mut replays = 0;
+        loop {
+            if self.replay && self.conn.is_going_away() {
+                try!(self.reconnect());
+            }
+            let id = try!(self.send(request));
+            let result = self.wait(id);
+            let retry = match result {
+                Err(ref e) => e.is_retryable() && replays < MAX_REPLAYS,
+                Ok(_) => false,
+            };
+            request = match self.take_refused(id) {
+                Some(refused) if retry => refused,
+                _ => return result,
+            };
+            replays += 1;
+            self.replays += 1;
+            debug!("replaying the request refused on {:?}", id);
+        }
+    }
+
+    /// Replaces the connection with a new one to the same server. Kept
+    /// requests still pending on the old one are sent again, their
+    /// responses come under the stream they were first sent on, the other
+    /// requests fail.
+    fn reconnect(&mut self) -> Result<()> {
+        let addr = match self.addr {
+            Some(addr) => addr,
+            None => try!(self.socket().peer_addr()),
+        };
+        let socket = try!(TcpStream::connect(&addr));
+        let settings = self.conn.local_settings().clone();
+        let mut conn = try!(Connection::new(socket, Role::Client, settings, self.conn.pool()));
+        // ids stay unique across connections, results are told apart by them
+        conn.skip_stream_ids(self.conn.next_stream_id());
+        let mut kept: Vec<StreamId> = self.responses
+            .iter()
+            .filter(|&(id, p)| p.request.is_some() && !self.promised.contains_key(id))
+            .map(|(&id, _)| id)
+            .collect();
+        kept.sort();
+        let kept: Vec<(StreamId, Request)> = kept.into_iter()
+            .map(|id| (id, self.responses.remove(&id).unwrap().request.unwrap()))
+            .collect();
+        self.fail_all(ErrorKind::Cancel);
+        self.bodies.reset_all(ErrorKind::Cancel);
+        // pushes only stand in for requests on the connection they came on
+        self.pushed.clear();
+        self.conn = conn;
+        self.addr = Some(addr);
+        for (id, request) in kept {
+            let first = self.replayed.remove(&id).unwrap_or(id);
+            debug!("replaying the request pending on {:?}", id);
+            match self.send(request) {
+                Ok(replayed) => {
+                    self.replays += 1;
+                    self.replayed.insert(replayed, first);
+                }
+                Err(e) => self.done.push_back((first, Err(e))),
+            }
         }
         Ok(())
     }
 
-    fn handle_settings_frame(&mut self, frame: SettingsFrame) {
-        self.settings.update(frame)
+    /// Blocks until the response on the stream is complete.
+    fn wait(&mut self, id: StreamId) -> Result<Response> {
+        let deadline = Instant::now() + Duration::from_millis(REQUEST_TIMEOUT as u64);
+        let mut poll = try!(Poll::new());
+        try!(poll.register(self.socket(),
+                           Token(0),
+                           EventSet::readable() | EventSet::writable() | EventSet::hup(),
+                           PollOpt::edge()));
+        loop {
+            try!(self.ready());
+            // other responses stay for `poll_response`
+            if let Some(index) = self.done.iter().position(|&(done, _)| done == id) {
+                let _ = poll.deregister(self.socket());
+                return self.done.remove(index).unwrap().1;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::new(ErrorKind::Cancel, "request timed out"));
+            }
+            let left = deadline - now;
+            let mut timeout = left.as_secs() as usize * 1000 +
+                              left.subsec_nanos() as usize / 1000000;
+            if self.is_awaiting_continue() {
+                // wake up to send the body if 100 (Continue) does not come
+                timeout = ::std::cmp::min(timeout, CONTINUE_TIMEOUT as usize);
+            }
+            try!(poll.poll(Some(timeout)));
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::io::Read;
-    use mock::MockStream;
-    use super::Client;
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+    use mio::tcp::TcpStream;
+    use {Settings, StreamId};
+    use connection::PREFACE;
+    use error::ErrorKind;
     use frame::{FrameKind, ReadFrame, WriteFrame};
+    use frame::data::DataFrame;
+    use frame::goaway::GoAwayFrame;
+    use frame::ping::PingFrame;
+    use frame::rst_stream::RstStreamFrame;
     use frame::settings::SettingsFrame;
+    use harness::{Script, Expect};
+    use message::{Request, Response};
+    use mock::MockStream;
+    use pool::BufferPool;
+    use upgrade;
+    use super::{Client, MAX_PUSHED, default_settings};
+
+    fn client() -> (MockStream, Client<MockStream>) {
+        let (sconn, cconn) = MockStream::new();
+        (sconn, Client::new(cconn, default_settings(), &BufferPool::new()).unwrap())
+    }
+
+    fn get(path: &'static str) -> [(&'static str, &'static str); 4] {
+        [(":method", "GET"), (":scheme", "http"), (":authority", "localhost"), (":path", path)]
+    }
 
     #[test]
     fn test_client_send_preface() {
-        let (mut sconn, cconn) = MockStream::new();
-        let mut client = Client::new(cconn);
-        client.send_preface().unwrap();
+        let (mut sconn, mut client) = client();
+        client.ready().unwrap();
         let mut buf = [0; 24];
-        sconn.read(&mut buf).unwrap();
-        let preface = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
-        assert_eq!(&buf, preface);
+        sconn.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], PREFACE);
         // settings frame
-        if let FrameKind::Settings(res) = sconn.read_frame(100).unwrap() {
+        if let FrameKind::Settings(res) = sconn.read_frame().unwrap() {
             assert!(!res.is_ack());
         } else {
             panic!("Wrong frame type")
@@ -91,11 +757,390 @@ mod test {
 
     #[test]
     fn test_client_receive_preface() {
-        let (mut sconn, cconn) = MockStream::new();
-        let mut client = Client::new(cconn);
+        let (mut sconn, mut client) = client();
         // server preface
-        let frame = SettingsFrame::default();
-        sconn.write_frame(frame).unwrap();
-        client.receive_preface().unwrap();
+        sconn.write_frame(SettingsFrame::default()).unwrap();
+        client.ready().unwrap();
+        let mut buf = [0; 24];
+        sconn.read_exact(&mut buf).unwrap();
+        sconn.read_frame().unwrap();
+        // the server's settings are acknowledged
+        if let FrameKind::Settings(res) = sconn.read_frame().unwrap() {
+            assert!(res.is_ack());
+        } else {
+            panic!("Wrong frame type")
+        }
     }
+
+    #[test]
+    fn test_client() {
+        let mut client = Script::new()
+            .handshake()
+            .act(|client: &mut Client<MockStream>| {
+                client.send(Request::new("GET", "http", "localhost", "/")).unwrap();
+            })
+            .expect(Expect::Field(1, ":method", "GET"))
+            .headers(1, &[(":status", "200")], false)
+            .send(DataFrame::new(StreamId(1)).data(&b"hi"[..]).end_stream())
+            .send(SettingsFrame::default())
+            .expect(Expect::SettingsAck)
+            .run_client();
+        let (id, response) = client.poll_response().unwrap();
+        assert_eq!(id, StreamId(1));
+        assert_eq!(response.unwrap(), Response::new(200).body(&b"hi"[..]));
+    }
+
+    #[test]
+    fn test_client_goaway() {
+        let mut client = Script::new()
+            .handshake()
+            .act(|client: &mut Client<MockStream>| {
+                client.send(Request::new("GET", "http", "localhost", "/")).unwrap();
+            })
+            .expect(Expect::Headers(1))
+            .send(GoAwayFrame::new(StreamId(0), ErrorKind::No))
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::PingAck([0; 8]))
+            .run_client();
+        let (_, response) = client.poll_response().unwrap();
+        assert_eq!(response.unwrap_err().kind(), ErrorKind::RefusedStream);
+    }
+
+    /// Refused idempotent requests are kept to be replayed.
+    #[test]
+    fn test_client_replay() {
+        let mut client = Script::new()
+            .handshake()
+            .act(|client: &mut Client<MockStream>| {
+                client.set_replay(true);
+                for &method in &["GET", "POST", "PUT", "GET"] {
+                    client.send(Request::new(method, "http", "localhost", "/")).unwrap();
+                }
+            })
+            .expect(Expect::Headers(7))
+            .send(RstStreamFrame::new(StreamId(3), ErrorKind::RefusedStream))
+            .send(GoAwayFrame::new(StreamId(1), ErrorKind::No))
+            .headers(1, &[(":status", "200")], true)
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::PingAck([0; 8]))
+            .run_client();
+        assert!(client.is_going_away());
+        let mut refused = Vec::new();
+        while let Some((id, response)) = client.poll_response() {
+            match response {
+                Ok(response) => assert_eq!((id, response.status), (StreamId(1), 200)),
+                Err(e) => {
+                    assert!(e.is_retryable());
+                    let method = client.take_refused(id).map(|r| r.method().unwrap().to_vec());
+                    refused.push((id, method));
+                }
+            }
+        }
+        assert_eq!(refused,
+                   vec![(StreamId(3), None),
+                        (StreamId(5), Some(b"PUT".to_vec())),
+                        (StreamId(7), Some(b"GET".to_vec()))]);
+    }
+
+    #[test]
+    fn test_client_malformed_response() {
+        let mut client = Script::new()
+            .handshake()
+            .act(|client: &mut Client<MockStream>| {
+                client.send(Request::new("GET", "http", "localhost", "/")).unwrap();
+            })
+            .expect(Expect::Headers(1))
+            .headers(1, &[(":status", "200"), ("content-length", "1")], false)
+            .send(DataFrame::new(StreamId(1)).data(&b"hi"[..]).end_stream())
+            .stream_error(1, ErrorKind::Protocol)
+            .run_client();
+        let (_, response) = client.poll_response().unwrap();
+        assert_eq!(response.unwrap_err().kind(), ErrorKind::Protocol);
+    }
+
+    #[test]
+    fn test_client_trailers() {
+        let mut client = Script::new()
+            .handshake()
+            .act(|client: &mut Client<MockStream>| {
+                let req = Request::new("POST", "http", "localhost", "/").trailer("checksum", "1");
+                client.send(req).unwrap();
+            })
+            .expect(Expect::Field(1, "checksum", "1"))
+            .headers(1, &[(":status", "200")], false)
+            .send(DataFrame::new(StreamId(1)).data(&b"hi"[..]))
+            .headers(1, &[("grpc-status", "0")], true)
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::PingAck([0; 8]))
+            .run_client();
+        let (_, response) = client.poll_response().unwrap();
+        assert_eq!(response.unwrap(),
+                   Response::new(200).body(&b"hi"[..]).trailer("grpc-status", "0"));
+    }
+
+    #[test]
+    fn test_client_streaming() {
+        let sender = Rc::new(RefCell::new(None));
+        let (start, finish) = (sender.clone(), sender.clone());
+        let mut client = Script::new()
+            .handshake()
+            .act(move |client: &mut Client<MockStream>| {
+                let req = Request::new("POST", "http", "localhost", "/");
+                let (_, mut body) = client.send_streaming(req).unwrap();
+                body.write_all(b"ab").unwrap();
+                *start.borrow_mut() = Some(body);
+            })
+            .expect(Expect::Data(1, b"ab".to_vec()))
+            .act(move |_: &mut Client<MockStream>| {
+                finish.borrow_mut().take().unwrap().finish();
+            })
+            .expect(Expect::EndStream(1))
+            .headers(1, &[(":status", "200")], false)
+            .send(DataFrame::new(StreamId(1)).data(&b"xy"[..]).end_stream())
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::PingAck([0; 8]))
+            .run_client();
+        let (id, response) = client.poll_streaming_response().unwrap();
+        let (response, mut body) = response.unwrap();
+        assert_eq!(id, StreamId(1));
+        assert_eq!(response.status, 200);
+        let mut data = Vec::new();
+        body.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"xy");
+        assert!(client.poll_response().is_none());
+    }
+
+    #[test]
+    fn test_client_informational() {
+        let statuses = Rc::new(RefCell::new(Vec::new()));
+        let seen = statuses.clone();
+        let mut client = Script::new()
+            .handshake()
+            .act(move |client: &mut Client<MockStream>| {
+                let seen = seen.clone();
+                client.on_informational(move |_, res| seen.borrow_mut().push(res.status));
+                let req = Request::new("POST", "http", "localhost", "/")
+                    .header("expect", "100-continue")
+                    .body(&b"body"[..]);
+                client.send(req).unwrap();
+            })
+            .expect(Expect::Headers(1))
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::PingAck([0; 8]))
+            .refute(Expect::Data(1, b"body".to_vec()))
+            .headers(1, &[(":status", "103"), ("link", "</a.css>")], false)
+            .headers(1, &[(":status", "100")], false)
+            .expect(Expect::Data(1, b"body".to_vec()))
+            .headers(1, &[(":status", "200")], true)
+            .send(PingFrame::new([1; 8]))
+            .expect(Expect::PingAck([1; 8]))
+            .run_client();
+        assert_eq!(*statuses.borrow(), vec![103, 100]);
+        let (_, response) = client.poll_response().unwrap();
+        assert_eq!(response.unwrap(), Response::new(200));
+    }
+
+    /// Kept requests pending on a connection going away are sent again on
+    /// the new one, their responses come under their first stream.
+    #[test]
+    fn test_client_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            // the requests come right after the preface, no handshake
+            Script::new()
+                .send(SettingsFrame::default())
+                .expect(Expect::Field(1, ":path", "/kept"))
+                .send(GoAwayFrame::new(StreamId(1), ErrorKind::No))
+                .expect(Expect::Closed)
+                .run_accept(&listener);
+            Script::new()
+                .send(SettingsFrame::default())
+                .expect(Expect::Field(3, ":path", "/kept"))
+                .expect(Expect::Field(5, ":path", "/next"))
+                .headers(3, &[(":status", "204")], true)
+                .headers(5, &[(":status", "200")], true)
+                .run_accept(&listener);
+        });
+        let pool = BufferPool::new();
+        let socket = TcpStream::connect(&addr).unwrap();
+        let mut client = Client::new(socket, default_settings(), &pool).unwrap();
+        client.set_replay(true);
+        let kept = client.send(Request::new("GET", "http", "localhost", "/kept")).unwrap();
+        while !client.is_going_away() {
+            client.ready().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+        let acquired = pool.stats().acquired;
+        let response = client.request(Request::new("GET", "http", "localhost", "/next"));
+        assert_eq!(response.unwrap(), Response::new(200));
+        // the response which came first waits for `poll_response`
+        let (id, response) = client.poll_response().unwrap();
+        assert_eq!(id, kept);
+        assert_eq!(response.unwrap(), Response::new(204));
+        assert!(client.poll_response().is_none());
+        assert_eq!(client.replayed(), 1);
+        // the new connection took its buffers from the same pool
+        assert!(pool.stats().acquired > acquired);
+        server.join().unwrap();
+    }
+
+    /// A final response refusing the request drops the body held for
+    /// 100 (Continue), the stream is reset once the response is complete.
+    #[test]
+    fn test_client_expectation_failed() {
+        let mut client = Script::new()
+            .handshake()
+            .act(|client: &mut Client<MockStream>| {
+                let req = Request::new("POST", "http", "localhost", "/")
+                    .header("expect", "100-continue")
+                    .body(&b"body"[..]);
+                client.send(req).unwrap();
+            })
+            .expect(Expect::Headers(1))
+            .headers(1, &[(":status", "417"), ("content-length", "4")], false)
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::PingAck([0; 8]))
+            .refute(Expect::Data(1, b"body".to_vec()))
+            .act(|client: &mut Client<MockStream>| assert!(!client.is_awaiting_continue()))
+            .send(DataFrame::new(StreamId(1)).data(&b"nope"[..]).end_stream())
+            .expect(Expect::Reset(1, ErrorKind::Cancel))
+            .refute(Expect::Data(1, b"body".to_vec()))
+            .run_client();
+        let (_, response) = client.poll_response().unwrap();
+        let response = response.unwrap();
+        assert_eq!(response.status, 417);
+        assert_eq!(response.body, b"nope");
+    }
+
+    /// 101 (Switching Protocols) does not exist in HTTP/2 (rfc 8.1.1).
+    #[test]
+    fn test_client_switching_protocols() {
+        let mut client = Script::new()
+            .handshake()
+            .act(|client: &mut Client<MockStream>| {
+                client.send(Request::new("GET", "http", "localhost", "/")).unwrap();
+            })
+            .expect(Expect::Headers(1))
+            .headers(1, &[(":status", "101")], false)
+            .stream_error(1, ErrorKind::Protocol)
+            .run_client();
+        let (_, response) = client.poll_response().unwrap();
+        assert_eq!(response.unwrap_err().kind(), ErrorKind::Protocol);
+    }
+
+    #[test]
+    fn test_client_push() {
+        let settings = Settings { enable_push: true, ..default_settings() };
+        let mut client = Script::new()
+            .handshake()
+            .act(|client: &mut Client<MockStream>| {
+                client.on_push(|_, req| req.path() != Some(b"/unwanted"));
+                client.send(Request::new("GET", "http", "localhost", "/")).unwrap();
+            })
+            .expect(Expect::Headers(1))
+            .push_promise(1, 2, &get("/style.css"))
+            .push_promise(1, 4, &get("/unwanted"))
+            .expect(Expect::Reset(4, ErrorKind::Cancel))
+            .push_promise(1, 6, &[(":method", "POST"), (":path", "/")])
+            .expect(Expect::Reset(6, ErrorKind::Protocol))
+            .headers(2, &[(":status", "200")], false)
+            .send(DataFrame::new(StreamId(2)).data(&b"css"[..]).end_stream())
+            .headers(1, &[(":status", "200")], true)
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::PingAck([0; 8]))
+            .run_client_with(settings);
+        let (id, _) = client.poll_response().unwrap();
+        assert_eq!(id, StreamId(1));
+        assert!(client.poll_response().is_none());
+        let css = Request::new("GET", "http", "localhost", "/style.css");
+        assert_eq!(client.take_pushed(&css), Some(Response::new(200).body(&b"css"[..])));
+        assert_eq!(client.take_pushed(&css), None);
+    }
+
+    /// Pushes nobody takes are kept up to a limit, later promises are
+    /// cancelled until one is taken.
+    #[test]
+    fn test_client_push_limit() {
+        let settings = Settings { enable_push: true, ..default_settings() };
+        let mut script = Script::new()
+            .handshake()
+            .act(|client: &mut Client<MockStream>| {
+                client.send(Request::new("GET", "http", "localhost", "/")).unwrap();
+            })
+            .expect(Expect::Headers(1));
+        let last = 2 * MAX_PUSHED as u32;
+        for id in (1..MAX_PUSHED as u32 + 1).map(|i| 2 * i) {
+            script = script.push_promise(1, id, &get("/pushed"))
+                .headers(id, &[(":status", "200")], true);
+        }
+        let mut client = script.push_promise(1, last + 2, &get("/pushed"))
+            .expect(Expect::Reset(last + 2, ErrorKind::Cancel))
+            .act(|client: &mut Client<MockStream>| {
+                let pushed = Request::new("GET", "http", "localhost", "/pushed");
+                assert!(client.take_pushed(&pushed).is_some());
+            })
+            .push_promise(1, last + 4, &get("/pushed"))
+            .headers(1, &[(":status", "200")], true)
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::PingAck([0; 8]))
+            .refute(Expect::Reset(last + 4, ErrorKind::Cancel))
+            .run_client_with(settings);
+        assert_eq!(client.promised.len() + client.pushed.len(), MAX_PUSHED);
+    }
+
+    #[test]
+    fn test_client_upgrade() {
+        let request = Request::new("GET", "http", "localhost", "/");
+        let mut client = Script::new()
+            .expect_raw(b"GET / HTTP/1.1\r\n\
+                          host: localhost\r\n\
+                          connection: Upgrade, HTTP2-Settings\r\n\
+                          upgrade: h2c\r\n\
+                          http2-settings: AAEAABAAAAIAAAAAAAQAAP__AAUAAEAA\r\n\r\n")
+            .act(|client: &mut Client<MockStream>| {
+                assert!(client.is_upgrading());
+                assert!(!client.has_capacity());
+                let request = Request::new("GET", "http", "localhost", "/");
+                assert_eq!(client.send(request).unwrap_err().kind(), ErrorKind::RefusedStream);
+            })
+            .send_raw(upgrade::SWITCHING_PROTOCOLS)
+            .handshake()
+            .headers(1, &[(":status", "200")], false)
+            .send(DataFrame::new(StreamId(1)).data(&b"hi"[..]).end_stream())
+            .act(|client: &mut Client<MockStream>| {
+                assert!(!client.is_upgrading());
+                client.send(Request::new("GET", "http", "localhost", "/next")).unwrap();
+            })
+            .expect(Expect::Field(3, ":path", "/next"))
+            .refute(Expect::Headers(1))
+            .run_client_upgrade(request);
+        let (id, response) = client.poll_response().unwrap();
+        assert_eq!(id, StreamId(1));
+        assert_eq!(response.unwrap(), Response::new(200).body(&b"hi"[..]));
+        assert!(!client.is_declined());
+    }
+
+    #[test]
+    fn test_client_upgrade_declined() {
+        let request = Request::new("POST", "http", "localhost", "/").body(&b"body"[..]);
+        let mut client = Script::new()
+            .expect_raw(b"POST / HTTP/1.1\r\n\
+                          host: localhost\r\n\
+                          content-length: 4\r\n\
+                          connection: Upgrade, HTTP2-Settings\r\n\
+                          upgrade: h2c\r\n\
+                          http2-settings: AAEAABAAAAIAAAAAAAQAAP__AAUAAEAA\r\n\r\n\
+                          body")
+            .send_raw(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+            .expect(Expect::Closed)
+            .run_client_upgrade(request);
+        let (id, response) = client.poll_response().unwrap();
+        assert_eq!(id, StreamId(1));
+        assert_eq!(response.unwrap(),
+                   Response::new(200).header("content-length", "5").body(&b"hello"[..]));
+        assert!(client.is_declined());
+        assert!(client.is_closed());
+    }
+
 }
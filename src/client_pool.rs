@@ -0,0 +1,424 @@
+//! Pool of client connections to many servers.
+//!
+//! Requests are multiplexed onto an existing connection to their scheme and
+//! authority until the server's MAX_CONCURRENT_STREAMS is reached, then
+//! another connection is opened. Connections are dropped after GOAWAY once
+//! their requests are complete, and after being idle for a while.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::ToSocketAddrs;
+use std::time::{Duration, Instant};
+use mio::tcp::TcpStream;
+use {Settings, StreamId};
+use client::{self, Client};
+use connection::Transport;
+use error::{Error, ErrorKind, Result};
+use message::{Request, Response};
+use pool::BufferPool;
+
+/// How long an idle connection is kept, in seconds.
+const DEFAULT_IDLE_TIMEOUT: u64 = 90;
+
+/// How often a refused request is replayed.
+const MAX_REPLAYS: usize = 3;
+
+/// The servers a pool keeps separate connections for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub scheme: String,
+    pub authority: String,
+}
+
+impl Key {
+    pub fn new(scheme: &str, authority: &str) -> Key {
+        Key {
+            scheme: scheme.to_owned(),
+            authority: authority.to_owned(),
+        }
+    }
+
+    /// The key of the server a request is for.
+    pub fn from_request(request: &Request) -> Result<Key> {
+        let field = |name| {
+            request.get(name)
+                .and_then(|value| ::std::str::from_utf8(value).ok())
+                .ok_or_else(|| Error::new(ErrorKind::Internal, format!("request without {}", name)))
+        };
+        Ok(Key::new(try!(field(":scheme")), try!(field(":authority"))))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}://{}", self.scheme, self.authority)
+    }
+}
+
+/// Opens the connections of a pool.
+pub trait Connect {
+    type Stream: Transport;
+
+    fn connect(&mut self, key: &Key) -> Result<Self::Stream>;
+}
+
+/// Connects over TCP, to port 80 or 443 unless the authority names one.
+///
+/// There is no TLS, `https` only selects the default port.
+pub struct TcpConnector;
+
+impl Connect for TcpConnector {
+    type Stream = TcpStream;
+
+    fn connect(&mut self, key: &Key) -> Result<TcpStream> {
+        let port = if key.scheme == "https" { 443 } else { 80 };
+        let addrs = match key.authority.to_socket_addrs() {
+            Ok(addrs) => addrs.collect::<Vec<_>>(),
+            Err(_) => {
+                let host = key.authority.trim_left_matches('[').trim_right_matches(']');
+                try!((host, port).to_socket_addrs()).collect()
+            }
+        };
+        let mut last = Error::new(ErrorKind::Internal, format!("{} has no address", key));
+        for addr in addrs {
+            match TcpStream::connect(&addr) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last = Error::from(e),
+            }
+        }
+        Err(last)
+    }
+}
+
+/// Identifies a request sent through a pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId {
+    /// The connection the request went out on, numbered by the pool.
+    pub connection: usize,
+    pub stream: StreamId,
+}
+
+/// Snapshot of the connections of a pool.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ClientPoolStats {
+    /// Connections currently open.
+    pub connections: usize,
+    /// Open connections without pending requests.
+    pub idle: usize,
+    /// Requests waiting for their response.
+    pub pending: usize,
+    /// Total number of connections opened.
+    pub opened: u64,
+    /// Total number of connections dropped.
+    pub evicted: u64,
+    /// Total number of refused requests sent again.
+    pub replayed: u64,
+}
+
+struct Entry<S> {
+    id: usize,
+    client: Client<S>,
+    last_used: Instant,
+}
+
+/// Client connections keyed by scheme and authority.
+pub struct ClientPool<C: Connect> {
+    connector: C,
+    settings: Settings,
+    buffers: BufferPool,
+    entries: HashMap<Key, Vec<Entry<C::Stream>>>,
+    idle_timeout: Duration,
+    next_id: usize,
+    // replayed requests and the request they stand in for, with the number
+    // of replays so far
+    replays: HashMap<RequestId, (RequestId, usize)>,
+    done: VecDeque<(RequestId, Result<Response>)>,
+    stats: ClientPoolStats,
+}
+
+impl<C: Connect> ClientPool<C> {
+    pub fn new(connector: C, settings: Settings) -> ClientPool<C> {
+        ClientPool {
+            connector: connector,
+            settings: settings,
+            buffers: BufferPool::new(),
+            entries: HashMap::new(),
+            idle_timeout: Duration::from_secs(DEFAULT_IDLE_TIMEOUT),
+            next_id: 0,
+            replays: HashMap::new(),
+            done: VecDeque::new(),
+            stats: ClientPoolStats::default(),
+        }
+    }
+
+    /// How long a connection without requests is kept open.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Sends the request on a connection to its server with room for another
+    /// stream, opening one if there is none. Call `ready` to send it.
+    pub fn send(&mut self, request: Request) -> Result<RequestId> {
+        let key = try!(Key::from_request(&request));
+        let index = try!(self.checkout(&key));
+        let entry = &mut self.entries.get_mut(&key).unwrap()[index];
+        let stream = try!(entry.client.send(request));
+        entry.last_used = Instant::now();
+        Ok(RequestId {
+            connection: entry.id,
+            stream: stream,
+        })
+    }
+
+    /// The index of a connection to the server which takes another request.
+    fn checkout(&mut self, key: &Key) -> Result<usize> {
+        let entries = self.entries.entry(key.clone()).or_insert_with(Vec::new);
+        if let Some(index) = entries.iter().position(|e| e.client.has_capacity()) {
+            return Ok(index);
+        }
+        let stream = try!(self.connector.connect(key));
+        let mut client = try!(Client::new(stream, self.settings.clone(), &self.buffers));
+        client.set_replay(true);
+        debug!("opened connection {} to {}", self.next_id, key);
+        entries.push(Entry {
+            id: self.next_id,
+            client: client,
+            last_used: Instant::now(),
+        });
+        self.next_id += 1;
+        self.stats.opened += 1;
+        Ok(entries.len() - 1)
+    }
+
+    /// Drives every connection, collects completed responses, replays
+    /// refused idempotent requests and drops connections which are done.
+    pub fn ready(&mut self) {
+        let mut refused = Vec::new();
+        for entries in self.entries.values_mut() {
+            for entry in entries.iter_mut() {
+                if let Err(e) = entry.client.ready() {
+                    debug!("connection {}: {}", entry.id, e);
+                }
+                while let Some((stream, result)) = entry.client.poll_response() {
+                    let id = RequestId {
+                        connection: entry.id,
+                        stream: stream,
+                    };
+                    let retryable = result.as_ref().err().map_or(false, |e| e.is_retryable());
+                    match entry.client.take_refused(stream) {
+                        Some(request) if retryable => refused.push((id, request, result)),
+                        _ => {
+                            let original = self.replays.remove(&id).map_or(id, |(o, _)| o);
+                            self.done.push_back((original, result));
+                        }
+                    }
+                    entry.last_used = Instant::now();
+                }
+            }
+        }
+        for (id, request, result) in refused {
+            self.replay(id, request, result);
+        }
+        self.evict();
+    }
+
+    /// Sends a refused request again, on another connection if its own is
+    /// going away.
+    fn replay(&mut self, id: RequestId, request: Request, result: Result<Response>) {
+        let (original, count) = self.replays.remove(&id).unwrap_or((id, 0));
+        if count == MAX_REPLAYS {
+            return self.done.push_back((original, result));
+        }
+        match self.send(request) {
+            Ok(new) => {
+                debug!("replaying {:?} as {:?}", original, new);
+                self.stats.replayed += 1;
+                self.replays.insert(new, (original, count + 1));
+            }
+            Err(e) => self.done.push_back((original, Err(e))),
+        }
+    }
+
+    /// Drops closed connections, those going away once they are done and
+    /// those idle for too long.
+    fn evict(&mut self) {
+        let idle_timeout = self.idle_timeout;
+        let mut evicted = 0;
+        for entries in self.entries.values_mut() {
+            entries.retain(|entry| {
+                let client = &entry.client;
+                let idle = client.pending() == 0;
+                let keep = !client.is_closed() && !(idle && client.is_going_away()) &&
+                           !(idle && entry.last_used.elapsed() >= idle_timeout);
+                if !keep {
+                    debug!("dropping connection {}", entry.id);
+                    evicted += 1;
+                }
+                keep
+            });
+        }
+        self.entries.retain(|_, entries| !entries.is_empty());
+        self.stats.evicted += evicted;
+    }
+
+    /// Takes the next completed response, or the error which ended its
+    /// request.
+    pub fn poll_response(&mut self) -> Option<(RequestId, Result<Response>)> {
+        self.done.pop_front()
+    }
+
+    /// Number of open connections to the server.
+    pub fn connections(&self, key: &Key) -> usize {
+        self.entries.get(key).map_or(0, |entries| entries.len())
+    }
+
+    pub fn stats(&self) -> ClientPoolStats {
+        let mut stats = self.stats;
+        for entry in self.entries.values().flat_map(|entries| entries.iter()) {
+            stats.connections += 1;
+            stats.pending += entry.client.pending();
+            if entry.client.pending() == 0 {
+                stats.idle += 1;
+            }
+        }
+        stats
+    }
+}
+
+impl ClientPool<TcpConnector> {
+    /// A pool of TCP connections announcing the default client settings.
+    pub fn tcp() -> ClientPool<TcpConnector> {
+        ClientPool::new(TcpConnector, client::default_settings())
+    }
+
+    /// Sends the request on a pooled connection and blocks until its
+    /// response is complete, see `Client::request`.
+    ///
+    /// Only that connection is driven meanwhile, the others wait for the
+    /// next `ready`, which also collects the responses to requests sent
+    /// before on the same connection.
+    pub fn request(&mut self, request: Request) -> Result<Response> {
+        let key = try!(Key::from_request(&request));
+        let index = try!(self.checkout(&key));
+        let entry = &mut self.entries.get_mut(&key).unwrap()[index];
+        entry.last_used = Instant::now();
+        let replayed = entry.client.replayed();
+        let result = entry.client.request(request);
+        self.stats.replayed += entry.client.replayed() - replayed;
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use Settings;
+    use client;
+    use error::Result;
+    use message::{Request, Response};
+    use mock::MockStream;
+    use pool::BufferPool;
+    use server::{self, ServerConnection};
+    use super::{ClientPool, Connect, Key};
+
+    type Servers = Rc<RefCell<Vec<ServerConnection<MockStream>>>>;
+
+    /// Connects to in-memory servers allowing one stream at a time.
+    struct MockConnector(Servers);
+
+    impl Connect for MockConnector {
+        type Stream = MockStream;
+
+        fn connect(&mut self, _: &Key) -> Result<MockStream> {
+            let (local, remote) = MockStream::new();
+            let settings = Settings {
+                max_concurrent_streams: Some(1),
+                ..server::default_settings()
+            };
+            let server = ServerConnection::new(remote, settings, &BufferPool::new()).unwrap();
+            self.0.borrow_mut().push(server);
+            Ok(local)
+        }
+    }
+
+    fn drive(pool: &mut ClientPool<MockConnector>, servers: &Servers) {
+        let mut service = |_: Request| Response::new(200);
+        for _ in 0..4 {
+            pool.ready();
+            for server in servers.borrow_mut().iter_mut() {
+                let _ = server.ready(&mut service);
+            }
+        }
+    }
+
+    fn get(authority: &str) -> Request {
+        Request::new("GET", "http", authority, "/")
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(Key::from_request(&get("a:80")).unwrap(), Key::new("http", "a:80"));
+        assert!(Key::from_request(&Request::default()).is_err());
+    }
+
+    #[test]
+    fn test_pool() {
+        let servers = Servers::default();
+        let mut pool = ClientPool::new(MockConnector(servers.clone()), client::default_settings());
+        let a = Key::new("http", "a");
+
+        // the first exchange brings the server's settings
+        let first = pool.send(get("a")).unwrap();
+        drive(&mut pool, &servers);
+        assert_eq!(pool.poll_response().unwrap().0, first);
+
+        // saturated connections are joined by another
+        pool.send(get("a")).unwrap();
+        pool.send(get("a")).unwrap();
+        pool.send(get("b")).unwrap();
+        assert_eq!((pool.connections(&a), pool.stats().pending), (2, 3));
+        drive(&mut pool, &servers);
+        let mut done = 0;
+        while let Some((_, response)) = pool.poll_response() {
+            assert_eq!(response.unwrap().status, 200);
+            done += 1;
+        }
+        assert_eq!(done, 3);
+        let stats = pool.stats();
+        assert_eq!((stats.connections, stats.idle, stats.opened), (3, 3, 3));
+
+        // connections going away are dropped
+        servers.borrow_mut()[0].shutdown();
+        drive(&mut pool, &servers);
+        assert_eq!(pool.connections(&a), 1);
+
+        pool.set_idle_timeout(Duration::from_secs(0));
+        pool.ready();
+        assert_eq!(pool.stats().connections, 0);
+        assert_eq!(pool.stats().evicted, 3);
+    }
+
+    /// A request refused by the server is replayed under its original id.
+    #[test]
+    fn test_replay() {
+        let servers = Servers::default();
+        let mut pool = ClientPool::new(MockConnector(servers.clone()), client::default_settings());
+        // both go out before the server's limit is known
+        let first = pool.send(get("a")).unwrap();
+        let second = pool.send(get("a")).unwrap();
+        assert_eq!(pool.connections(&Key::new("http", "a")), 1);
+        drive(&mut pool, &servers);
+        let mut ids = vec![pool.poll_response().unwrap(), pool.poll_response().unwrap()]
+            .into_iter()
+            .map(|(id, response)| {
+                assert_eq!(response.unwrap().status, 200);
+                id
+            })
+            .collect::<Vec<_>>();
+        ids.sort_by_key(|id| id.stream);
+        assert_eq!(ids, vec![first, second]);
+        assert_eq!(pool.stats().replayed, 1);
+    }
+}
@@ -0,0 +1,331 @@
+//! Conversions from and to the types of the `http` crate.
+//!
+//! Pseudo-header fields become the method, uri and status of the `http`
+//! types and back, all other fields go to and from a `HeaderMap`. Bodies are
+//! plain `Vec<u8>`, trailers are kept in the extensions as `Trailers`.
+
+use mio::tcp::TcpStream;
+use http::{self, HeaderMap, Method, StatusCode, Uri, Version};
+use http::header::{HeaderName, HeaderValue, CONNECTION, HOST, TE};
+use StreamId;
+use client::Client;
+use connection::Transport;
+use error::{Error, Result};
+use hpack::Header;
+use message::{Request, Response};
+use server::Service;
+
+/// Header fields only meaningful for HTTP/1 connections (rfc 8.1.2.2).
+const CONNECTION_HEADERS: [&'static str; 5] = ["connection",
+                                               "keep-alive",
+                                               "proxy-connection",
+                                               "transfer-encoding",
+                                               "upgrade"];
+
+/// The trailers of an `http::Request` or `http::Response`, found in its
+/// extensions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trailers(pub HeaderMap);
+
+/// The regular header fields of a header block.
+pub fn header_map(headers: &[Header]) -> Result<HeaderMap> {
+    let mut map = HeaderMap::with_capacity(headers.len());
+    for &(ref name, ref value) in headers.iter().filter(|&&(ref n, _)| n.first() != Some(&b':')) {
+        let name = try!(HeaderName::from_bytes(name).map_err(Error::protocol));
+        let value = try!(HeaderValue::from_bytes(value).map_err(Error::protocol));
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+/// The header fields of a map, in the order of the map.
+pub fn header_list(map: &HeaderMap) -> Vec<Header> {
+    map.iter()
+        .map(|(name, value)| (name.as_str().as_bytes().to_vec(), value.as_bytes().to_vec()))
+        .collect()
+}
+
+/// The header fields of a map without the connection-specific ones, which
+/// would make the message malformed in HTTP/2, and without those the
+/// `connection` field names.
+fn h2_header_list(map: &HeaderMap) -> Vec<Header> {
+    let nominated: Vec<String> = map.get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .collect();
+    map.iter()
+        .filter(|&(name, value)| {
+            let name = name.as_str();
+            !CONNECTION_HEADERS.contains(&name) && !nominated.iter().any(|n| n == name) &&
+            (name != TE || value == "trailers")
+        })
+        .map(|(name, value)| (name.as_str().as_bytes().to_vec(), value.as_bytes().to_vec()))
+        .collect()
+}
+
+fn to_str(value: &[u8]) -> Result<&str> {
+    ::std::str::from_utf8(value).map_err(Error::protocol)
+}
+
+impl Request {
+    /// Converts an `http::Request`, the authority is taken from the uri or
+    /// else the host header field, a uri without scheme means `http`.
+    pub fn from_http(request: http::Request<Vec<u8>>) -> Request {
+        let (mut parts, body) = request.into_parts();
+        let mut req = Request::default().header(":method", parts.method.as_str());
+        let authority = parts.uri
+            .authority_part()
+            .map(|a| a.as_str().as_bytes().to_vec())
+            .or_else(|| parts.headers.get(HOST).map(|h| h.as_bytes().to_vec()));
+        if parts.method != Method::CONNECT {
+            req = req.header(":scheme", parts.uri.scheme_str().unwrap_or("http"));
+        }
+        if let Some(authority) = authority {
+            req = req.header(":authority", authority);
+        }
+        if parts.method != Method::CONNECT {
+            let path = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+            req = req.header(":path", if path.is_empty() { "/" } else { path });
+        }
+        // :authority replaces the host header field (rfc 8.1.2.3)
+        req.headers.extend(h2_header_list(&parts.headers)
+            .into_iter()
+            .filter(|&(ref n, _)| n != b"host"));
+        if let Some(Trailers(trailers)) = parts.extensions.remove() {
+            req.trailers = header_list(&trailers);
+        }
+        req.body(body)
+    }
+
+    /// Converts to an `http::Request`, fails for fields the `http` types
+    /// cannot represent.
+    pub fn into_http(self) -> Result<http::Request<Vec<u8>>> {
+        let method = match self.method() {
+            Some(method) => try!(Method::from_bytes(method).map_err(Error::protocol)),
+            None => return Err(Error::protocol("request without :method")),
+        };
+        let authority = match self.get(":authority") {
+            Some(authority) => Some(try!(to_str(authority))),
+            None => None,
+        };
+        let uri = if method == Method::CONNECT {
+            authority.unwrap_or("").parse::<Uri>()
+        } else {
+            let scheme = try!(to_str(self.get(":scheme").unwrap_or(b"http")));
+            let path = try!(to_str(self.path().unwrap_or(b"/")));
+            match authority {
+                Some(authority) => format!("{}://{}{}", scheme, authority, path).parse(),
+                None => path.parse(),
+            }
+        };
+        let mut request = http::Request::new(Vec::new());
+        *request.uri_mut() = try!(uri.map_err(Error::protocol));
+        *request.method_mut() = method;
+        *request.version_mut() = Version::HTTP_2;
+        *request.headers_mut() = try!(header_map(&self.headers));
+        if !self.trailers.is_empty() {
+            request.extensions_mut().insert(Trailers(try!(header_map(&self.trailers))));
+        }
+        *request.body_mut() = self.body;
+        Ok(request)
+    }
+}
+
+impl Response {
+    pub fn from_http(response: http::Response<Vec<u8>>) -> Response {
+        let (mut parts, body) = response.into_parts();
+        Response {
+            status: parts.status.as_u16(),
+            headers: h2_header_list(&parts.headers),
+            body: body,
+            trailers: parts.extensions
+                .remove()
+                .map(|Trailers(trailers)| header_list(&trailers))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn into_http(self) -> Result<http::Response<Vec<u8>>> {
+        let mut response = http::Response::new(self.body);
+        *response.status_mut() = try!(StatusCode::from_u16(self.status).map_err(Error::protocol));
+        *response.version_mut() = Version::HTTP_2;
+        *response.headers_mut() = try!(header_map(&self.headers));
+        if !self.trailers.is_empty() {
+            response.extensions_mut().insert(Trailers(try!(header_map(&self.trailers))));
+        }
+        Ok(response)
+    }
+}
+
+/// A `Service` answering `http::Request`s.
+///
+/// Requests the `http` types cannot represent, such as header values with
+/// control characters, are answered with 400.
+pub struct HttpService<F>(pub F);
+
+impl<F> Service for HttpService<F>
+    where F: FnMut(http::Request<Vec<u8>>) -> http::Response<Vec<u8>>
+{
+    fn call(&mut self, request: Request) -> Response {
+        match request.into_http() {
+            Ok(request) => Response::from_http((self.0)(request)),
+            Err(e) => {
+                debug!("unrepresentable request: {}", e);
+                Response::new(400)
+            }
+        }
+    }
+}
+
+impl<S: Transport> Client<S> {
+    /// Queues an `http::Request` on a new stream, see `send`.
+    pub fn send_http(&mut self, request: http::Request<Vec<u8>>) -> Result<StreamId> {
+        self.send(Request::from_http(request))
+    }
+
+    /// Takes the next completed response as an `http::Response`, see
+    /// `poll_response`.
+    pub fn poll_http_response(&mut self)
+                              -> Option<(StreamId, Result<http::Response<Vec<u8>>>)> {
+        self.poll_response()
+            .map(|(id, result)| (id, result.and_then(|response| response.into_http())))
+    }
+}
+
+impl Client<TcpStream> {
+    /// Sends the `http::Request` and blocks until its response is complete.
+    pub fn request_http(&mut self, request: http::Request<Vec<u8>>)
+                        -> Result<http::Response<Vec<u8>>> {
+        self.request(Request::from_http(request)).and_then(|response| response.into_http())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::{self, Method, StatusCode};
+    use message::{self, Request, Response};
+    use server::Service;
+    use super::{HttpService, Trailers};
+
+    #[test]
+    fn test_request() {
+        let request = http::Request::builder()
+            .method(Method::POST)
+            .uri("https://example.com/a?b=c")
+            .header("accept", "*/*")
+            .header("cookie", "a=1")
+            .header("cookie", "b=2")
+            .body(b"body".to_vec())
+            .unwrap();
+        let req = Request::from_http(request);
+        assert_eq!(req, Request::new("POST", "https", "example.com", "/a?b=c")
+            .header("accept", "*/*")
+            .header("cookie", "a=1")
+            .header("cookie", "b=2")
+            .body(&b"body"[..]));
+
+        let request = req.into_http().unwrap();
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.uri(), "https://example.com/a?b=c");
+        assert_eq!(request.headers().get_all("cookie").iter().count(), 2);
+        assert_eq!(request.body(), b"body");
+    }
+
+    #[test]
+    fn test_request_host() {
+        let request = http::Request::builder()
+            .uri("/")
+            .header("host", "example.com")
+            .body(Vec::new())
+            .unwrap();
+        assert_eq!(Request::from_http(request),
+                   Request::new("GET", "http", "example.com", "/"));
+
+        let request = http::Request::builder()
+            .method(Method::CONNECT)
+            .uri("example.com:443")
+            .body(Vec::new())
+            .unwrap();
+        let req = Request::from_http(request);
+        assert_eq!(req.headers,
+                   vec![(b":method".to_vec(), b"CONNECT".to_vec()),
+                        (b":authority".to_vec(), b"example.com:443".to_vec())]);
+        assert_eq!(req.into_http().unwrap().uri(), "example.com:443");
+    }
+
+    #[test]
+    fn test_connection_specific_fields() {
+        let request = http::Request::builder()
+            .uri("http://example.com/")
+            .header("connection", "keep-alive, x-hop")
+            .header("keep-alive", "timeout=5")
+            .header("transfer-encoding", "chunked")
+            .header("upgrade", "websocket")
+            .header("x-hop", "1")
+            .header("te", "gzip")
+            .header("accept", "*/*")
+            .body(Vec::new())
+            .unwrap();
+        assert_eq!(Request::from_http(request),
+                   Request::new("GET", "http", "example.com", "/").header("accept", "*/*"));
+
+        let request = http::Request::builder()
+            .uri("http://example.com/")
+            .header("te", "trailers")
+            .body(Vec::new())
+            .unwrap();
+        let req = Request::from_http(request);
+        assert_eq!(req, Request::new("GET", "http", "example.com", "/").header("te", "trailers"));
+        assert!(message::check_request(&req.headers).is_ok());
+
+        let response = http::Response::builder()
+            .header("connection", "close")
+            .header("server", "deuter")
+            .body(Vec::new())
+            .unwrap();
+        assert_eq!(Response::from_http(response), Response::new(200).header("server", "deuter"));
+    }
+
+    #[test]
+    fn test_response() {
+        let response = http::Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("server", "deuter")
+            .body(Vec::new())
+            .unwrap();
+        let res = Response::from_http(response);
+        assert_eq!(res, Response::new(404).header("server", "deuter"));
+        let response = res.into_http().unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["server"], "deuter");
+        assert!(Response::new(42).into_http().is_err());
+    }
+
+    #[test]
+    fn test_trailers() {
+        let res = Response::new(200).body(&b"data"[..]).trailer("grpc-status", "0");
+        let response = res.clone().into_http().unwrap();
+        let &Trailers(ref trailers) = response.extensions().get::<Trailers>().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+        assert_eq!(Response::from_http(response), res);
+
+        let req = Request::new("POST", "http", "localhost", "/").trailer("checksum", "1");
+        assert_eq!(Request::from_http(req.clone().into_http().unwrap()), req);
+    }
+
+    #[test]
+    fn test_service() {
+        let mut service = HttpService(|req: http::Request<Vec<u8>>| {
+            http::Response::builder()
+                .status(201)
+                .body(req.uri().path().as_bytes().to_vec())
+                .unwrap()
+        });
+        let res = service.call(Request::new("GET", "http", "localhost", "/p"));
+        assert_eq!(res, Response::new(201).body(&b"/p"[..]));
+        let res = service.call(Request::new("GET", "http", "localhost", "/").header("a", "\n"));
+        assert_eq!(res.status, 400);
+    }
+}
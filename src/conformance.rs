@@ -0,0 +1,945 @@
+//! Conformance tests against a server on the loopback interface, grouped by
+//! the sections of rfc 7540 and rfc 7541 they check, after the cases of
+//! h2spec.
+//!
+//! Frames the frame types refuse to build are sent as raw bytes, see `raw`.
+
+use harness::{Script, Remote, Expect, TestServer};
+use message::{Request, Response};
+
+fn service(req: Request) -> Response {
+    match req.method() {
+        Some(b"POST") => Response::new(200).body(req.body),
+        _ => Response::new(200).body(&b"hello"[..]),
+    }
+}
+
+/// Starts a server on an unused port.
+fn server() -> TestServer {
+    TestServer::start(service)
+}
+
+fn script() -> Script<Remote> {
+    Script::new()
+}
+
+/// A frame with the payload as given.
+fn raw(frame_type: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+    let len = payload.len();
+    let mut frame = vec![(len >> 16) as u8,
+                         (len >> 8) as u8,
+                         len as u8,
+                         frame_type,
+                         flags,
+                         (stream_id >> 24) as u8,
+                         (stream_id >> 16) as u8,
+                         (stream_id >> 8) as u8,
+                         stream_id as u8];
+    frame.extend(payload);
+    frame
+}
+
+fn get() -> [(&'static str, &'static str); 4] {
+    [(":method", "GET"), (":scheme", "http"), (":authority", "localhost"), (":path", "/")]
+}
+
+fn post() -> [(&'static str, &'static str); 4] {
+    [(":method", "POST"), (":scheme", "http"), (":authority", "localhost"), (":path", "/")]
+}
+
+/// 3.5 HTTP/2 Connection Preface
+mod preface {
+    use error::ErrorKind;
+    use frame::ping::PingFrame;
+    use harness::Expect;
+    use super::{server, script};
+
+    #[test]
+    fn test_valid_preface() {
+        script()
+            .handshake()
+            .send(PingFrame::new([1; 8]))
+            .expect(Expect::PingAck([1; 8]))
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_invalid_preface() {
+        script()
+            .send_raw(b"PRI * HTTP/1.1\r\n\r\nSM\r\n\r\n")
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_preface_without_settings() {
+        script()
+            .preface()
+            .send(PingFrame::new([1; 8]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+}
+
+/// 4. HTTP Frames
+mod frames {
+    use error::ErrorKind;
+    use frame::ping::PingFrame;
+    use harness::Expect;
+    use super::{server, script, raw, get};
+
+    /// 4.1 frames of unknown types and unknown flags are ignored
+    #[test]
+    fn test_unknown_type_and_flags() {
+        script()
+            .handshake()
+            .send_raw(&raw(0xff, 0, 0, &[1, 2, 3]))
+            .send_raw(&raw(0x6, 0x10, 0, &[2; 8]))
+            .expect(Expect::PingAck([2; 8]))
+            .run_remote(&server());
+    }
+
+    /// 4.2 a DATA frame exceeding SETTINGS_MAX_FRAME_SIZE
+    #[test]
+    fn test_data_exceeds_max_frame_size() {
+        script()
+            .handshake()
+            .headers(1, &get(), false)
+            .send_raw(&raw(0x0, 0x1, 1, &[0; 16385]))
+            .connection_error(ErrorKind::FrameSize)
+            .run_remote(&server());
+    }
+
+    /// 4.2 a HEADERS frame exceeding SETTINGS_MAX_FRAME_SIZE
+    #[test]
+    fn test_headers_exceed_max_frame_size() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x1, 0x5, 1, &[0; 16385]))
+            .connection_error(ErrorKind::FrameSize)
+            .run_remote(&server());
+    }
+
+    /// 4.3 a header block which can't be decoded
+    #[test]
+    fn test_invalid_header_block() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x1, 0x5, 1, &[0x80]))
+            .connection_error(ErrorKind::Compression)
+            .run_remote(&server());
+    }
+
+    /// 5.5 an unknown frame inside a header block
+    #[test]
+    fn test_unknown_frame_in_header_block() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x1, 0x1, 1, &[0x82]))
+            .send_raw(&raw(0xff, 0, 0, &[]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+}
+
+/// 5.1 Stream States
+mod stream_states {
+    use error::ErrorKind;
+    use frame::data::DataFrame;
+    use frame::ping::PingFrame;
+    use frame::rst_stream::RstStreamFrame;
+    use frame::window_update::WindowUpdateFrame;
+    use harness::Expect;
+    use StreamId;
+    use super::{server, script, raw, get};
+
+    /// idle: DATA frame
+    #[test]
+    fn test_idle_data() {
+        script()
+            .handshake()
+            .send(DataFrame::new(StreamId(1)).data(&b"test"[..]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    /// idle: RST_STREAM frame
+    #[test]
+    fn test_idle_rst_stream() {
+        script()
+            .handshake()
+            .send(RstStreamFrame::new(StreamId(1), ErrorKind::Cancel))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    /// idle: WINDOW_UPDATE frame
+    #[test]
+    fn test_idle_window_update() {
+        script()
+            .handshake()
+            .send(WindowUpdateFrame::new(StreamId(1), 100))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    /// idle: CONTINUATION frame
+    #[test]
+    fn test_idle_continuation() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x9, 0x4, 1, &[0x82]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    /// closed: DATA frame after the stream ended
+    #[test]
+    fn test_closed_data() {
+        script()
+            .handshake()
+            .headers(1, &get(), true)
+            .expect(Expect::EndStream(1))
+            .send(DataFrame::new(StreamId(1)).data(&b"test"[..]))
+            .stream_error(1, ErrorKind::StreamClosed)
+            .run_remote(&server());
+    }
+
+    /// closed: HEADERS frame after the stream ended
+    #[test]
+    fn test_closed_headers() {
+        script()
+            .handshake()
+            .headers(1, &get(), true)
+            .expect(Expect::EndStream(1))
+            .headers(1, &get(), true)
+            .connection_error(ErrorKind::StreamClosed)
+            .run_remote(&server());
+    }
+
+    /// closed: RST_STREAM is accepted, PRIORITY too
+    #[test]
+    fn test_closed_rst_stream() {
+        script()
+            .handshake()
+            .headers(1, &get(), true)
+            .expect(Expect::EndStream(1))
+            .send(RstStreamFrame::new(StreamId(1), ErrorKind::Cancel))
+            .send_raw(&raw(0x2, 0, 1, &[0, 0, 0, 0, 15]))
+            .send(PingFrame::new([3; 8]))
+            .expect(Expect::PingAck([3; 8]))
+            .run_remote(&server());
+    }
+
+    /// 5.1.1 an even stream identifier
+    #[test]
+    fn test_even_stream_id() {
+        script()
+            .handshake()
+            .headers(2, &get(), true)
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    /// 5.1.1 a stream identifier lower than a previous one
+    #[test]
+    fn test_decreasing_stream_id() {
+        script()
+            .handshake()
+            .headers(5, &get(), true)
+            .expect(Expect::EndStream(5))
+            .headers(3, &get(), true)
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+}
+
+/// 6.1 DATA
+mod data {
+    use error::ErrorKind;
+    use frame::data::DataFrame;
+    use harness::Expect;
+    use StreamId;
+    use super::{server, script, raw, post};
+
+    #[test]
+    fn test_stream_zero() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x0, 0x1, 0, b"test"))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_pad_length_exceeds_payload() {
+        script()
+            .handshake()
+            .headers(1, &post(), false)
+            .send_raw(&raw(0x0, 0x9, 1, &[6, b't', b'e', b's', b't']))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_padded_data() {
+        script()
+            .handshake()
+            .headers(1, &post(), false)
+            .send_raw(&raw(0x0, 0x8, 1, &[2, b'a', b'b', 0, 0]))
+            .send(DataFrame::new(StreamId(1)).data(&b"c"[..]).end_stream())
+            .expect(Expect::Data(1, b"abc".to_vec()))
+            .run_remote(&server());
+    }
+}
+
+/// 6.2 HEADERS
+mod headers {
+    use error::ErrorKind;
+    use frame::priority::PriorityFrame;
+    use StreamId;
+    use super::{server, script, raw, get};
+
+    #[test]
+    fn test_stream_zero() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x1, 0x5, 0, &[0x82]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_pad_length_exceeds_payload() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x1, 0xd, 1, &[3, 0x82, 0]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_not_followed_by_continuation() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x1, 0x1, 1, &[0x82]))
+            .send(PriorityFrame::new(StreamId(1)).dependency(StreamId(3)))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_depends_on_itself() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x1, 0x25, 1, &[0, 0, 0, 1, 15, 0x82]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+}
+
+/// 6.3 PRIORITY
+mod priority {
+    use error::ErrorKind;
+    use frame::ping::PingFrame;
+    use harness::Expect;
+    use super::{server, script, raw};
+
+    #[test]
+    fn test_stream_zero() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x2, 0, 0, &[0, 0, 0, 1, 15]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x2, 0, 1, &[0, 0, 0, 3, 15, 0]))
+            .connection_error(ErrorKind::FrameSize)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_idle_stream() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x2, 0, 3, &[0, 0, 0, 1, 15]))
+            .send(PingFrame::new([4; 8]))
+            .expect(Expect::PingAck([4; 8]))
+            .run_remote(&server());
+    }
+}
+
+/// 6.4 RST_STREAM
+mod rst_stream {
+    use error::ErrorKind;
+    use super::{server, script, raw};
+
+    #[test]
+    fn test_stream_zero() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x3, 0, 0, &[0, 0, 0, 8]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x3, 0, 1, &[0, 0, 0, 8, 0]))
+            .connection_error(ErrorKind::FrameSize)
+            .run_remote(&server());
+    }
+}
+
+/// 6.5 SETTINGS
+mod settings {
+    use error::ErrorKind;
+    use frame::settings::SettingsFrame;
+    use harness::Expect;
+    use super::{server, script, raw};
+
+    #[test]
+    fn test_ack_with_payload() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x4, 0x1, 0, &[0, 1, 0, 0, 0x10, 0]))
+            .connection_error(ErrorKind::FrameSize)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_stream_id() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x4, 0, 1, &[0, 1, 0, 0, 0x10, 0]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x4, 0, 0, &[0, 1, 0, 0, 0x10]))
+            .connection_error(ErrorKind::FrameSize)
+            .run_remote(&server());
+    }
+
+    /// 6.5.2 SETTINGS_ENABLE_PUSH other than 0 or 1
+    #[test]
+    fn test_invalid_enable_push() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x4, 0, 0, &[0, 2, 0, 0, 0, 2]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    /// 6.5.2 SETTINGS_INITIAL_WINDOW_SIZE above the maximum window size
+    #[test]
+    fn test_invalid_initial_window_size() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x4, 0, 0, &[0, 4, 0x80, 0, 0, 0]))
+            .connection_error(ErrorKind::FlowControl)
+            .run_remote(&server());
+    }
+
+    /// 6.5.2 SETTINGS_MAX_FRAME_SIZE below the initial value and above the
+    /// maximum frame size
+    #[test]
+    fn test_invalid_max_frame_size() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x4, 0, 0, &[0, 5, 0, 0, 0x3f, 0xff]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+        script()
+            .handshake()
+            .send_raw(&raw(0x4, 0, 0, &[0, 5, 1, 0, 0, 0]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    /// 6.5.2 unknown settings are ignored, 6.5.3 and acknowledged
+    #[test]
+    fn test_unknown_setting() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x4, 0, 0, &[0, 0xff, 0, 0, 0, 1]))
+            .expect(Expect::SettingsAck)
+            .send(SettingsFrame::default())
+            .expect(Expect::SettingsAck)
+            .run_remote(&server());
+    }
+}
+
+/// 6.6 PUSH_PROMISE
+mod push_promise {
+    use error::ErrorKind;
+    use super::{server, script, raw, post};
+
+    /// clients can't push
+    #[test]
+    fn test_sent_by_client() {
+        script()
+            .handshake()
+            .headers(1, &post(), false)
+            .send_raw(&raw(0x5, 0x4, 1, &[0, 0, 0, 2, 0x82, 0x86, 0x84]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_stream_zero() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x5, 0x4, 0, &[0, 0, 0, 2, 0x82, 0x86, 0x84]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+}
+
+/// 6.7 PING
+mod ping {
+    use error::ErrorKind;
+    use frame::ping::PingFrame;
+    use harness::Expect;
+    use super::{server, script, raw};
+
+    #[test]
+    fn test_ack() {
+        script()
+            .handshake()
+            .send(PingFrame::pong([5; 8]))
+            .send(PingFrame::new([6; 8]))
+            .expect(Expect::PingAck([6; 8]))
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_stream_id() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x6, 0, 1, &[0; 8]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x6, 0, 0, &[0; 6]))
+            .connection_error(ErrorKind::FrameSize)
+            .run_remote(&server());
+    }
+}
+
+/// 6.8 GOAWAY
+mod goaway {
+    use error::ErrorKind;
+    use super::{server, script, raw};
+
+    #[test]
+    fn test_stream_id() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x7, 0, 1, &[0; 8]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+}
+
+/// 6.9 WINDOW_UPDATE
+mod window_update {
+    use error::ErrorKind;
+    use frame::settings::{Setting, SettingsFrame};
+    use frame::window_update::WindowUpdateFrame;
+    use StreamId;
+    use super::{server, script, raw, post};
+
+    #[test]
+    fn test_zero_increment() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x8, 0, 0, &[0; 4]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x8, 0, 0, &[0, 0, 0, 1, 0]))
+            .connection_error(ErrorKind::FrameSize)
+            .run_remote(&server());
+    }
+
+    /// 6.9.1 the connection window exceeding 2^31-1
+    #[test]
+    fn test_connection_window_overflow() {
+        script()
+            .handshake()
+            .send(WindowUpdateFrame::new(StreamId(0), 0x7fffffff))
+            .connection_error(ErrorKind::FlowControl)
+            .run_remote(&server());
+    }
+
+    /// 6.9.1 a stream window exceeding 2^31-1
+    #[test]
+    fn test_stream_window_overflow() {
+        script()
+            .handshake()
+            .headers(1, &post(), false)
+            .send(WindowUpdateFrame::new(StreamId(1), 0x7fffffff))
+            .stream_error(1, ErrorKind::FlowControl)
+            .run_remote(&server());
+    }
+
+    /// 6.9.2 a change of SETTINGS_INITIAL_WINDOW_SIZE overflowing a stream
+    /// window
+    #[test]
+    fn test_initial_window_size_overflow() {
+        let mut settings = SettingsFrame::default();
+        settings.add_setting(Setting::InitialWindowSize(65536));
+        script()
+            .handshake()
+            .headers(1, &post(), false)
+            .send(WindowUpdateFrame::new(StreamId(1), 0x7fffffff - 65535))
+            .send(settings)
+            .connection_error(ErrorKind::FlowControl)
+            .run_remote(&server());
+    }
+}
+
+/// 6.10 CONTINUATION
+mod continuation {
+    use error::ErrorKind;
+    use harness::Expect;
+    use super::{server, script, raw, get};
+
+    #[test]
+    fn test_header_block() {
+        script()
+            .handshake()
+            .header_block(1, &get(), true, Some(3))
+            .expect(Expect::Field(1, ":status", "200"))
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_other_stream() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x1, 0x1, 1, &[0x82]))
+            .send_raw(&raw(0x9, 0x4, 3, &[0x86]))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_after_end_headers() {
+        script()
+            .handshake()
+            .send_raw(&raw(0x1, 0x4, 1, &[0x82, 0x86, 0x84]))
+            .send_raw(&raw(0x9, 0x4, 1, &[0x41, 0x1, b'a']))
+            .connection_error(ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+}
+
+/// 8.1 HTTP Request/Response Exchange
+mod http {
+    use error::ErrorKind;
+    use frame::data::DataFrame;
+    use harness::Expect;
+    use StreamId;
+    use super::{server, script, get, post};
+
+    /// A request with the fields, which the server must refuse as malformed.
+    fn malformed(fields: &[(&str, &str)]) {
+        script()
+            .handshake()
+            .headers(1, fields, true)
+            .stream_error(1, ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_get() {
+        script()
+            .handshake()
+            .headers(1, &get(), true)
+            .expect(Expect::Field(1, ":status", "200"))
+            .expect(Expect::Data(1, b"hello".to_vec()))
+            .run_remote(&server());
+    }
+
+    #[test]
+    fn test_post_with_body() {
+        script()
+            .handshake()
+            .headers(1, &post(), false)
+            .send(DataFrame::new(StreamId(1)).data(&b"a"[..]))
+            .send(DataFrame::new(StreamId(1)).data(&b"b"[..]).end_stream())
+            .expect(Expect::Field(1, ":status", "200"))
+            .expect(Expect::Data(1, b"ab".to_vec()))
+            .run_remote(&server());
+    }
+
+    /// 8.1 multiple requests on one connection
+    #[test]
+    fn test_concurrent_requests() {
+        script()
+            .handshake()
+            .headers(1, &post(), false)
+            .headers(3, &get(), true)
+            .expect(Expect::Field(3, ":status", "200"))
+            .send(DataFrame::new(StreamId(1)).data(&b"late"[..]).end_stream())
+            .expect(Expect::Data(1, b"late".to_vec()))
+            .run_remote(&server());
+    }
+
+    /// 8.1.2 uppercase header field names
+    #[test]
+    fn test_uppercase_field_name() {
+        let mut fields = get().to_vec();
+        fields.push(("X-Custom", "1"));
+        malformed(&fields);
+    }
+
+    /// 8.1.2.1 pseudo-header fields after regular header fields
+    #[test]
+    fn test_pseudo_header_after_regular() {
+        malformed(&[(":method", "GET"), (":scheme", "http"), ("accept", "*/*"), (":path", "/")]);
+    }
+
+    /// 8.1.2.1 undefined pseudo-header fields
+    #[test]
+    fn test_unknown_pseudo_header() {
+        let mut fields = get().to_vec();
+        fields.insert(0, (":foo", "bar"));
+        malformed(&fields);
+    }
+
+    /// 8.1.2.1 response pseudo-header fields in requests
+    #[test]
+    fn test_status_in_request() {
+        let mut fields = get().to_vec();
+        fields.insert(0, (":status", "200"));
+        malformed(&fields);
+    }
+
+    /// 8.1.2.2 connection-specific header fields
+    #[test]
+    fn test_connection_header() {
+        let mut fields = get().to_vec();
+        fields.push(("connection", "keep-alive"));
+        malformed(&fields);
+    }
+
+    /// 8.1.2.2 TE with a value other than "trailers"
+    #[test]
+    fn test_te_header() {
+        let mut fields = get().to_vec();
+        fields.push(("te", "gzip"));
+        malformed(&fields);
+
+        let mut fields = get().to_vec();
+        fields.push(("te", "trailers"));
+        script()
+            .handshake()
+            .headers(1, &fields, true)
+            .expect(Expect::Field(1, ":status", "200"))
+            .run_remote(&server());
+    }
+
+    /// 8.1.2.3 requests without mandatory pseudo-header fields
+    #[test]
+    fn test_missing_pseudo_headers() {
+        malformed(&[(":scheme", "http"), (":authority", "localhost"), (":path", "/")]);
+        malformed(&[(":method", "GET"), (":authority", "localhost"), (":path", "/")]);
+        malformed(&[(":method", "GET"), (":scheme", "http"), (":authority", "localhost")]);
+    }
+
+    /// 8.1.2.3 empty :path
+    #[test]
+    fn test_empty_path() {
+        malformed(&[(":method", "GET"), (":scheme", "http"), (":path", "")]);
+    }
+
+    /// 8.1.2.3 duplicate pseudo-header fields
+    #[test]
+    fn test_duplicate_path() {
+        let mut fields = get().to_vec();
+        fields.push((":path", "/"));
+        malformed(&fields);
+    }
+
+    /// 8.1.2.6 body shorter than the content-length
+    #[test]
+    fn test_content_length_mismatch() {
+        let mut fields = post().to_vec();
+        fields.push(("content-length", "3"));
+        script()
+            .handshake()
+            .headers(1, &fields, false)
+            .send(DataFrame::new(StreamId(1)).data(&b"ab"[..]).end_stream())
+            .stream_error(1, ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    /// 8.1.2.6 body longer than the content-length
+    #[test]
+    fn test_content_length_exceeded() {
+        let mut fields = post().to_vec();
+        fields.push(("content-length", "1"));
+        script()
+            .handshake()
+            .headers(1, &fields, false)
+            .send(DataFrame::new(StreamId(1)).data(&b"ab"[..]))
+            .stream_error(1, ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    /// 8.1 trailers, without pseudo-header fields
+    #[test]
+    fn test_trailers() {
+        script()
+            .handshake()
+            .headers(1, &post(), false)
+            .send(DataFrame::new(StreamId(1)).data(&b"a"[..]))
+            .headers(1, &[("checksum", "1")], true)
+            .expect(Expect::Field(1, ":status", "200"))
+            .expect(Expect::Data(1, b"a".to_vec()))
+            .run_remote(&server());
+    }
+
+    /// 8.1 trailers with pseudo-header fields
+    #[test]
+    fn test_trailers_with_pseudo_header() {
+        script()
+            .handshake()
+            .headers(1, &post(), false)
+            .send(DataFrame::new(StreamId(1)).data(&b"a"[..]))
+            .headers(1, &[(":path", "/")], true)
+            .stream_error(1, ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+
+    /// 8.1 a second HEADERS frame without END_STREAM
+    #[test]
+    fn test_trailers_without_end_stream() {
+        script()
+            .handshake()
+            .headers(1, &post(), false)
+            .headers(1, &[("checksum", "1")], false)
+            .stream_error(1, ErrorKind::Protocol)
+            .run_remote(&server());
+    }
+}
+
+/// rfc 7541 HPACK, every decoding error is a connection error of type
+/// COMPRESSION_ERROR (rfc 7540 4.3)
+mod hpack {
+    use error::ErrorKind;
+    use super::{server, script, raw};
+
+    /// A HEADERS frame on stream 1 with END_HEADERS and END_STREAM.
+    fn header_block(block: &[u8]) -> Vec<u8> {
+        raw(0x1, 0x5, 1, block)
+    }
+
+    /// 2.3.3 index 0 is not used
+    #[test]
+    fn test_index_zero() {
+        script()
+            .handshake()
+            .send_raw(&header_block(&[0x80]))
+            .connection_error(ErrorKind::Compression)
+            .run_remote(&server());
+    }
+
+    /// 2.3.3 an index beyond the static and the empty dynamic table
+    #[test]
+    fn test_index_out_of_range() {
+        script()
+            .handshake()
+            .send_raw(&header_block(&[0xbe]))
+            .connection_error(ErrorKind::Compression)
+            .run_remote(&server());
+    }
+
+    /// 2.3.3 a literal with a name index out of range
+    #[test]
+    fn test_name_index_out_of_range() {
+        script()
+            .handshake()
+            .send_raw(&header_block(&[0x0f, 0x30, 0x01, b'a']))
+            .connection_error(ErrorKind::Compression)
+            .run_remote(&server());
+    }
+
+    /// 6.3 a table size update above SETTINGS_HEADER_TABLE_SIZE
+    #[test]
+    fn test_table_size_update_exceeds_limit() {
+        script()
+            .handshake()
+            // 4097
+            .send_raw(&header_block(&[0x3f, 0xe2, 0x1f, 0x82, 0x86, 0x84]))
+            .connection_error(ErrorKind::Compression)
+            .run_remote(&server());
+    }
+
+    /// 4.2 a table size update after a header field
+    #[test]
+    fn test_table_size_update_after_field() {
+        script()
+            .handshake()
+            .send_raw(&header_block(&[0x82, 0x20, 0x86, 0x84]))
+            .connection_error(ErrorKind::Compression)
+            .run_remote(&server());
+    }
+
+    /// 5.2 a Huffman string containing EOS
+    #[test]
+    fn test_huffman_eos() {
+        script()
+            .handshake()
+            // :path with a Huffman value of 30 ones and padding
+            .send_raw(&header_block(&[0x82, 0x86, 0x04, 0x84, 0xff, 0xff, 0xff, 0xfc]))
+            .connection_error(ErrorKind::Compression)
+            .run_remote(&server());
+    }
+
+    /// 5.2 Huffman padding longer than 7 bits
+    #[test]
+    fn test_huffman_padding_too_long() {
+        script()
+            .handshake()
+            // "a" followed by 11 bits of ones
+            .send_raw(&header_block(&[0x82, 0x86, 0x04, 0x82, 0x1f, 0xff]))
+            .connection_error(ErrorKind::Compression)
+            .run_remote(&server());
+    }
+
+    /// 5.2 Huffman padding which is not the prefix of EOS
+    #[test]
+    fn test_huffman_padding_zeros() {
+        script()
+            .handshake()
+            // "a" followed by 3 bits of zeros
+            .send_raw(&header_block(&[0x82, 0x86, 0x04, 0x81, 0x18]))
+            .connection_error(ErrorKind::Compression)
+            .run_remote(&server());
+    }
+}
@@ -1,44 +1,1516 @@
-use mio::{EventLoop, EventSet, Token};
+//! The HTTP/2 connection state machine shared by servers and clients.
+//!
+//! A `Connection` reads frames from its transport, checks them against the
+//! connection and stream states, answers SETTINGS and PING frames and turns
+//! everything the application has to know about into `Event`s. Protocol
+//! violations of the peer end the connection with a GOAWAY frame or reset a
+//! single stream, as required by rfc 5.4.
+
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net;
 use mio::tcp::TcpStream;
+use {Settings, StreamId, WindowSize};
+use buffer::{AsyncBufWriter, FrameReader};
+use error::{Error, ErrorKind, Result};
+use frame::{Frame, FrameKind, FrameRef, HEADER_SIZE};
+use frame::continuation::{ContinuationFrame, ContinuationFrameRef};
+use frame::data::{DataFrame, DataFrameRef};
+use frame::goaway::GoAwayFrame;
+use frame::headers::{HeadersFrame, HeadersFrameRef};
+use frame::padding::{Pad, PaddingPolicy};
+use frame::ping::PingFrame;
+use frame::push_promise::{PushPromiseFrame, PushPromiseFrameRef};
+use frame::rst_stream::RstStreamFrame;
+use frame::settings::SettingsFrame;
+use frame::window_update::WindowUpdateFrame;
+use hpack::{self, Header};
+use message::{Request, Response};
+use pool::BufferPool;
+use stream::{self, Stream};
+use upgrade::{self, Reply, Upgrade};
+
+/// The connection preface sent by clients (rfc 3.5).
+pub const PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Number of closed streams remembered to tell them apart from idle streams.
+const MAX_CLOSED_STREAMS: usize = 128;
+
+/// Limit of the encoded octets of a header block spread over CONTINUATION
+/// frames.
+const MAX_HEADER_BLOCK_SIZE: usize = 64 * 1024;
+
+/// A byte stream a connection runs on.
+///
+/// The connection reads and writes through separate handles of the same
+/// stream, like a socket and its `try_clone`.
+pub trait Transport: Read + Write + Sized {
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+}
+
+impl Transport for net::TcpStream {
+    fn try_clone(&self) -> io::Result<net::TcpStream> {
+        net::TcpStream::try_clone(self)
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Transport for ::mock::MockStream {
+    fn try_clone(&self) -> io::Result<::mock::MockStream> {
+        Ok(self.clone())
+    }
+}
+
+/// The endpoint a connection acts as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// What happened on a connection, see `Connection::poll`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A complete header block was received on the stream.
+    Headers {
+        stream_id: StreamId,
+        headers: Vec<Header>,
+        end_stream: bool,
+    },
+    Data {
+        stream_id: StreamId,
+        data: Vec<u8>,
+        end_stream: bool,
+    },
+    /// The peer reserved a stream for a pushed response.
+    PushPromise {
+        stream_id: StreamId,
+        promised_stream_id: StreamId,
+        headers: Vec<Header>,
+    },
+    /// The stream was reset, by the peer or because of a stream error.
+    Reset {
+        stream_id: StreamId,
+        error: ErrorKind,
+    },
+    /// The peer is shutting the connection down, streams above
+    /// `last_stream_id` were not processed.
+    GoAway {
+        last_stream_id: StreamId,
+        error: ErrorKind,
+    },
+    /// The peer acknowledged a PING.
+    Pong([u8; 8]),
+    /// The server declined the upgrade to h2c and answered the request of
+    /// stream 1 over HTTP/1.1, the connection is closed.
+    Declined(Response),
+}
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum State {
+    /// Waiting for the client preface or an HTTP/1.1 upgrade request.
     Preface,
+    /// Upgraded from HTTP/1.1, waiting for the client preface (rfc 3.2).
+    Upgraded,
+    /// Sent an HTTP/1.1 request upgrading to h2c, waiting for the answer,
+    /// which has no body if the request is a HEAD request.
+    Upgrading { head: bool },
+    /// Waiting for the SETTINGS frame which has to follow the preface.
     Settings,
+    Open,
     Closed,
 }
 
-pub struct Connection {
-    pub socket: TcpStream,
-    token: Token,
+/// A header block spread over HEADERS or PUSH_PROMISE and CONTINUATION frames.
+struct HeaderBlock {
+    stream_id: StreamId,
+    promised_stream_id: Option<StreamId>,
+    end_stream: bool,
+    fragment: Vec<u8>,
+}
+
+pub struct Connection<S> {
+    reader: FrameReader<S>,
+    writer: AsyncBufWriter<S>,
+    pool: BufferPool,
+    role: Role,
     state: State,
+    local: Settings,
+    remote: Settings,
+    padding: PaddingPolicy,
+    encoder: hpack::Encoder,
+    decoder: hpack::Decoder,
+    streams: HashMap<StreamId, Stream>,
+    // recently closed streams
+    closed: VecDeque<StreamId>,
+    // highest stream id initiated by the peer
+    last_remote_id: StreamId,
+    next_local_id: u32,
+    block: Option<HeaderBlock>,
+    send_window: WindowSize,
+    recv_window: WindowSize,
+    // consumed octets not given back to the peer yet
+    recv_released: usize,
+    events: VecDeque<Event>,
+    goaway_sent: Option<StreamId>,
+    goaway_received: Option<StreamId>,
+    // the transport failed, queued frames can't be written anymore
+    broken: bool,
 }
 
-impl Connection {
-    pub fn new(socket: TcpStream, token: Token) -> Connection {
-        Connection {
-            socket: socket,
-            token: token,
-            state: State::Preface,
+impl<S: Transport> Connection<S> {
+    /// Creates a connection reading and writing through buffers of the `pool`.
+    ///
+    /// A client queues the preface and the `settings` right away, a server
+    /// sends its `settings` once it read the preface or an upgrade request.
+    pub fn new(socket: S, role: Role, settings: Settings, pool: &BufferPool)
+               -> io::Result<Connection<S>> {
+        let mut conn = try!(Connection::create(socket, role, settings, pool));
+        if role == Role::Client {
+            conn.writer.write_all(PREFACE).unwrap();
+            conn.writer.write_frame(conn.local.to_frame());
         }
+        Ok(conn)
     }
 
-    pub fn read(&self) {
-        match self.state {
-            State::Preface => self.read_preface(),
-            State::Settings => self.read_settings(),
-            _ => {}
-        }
+    /// Creates a client connection which starts with `request` over
+    /// HTTP/1.1, asking the server to upgrade to h2c (rfc 3.2).
+    ///
+    /// Once the server switches protocols, the request is stream 1 in
+    /// half-closed (local) state and its response arrives like any other. A
+    /// server declining the upgrade answers over HTTP/1.1, see
+    /// `Event::Declined`. No other stream can be opened until then.
+    pub fn upgrade(socket: S, settings: Settings, pool: &BufferPool, request: &Request)
+                   -> Result<Connection<S>> {
+        let mut conn = try!(Connection::create(socket, Role::Client, settings, pool));
+        let bytes = try!(upgrade::encode_request(request, &conn.local.to_frame()));
+        conn.writer.write_all(&bytes).unwrap();
+        conn.state = State::Upgrading { head: request.method() == Some(b"HEAD") };
+        conn.next_local_id = 3;
+        Ok(conn)
+    }
+
+    fn create(socket: S, role: Role, settings: Settings, pool: &BufferPool)
+              -> io::Result<Connection<S>> {
+        let max_frame_size = settings.max_frame_size as usize;
+        // the peer may send frames of the default size until it acknowledged
+        // our settings
+        let max_payload = Settings::default().max_frame_size as usize;
+        let sink = try!(socket.try_clone());
+        let writer = AsyncBufWriter::with_pool(sink, pool.clone(), 4 * max_frame_size);
+        let mut decoder = hpack::Decoder::new();
+        decoder.set_max_table_size(settings.header_table_size as usize);
+        Ok(Connection {
+            reader: FrameReader::with_pool(socket, pool.clone(), max_payload),
+            writer: writer,
+            pool: pool.clone(),
+            role: role,
+            state: match role {
+                Role::Client => State::Settings,
+                Role::Server => State::Preface,
+            },
+            local: settings,
+            remote: Settings::default(),
+            padding: PaddingPolicy::default(),
+            encoder: hpack::Encoder::new(),
+            decoder: decoder,
+            streams: HashMap::new(),
+            closed: VecDeque::new(),
+            last_remote_id: StreamId(0),
+            next_local_id: match role {
+                Role::Client => 1,
+                Role::Server => 2,
+            },
+            block: None,
+            send_window: WindowSize::default(),
+            recv_window: WindowSize::default(),
+            recv_released: 0,
+            events: VecDeque::new(),
+            goaway_sent: None,
+            goaway_received: None,
+            broken: false,
+        })
+    }
+
+    /// The socket to register with the event loop.
+    pub fn socket(&self) -> &S {
+        self.reader.get_ref()
+    }
+
+    #[inline]
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// The settings we announced.
+    pub fn local_settings(&self) -> &Settings {
+        &self.local
+    }
+
+    /// The pool the buffers of the connection come from.
+    pub fn pool(&self) -> &BufferPool {
+        &self.pool
+    }
+
+    /// The settings of the peer.
+    pub fn remote_settings(&self) -> &Settings {
+        &self.remote
+    }
+
+    /// Set the padding policy for all DATA, HEADERS and PUSH_PROMISE frames
+    /// sent on this connection.
+    pub fn set_padding(&mut self, padding: PaddingPolicy) {
+        self.padding = padding;
     }
 
-    pub fn write(&self) {}
+    /// Pad an outgoing frame according to the connection's padding policy.
+    fn pad<F: Pad>(&self, frame: F) -> F {
+        self.padding.apply(frame, self.remote.max_frame_size as usize)
+    }
 
+    /// Returns true once the connection is closed and all queued frames are
+    /// written.
     pub fn is_closed(&self) -> bool {
+        self.state == State::Closed && (self.writer.pending() == 0 || self.broken)
+    }
+
+    /// Returns true if frames wait for the transport to become writable.
+    pub fn wants_write(&self) -> bool {
+        self.writer.pending() > 0
+    }
+
+    /// Takes the next event.
+    pub fn poll(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    /// Reads and handles all available frames, call this on readable events.
+    ///
+    /// While the write buffer is congested no frames are read, a peer which
+    /// doesn't read could otherwise make the answers to its PING or SETTINGS
+    /// frames pile up. Reading resumes once the buffer drains, so call this
+    /// on writable events as well.
+    ///
+    /// A connection error is answered with a GOAWAY frame and returned, the
+    /// connection is closed once the GOAWAY frame is written.
+    pub fn read(&mut self) -> Result<()> {
+        if self.state == State::Closed {
+            return Ok(());
+        }
+        match self.read_frames() {
+            Ok(()) => {
+                if self.reader.is_eof() {
+                    debug!("connection closed by peer");
+                    self.state = State::Closed;
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.connection_error(&e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes queued frames, call this on writable events.
+    ///
+    /// Stream data held back while the write buffer was congested is queued
+    /// as room becomes available.
+    pub fn write(&mut self) -> Result<()> {
+        try!(self.flush_buf());
+        self.flush_streams();
+        self.flush_buf()
+    }
+
+    fn flush_buf(&mut self) -> Result<()> {
+        if let Err(e) = self.writer.flush_buf() {
+            self.state = State::Closed;
+            self.broken = true;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn read_frames(&mut self) -> Result<()> {
+        loop {
+            if self.writer.is_congested() {
+                try!(self.flush_buf());
+                if self.writer.is_congested() {
+                    debug!("write buffer congested, not reading");
+                    return Ok(());
+                }
+            }
+            let more = match self.reader.fill() {
+                Ok(more) => more,
+                Err(e) => {
+                    // the transport is gone, there is no one to send GOAWAY to
+                    self.state = State::Closed;
+                    self.broken = true;
+                    return Err(e.into());
+                }
+            };
+            if let State::Upgrading { head } = self.state {
+                if !try!(self.read_upgrade(head, more)) {
+                    return Ok(());
+                }
+            }
+            if self.state == State::Preface || self.state == State::Upgraded {
+                if !try!(self.read_preface()) {
+                    return Ok(());
+                }
+            }
+            // the handlers borrow the connection, the frames borrow from the
+            // detached read buffer
+            let mut frames = self.reader.detach();
+            let result = self.handle_frames(&mut frames);
+            self.reader.attach(frames);
+            try!(result);
+            if self.state == State::Closed {
+                return Ok(());
+            }
+            if !more {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads the client preface and answers with our SETTINGS. An HTTP/1.1
+    /// request in place of the preface has to upgrade to h2c, otherwise it is
+    /// answered with 505 and the connection is closed.
+    ///
+    /// Returns false while the preface or the upgrade request is incomplete.
+    fn read_preface(&mut self) -> Result<bool> {
+        if self.state == State::Preface && upgrade::is_http1(self.reader.buffered()) {
+            let parsed = upgrade::parse_request(self.reader.buffered());
+            match parsed {
+                Ok(Some((upgrade, len))) => {
+                    self.reader.consume(len);
+                    try!(self.accept_upgrade(upgrade));
+                }
+                Ok(None) => return Ok(false),
+                Err(e) => {
+                    info!("{}", e);
+                    self.writer.write_all(upgrade::VERSION_NOT_SUPPORTED).unwrap();
+                    self.state = State::Closed;
+                    return Err(e);
+                }
+            }
+        }
+        if !try!(self.reader.read_preface(PREFACE)) {
+            return Ok(false);
+        }
+        if self.state == State::Preface {
+            self.writer.write_frame(self.local.to_frame());
+        }
+        self.state = State::Settings;
+        Ok(true)
+    }
+
+    /// Switches protocols and continues the upgrade request as stream 1,
+    /// which is half-closed (remote) (rfc 3.2).
+    ///
+    /// Our SETTINGS are the first HTTP/2 frame after the 101 response, the
+    /// client's settings from the request are applied without acknowledgment
+    /// (rfc 3.2.1).
+    fn accept_upgrade(&mut self, upgrade: Upgrade) -> Result<()> {
+        debug!("upgrading from HTTP/1.1 to h2c");
+        self.writer.write_all(upgrade::SWITCHING_PROTOCOLS).unwrap();
+        self.writer.write_frame(self.local.to_frame());
+        self.state = State::Upgraded;
+        try!(self.apply_settings(upgrade.settings));
+
+        let id = StreamId(1);
+        let mut stream = Stream::new(id,
+                                     stream::State::Open,
+                                     self.remote.initial_window_size,
+                                     self.local.initial_window_size);
+        stream.recv_end();
+        self.streams.insert(id, stream);
+        self.last_remote_id = id;
+
+        let Request { headers, body, .. } = upgrade.request;
+        // the body is handed out like DATA and released the same way
+        self.recv_window.decrease(body.len());
+        self.events.push_back(Event::Headers {
+            stream_id: id,
+            headers: headers,
+            end_stream: body.is_empty(),
+        });
+        if !body.is_empty() {
+            self.events.push_back(Event::Data {
+                stream_id: id,
+                data: body,
+                end_stream: true,
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the server's answer to the upgrade request, see `upgrade`.
+    ///
+    /// Returns true once the server switched protocols, our preface and
+    /// SETTINGS are sent then. A declined answer has to fit in the buffer,
+    /// it fails if the buffer is `full` before it is complete.
+    fn read_upgrade(&mut self, head: bool, full: bool) -> Result<bool> {
+        let parsed = upgrade::parse_response(self.reader.buffered(), self.reader.is_eof(), head);
+        let reply = match parsed {
+            Ok(Some(reply)) => reply,
+            Ok(None) if !full => return Ok(false),
+            other => {
+                // no GOAWAY for an HTTP/1.1 server
+                self.state = State::Closed;
+                let error = other.err();
+                return Err(error.unwrap_or_else(|| Error::protocol("HTTP/1.1 response too large")));
+            }
+        };
+        match reply {
+            Reply::Switched(len) => {
+                debug!("server switched to h2c");
+                self.reader.consume(len);
+                self.writer.write_all(PREFACE).unwrap();
+                self.writer.write_frame(self.local.to_frame());
+                let id = StreamId(1);
+                let mut stream = Stream::new(id,
+                                             stream::State::Open,
+                                             self.remote.initial_window_size,
+                                             self.local.initial_window_size);
+                stream.send_end();
+                self.streams.insert(id, stream);
+                self.state = State::Settings;
+                Ok(true)
+            }
+            Reply::Declined(response) => {
+                info!("server declined the upgrade to h2c with {}", response.status);
+                self.events.push_back(Event::Declined(response));
+                self.state = State::Closed;
+                Ok(false)
+            }
+        }
+    }
+
+    fn handle_frames(&mut self, frames: &mut FrameReader<io::Empty>) -> Result<()> {
+        while let Some(frame) = frames.next_frame_ref() {
+            try!(self.handle_frame(try!(frame)));
+            if self.state == State::Closed || self.writer.is_congested() {
+                // frames left over stay buffered
+                break;
+            }
+            // a SETTINGS ACK applies our frame size to the following frames
+            frames.set_max_payload(self.reader.max_payload());
+        }
+        Ok(())
+    }
+
+    fn handle_frame(&mut self, frame: FrameRef) -> Result<()> {
+        if let Some(ref block) = self.block {
+            match frame {
+                FrameRef::Continuation(ref f) if f.stream_id() == block.stream_id => {}
+                _ => return Err(Error::protocol("expected CONTINUATION frame")),
+            }
+        }
+        if self.state == State::Settings {
+            match frame {
+                FrameRef::Control(FrameKind::Settings(ref f)) if !f.is_ack() => {
+                    self.state = State::Open
+                }
+                _ => return Err(Error::protocol("the preface must be followed by SETTINGS")),
+            }
+        }
+        match frame {
+            FrameRef::Data(f) => self.recv_data(f),
+            FrameRef::Headers(f) => self.recv_headers(f),
+            FrameRef::PushPromise(f) => self.recv_push_promise(f),
+            FrameRef::Continuation(f) => self.recv_continuation(f),
+            FrameRef::Control(f) => self.handle_control(f),
+        }
+    }
+
+    fn handle_control(&mut self, frame: FrameKind) -> Result<()> {
+        match frame {
+            FrameKind::Priority(_) => Ok(()),
+            FrameKind::RstStream(f) => self.recv_reset(f),
+            FrameKind::Settings(f) => self.recv_settings(f),
+            FrameKind::Ping(f) => {
+                if f.is_ack() {
+                    self.events.push_back(Event::Pong(f.data()));
+                } else {
+                    self.writer.write_frame(PingFrame::pong(f.data()));
+                }
+                Ok(())
+            }
+            FrameKind::GoAway(f) => {
+                debug!("received GOAWAY {:?}", f);
+                self.goaway_received = Some(f.last_stream_id());
+                self.events.push_back(Event::GoAway {
+                    last_stream_id: f.last_stream_id(),
+                    error: f.error(),
+                });
+                Ok(())
+            }
+            FrameKind::WindowUpdate(f) => self.recv_window_update(f),
+            // frames of unknown types are ignored (rfc 4.1), frames carrying
+            // payload are never parsed as control frames
+            _ => Ok(()),
+        }
+    }
+
+    /// Limit of a header block buffered until its last CONTINUATION frame.
+    pub fn max_header_block(&self) -> usize {
+        MAX_HEADER_BLOCK_SIZE
+    }
+
+    /// Limit of a decoded header list, the `Settings::max_header_list_size`
+    /// we announced.
+    pub fn max_header_list(&self) -> usize {
+        self.local
+            .max_header_list_size
+            .map_or(hpack::DEFAULT_MAX_HEADER_LIST_SIZE, |size| size as usize)
+    }
+
+    /// Octets of a header block buffered until its last CONTINUATION frame.
+    pub fn header_block_len(&self) -> usize {
+        self.block.as_ref().map_or(0, |block| block.fragment.len())
+    }
+
+    /// Returns true if the stream id belongs to streams we initiate.
+    fn is_local_id(&self, id: StreamId) -> bool {
+        match self.role {
+            Role::Client => id.0 % 2 == 1,
+            Role::Server => id.0 % 2 == 0,
+        }
+    }
+
+    /// Returns true if a stream which is not stored was never opened.
+    fn is_idle(&self, id: StreamId) -> bool {
+        if self.is_local_id(id) {
+            id.0 >= self.next_local_id
+        } else {
+            id > self.last_remote_id
+        }
+    }
+
+    fn recv_headers(&mut self, frame: HeadersFrameRef) -> Result<()> {
+        self.recv_header_block(HeaderBlock {
+                                   stream_id: frame.stream_id(),
+                                   promised_stream_id: None,
+                                   end_stream: frame.is_end_stream(),
+                                   fragment: Vec::new(),
+                               },
+                               frame.fragment(),
+                               frame.is_end_headers())
+    }
+
+    fn recv_push_promise(&mut self, frame: PushPromiseFrameRef) -> Result<()> {
+        if self.role == Role::Server || !self.local.enable_push {
+            return Err(Error::protocol("PUSH_PROMISE while push is disabled"));
+        }
+        let promised = frame.promised_stream_id();
+        if self.is_local_id(promised) || !self.is_idle(promised) {
+            return Err(Error::protocol("invalid promised stream id"));
+        }
+        match self.streams.get(&frame.stream_id()) {
+            Some(stream) if stream.is_recv_open() => {}
+            _ => return Err(Error::protocol("PUSH_PROMISE on a stream which is not open")),
+        }
+        self.recv_header_block(HeaderBlock {
+                                   stream_id: frame.stream_id(),
+                                   promised_stream_id: Some(promised),
+                                   end_stream: false,
+                                   fragment: Vec::new(),
+                               },
+                               frame.fragment(),
+                               frame.is_end_headers())
+    }
+
+    /// Starts a header block, a complete one is decoded straight from the
+    /// read buffer and only a fragment continued later is copied.
+    fn recv_header_block(&mut self,
+                         mut block: HeaderBlock,
+                         fragment: &[u8],
+                         end_headers: bool)
+                         -> Result<()> {
+        if end_headers {
+            return self.end_header_block(block, fragment);
+        }
+        block.fragment.extend_from_slice(fragment);
+        self.block = Some(block);
+        Ok(())
+    }
+
+    fn recv_continuation(&mut self, frame: ContinuationFrameRef) -> Result<()> {
+        let max = self.max_header_block();
+        match self.block {
+            Some(ref block) if block.fragment.len() + frame.fragment().len() > max => {
+                // the block can't be dropped without losing the decoder state
+                return Err(Error::new(ErrorKind::EnhanceYourCalm, "header block too large"));
+            }
+            Some(ref mut block) => block.fragment.extend_from_slice(frame.fragment()),
+            None => return Err(Error::protocol("unexpected CONTINUATION frame")),
+        }
+        if frame.is_end_headers() {
+            let mut block = self.block.take().unwrap();
+            let fragment = mem::replace(&mut block.fragment, Vec::new());
+            return self.end_header_block(block, &fragment);
+        }
+        Ok(())
+    }
+
+    /// Decodes the complete `fragment` of a header block and applies it to
+    /// its stream.
+    fn end_header_block(&mut self, block: HeaderBlock, fragment: &[u8]) -> Result<()> {
+        // decode even if the stream is refused, to keep the table in sync
+        let max = self.max_header_list();
+        let headers = try!(self.decoder.decode(fragment, max));
+        match block.promised_stream_id {
+            Some(promised) => {
+                self.last_remote_id = promised;
+                let stream = Stream::new(promised,
+                                         stream::State::ReservedRemote,
+                                         self.remote.initial_window_size,
+                                         self.local.initial_window_size);
+                self.streams.insert(promised, stream);
+                self.events.push_back(Event::PushPromise {
+                    stream_id: block.stream_id,
+                    promised_stream_id: promised,
+                    headers: headers,
+                });
+                Ok(())
+            }
+            None => self.headers_received(block.stream_id, headers, block.end_stream),
+        }
+    }
+
+    fn headers_received(&mut self,
+                        id: StreamId,
+                        headers: Vec<Header>,
+                        end_stream: bool)
+                        -> Result<()> {
+        if let Some(stream) = self.streams.get_mut(&id) {
+            match stream.state() {
+                stream::State::ReservedRemote => stream.recv_reserved_headers(),
+                stream::State::Open | stream::State::HalfClosedLocal => {}
+                _ => return Err(Error::new(ErrorKind::StreamClosed, "HEADERS on closed stream")),
+            }
+            if end_stream {
+                stream.recv_end();
+            }
+        }
+        if self.streams.contains_key(&id) {
+            self.events.push_back(Event::Headers {
+                stream_id: id,
+                headers: headers,
+                end_stream: end_stream,
+            });
+            self.reap(id);
+            return Ok(());
+        }
+        if self.is_local_id(id) || self.role == Role::Client {
+            if self.is_idle(id) {
+                return Err(Error::protocol("HEADERS on idle stream"));
+            }
+            return Err(Error::new(ErrorKind::StreamClosed, "HEADERS on closed stream"));
+        }
+        if id <= self.last_remote_id {
+            if self.closed.contains(&id) {
+                return Err(Error::new(ErrorKind::StreamClosed, "HEADERS on closed stream"));
+            }
+            return Err(Error::protocol("stream id lower than a previous one"));
+        }
+        self.last_remote_id = id;
+        if self.goaway_sent.is_some() {
+            // streams initiated after GOAWAY are ignored (rfc 6.8)
+            return Ok(());
+        }
+        let limit = self.local.max_concurrent_streams.unwrap_or(u32::max_value()) as usize;
+        if self.stream_count(false) >= limit {
+            return self.stream_error(id, ErrorKind::RefusedStream);
+        }
+        let mut stream = Stream::new(id,
+                                     stream::State::Open,
+                                     self.remote.initial_window_size,
+                                     self.local.initial_window_size);
+        if end_stream {
+            stream.recv_end();
+        }
+        self.streams.insert(id, stream);
+        self.events.push_back(Event::Headers {
+            stream_id: id,
+            headers: headers,
+            end_stream: end_stream,
+        });
+        Ok(())
+    }
+
+    /// Number of active streams initiated by us or by the peer, reserved
+    /// streams included.
+    fn stream_count(&self, local: bool) -> usize {
+        self.streams
+            .values()
+            .filter(|s| self.is_local_id(s.id()) == local && !s.is_closed())
+            .count()
+    }
+
+    /// Received data is only given back to the flow control windows once it
+    /// is consumed, see `release_capacity`.
+    fn recv_data(&mut self, frame: DataFrameRef) -> Result<()> {
+        let id = frame.stream_id();
+        let len = frame.flow_len();
+        if len > self.recv_window.available() {
+            return Err(Error::new(ErrorKind::FlowControl, "connection flow control window \
+                                                           exceeded"));
+        }
+        self.recv_window.decrease(len);
+        let result = match self.streams.get_mut(&id) {
+            None => None,
+            Some(ref stream) if !stream.is_recv_open() => Some(ErrorKind::StreamClosed),
+            Some(ref stream) if len > stream.recv_window.available() => {
+                Some(ErrorKind::FlowControl)
+            }
+            Some(stream) => {
+                stream.recv_window.decrease(len);
+                if frame.is_end_stream() {
+                    stream.recv_end();
+                }
+                None
+            }
+        };
+        if self.streams.contains_key(&id) && result.is_none() {
+            // padding is never consumed by anyone
+            let padding = len - frame.payload().len();
+            self.release_capacity(id, padding);
+            self.events.push_back(Event::Data {
+                stream_id: id,
+                end_stream: frame.is_end_stream(),
+                // the consumer gets its own copy of the payload
+                data: frame.payload().to_vec(),
+            });
+            self.reap(id);
+            return Ok(());
+        }
+        // nobody consumes data of streams in error
+        self.release_connection(len);
+        match result {
+            Some(error) => self.stream_error(id, error),
+            None if self.is_idle(id) => Err(Error::protocol("DATA on idle stream")),
+            None => self.stream_error(id, ErrorKind::StreamClosed),
+        }
+    }
+
+    /// Gives `len` consumed octets back to the connection window, announced
+    /// once half of the window is used.
+    fn release_connection(&mut self, len: usize) {
+        let initial = WindowSize::default().0;
+        if let Some(increment) = release(&mut self.recv_window, &mut self.recv_released, len,
+                                         initial) {
+            self.writer.write_frame(WindowUpdateFrame::new(StreamId(0), increment));
+        }
+    }
+
+    /// Gives `len` octets of data received on the stream back to the flow
+    /// control windows, call this once the data is consumed.
+    ///
+    /// The peer is told with WINDOW_UPDATE frames once half of a window is
+    /// used, so a slow consumer stops the peer instead of growing buffers.
+    pub fn release_capacity(&mut self, id: StreamId, len: usize) {
+        self.release_connection(len);
+        let initial = self.local.initial_window_size;
+        let increment = match self.streams.get_mut(&id) {
+            // no more data on the stream, the window does not matter anymore
+            Some(ref mut stream) if stream.is_recv_open() => {
+                release(&mut stream.recv_window, &mut stream.recv_released, len, initial)
+            }
+            _ => None,
+        };
+        if let Some(increment) = increment {
+            self.writer.write_frame(WindowUpdateFrame::new(id, increment));
+        }
+    }
+
+    /// Room for data on the stream, the smaller of the flow control windows
+    /// less the data already queued.
+    pub fn send_capacity(&self, id: StreamId) -> usize {
+        match self.streams.get(&id) {
+            Some(stream) if stream.is_send_open() => {
+                let window = cmp::min(self.send_window.available(),
+                                      stream.send_window.available());
+                cmp::min(window, self.writer.available()).saturating_sub(stream.queued())
+            }
+            _ => 0,
+        }
+    }
+
+    fn recv_reset(&mut self, frame: RstStreamFrame) -> Result<()> {
+        let id = frame.stream_id();
+        if !self.streams.contains_key(&id) {
+            if self.is_idle(id) {
+                return Err(Error::protocol("RST_STREAM on idle stream"));
+            }
+            return Ok(());
+        }
+        self.close_stream(id);
+        self.events.push_back(Event::Reset {
+            stream_id: id,
+            error: frame.error(),
+        });
+        Ok(())
+    }
+
+    fn recv_settings(&mut self, frame: SettingsFrame) -> Result<()> {
+        if frame.is_ack() {
+            self.reader.set_max_payload(self.local.max_frame_size as usize);
+            return Ok(());
+        }
+        self.writer.write_frame(SettingsFrame::ack());
+        self.apply_settings(frame)
+    }
+
+    fn apply_settings(&mut self, frame: SettingsFrame) -> Result<()> {
+        let old_window = self.remote.initial_window_size;
+        self.remote.update(frame);
+        self.encoder.set_max_table_size(self.remote.header_table_size as usize);
+        let delta = self.remote.initial_window_size as i64 - old_window as i64;
+        if delta != 0 {
+            for stream in self.streams.values_mut() {
+                try!(stream.send_window.increase(delta));
+            }
+            self.flush_streams();
+        }
+        Ok(())
+    }
+
+    fn recv_window_update(&mut self, frame: WindowUpdateFrame) -> Result<()> {
+        let id = frame.stream_id();
+        let increment = frame.increment() as i64;
+        if id == 0 {
+            try!(self.send_window.increase(increment));
+        } else {
+            if !self.streams.contains_key(&id) && self.is_idle(id) {
+                return Err(Error::protocol("WINDOW_UPDATE on idle stream"));
+            }
+            let overflow = match self.streams.get_mut(&id) {
+                Some(stream) => stream.send_window.increase(increment).is_err(),
+                None => false,
+            };
+            if overflow {
+                return self.stream_error(id, ErrorKind::FlowControl);
+            }
+        }
+        self.flush_streams();
+        Ok(())
+    }
+
+    /// Answers a stream error with RST_STREAM and closes the stream.
+    fn stream_error(&mut self, id: StreamId, error: ErrorKind) -> Result<()> {
+        debug!("stream error on {:?}: {:?}", id, error);
+        self.writer.write_frame(RstStreamFrame::new(id, error));
+        if self.streams.contains_key(&id) {
+            self.close_stream(id);
+            self.events.push_back(Event::Reset {
+                stream_id: id,
+                error: error,
+            });
+        } else if !self.closed.contains(&id) {
+            self.remember_closed(id);
+        }
+        Ok(())
+    }
+
+    /// Answers a connection error with GOAWAY and closes the connection.
+    fn connection_error(&mut self, error: &Error) {
+        if self.state == State::Closed {
+            return;
+        }
+        info!("connection error: {}", error);
+        let frame = GoAwayFrame::new(self.last_remote_id, error.kind())
+            .debug_data(error.to_string());
+        self.writer.write_frame(frame);
+        self.goaway_sent = Some(self.last_remote_id);
+        self.state = State::Closed;
+    }
+
+    fn close_stream(&mut self, id: StreamId) {
+        if let Some(mut stream) = self.streams.remove(&id) {
+            stream.close();
+        }
+        self.remember_closed(id);
+    }
+
+    fn remember_closed(&mut self, id: StreamId) {
+        if self.closed.len() == MAX_CLOSED_STREAMS {
+            self.closed.pop_front();
+        }
+        self.closed.push_back(id);
+    }
+
+    /// Forgets a stream once it is closed and nothing is left to send.
+    fn reap(&mut self, id: StreamId) {
+        let done = match self.streams.get(&id) {
+            Some(stream) => stream.is_closed() && !stream.has_queued(),
+            None => false,
+        };
+        if done {
+            self.close_stream(id);
+        }
+    }
+
+    /// Returns true once no more streams can be opened, after GOAWAY from
+    /// the peer or when the connection is closed.
+    pub fn is_going_away(&self) -> bool {
+        self.goaway_received.is_some() || self.state == State::Closed
+    }
+
+    /// Returns true if another stream can be opened within the peer's
+    /// MAX_CONCURRENT_STREAMS.
+    pub fn can_open_stream(&self) -> bool {
+        let limit = self.remote.max_concurrent_streams.unwrap_or(u32::max_value()) as usize;
+        !self.is_going_away() && !self.is_upgrading() && self.stream_count(true) < limit
+    }
+
+    /// Returns true while the server did not answer the upgrade request, see
+    /// `upgrade`.
+    pub fn is_upgrading(&self) -> bool {
         match self.state {
-            State::Closed => true,
+            State::Upgrading { .. } => true,
             _ => false,
         }
     }
 
-    fn read_preface(&self) {}
-    fn read_settings(&self) {}
+    /// The id `open_stream` allocates next.
+    pub fn next_stream_id(&self) -> StreamId {
+        StreamId(self.next_local_id)
+    }
+
+    /// Opens new streams from `id` on, the ids skipped are never used
+    /// (rfc 5.1.1). Ids below the next one are ignored.
+    pub fn skip_stream_ids(&mut self, id: StreamId) {
+        if self.is_local_id(id) && id.0 > self.next_local_id {
+            self.next_local_id = id.0;
+        }
+    }
+
+    /// Allocates the id for a new stream we initiate, clients open it right
+    /// away for a request.
+    pub fn open_stream(&mut self) -> Result<StreamId> {
+        if self.is_going_away() {
+            return Err(Error::new(ErrorKind::RefusedStream, "connection is going away"));
+        }
+        if self.is_upgrading() {
+            return Err(Error::new(ErrorKind::RefusedStream, "upgrade to h2c pending"));
+        }
+        if self.next_local_id > 0x7fffffff {
+            return Err(Error::new(ErrorKind::RefusedStream, "stream ids exhausted"));
+        }
+        let id = StreamId(self.next_local_id);
+        self.next_local_id += 2;
+        let state = match self.role {
+            Role::Client => stream::State::Open,
+            Role::Server => stream::State::ReservedLocal,
+        };
+        let stream = Stream::new(id,
+                                 state,
+                                 self.remote.initial_window_size,
+                                 self.local.initial_window_size);
+        self.streams.insert(id, stream);
+        Ok(id)
+    }
+
+    /// Number of streams a server may still promise on the stream, none if
+    /// the peer disabled push or the stream was not opened by the peer.
+    pub fn push_capacity(&self, parent: StreamId) -> usize {
+        if self.role == Role::Client || !self.remote.enable_push || self.is_local_id(parent) ||
+           self.goaway_received.is_some() || self.state == State::Closed {
+            return 0;
+        }
+        match self.streams.get(&parent) {
+            Some(stream) if stream.is_send_open() => {}
+            _ => return 0,
+        }
+        // reserved streams count towards the limit of the peer as well
+        let limit = self.remote.max_concurrent_streams.unwrap_or(u32::max_value()) as usize;
+        limit.saturating_sub(self.stream_count(true))
+    }
+
+    /// Promises a pushed response to the request in `headers` on the parent
+    /// stream, the response is sent on the returned reserved stream.
+    pub fn send_push_promise(&mut self, parent: StreamId, headers: &[Header])
+                             -> Result<StreamId> {
+        if self.push_capacity(parent) == 0 {
+            return Err(Error::new(ErrorKind::RefusedStream, "push is not allowed"));
+        }
+        let id = try!(self.open_stream());
+        let mut block = Vec::new();
+        self.encoder.encode(headers, &mut block);
+        // the promised stream id takes 4 octets of the first frame
+        let max = self.remote.max_frame_size as usize;
+        let first_len = ::std::cmp::min(block.len(), max - 4);
+        let mut frame = PushPromiseFrame::new(parent, id).fragment(&block[..first_len]);
+        if first_len == block.len() {
+            frame = frame.end_headers();
+        }
+        let frame = self.pad(frame);
+        self.writer.write_frame(frame);
+        let mut sent = first_len;
+        for chunk in block[first_len..].chunks(max) {
+            sent += chunk.len();
+            let mut frame = ContinuationFrame::new(parent).fragment(chunk);
+            if sent == block.len() {
+                frame = frame.end_headers();
+            }
+            self.writer.write_frame(frame);
+        }
+        Ok(id)
+    }
+
+    /// Sends a header block on the stream, split into HEADERS and
+    /// CONTINUATION frames according to the peer's maximum frame size.
+    pub fn send_headers(&mut self, id: StreamId, headers: &[Header], end_stream: bool)
+                        -> Result<()> {
+        match self.streams.get_mut(&id) {
+            Some(stream) => {
+                stream.send_reserved_headers();
+                if !stream.is_send_open() {
+                    return Err(Error::new(ErrorKind::StreamClosed, "stream is closed"));
+                }
+                if end_stream {
+                    stream.send_end();
+                }
+            }
+            None => return Err(Error::new(ErrorKind::StreamClosed, "stream is not open")),
+        }
+        let mut block = Vec::new();
+        self.encoder.encode(headers, &mut block);
+        let max = self.remote.max_frame_size as usize;
+        let mut chunks = block.chunks(max);
+        let first = chunks.next().unwrap_or(&[]);
+        let mut frame = HeadersFrame::new(id).fragment(first);
+        if end_stream {
+            frame = frame.end_stream();
+        }
+        if block.len() <= max {
+            frame = frame.end_headers();
+        }
+        let frame = self.pad(frame);
+        self.writer.write_frame(frame);
+        let mut sent = first.len();
+        for chunk in chunks {
+            sent += chunk.len();
+            let mut frame = ContinuationFrame::new(id).fragment(chunk);
+            if sent == block.len() {
+                frame = frame.end_headers();
+            }
+            self.writer.write_frame(frame);
+        }
+        self.reap(id);
+        Ok(())
+    }
+
+    /// Queues data on the stream, it is sent as far as the flow control
+    /// windows allow.
+    pub fn send_data(&mut self, id: StreamId, data: &[u8], end_stream: bool) -> Result<()> {
+        match self.streams.get_mut(&id) {
+            Some(ref mut stream) if stream.is_send_open() => stream.queue_data(data, end_stream),
+            _ => return Err(Error::new(ErrorKind::StreamClosed, "stream is closed")),
+        }
+        self.flush_streams();
+        Ok(())
+    }
+
+    /// Ends the stream with trailers, they are sent once the data queued
+    /// before is.
+    pub fn send_trailers(&mut self, id: StreamId, trailers: &[Header]) -> Result<()> {
+        match self.streams.get_mut(&id) {
+            Some(ref mut stream) if stream.is_send_open() => {
+                stream.queue_trailers(trailers.to_vec())
+            }
+            _ => return Err(Error::new(ErrorKind::StreamClosed, "stream is closed")),
+        }
+        self.flush_streams();
+        Ok(())
+    }
+
+    /// Sends a header block followed by the body and trailers if there are
+    /// any, the last of them ends the stream.
+    pub fn send_message(&mut self,
+                        id: StreamId,
+                        headers: &[Header],
+                        body: &[u8],
+                        trailers: &[Header])
+                        -> Result<()> {
+        try!(self.send_headers(id, headers, body.is_empty() && trailers.is_empty()));
+        if !body.is_empty() {
+            try!(self.send_data(id, body, trailers.is_empty()));
+        }
+        if !trailers.is_empty() {
+            try!(self.send_trailers(id, trailers));
+        }
+        Ok(())
+    }
+
+    /// Sends queued data of all streams within the flow control windows.
+    fn flush_streams(&mut self) {
+        let mut ids: Vec<StreamId> = self.streams
+            .values()
+            .filter(|s| s.has_queued())
+            .map(|s| s.id())
+            .collect();
+        ids.sort();
+        for id in ids {
+            if self.writer.available() <= HEADER_SIZE {
+                // the rest is queued once the writer made room, see `write`
+                return;
+            }
+            let trailers = loop {
+                let frame = {
+                    let stream = self.streams.get_mut(&id).unwrap();
+                    if let Some(trailers) = stream.take_trailers() {
+                        break Some(trailers);
+                    }
+                    let window = cmp::min(self.send_window.available(),
+                                          stream.send_window.available());
+                    let room = self.writer.available().saturating_sub(HEADER_SIZE);
+                    let max = cmp::min(cmp::min(window, self.remote.max_frame_size as usize),
+                                       room);
+                    if !stream.has_queued() || max == 0 && stream.queued() > 0 {
+                        break None;
+                    }
+                    let (data, end_stream) = stream.take_queued(max);
+                    if end_stream {
+                        stream.send_end();
+                    }
+                    let mut frame = DataFrame::new(id).data(data);
+                    if end_stream {
+                        frame = frame.end_stream();
+                    }
+                    // padding counts towards flow control, keep it within the window
+                    let frame = self.padding.apply(frame, max);
+                    stream.send_window.decrease(frame.flow_len());
+                    frame
+                };
+                self.send_window.decrease(frame.flow_len());
+                self.writer.write_frame(frame);
+            };
+            match trailers {
+                Some(trailers) => {
+                    // cannot fail, the stream is open for sending
+                    let _ = self.send_headers(id, &trailers, true);
+                }
+                None => self.reap(id),
+            }
+        }
+    }
+
+    /// Resets the stream with the error code.
+    pub fn reset(&mut self, id: StreamId, error: ErrorKind) {
+        self.writer.write_frame(RstStreamFrame::new(id, error));
+        self.close_stream(id);
+    }
+
+    /// Announces a shutdown with GOAWAY carrying the highest stream id, the
+    /// peer stops opening streams while those already on their way are
+    /// still processed. A second GOAWAY from `go_away` has to follow.
+    pub fn drain(&mut self) {
+        self.writer.write_frame(GoAwayFrame::new(StreamId(0x7fffffff), ErrorKind::No));
+    }
+
+    /// Number of streams which are not closed or have frames left to send.
+    pub fn active_streams(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Sends GOAWAY, streams initiated by the peer afterwards are ignored.
+    pub fn go_away(&mut self, error: ErrorKind) {
+        self.writer.write_frame(GoAwayFrame::new(self.last_remote_id, error));
+        self.goaway_sent = Some(self.last_remote_id);
+    }
+
+    pub fn ping(&mut self, data: [u8; 8]) {
+        self.writer.write_frame(PingFrame::new(data));
+    }
+
+    /// Closes the connection, pending frames are still written.
+    pub fn close(&mut self) {
+        self.state = State::Closed;
+    }
+}
+
+/// Adds `len` released octets, returns the window increment to announce once
+/// they amount to half of the `initial` window.
+fn release(window: &mut WindowSize, released: &mut usize, len: usize, initial: i32)
+           -> Option<u32> {
+    *released += len;
+    if *released == 0 || *released < (initial / 2) as usize {
+        return None;
+    }
+    let increment = *released;
+    *released = 0;
+    // never above the initial window, the increment can't overflow
+    let _ = window.increase(increment as i64);
+    Some(increment as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use {Settings, StreamId};
+    use buffer::FrameReader;
+    use client::{self, Client};
+    use error::ErrorKind;
+    use frame::{FrameKind, WriteFrame};
+    use frame::data::DataFrame;
+    use frame::headers::HeadersFrame;
+    use frame::ping::PingFrame;
+    use frame::settings::{Setting, SettingsFrame};
+    use frame::window_update::WindowUpdateFrame;
+    use harness::{Script, Expect};
+    use message::{Request, Response};
+    use mock::MockStream;
+    use pool::BufferPool;
+    use super::{Connection, Role, PREFACE};
+
+    fn hello(req: Request) -> Response {
+        match req.path() {
+            Some(b"/") => Response::new(200).body(&b"hello"[..]),
+            _ => Response::new(404),
+        }
+    }
+
+    fn get(path: &'static str) -> [(&'static str, &'static str); 4] {
+        [(":method", "GET"), (":scheme", "http"), (":authority", "localhost"), (":path", path)]
+    }
+
+    #[test]
+    fn test_request_body_and_continuation() {
+        Script::new()
+            .handshake()
+            .header_block(1, &get("/"), false, Some(3))
+            .send(DataFrame::new(StreamId(1)).data(&b"body"[..]).end_stream())
+            .expect(Expect::Field(1, ":status", "200"))
+            .run_server(hello);
+    }
+
+    /// A header block is not buffered beyond its limit.
+    #[test]
+    fn test_header_block_too_large() {
+        let large = vec!['a'; 120000].into_iter().collect::<String>();
+        let mut fields = get("/").to_vec();
+        fields.push(("x-large", &large));
+        Script::new()
+            .handshake()
+            .header_block(1, &fields, true, Some(16384))
+            .connection_error(ErrorKind::EnhanceYourCalm)
+            .run_server(hello);
+    }
+
+    /// References to a large table entry can't expand a header block into
+    /// a huge header list.
+    #[test]
+    fn test_header_list_too_large() {
+        let large = vec!['a'; 4000].into_iter().collect::<String>();
+        let mut fields = get("/").to_vec();
+        fields.push(("x-large", &large));
+        // the entry is the first one in the dynamic table, index 62
+        let block = vec![0xbe; 16000];
+        Script::new()
+            .handshake()
+            .headers(1, &fields, true)
+            .expect(Expect::Field(1, ":status", "200"))
+            .send(HeadersFrame::new(StreamId(3)).fragment(block).end_headers().end_stream())
+            .connection_error(ErrorKind::EnhanceYourCalm)
+            .run_server(hello);
+    }
+
+    #[test]
+    fn test_connection_errors() {
+        // HEADERS on a stream initiated by the server
+        Script::new()
+            .handshake()
+            .headers(2, &get("/"), true)
+            .connection_error(ErrorKind::Protocol)
+            .run_server(hello);
+        // the preface has to be followed by SETTINGS
+        Script::new()
+            .preface()
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::Settings)
+            .connection_error(ErrorKind::Protocol)
+            .run_server(hello);
+    }
+
+    #[test]
+    fn test_stream_error() {
+        Script::new()
+            .handshake()
+            .headers(1, &get("/"), true)
+            .expect(Expect::EndStream(1))
+            .send(DataFrame::new(StreamId(1)).data(&b"late"[..]))
+            .stream_error(1, ErrorKind::StreamClosed)
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::PingAck([0; 8]))
+            .run_server(hello);
+    }
+
+    /// Trailers wait for data held back by flow control.
+    #[test]
+    fn test_trailers_after_blocked_data() {
+        let respond = |_| Response::new(200).body(&b"body"[..]).trailer("checksum", "1");
+        let mut settings = SettingsFrame::default();
+        settings.add_setting(Setting::InitialWindowSize(2));
+        Script::new()
+            .handshake()
+            .send(settings)
+            .expect(Expect::SettingsAck)
+            .headers(1, &get("/"), true)
+            .expect(Expect::Data(1, b"bo".to_vec()))
+            .send(WindowUpdateFrame::new(StreamId(1), 2))
+            .expect(Expect::Data(1, b"dy".to_vec()))
+            .expect(Expect::Field(1, "checksum", "1"))
+            .run_server(respond);
+    }
+
+    /// Our max frame size applies once the peer acknowledged our SETTINGS.
+    #[test]
+    fn test_max_frame_size() {
+        let settings = Settings { max_frame_size: 32768, ..client::default_settings() };
+        let client = Script::new()
+            .send(SettingsFrame::default())
+            .expect(Expect::Settings)
+            .send(DataFrame::new(StreamId(1)).data(vec![0; 20000]))
+            .connection_error(ErrorKind::FrameSize)
+            .run_client_with(settings.clone());
+        assert!(client.is_closed());
+
+        let request = |client: &mut Client<MockStream>| {
+            client.send(Request::new("GET", "http", "localhost", "/")).unwrap();
+        };
+        Script::new()
+            .handshake()
+            .act(request)
+            .expect(Expect::Headers(1))
+            .headers(1, &[(":status", "200")], false)
+            .send(DataFrame::new(StreamId(1)).data(vec![0; 20000]))
+            .send(PingFrame::new([0; 8]))
+            .expect(Expect::PingAck([0; 8]))
+            .send(DataFrame::new(StreamId(1)).data(vec![0; 32769]))
+            .connection_error(ErrorKind::FrameSize)
+            .run_client_with(settings);
+    }
+
+    /// Stream data stays queued in the stream while the transport does not
+    /// take it, the write buffer does not grow beyond its limit.
+    #[test]
+    fn test_congested_writer() {
+        let (local, mut remote) = MockStream::new();
+        let settings = client::default_settings();
+        let mut conn = Connection::new(local.clone(), Role::Client, settings, &BufferPool::new())
+            .unwrap();
+        let mut peer_settings = SettingsFrame::default();
+        peer_settings.add_setting(Setting::InitialWindowSize(0x7fffffff));
+        remote.write_frame(peer_settings).unwrap();
+        remote.write_frame(WindowUpdateFrame::new(StreamId(0), 0x7fffffff - 65535)).unwrap();
+        conn.read().unwrap();
+
+        local.set_send_capacity(0);
+        let id = conn.open_stream().unwrap();
+        let headers = vec![(b":method".to_vec(), b"POST".to_vec())];
+        conn.send_headers(id, &headers, false).unwrap();
+        let body = vec![7; 1 << 20];
+        conn.send_data(id, &body, true).unwrap();
+        for _ in 0..3 {
+            let _ = conn.write();
+            assert!(conn.writer.pending() <= conn.writer.max_size());
+        }
+        assert_eq!(conn.send_capacity(id), 0);
+
+        local.set_send_capacity(usize::max_value());
+        let mut reader = FrameReader::new(remote, 16384);
+        let mut received = 0;
+        let mut end_stream = false;
+        let mut preface = false;
+        while !end_stream {
+            conn.write().unwrap();
+            assert!(conn.writer.pending() <= conn.writer.max_size());
+            reader.fill().unwrap();
+            if !preface {
+                preface = reader.read_preface(PREFACE).unwrap();
+            }
+            while let Some(frame) = reader.next_frame() {
+                if let FrameKind::Data(frame) = frame.unwrap() {
+                    received += frame.payload().len();
+                    end_stream = frame.is_end_stream();
+                }
+            }
+        }
+        assert_eq!(received, body.len());
+    }
+
+    /// A peer flooding PINGs without reading the acknowledgements only fills
+    /// the write buffer up to its limit.
+    #[test]
+    fn test_ping_flood() {
+        let (local, mut remote) = MockStream::new();
+        let settings = client::default_settings();
+        let mut conn = Connection::new(local.clone(), Role::Client, settings, &BufferPool::new())
+            .unwrap();
+        remote.write_frame(SettingsFrame::default()).unwrap();
+        conn.read().unwrap();
+        conn.write().unwrap();
+
+        local.set_send_capacity(0);
+        let limit = conn.writer.max_size() + 17;
+        for _ in 0..3 {
+            for _ in 0..10000 {
+                remote.write_frame(PingFrame::new([1; 8])).unwrap();
+            }
+            conn.read().unwrap();
+            conn.write().unwrap();
+            assert!(conn.writer.pending() <= limit);
+        }
+
+        // every PING is acknowledged once the peer reads
+        local.set_send_capacity(usize::max_value());
+        let mut reader = FrameReader::new(remote, 16384);
+        let mut preface = false;
+        let mut acks = 0;
+        while acks < 30000 {
+            conn.read().unwrap();
+            conn.write().unwrap();
+            assert!(conn.writer.pending() <= limit);
+            reader.fill().unwrap();
+            if !preface {
+                preface = reader.read_preface(PREFACE).unwrap();
+            }
+            while let Some(frame) = reader.next_frame() {
+                if let FrameKind::Ping(ping) = frame.unwrap() {
+                    assert!(ping.is_ack());
+                    acks += 1;
+                }
+            }
+        }
+    }
+
+    /// A connection dropped with unread and unwritten data gives its chunks
+    /// back to the pool.
+    #[test]
+    fn test_drop_releases_buffers() {
+        let pool = BufferPool::new();
+        let (local, mut remote) = MockStream::new();
+        let mut conn = Connection::new(local.clone(), Role::Client, client::default_settings(),
+                                       &pool)
+            .unwrap();
+        local.set_send_capacity(0);
+        remote.write_all(&[0, 0, 8, 6]).unwrap();
+        conn.read().unwrap();
+        conn.write().unwrap();
+        assert_eq!(pool.stats().in_use, 2);
+        drop(conn);
+        assert_eq!(pool.stats().in_use, 0);
+    }
 }
@@ -12,18 +12,18 @@ pub struct Error {
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum ErrorKind {
-    // The associated condition is not a result of an error.
-    // No = 0x0,
+    /// The associated condition is not a result of an error.
+    No = 0x0,
     /// The endpoint detected an unspecific protocol error.
     Protocol = 0x1,
     /// The endpoint encountered an unexpected internal error.
     Internal = 0x2,
     /// The endpoint detected that its peer violated the flow-control protocol.
     FlowControl = 0x3,
-    // The endpoint sent a SETTINGS frame but did not receive a response in a timely manner.
-    // SettingsTimeout = 0x4,
-    // The endpoint received a frame after a stream was half-closed.
-    // StreamClosed = 0x5,
+    /// The endpoint sent a SETTINGS frame but did not receive a response in a timely manner.
+    SettingsTimeout = 0x4,
+    /// The endpoint received a frame after a stream was half-closed.
+    StreamClosed = 0x5,
     /// The endpoint received a frame with an invalid size.
     FrameSize = 0x6,
     /// The endpoint refused the stream prior to performing any application processing.
@@ -43,6 +43,36 @@ pub enum ErrorKind {
     Http11Required = 0xd,
 }
 
+impl ErrorKind {
+    /// Maps an error code received in a RST_STREAM or GOAWAY frame.
+    ///
+    /// Unknown error codes are treated as `Internal` (rfc 7).
+    pub fn from_code(code: u32) -> ErrorKind {
+        match code {
+            0x0 => ErrorKind::No,
+            0x1 => ErrorKind::Protocol,
+            0x3 => ErrorKind::FlowControl,
+            0x4 => ErrorKind::SettingsTimeout,
+            0x5 => ErrorKind::StreamClosed,
+            0x6 => ErrorKind::FrameSize,
+            0x7 => ErrorKind::RefusedStream,
+            0x8 => ErrorKind::Cancel,
+            0x9 => ErrorKind::Compression,
+            0xa => ErrorKind::Connect,
+            0xb => ErrorKind::EnhanceYourCalm,
+            0xc => ErrorKind::InadequateSecurity,
+            0xd => ErrorKind::Http11Required,
+            _ => ErrorKind::Internal,
+        }
+    }
+
+    /// The error code sent on the wire.
+    #[inline]
+    pub fn code(&self) -> u32 {
+        *self as u32
+    }
+}
+
 impl Error {
     pub fn new<E>(kind: ErrorKind, error: E) -> Error
         where E: Into<Box<error::Error>>
@@ -69,6 +99,13 @@ impl Error {
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
+
+    /// Returns true if the peer did not process the request, because it
+    /// refused the stream or the stream was above the last stream id of a
+    /// GOAWAY (rfc 8.1.4). The request can be sent again on a new connection.
+    pub fn is_retryable(&self) -> bool {
+        self.kind == ErrorKind::RefusedStream
+    }
 }
 
 impl fmt::Display for Error {
@@ -0,0 +1,214 @@
+//! Random frames for property tests.
+//!
+//! Generated frames are valid, i.e. they survive being written and read
+//! back, so the properties can compare them for equality.
+
+use quickcheck::{Arbitrary, Gen, quickcheck};
+use StreamId;
+use error::ErrorKind;
+use frame::{FrameIter, FrameKind, Flags, ReadFrame, WriteFrame};
+use frame::continuation::ContinuationFrame;
+use frame::data::DataFrame;
+use frame::goaway::GoAwayFrame;
+use frame::headers::HeadersFrame;
+use frame::padding::Pad;
+use frame::ping::PingFrame;
+use frame::priority::PriorityFrame;
+use frame::push_promise::PushPromiseFrame;
+use frame::rst_stream::RstStreamFrame;
+use frame::settings::{Setting, SettingsFrame};
+use frame::unknown::UnknownFrame;
+use frame::window_update::WindowUpdateFrame;
+
+/// Any stream id, including zero.
+fn any_id<G: Gen>(g: &mut G) -> StreamId {
+    StreamId(g.gen_range(0, 0x80000000))
+}
+
+/// A stream id other than zero, for frames associated with a stream.
+fn stream_id<G: Gen>(g: &mut G) -> StreamId {
+    StreamId(g.gen_range(1, 0x80000000))
+}
+
+fn error_kind<G: Gen>(g: &mut G) -> ErrorKind {
+    ErrorKind::from_code(g.gen_range(0, 0xe))
+}
+
+fn bytes<G: Gen>(g: &mut G) -> Vec<u8> {
+    Arbitrary::arbitrary(g)
+}
+
+fn maybe_pad<G: Gen, F: Pad>(g: &mut G, frame: F) -> F {
+    if g.gen() { frame.pad(g.gen()) } else { frame }
+}
+
+/// Priority fields of the stream, never depending on the stream itself.
+fn priority<G: Gen>(g: &mut G, id: StreamId) -> PriorityFrame {
+    let mut dependency = any_id(g);
+    if dependency == id {
+        dependency = StreamId(0);
+    }
+    let frame = PriorityFrame::new(id).dependency(dependency).weight(g.gen_range(1, 257));
+    if g.gen() { frame.exclusive() } else { frame }
+}
+
+impl Arbitrary for Setting {
+    fn arbitrary<G: Gen>(g: &mut G) -> Setting {
+        match g.gen_range(0, 6) {
+            0 => Setting::HeaderTableSize(g.gen()),
+            1 => Setting::EnablePush(g.gen()),
+            2 => Setting::MaxConcurrentStreams(g.gen()),
+            3 => Setting::InitialWindowSize(g.gen_range(0, i32::max_value())),
+            4 => Setting::MaxFrameSize(g.gen_range(1 << 14, 1 << 24)),
+            _ => Setting::MaxHeaderListSize(g.gen()),
+        }
+    }
+}
+
+impl Arbitrary for FrameKind {
+    fn arbitrary<G: Gen>(g: &mut G) -> FrameKind {
+        match g.gen_range(0, 11) {
+            0 => {
+                let frame = DataFrame::new(stream_id(g)).data(bytes(g));
+                let frame = if g.gen() { frame.end_stream() } else { frame };
+                FrameKind::Data(maybe_pad(g, frame))
+            }
+            1 => {
+                let id = stream_id(g);
+                let mut frame = HeadersFrame::new(id).fragment(bytes(g));
+                if g.gen() {
+                    frame = frame.priority(priority(g, id));
+                }
+                if g.gen() {
+                    frame = frame.end_headers();
+                }
+                if g.gen() {
+                    frame = frame.end_stream();
+                }
+                FrameKind::Headers(maybe_pad(g, frame))
+            }
+            2 => {
+                let id = stream_id(g);
+                FrameKind::Priority(priority(g, id))
+            }
+            3 => FrameKind::RstStream(RstStreamFrame::new(stream_id(g), error_kind(g))),
+            4 => {
+                if g.gen() {
+                    return FrameKind::Settings(SettingsFrame::ack());
+                }
+                let mut frame = SettingsFrame::default();
+                for setting in Vec::<Setting>::arbitrary(g) {
+                    frame.add_setting(setting);
+                }
+                FrameKind::Settings(frame)
+            }
+            5 => {
+                let frame = PushPromiseFrame::new(stream_id(g), stream_id(g)).fragment(bytes(g));
+                let frame = if g.gen() { frame.end_headers() } else { frame };
+                FrameKind::PushPromise(maybe_pad(g, frame))
+            }
+            6 => {
+                let data = g.gen();
+                FrameKind::Ping(if g.gen() { PingFrame::pong(data) } else { PingFrame::new(data) })
+            }
+            7 => {
+                let frame = GoAwayFrame::new(any_id(g), error_kind(g)).debug_data(bytes(g));
+                FrameKind::GoAway(frame)
+            }
+            8 => {
+                let increment = g.gen_range(1, 0x80000000);
+                FrameKind::WindowUpdate(WindowUpdateFrame::new(any_id(g), increment))
+            }
+            9 => {
+                let frame = ContinuationFrame::new(stream_id(g)).fragment(bytes(g));
+                FrameKind::Continuation(if g.gen() { frame.end_headers() } else { frame })
+            }
+            _ => {
+                // types above CONTINUATION, with the flags a receiver keeps
+                let flags = Flags::from_bits_truncate(g.gen());
+                let frame_type = g.gen_range(0xa, 0x100) as u8;
+                FrameKind::Unknown(UnknownFrame::new(any_id(g), flags, frame_type, bytes(g)))
+            }
+        }
+    }
+}
+
+/// Serializes a frame of any kind.
+fn write(frame: FrameKind, buf: &mut Vec<u8>) {
+    match frame {
+        FrameKind::Data(f) => buf.write_frame(f),
+        FrameKind::Headers(f) => buf.write_frame(f),
+        FrameKind::Priority(f) => buf.write_frame(f),
+        FrameKind::RstStream(f) => buf.write_frame(f),
+        FrameKind::Settings(f) => buf.write_frame(f),
+        FrameKind::PushPromise(f) => buf.write_frame(f),
+        FrameKind::Ping(f) => buf.write_frame(f),
+        FrameKind::GoAway(f) => buf.write_frame(f),
+        FrameKind::WindowUpdate(f) => buf.write_frame(f),
+        FrameKind::Continuation(f) => buf.write_frame(f),
+        FrameKind::Unknown(f) => buf.write_frame(f),
+    }
+    .unwrap()
+}
+
+#[test]
+fn test_roundtrip() {
+    fn prop(frame: FrameKind) -> bool {
+        let mut buf = Vec::new();
+        write(frame.clone(), &mut buf);
+        let read = (&buf[..]).read_frame().unwrap();
+        let iterated = FrameIter::new(&buf, usize::max_value()).next().unwrap().unwrap();
+        read == frame && iterated == frame
+    }
+    quickcheck(prop as fn(FrameKind) -> bool);
+}
+
+#[test]
+fn test_settings_roundtrip() {
+    fn prop(settings: Vec<Setting>) -> bool {
+        let mut frame = SettingsFrame::default();
+        for setting in settings.iter().cloned() {
+            frame.add_setting(setting);
+        }
+        let mut buf = Vec::new();
+        buf.write_frame(frame).unwrap();
+        match (&buf[..]).read_frame().unwrap() {
+            FrameKind::Settings(read) => read.settings() == settings,
+            _ => false,
+        }
+    }
+    quickcheck(prop as fn(Vec<Setting>) -> bool);
+}
+
+/// Frames of a stream of bytes arriving in pieces are the frames of the
+/// whole stream, wherever it is split.
+#[test]
+fn test_split_invariance() {
+    fn prop(frames: Vec<FrameKind>, splits: Vec<usize>) -> bool {
+        let mut bytes = Vec::new();
+        for frame in frames.iter().cloned() {
+            write(frame, &mut bytes);
+        }
+        let mut cuts: Vec<usize> = splits.iter().map(|n| n % (bytes.len() + 1)).collect();
+        cuts.push(bytes.len());
+        cuts.sort();
+
+        let mut buf = Vec::new();
+        let mut parsed = Vec::new();
+        let mut start = 0;
+        for cut in cuts {
+            buf.extend(&bytes[start..cut]);
+            start = cut;
+            let consumed = {
+                let mut iter = FrameIter::new(&buf, usize::max_value());
+                while let Some(frame) = iter.next() {
+                    parsed.push(frame.unwrap());
+                }
+                iter.position()
+            };
+            buf.drain(..consumed);
+        }
+        buf.is_empty() && parsed == frames
+    }
+    quickcheck(prop as fn(Vec<FrameKind>, Vec<usize>) -> bool);
+}
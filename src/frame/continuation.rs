@@ -0,0 +1,158 @@
+use std::io::{Read, Write};
+use StreamId;
+use frame::{Frame, FrameHeader, FrameType, Flags, FLAG_END_HEADERS};
+use error::{Error, Result};
+
+pub const TYPE_CONTINUATION: FrameType = 0x9;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContinuationFrame {
+    stream_id: StreamId,
+    fragment: Vec<u8>,
+    end_headers: bool,
+}
+
+impl ContinuationFrame {
+    pub fn new(stream_id: StreamId) -> Self {
+        ContinuationFrame {
+            stream_id: stream_id,
+            fragment: Vec::new(),
+            end_headers: false,
+        }
+    }
+
+    pub fn fragment<T: Into<Vec<u8>>>(mut self, fragment: T) -> Self {
+        self.fragment = fragment.into();
+        self
+    }
+
+    pub fn end_headers(mut self) -> Self {
+        self.end_headers = true;
+        self
+    }
+
+    #[inline]
+    pub fn is_end_headers(&self) -> bool {
+        self.end_headers
+    }
+
+    /// Takes the header block fragment.
+    pub fn into_fragment(self) -> Vec<u8> {
+        self.fragment
+    }
+}
+
+/// A continuation frame borrowing its fragment from the receive buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContinuationFrameRef<'a> {
+    stream_id: StreamId,
+    fragment: &'a [u8],
+    end_headers: bool,
+}
+
+impl<'a> ContinuationFrameRef<'a> {
+    /// Parses the frame from its complete payload.
+    pub fn from_slice(header: FrameHeader, payload: &'a [u8]) -> Result<ContinuationFrameRef<'a>> {
+        try!(header.check_payload(payload));
+        if header.stream_id == 0 {
+            return Err(Error::protocol("Continuation frame must be associated with a stream, \
+                                        stream id was zero"));
+        }
+        Ok(ContinuationFrameRef {
+            stream_id: header.stream_id,
+            fragment: &payload[..header.payload_len],
+            end_headers: header.flags.contains(FLAG_END_HEADERS),
+        })
+    }
+
+    #[inline]
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    #[inline]
+    pub fn fragment(&self) -> &'a [u8] {
+        self.fragment
+    }
+
+    #[inline]
+    pub fn is_end_headers(&self) -> bool {
+        self.end_headers
+    }
+
+    pub fn into_owned(self) -> ContinuationFrame {
+        ContinuationFrame {
+            stream_id: self.stream_id,
+            fragment: self.fragment.to_vec(),
+            end_headers: self.end_headers,
+        }
+    }
+}
+
+impl Frame for ContinuationFrame {
+    fn from_reader<R: Read>(header: FrameHeader, mut reader: R) -> Result<ContinuationFrame> {
+        if header.stream_id == 0 {
+            return Err(Error::protocol("Continuation frame must be associated with a stream, \
+                                        stream id was zero"));
+        }
+        let mut fragment = vec![0; header.payload_len];
+        try!(reader.read_exact(&mut fragment));
+        Ok(ContinuationFrame {
+            stream_id: header.stream_id,
+            fragment: fragment,
+            end_headers: header.flags.contains(FLAG_END_HEADERS),
+        })
+    }
+
+    fn into_writer<W: Write>(self, mut writer: W) -> Result<()> {
+        try!(writer.write_all(self.fragment.as_ref()));
+        Ok(())
+    }
+
+    fn payload_len(&self) -> usize {
+        self.fragment.len()
+    }
+
+    fn frame_type(&self) -> FrameType {
+        TYPE_CONTINUATION
+    }
+
+    fn flags(&self) -> Flags {
+        if self.end_headers {
+            FLAG_END_HEADERS
+        } else {
+            Flags::empty()
+        }
+    }
+
+    fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ContinuationFrame;
+    use StreamId;
+    use frame::{ReadFrame, WriteFrame, FrameKind};
+
+    #[test]
+    fn test_continuation_frame() {
+        let frame = ContinuationFrame::new(StreamId(1)).fragment(vec![1, 2]).end_headers();
+        let mut b = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        let expected = vec![0, 0, 2,    // length
+                            9,          // type
+                            4,          // flags
+                            0, 0, 0, 1, // stream id
+                            1, 2,       // fragment
+                           ];
+        assert_eq!(b, expected);
+        let mut sl = &b[..];
+        let res = match sl.read_frame().unwrap() {
+            FrameKind::Continuation(frame) => frame,
+            _ => panic!("Wrong frame type"),
+        };
+        assert_eq!(frame, res);
+    }
+}
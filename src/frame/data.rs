@@ -0,0 +1,236 @@
+use std::io::{Read, Write};
+use StreamId;
+use frame::{Frame, FrameHeader, FrameType, Flags, FLAG_PADDED, FLAG_END_STREAM};
+use frame::padding::{self, Pad};
+use error::{Error, Result};
+
+pub const TYPE_DATA: FrameType = 0x0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataFrame {
+    stream_id: StreamId,
+    data: Vec<u8>,
+    pad_len: Option<u8>,
+    end_stream: bool,
+}
+
+impl DataFrame {
+    pub fn new(stream_id: StreamId) -> Self {
+        DataFrame {
+            stream_id: stream_id,
+            data: Vec::new(),
+            pad_len: None,
+            end_stream: false,
+        }
+    }
+
+    pub fn data<T: Into<Vec<u8>>>(mut self, data: T) -> Self {
+        self.data = data.into();
+        self
+    }
+
+    pub fn end_stream(mut self) -> Self {
+        self.end_stream = true;
+        self
+    }
+
+    #[inline]
+    pub fn is_end_stream(&self) -> bool {
+        self.end_stream
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_payload(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// The flow-controlled length of the frame, including padding (rfc 6.9.1).
+    #[inline]
+    pub fn flow_len(&self) -> usize {
+        self.payload_len()
+    }
+}
+
+/// A data frame borrowing its data from the receive buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataFrameRef<'a> {
+    stream_id: StreamId,
+    data: &'a [u8],
+    pad_len: Option<u8>,
+    end_stream: bool,
+}
+
+impl<'a> DataFrameRef<'a> {
+    /// Parses the frame from its complete payload.
+    pub fn from_slice(header: FrameHeader, mut payload: &'a [u8]) -> Result<DataFrameRef<'a>> {
+        try!(header.check_payload(payload));
+        if header.stream_id == 0 {
+            return Err(Error::protocol("Data frame must be associated with a stream, stream id \
+                                        was zero"));
+        }
+        let (pad_len, data_len) = try!(padding::read_pad_len(&header, &mut payload));
+        Ok(DataFrameRef {
+            stream_id: header.stream_id,
+            data: &payload[..data_len],
+            pad_len: pad_len,
+            end_stream: header.flags.contains(FLAG_END_STREAM),
+        })
+    }
+
+    #[inline]
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        self.data
+    }
+
+    #[inline]
+    pub fn is_end_stream(&self) -> bool {
+        self.end_stream
+    }
+
+    /// The flow-controlled length of the frame, including padding (rfc 6.9.1).
+    #[inline]
+    pub fn flow_len(&self) -> usize {
+        self.data.len() + padding::padded_len(self.pad_len)
+    }
+
+    pub fn into_owned(self) -> DataFrame {
+        DataFrame {
+            stream_id: self.stream_id,
+            data: self.data.to_vec(),
+            pad_len: self.pad_len,
+            end_stream: self.end_stream,
+        }
+    }
+}
+
+impl Pad for DataFrame {
+    fn pad(mut self, pad_len: u8) -> Self {
+        self.pad_len = Some(pad_len);
+        self
+    }
+}
+
+impl Frame for DataFrame {
+    fn from_reader<R: Read>(header: FrameHeader, mut reader: R) -> Result<DataFrame> {
+        if header.stream_id == 0 {
+            return Err(Error::protocol("Data frame must be associated with a stream, stream id \
+                                        was zero"));
+        }
+        let (pad_len, data_len) = try!(padding::read_pad_len(&header, reader.by_ref()));
+        let mut data = vec![0; data_len];
+        try!(reader.read_exact(&mut data));
+        try!(padding::skip_padding(pad_len, reader.by_ref()));
+        Ok(DataFrame {
+            stream_id: header.stream_id,
+            data: data,
+            pad_len: pad_len,
+            end_stream: header.flags.contains(FLAG_END_STREAM),
+        })
+    }
+
+    fn into_writer<W: Write>(self, mut writer: W) -> Result<()> {
+        try!(padding::write_pad_len(self.pad_len, writer.by_ref()));
+        try!(writer.write_all(self.data.as_ref()));
+        try!(padding::write_padding(self.pad_len, writer.by_ref()));
+        Ok(())
+    }
+
+    fn payload_len(&self) -> usize {
+        self.data.len() + padding::padded_len(self.pad_len)
+    }
+
+    fn frame_type(&self) -> FrameType {
+        TYPE_DATA
+    }
+
+    fn flags(&self) -> Flags {
+        let mut flags = Flags::empty();
+        if self.end_stream {
+            flags.insert(FLAG_END_STREAM);
+        }
+        if self.pad_len.is_some() {
+            flags.insert(FLAG_PADDED);
+        }
+        flags
+    }
+
+    fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DataFrame;
+    use StreamId;
+    use frame::{ReadFrame, WriteFrame, FrameKind};
+    use frame::padding::{Pad, PaddingPolicy};
+    use error::ErrorKind;
+
+    #[test]
+    fn test_data_frame() {
+        let frame = DataFrame::new(StreamId(1)).data(vec![1, 2, 3]).end_stream();
+        let mut b = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        let expected = vec![0, 0, 3,    // length
+                            0,          // type
+                            1,          // flags
+                            0, 0, 0, 1, // stream id
+                            1, 2, 3,    // data
+                           ];
+        assert_eq!(b, expected);
+        let mut sl = &b[..];
+        let res = match sl.read_frame().unwrap() {
+            FrameKind::Data(frame) => frame,
+            _ => panic!("Wrong frame type"),
+        };
+        assert_eq!(frame, res);
+    }
+
+    #[test]
+    fn test_padded_data_frame() {
+        let frame = DataFrame::new(StreamId(1)).data(vec![1, 2, 3]).pad(2);
+        let mut b = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        let expected = vec![0, 0, 6,    // length
+                            0,          // type
+                            8,          // flags
+                            0, 0, 0, 1, // stream id
+                            2,          // padding length
+                            1, 2, 3,    // data
+                            0, 0,       // padding
+                           ];
+        assert_eq!(b, expected);
+        let mut sl = &b[..];
+        let res = match sl.read_frame().unwrap() {
+            FrameKind::Data(frame) => frame,
+            _ => panic!("Wrong frame type"),
+        };
+        assert_eq!(frame, res);
+        assert_eq!(res.payload(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_block_padding_policy() {
+        let frame = PaddingPolicy::Block(8).apply(DataFrame::new(StreamId(1)).data(vec![1, 2, 3]),
+                                                  16384);
+        let mut b = Vec::new();
+        b.write_frame(frame).unwrap();
+        assert_eq!(b.len(), 9 + 8);
+    }
+
+    #[test]
+    fn test_error_pad_len_too_large() {
+        let mut b = &vec![0, 0, 2, 0, 8, 0, 0, 0, 1, 2, 0][..];
+        assert_eq!(b.read_frame().unwrap_err().kind(), ErrorKind::Protocol);
+    }
+}
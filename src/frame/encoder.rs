@@ -0,0 +1,438 @@
+//! Encoder serialising frames into reusable buffers for vectored writes.
+
+use std::io::{self, Write, IoSlice, ErrorKind};
+use std::mem;
+use frame::{Frame, FrameHeader, HEADER_SIZE};
+use pool::BufferPool;
+
+/// Frames with a payload up to this size are copied next to their header,
+/// consecutive small frames end up in one contiguous block.
+const COALESCE_THRESHOLD: usize = 1024;
+/// Number of payload buffers kept around for reuse.
+const MAX_SPARE_BUFFERS: usize = 16;
+/// Maximum number of slices passed to a single `write_vectored` call.
+const MAX_IO_SLICES: usize = 64;
+
+#[derive(Debug)]
+enum Chunk {
+    /// A range of the shared header buffer.
+    Buffered(usize, usize),
+    /// The payload of a large frame.
+    Payload(Vec<u8>),
+}
+
+/// The `FrameEncoder` queues encoded frames and writes them with as few
+/// syscalls as possible.
+///
+/// Frame headers and small control frames are serialised into one reusable
+/// buffer, so a burst of e.g. SETTINGS ACK, PING and WINDOW_UPDATE frames is
+/// written by a single `write`. Large payloads are kept in separate buffers
+/// and written together with their header through `write_vectored`. Buffers
+/// are reused once they have been written, so encoding does not allocate in
+/// the steady state.
+///
+/// An encoder created `with_pool` takes all its buffers from a `BufferPool`
+/// and gives them back once written, an idle encoder holds no memory.
+#[derive(Debug, Default)]
+pub struct FrameEncoder {
+    buf: Vec<u8>,
+    chunks: Vec<Chunk>,
+    // bytes of the first chunk already written
+    written: usize,
+    pending: usize,
+    spare: Vec<Vec<u8>>,
+    pool: Option<BufferPool>,
+}
+
+impl FrameEncoder {
+    pub fn new() -> FrameEncoder {
+        FrameEncoder::default()
+    }
+
+    pub fn with_pool(pool: BufferPool) -> FrameEncoder {
+        let mut encoder = FrameEncoder::default();
+        encoder.pool = Some(pool);
+        encoder
+    }
+
+    /// Number of encoded bytes not yet written.
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.pending == 0
+    }
+
+    /// Queues the frame for writing.
+    pub fn encode<F: Frame>(&mut self, frame: F) {
+        let payload_len = frame.payload_len();
+        self.acquire_buf();
+        let start = self.buf.len();
+        // writing into a `Vec` never fails
+        FrameHeader::new(&frame).into_writer(&mut self.buf).unwrap();
+        if payload_len <= COALESCE_THRESHOLD {
+            frame.into_writer(&mut self.buf).unwrap();
+            self.push_buffered(start);
+        } else {
+            self.push_buffered(start);
+            let mut payload = self.take_spare();
+            frame.into_writer(&mut payload).unwrap();
+            self.chunks.push(Chunk::Payload(payload));
+        }
+        self.pending += HEADER_SIZE + payload_len;
+    }
+
+    /// Queues raw bytes, e.g. the connection preface.
+    pub fn encode_bytes(&mut self, bytes: &[u8]) {
+        if bytes.len() <= COALESCE_THRESHOLD {
+            self.acquire_buf();
+            let start = self.buf.len();
+            self.buf.extend_from_slice(bytes);
+            self.push_buffered(start);
+        } else {
+            let mut payload = self.take_spare();
+            payload.extend_from_slice(bytes);
+            self.chunks.push(Chunk::Payload(payload));
+        }
+        self.pending += bytes.len();
+    }
+
+    /// Writes as much of the queued frames as the writer accepts with a single
+    /// vectored write.
+    ///
+    /// Returns the number of bytes written, a writer which would block
+    /// accepts zero bytes.
+    pub fn write_to<W: Write>(&mut self, writer: &mut W) -> io::Result<usize> {
+        if self.chunks.is_empty() {
+            return Ok(0);
+        }
+        let nwritten = {
+            let mut slices = [IoSlice::new(&[]); MAX_IO_SLICES];
+            let mut offset = self.written;
+            let mut n = 0;
+            for chunk in self.chunks.iter().take(MAX_IO_SLICES) {
+                let bytes = match *chunk {
+                    Chunk::Buffered(start, end) => &self.buf[start..end],
+                    Chunk::Payload(ref payload) => payload,
+                };
+                slices[n] = IoSlice::new(&bytes[offset..]);
+                offset = 0;
+                n += 1;
+            }
+            match writer.write_vectored(&slices[..n]) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => 0,
+                Err(e) => return Err(e),
+            }
+        };
+        self.consume(nwritten);
+        Ok(nwritten)
+    }
+
+    /// Writes queued frames until all are written or the writer would block.
+    pub fn flush_to<W: Write>(&mut self, writer: &mut W) -> io::Result<usize> {
+        let mut total = 0;
+        while !self.is_empty() {
+            match try!(self.write_to(writer)) {
+                0 => break,
+                n => total += n,
+            }
+        }
+        Ok(total)
+    }
+
+    /// Takes the shared buffer from the pool, if not held yet.
+    fn acquire_buf(&mut self) {
+        if let Some(ref pool) = self.pool {
+            if self.buf.capacity() == 0 {
+                self.buf = pool.acquire();
+            }
+        }
+    }
+
+    /// Takes an empty buffer for a large payload.
+    fn take_spare(&mut self) -> Vec<u8> {
+        match self.pool {
+            Some(ref pool) => pool.acquire(),
+            None => self.spare.pop().unwrap_or_default(),
+        }
+    }
+
+    /// Adds the bytes from `start` to the end of the shared buffer as chunk,
+    /// extending the last chunk if it is contiguous.
+    fn push_buffered(&mut self, start: usize) {
+        let end = self.buf.len();
+        if let Some(&mut Chunk::Buffered(_, ref mut last_end)) = self.chunks.last_mut() {
+            if *last_end == start {
+                *last_end = end;
+                return;
+            }
+        }
+        self.chunks.push(Chunk::Buffered(start, end));
+    }
+
+    fn consume(&mut self, mut amt: usize) {
+        self.pending -= amt;
+        let mut done = 0;
+        for chunk in self.chunks.iter_mut() {
+            let len = match *chunk {
+                Chunk::Buffered(start, end) => end - start,
+                Chunk::Payload(ref payload) => payload.len(),
+            } - self.written;
+            if amt < len {
+                self.written += amt;
+                break;
+            }
+            amt -= len;
+            self.written = 0;
+            done += 1;
+            if let Chunk::Payload(ref mut payload) = *chunk {
+                let mut spare = mem::replace(payload, Vec::new());
+                match self.pool {
+                    Some(ref pool) => pool.release(spare),
+                    None if self.spare.len() < MAX_SPARE_BUFFERS => {
+                        spare.clear();
+                        self.spare.push(spare);
+                    }
+                    None => {}
+                }
+            }
+        }
+        self.chunks.drain(..done);
+        if self.chunks.is_empty() {
+            self.buf.clear();
+            if let Some(ref pool) = self.pool {
+                if self.buf.capacity() > 0 {
+                    pool.release(mem::replace(&mut self.buf, Vec::new()));
+                }
+            }
+        } else {
+            self.compact();
+        }
+    }
+
+    /// Drops the written bytes at the front of the shared buffer once they
+    /// take up half of it, so the buffer stays bounded while new frames are
+    /// queued before the previous ones are written completely.
+    fn compact(&mut self) {
+        // the written part of a partially written chunk is dead as well
+        if let Some(&mut Chunk::Buffered(ref mut start, _)) = self.chunks.first_mut() {
+            *start += self.written;
+            self.written = 0;
+        }
+        let first = self.chunks
+            .iter()
+            .filter_map(|chunk| match *chunk {
+                Chunk::Buffered(start, _) => Some(start),
+                Chunk::Payload(_) => None,
+            })
+            .next();
+        let written = first.unwrap_or(self.buf.len());
+        if written == 0 || written < self.buf.len() / 2 {
+            return;
+        }
+        self.buf.drain(..written);
+        for chunk in self.chunks.iter_mut() {
+            if let Chunk::Buffered(ref mut start, ref mut end) = *chunk {
+                *start -= written;
+                *end -= written;
+            }
+        }
+    }
+}
+
+/// Gives the buffers of unwritten frames back to the pool.
+impl Drop for FrameEncoder {
+    fn drop(&mut self) {
+        if let Some(ref pool) = self.pool {
+            if self.buf.capacity() > 0 {
+                pool.release(mem::replace(&mut self.buf, Vec::new()));
+            }
+            for chunk in self.chunks.drain(..) {
+                if let Chunk::Payload(payload) = chunk {
+                    pool.release(payload);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Write, IoSlice};
+    use std::cmp;
+    use super::FrameEncoder;
+    use pool::BufferPool;
+    use StreamId;
+    use frame::WriteFrame;
+    use frame::data::DataFrame;
+    use frame::ping::PingFrame;
+    use frame::settings::SettingsFrame;
+    use frame::window_update::WindowUpdateFrame;
+
+    /// Writer counting the calls, accepting at most `limit` bytes per call.
+    struct Socket {
+        data: Vec<u8>,
+        calls: usize,
+        limit: usize,
+    }
+
+    impl Socket {
+        fn new(limit: usize) -> Socket {
+            Socket {
+                data: Vec::new(),
+                calls: 0,
+                limit: limit,
+            }
+        }
+    }
+
+    impl Write for Socket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.limit == 0 {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+            }
+            let mut n = 0;
+            for buf in bufs {
+                let len = cmp::min(buf.len(), self.limit - n);
+                self.data.extend_from_slice(&buf[..len]);
+                n += len;
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_coalesce_control_frames() {
+        let mut encoder = FrameEncoder::new();
+        encoder.encode(SettingsFrame::ack());
+        encoder.encode(PingFrame::pong([1; 8]));
+        encoder.encode(WindowUpdateFrame::new(StreamId(0), 100));
+        let mut expected = Vec::new();
+        expected.write_frame(SettingsFrame::ack()).unwrap();
+        expected.write_frame(PingFrame::pong([1; 8])).unwrap();
+        expected.write_frame(WindowUpdateFrame::new(StreamId(0), 100)).unwrap();
+        assert_eq!(encoder.pending(), expected.len());
+
+        let mut socket = Socket::new(usize::max_value());
+        assert_eq!(encoder.write_to(&mut socket).unwrap(), expected.len());
+        assert_eq!(socket.calls, 1);
+        assert_eq!(socket.data, expected);
+        assert!(encoder.is_empty());
+    }
+
+    #[test]
+    fn test_vectored_large_payload() {
+        let mut encoder = FrameEncoder::new();
+        let data = DataFrame::new(StreamId(1)).data(vec![7; 4000]);
+        encoder.encode(PingFrame::new([1; 8]));
+        encoder.encode(data.clone());
+        encoder.encode(SettingsFrame::ack());
+        let mut expected = Vec::new();
+        expected.write_frame(PingFrame::new([1; 8])).unwrap();
+        expected.write_frame(data).unwrap();
+        expected.write_frame(SettingsFrame::ack()).unwrap();
+
+        let mut socket = Socket::new(usize::max_value());
+        encoder.write_to(&mut socket).unwrap();
+        assert_eq!(socket.calls, 1);
+        assert_eq!(socket.data, expected);
+    }
+
+    #[test]
+    fn test_partial_writes() {
+        let mut encoder = FrameEncoder::new();
+        let data = DataFrame::new(StreamId(1)).data(vec![7; 2000]);
+        encoder.encode(data.clone());
+        encoder.encode(PingFrame::new([1; 8]));
+        let mut expected = Vec::new();
+        expected.write_frame(data).unwrap();
+        expected.write_frame(PingFrame::new([1; 8])).unwrap();
+
+        let mut socket = Socket::new(100);
+        assert_eq!(encoder.flush_to(&mut socket).unwrap(), expected.len());
+        assert_eq!(socket.data, expected);
+        assert!(encoder.is_empty());
+    }
+
+    #[test]
+    fn test_encode_bytes() {
+        let mut encoder = FrameEncoder::new();
+        encoder.encode_bytes(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+        encoder.encode(SettingsFrame::ack());
+        encoder.encode_bytes(&[5; 2000]);
+        let mut socket = Socket::new(usize::max_value());
+        encoder.flush_to(&mut socket).unwrap();
+        assert_eq!(socket.calls, 1);
+        assert_eq!(&socket.data[..24], b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+        assert_eq!(socket.data[24..33], [0, 0, 0, 4, 1, 0, 0, 0, 0]);
+        assert_eq!(socket.data.len(), 33 + 2000);
+    }
+
+    #[test]
+    fn test_would_block() {
+        let mut encoder = FrameEncoder::new();
+        encoder.encode(PingFrame::new([1; 8]));
+        let mut socket = Socket::new(0);
+        assert_eq!(encoder.flush_to(&mut socket).unwrap(), 0);
+        assert_eq!(encoder.pending(), 17);
+    }
+
+    #[test]
+    fn test_interleaved_writes_bounded() {
+        let mut encoder = FrameEncoder::new();
+        let mut expected = Vec::new();
+        encoder.encode(SettingsFrame::ack());
+        expected.write_frame(SettingsFrame::ack()).unwrap();
+        let mut socket = Socket::new(5);
+        encoder.write_to(&mut socket).unwrap();
+        // the encoder never runs empty, every write ends within a frame
+        socket.limit = 17;
+        for i in 0..10000 {
+            encoder.encode(PingFrame::new([i as u8; 8]));
+            expected.write_frame(PingFrame::new([i as u8; 8])).unwrap();
+            encoder.write_to(&mut socket).unwrap();
+            assert!(!encoder.is_empty());
+            assert!(encoder.buf.capacity() <= 1024);
+        }
+        encoder.flush_to(&mut socket).unwrap();
+        assert_eq!(socket.data, expected);
+    }
+
+    #[test]
+    fn test_drop_releases_buffers() {
+        let pool = BufferPool::new();
+        let mut encoder = FrameEncoder::with_pool(pool.clone());
+        encoder.encode(PingFrame::new([1; 8]));
+        encoder.encode(DataFrame::new(StreamId(1)).data(vec![7; 2000]));
+        assert_eq!(pool.stats().in_use, 2);
+        drop(encoder);
+        assert_eq!(pool.stats().in_use, 0);
+    }
+
+    #[test]
+    fn test_reuse_buffers() {
+        let mut encoder = FrameEncoder::new();
+        let mut socket = Socket::new(usize::max_value());
+        encoder.encode(DataFrame::new(StreamId(1)).data(vec![7; 2000]));
+        encoder.flush_to(&mut socket).unwrap();
+        assert_eq!(encoder.spare.len(), 1);
+        assert!(encoder.spare[0].capacity() >= 2000);
+        encoder.encode(DataFrame::new(StreamId(1)).data(vec![7; 2000]));
+        assert!(encoder.spare.is_empty());
+        encoder.flush_to(&mut socket).unwrap();
+        assert_eq!(socket.data.len(), 2 * 2009);
+    }
+}
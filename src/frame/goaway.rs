@@ -0,0 +1,112 @@
+use std::io::{Read, Write};
+use byteorder::{ByteOrder, BigEndian};
+use StreamId;
+use frame::{Frame, FrameHeader, FrameType};
+use error::{Error, ErrorKind, Result};
+
+pub const TYPE_GOAWAY: FrameType = 0x7;
+
+// last stream id and error code, followed by optional debug data
+const GOAWAY_MIN_PAYLOAD_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoAwayFrame {
+    last_stream_id: StreamId,
+    error: ErrorKind,
+    debug_data: Vec<u8>,
+}
+
+impl GoAwayFrame {
+    pub fn new(last_stream_id: StreamId, error: ErrorKind) -> Self {
+        GoAwayFrame {
+            last_stream_id: last_stream_id,
+            error: error,
+            debug_data: Vec::new(),
+        }
+    }
+
+    pub fn debug_data<T: Into<Vec<u8>>>(mut self, data: T) -> Self {
+        self.debug_data = data.into();
+        self
+    }
+
+    #[inline]
+    pub fn last_stream_id(&self) -> StreamId {
+        self.last_stream_id
+    }
+
+    #[inline]
+    pub fn error(&self) -> ErrorKind {
+        self.error
+    }
+}
+
+impl Frame for GoAwayFrame {
+    fn from_reader<R: Read>(header: FrameHeader, mut reader: R) -> Result<GoAwayFrame> {
+        if header.stream_id != 0 {
+            return Err(Error::protocol("The stream identifier for a goaway frame must be zero"));
+        }
+        if header.payload_len < GOAWAY_MIN_PAYLOAD_LENGTH {
+            return Err(Error::frame_size(format!("Bad payload length '{:?}'! The payload \
+                                                  length for a goaway frame must be at least \
+                                                  8 octets",
+                                                 header.payload_len)));
+        }
+        let mut buf = [0; GOAWAY_MIN_PAYLOAD_LENGTH];
+        try!(reader.read_exact(&mut buf));
+        let mut debug_data = vec![0; header.payload_len - GOAWAY_MIN_PAYLOAD_LENGTH];
+        try!(reader.read_exact(&mut debug_data));
+        Ok(GoAwayFrame {
+            last_stream_id: BigEndian::read_u32(&buf).into(),
+            error: ErrorKind::from_code(BigEndian::read_u32(&buf[4..])),
+            debug_data: debug_data,
+        })
+    }
+
+    fn into_writer<W: Write>(self, mut writer: W) -> Result<()> {
+        let mut buf = [0; GOAWAY_MIN_PAYLOAD_LENGTH];
+        BigEndian::write_u32(&mut buf, self.last_stream_id.into());
+        BigEndian::write_u32(&mut buf[4..], self.error.code());
+        try!(writer.write_all(&buf));
+        try!(writer.write_all(self.debug_data.as_ref()));
+        Ok(())
+    }
+
+    fn payload_len(&self) -> usize {
+        GOAWAY_MIN_PAYLOAD_LENGTH + self.debug_data.len()
+    }
+
+    fn frame_type(&self) -> FrameType {
+        TYPE_GOAWAY
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GoAwayFrame;
+    use StreamId;
+    use frame::{ReadFrame, WriteFrame, FrameKind};
+    use error::ErrorKind;
+
+    #[test]
+    fn test_goaway_frame() {
+        let frame = GoAwayFrame::new(StreamId(3), ErrorKind::Protocol).debug_data("bye");
+        let mut b = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        let expected = vec![0, 0, 11,   // length
+                            7,          // type
+                            0,          // flags
+                            0, 0, 0, 0, // stream id
+                            0, 0, 0, 3, // last stream id
+                            0, 0, 0, 1, // error code
+                            98, 121, 101, // debug data
+                           ];
+        assert_eq!(b, expected);
+        let mut sl = &b[..];
+        let res = match sl.read_frame().unwrap() {
+            FrameKind::GoAway(frame) => frame,
+            _ => panic!("Wrong frame type"),
+        };
+        assert_eq!(frame, res);
+    }
+}
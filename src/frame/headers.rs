@@ -3,6 +3,7 @@ use StreamId;
 use frame::{Frame, FrameHeader, FrameType, Flags, FLAG_PADDED, FLAG_PRIORITY, FLAG_END_HEADERS,
             FLAG_END_STREAM};
 use frame::priority::{PriorityFrame, PRIORITY_PAYLOAD_LENGTH};
+use frame::padding::{self, Pad};
 use error::{Error, Result};
 
 pub const TYPE_HEADERS: FrameType = 0x1;
@@ -12,6 +13,7 @@ pub struct HeadersFrame {
     stream_id: StreamId,
     fragment: Vec<u8>,
     priority: Option<PriorityFrame>,
+    pad_len: Option<u8>,
     end_headers: bool,
     end_stream: bool,
 }
@@ -22,79 +24,170 @@ impl HeadersFrame {
             stream_id: stream_id,
             fragment: Vec::new(),
             priority: None,
+            pad_len: None,
             end_headers: false,
             end_stream: false,
         }
     }
 
-    fn priority(mut self, priority: PriorityFrame) -> Self {
+    pub fn priority(mut self, priority: PriorityFrame) -> Self {
         self.priority = Some(priority);
         self
     }
 
-    fn fragment<T: Into<Vec<u8>>>(mut self, fragment: T) -> Self {
+    pub fn fragment<T: Into<Vec<u8>>>(mut self, fragment: T) -> Self {
         self.fragment = fragment.into();
         self
     }
 
-    fn end_headers(mut self) -> Self {
+    pub fn end_headers(mut self) -> Self {
         self.end_headers = true;
         self
     }
 
-    fn end_stream(mut self) -> Self {
+    pub fn end_stream(mut self) -> Self {
         self.end_stream = true;
         self
     }
+
+    #[inline]
+    pub fn is_end_headers(&self) -> bool {
+        self.end_headers
+    }
+
+    #[inline]
+    pub fn is_end_stream(&self) -> bool {
+        self.end_stream
+    }
+
+    /// Takes the header block fragment.
+    pub fn into_fragment(self) -> Vec<u8> {
+        self.fragment
+    }
 }
 
-impl Frame for HeadersFrame {
-    fn from_reader<R: Read>(header: FrameHeader, mut reader: R) -> Result<HeadersFrame> {
-        if header.stream_id == 0 {
-            return Err(Error::protocol("Headers frame must be associated with a stream, stream \
-                                        id was zero"));
-        }
+/// A headers frame borrowing its fragment from the receive buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadersFrameRef<'a> {
+    stream_id: StreamId,
+    fragment: &'a [u8],
+    priority: Option<PriorityFrame>,
+    pad_len: Option<u8>,
+    end_headers: bool,
+    end_stream: bool,
+}
 
-        let mut payload_len = header.payload_len;
+impl<'a> HeadersFrameRef<'a> {
+    /// Parses the frame from its complete payload.
+    pub fn from_slice(header: FrameHeader, mut payload: &'a [u8]) -> Result<HeadersFrameRef<'a>> {
+        try!(header.check_payload(payload));
+        let (pad_len, priority, len) = try!(read_prelude(&header, &mut payload));
+        Ok(HeadersFrameRef {
+            stream_id: header.stream_id,
+            fragment: &payload[..len],
+            priority: priority,
+            pad_len: pad_len,
+            end_headers: header.flags.contains(FLAG_END_HEADERS),
+            end_stream: header.flags.contains(FLAG_END_STREAM),
+        })
+    }
+
+    #[inline]
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    #[inline]
+    pub fn fragment(&self) -> &'a [u8] {
+        self.fragment
+    }
+
+    #[inline]
+    pub fn is_end_headers(&self) -> bool {
+        self.end_headers
+    }
+
+    #[inline]
+    pub fn is_end_stream(&self) -> bool {
+        self.end_stream
+    }
 
-        let mut pad_len = 0;
-        if header.flags.contains(FLAG_PADDED) {
-            let mut buf = [0; 1];
-            try!(reader.read_exact(&mut buf));
-            pad_len = buf[0] as usize;
-            payload_len -= pad_len + 1;
+    pub fn into_owned(self) -> HeadersFrame {
+        HeadersFrame {
+            stream_id: self.stream_id,
+            fragment: self.fragment.to_vec(),
+            priority: self.priority,
+            pad_len: self.pad_len,
+            end_headers: self.end_headers,
+            end_stream: self.end_stream,
         }
+    }
+}
 
-        let mut priority = None;
-        if header.flags.contains(FLAG_PRIORITY) {
-            priority = Some(try!(PriorityFrame::from_reader(header.clone(), reader.by_ref())));
-            payload_len -= PRIORITY_PAYLOAD_LENGTH;
+/// Reads the pad length and priority fields preceding the fragment.
+///
+/// Returns the pad length, the priority and the length of the fragment.
+fn read_prelude<R: Read>(header: &FrameHeader,
+                         mut reader: R)
+                         -> Result<(Option<u8>, Option<PriorityFrame>, usize)> {
+    if header.stream_id == 0 {
+        return Err(Error::protocol("Headers frame must be associated with a stream, stream id \
+                                    was zero"));
+    }
+
+    let (pad_len, mut payload_len) = try!(padding::read_pad_len(header, reader.by_ref()));
+
+    let mut priority = None;
+    if header.flags.contains(FLAG_PRIORITY) {
+        if payload_len < PRIORITY_PAYLOAD_LENGTH {
+            return Err(padding::too_short(header, "Headers frame is too short to contain the \
+                                                   priority fields"));
         }
+        // the priority fields are read like a priority frame of their own
+        let priority_header = FrameHeader {
+            payload_len: PRIORITY_PAYLOAD_LENGTH,
+            ..header.clone()
+        };
+        priority = Some(try!(PriorityFrame::from_reader(priority_header, reader.by_ref())));
+        payload_len -= PRIORITY_PAYLOAD_LENGTH;
+    }
+    Ok((pad_len, priority, payload_len))
+}
+
+impl Pad for HeadersFrame {
+    fn pad(mut self, pad_len: u8) -> Self {
+        self.pad_len = Some(pad_len);
+        self
+    }
+}
+
+impl Frame for HeadersFrame {
+    fn from_reader<R: Read>(header: FrameHeader, mut reader: R) -> Result<HeadersFrame> {
+        let (pad_len, priority, payload_len) = try!(read_prelude(&header, reader.by_ref()));
 
         let mut fragment = vec![0; payload_len];
         try!(reader.read_exact(&mut fragment));
 
         // read, discard padding
-        if header.flags.contains(FLAG_PADDED) {
-            let mut padding = vec![0; pad_len];
-            try!(reader.read_exact(&mut padding));
-        }
+        try!(padding::skip_padding(pad_len, reader.by_ref()));
 
         Ok(HeadersFrame {
             stream_id: header.stream_id,
             fragment: fragment,
             priority: priority,
+            pad_len: pad_len,
             end_headers: header.flags.contains(FLAG_END_HEADERS),
             end_stream: header.flags.contains(FLAG_END_STREAM),
         })
     }
 
     fn into_writer<W: Write>(self, mut writer: W) -> Result<()> {
-        // TODO padding
+        try!(padding::write_pad_len(self.pad_len, writer.by_ref()));
         if let Some(priority) = self.priority {
             try!(priority.into_writer(writer.by_ref()));
         }
         try!(writer.write_all(self.fragment.as_ref()));
+        try!(padding::write_padding(self.pad_len, writer.by_ref()));
         Ok(())
     }
 
@@ -103,7 +196,7 @@ impl Frame for HeadersFrame {
         if let Some(_) = self.priority {
             len += PRIORITY_PAYLOAD_LENGTH;
         }
-        len
+        len + padding::padded_len(self.pad_len)
     }
 
     fn frame_type(&self) -> FrameType {
@@ -121,6 +214,9 @@ impl Frame for HeadersFrame {
         if let Some(_) = self.priority {
             flags.insert(FLAG_PRIORITY);
         }
+        if self.pad_len.is_some() {
+            flags.insert(FLAG_PADDED);
+        }
         flags
     }
 
@@ -136,6 +232,7 @@ mod test {
     use StreamId;
     use frame::{ReadFrame, WriteFrame, FrameKind};
     use frame::priority::PriorityFrame;
+    use frame::padding::Pad;
 
     #[test]
     fn test_empty_headers_frame() {
@@ -234,4 +331,33 @@ mod test {
         sl.read_exact(&mut buf).unwrap();
         assert_eq!(buf, [4, 4, 4, 4]);
     }
+
+    #[test]
+    fn test_write_padded_headers_frame() {
+        let priority = PriorityFrame::new(StreamId(1));
+        let frame = HeadersFrame::new(StreamId(1))
+            .priority(priority)
+            .fragment(vec![0, 1])
+            .end_headers()
+            .pad(3);
+        let mut b = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        let expected = vec![0, 0, 11,   // length
+                            1,          // type
+                            0x2C,       // flags
+                            0, 0, 0, 1, // stream id
+                            3,          // padding length
+                            0, 0, 0, 0, // dependency
+                            15,         // weight
+                            0, 1,       // fragment
+                            0, 0, 0,    // padding
+                           ];
+        assert_eq!(b, expected);
+        let mut sl = &b[..];
+        let res = match sl.read_frame().unwrap() {
+            FrameKind::Headers(frame) => frame,
+            _ => panic!("Wrong frame type"),
+        };
+        assert_eq!(frame, res);
+    }
 }
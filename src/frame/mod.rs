@@ -1,16 +1,36 @@
+pub mod continuation;
+pub mod data;
+pub mod encoder;
+pub mod goaway;
 pub mod headers;
+pub mod padding;
+pub mod ping;
 pub mod priority;
+pub mod push_promise;
+pub mod rst_stream;
 pub mod settings;
 pub mod unknown;
+pub mod validate;
+pub mod window_update;
+#[cfg(test)]
+mod arbitrary;
 
 use std::io::{Read, Write};
 use byteorder::{ByteOrder, BigEndian};
 use error::{Error, ErrorKind, Result};
 use super::StreamId;
+use self::data::{DataFrame, DataFrameRef, TYPE_DATA};
 use self::settings::{SettingsFrame, TYPE_SETTINGS};
-use self::headers::{HeadersFrame, TYPE_HEADERS};
+use self::headers::{HeadersFrame, HeadersFrameRef, TYPE_HEADERS};
 use self::priority::{PriorityFrame, TYPE_PRIORITY};
+use self::rst_stream::{RstStreamFrame, TYPE_RST_STREAM};
+use self::push_promise::{PushPromiseFrame, PushPromiseFrameRef, TYPE_PUSH_PROMISE};
+use self::ping::{PingFrame, TYPE_PING};
+use self::goaway::{GoAwayFrame, TYPE_GOAWAY};
+use self::window_update::{WindowUpdateFrame, TYPE_WINDOW_UPDATE};
+use self::continuation::{ContinuationFrame, ContinuationFrameRef, TYPE_CONTINUATION};
 use self::unknown::UnknownFrame;
+use self::validate::validate_header;
 
 pub type FrameType = u8;
 
@@ -39,23 +59,98 @@ pub trait Frame: Sized {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FrameKind {
-    // Data,
+    Data(DataFrame),
     Headers(HeadersFrame),
     Priority(PriorityFrame),
-    // RstConn,
+    RstStream(RstStreamFrame),
     Settings(SettingsFrame),
-    // PushPromise,
-    // Ping,
-    // GoAway,
-    // WindowUpdate,
-    // Continuation,
+    PushPromise(PushPromiseFrame),
+    Ping(PingFrame),
+    GoAway(GoAwayFrame),
+    WindowUpdate(WindowUpdateFrame),
+    Continuation(ContinuationFrame),
     // TODO remove 'Unknown', discard unknown frames or
     // better return Unknown Frame with raw payload
     Unknown(UnknownFrame),
 }
 
+impl FrameKind {
+    /// Parses the payload of the frame described by `header`.
+    pub fn from_reader<R: Read>(header: FrameHeader, reader: R) -> Result<FrameKind> {
+        match header.frame_type {
+            TYPE_DATA => Ok(FrameKind::Data(try!(DataFrame::from_reader(header, reader)))),
+            TYPE_HEADERS => Ok(FrameKind::Headers(try!(HeadersFrame::from_reader(header, reader)))),
+            TYPE_SETTINGS => {
+                Ok(FrameKind::Settings(try!(SettingsFrame::from_reader(header, reader))))
+            }
+            TYPE_PRIORITY => {
+                Ok(FrameKind::Priority(try!(PriorityFrame::from_reader(header, reader))))
+            }
+            TYPE_RST_STREAM => {
+                Ok(FrameKind::RstStream(try!(RstStreamFrame::from_reader(header, reader))))
+            }
+            TYPE_PUSH_PROMISE => {
+                Ok(FrameKind::PushPromise(try!(PushPromiseFrame::from_reader(header, reader))))
+            }
+            TYPE_PING => Ok(FrameKind::Ping(try!(PingFrame::from_reader(header, reader)))),
+            TYPE_GOAWAY => Ok(FrameKind::GoAway(try!(GoAwayFrame::from_reader(header, reader)))),
+            TYPE_WINDOW_UPDATE => {
+                Ok(FrameKind::WindowUpdate(try!(WindowUpdateFrame::from_reader(header, reader))))
+            }
+            TYPE_CONTINUATION => {
+                Ok(FrameKind::Continuation(try!(ContinuationFrame::from_reader(header, reader))))
+            }
+            _ => Ok(FrameKind::Unknown(try!(UnknownFrame::from_reader(header, reader)))),
+        }
+    }
+}
+
+/// A frame borrowing its payload from the buffer it was parsed from.
+///
+/// Only frames carrying a variable sized block of data are borrowed, all other
+/// frames are small and parsed into their owned form.
+#[derive(Debug)]
+pub enum FrameRef<'a> {
+    Data(DataFrameRef<'a>),
+    Headers(HeadersFrameRef<'a>),
+    PushPromise(PushPromiseFrameRef<'a>),
+    Continuation(ContinuationFrameRef<'a>),
+    Control(FrameKind),
+}
+
+impl<'a> FrameRef<'a> {
+    /// Parses the frame described by `header` from its complete payload.
+    pub fn from_slice(header: FrameHeader, payload: &'a [u8]) -> Result<FrameRef<'a>> {
+        match header.frame_type {
+            TYPE_DATA => Ok(FrameRef::Data(try!(DataFrameRef::from_slice(header, payload)))),
+            TYPE_HEADERS => {
+                Ok(FrameRef::Headers(try!(HeadersFrameRef::from_slice(header, payload))))
+            }
+            TYPE_PUSH_PROMISE => {
+                Ok(FrameRef::PushPromise(try!(PushPromiseFrameRef::from_slice(header, payload))))
+            }
+            TYPE_CONTINUATION => {
+                Ok(FrameRef::Continuation(try!(ContinuationFrameRef::from_slice(header,
+                                                                                 payload))))
+            }
+            _ => Ok(FrameRef::Control(try!(FrameKind::from_reader(header, payload)))),
+        }
+    }
+
+    /// Copies the borrowed payload into an owned frame.
+    pub fn into_owned(self) -> FrameKind {
+        match self {
+            FrameRef::Data(frame) => FrameKind::Data(frame.into_owned()),
+            FrameRef::Headers(frame) => FrameKind::Headers(frame.into_owned()),
+            FrameRef::PushPromise(frame) => FrameKind::PushPromise(frame.into_owned()),
+            FrameRef::Continuation(frame) => FrameKind::Continuation(frame.into_owned()),
+            FrameRef::Control(frame) => frame,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct FrameHeader {
     payload_len: usize,
@@ -85,6 +180,17 @@ impl FrameHeader {
         })
     }
 
+    /// Fails unless `payload` is exactly the payload the header announces,
+    /// frames borrowing from it slice it by the announced length.
+    fn check_payload(&self, payload: &[u8]) -> Result<()> {
+        if payload.len() != self.payload_len {
+            return Err(Error::frame_size(format!("payload of {} octets, the header announces {}",
+                                                 payload.len(),
+                                                 self.payload_len)));
+        }
+        Ok(())
+    }
+
     fn into_writer<W: Write>(self, mut writer: W) -> Result<()> {
         let mut buf = [0; HEADER_SIZE];
         BigEndian::write_uint(&mut buf, self.payload_len as u64, 3);
@@ -104,26 +210,17 @@ pub trait ReadFrame: Read + Sized {
     fn read_frame_checked(&mut self, max_size: usize) -> Result<FrameKind> {
         // TODO use Read::take()
         let header = try!(FrameHeader::from_reader(self.by_ref()));
-        if header.payload_len > max_size {
-            return Err(Error::new(ErrorKind::FrameSize,
-                                  "payload length exceeds max frame size setting"));
-        }
-        match header.frame_type {
-            TYPE_HEADERS => Ok(FrameKind::Headers(try!(HeadersFrame::from_reader(header, self)))),
-            TYPE_SETTINGS => {
-                Ok(FrameKind::Settings(try!(SettingsFrame::from_reader(header, self))))
-            }
-            TYPE_PRIORITY => {
-                Ok(FrameKind::Priority(try!(PriorityFrame::from_reader(header, self))))
-            }
-            _ => Ok(FrameKind::Unknown(try!(UnknownFrame::from_reader(header, self)))),
-        }
+        try!(validate_header(&header, max_size));
+        FrameKind::from_reader(header, self)
     }
 }
 
 /// All types implementing `io::Read` get `ReadFrame` by using the trait.
 impl<R: Read> ReadFrame for R {}
 
+/// Writes single frames, each with two `write_all` calls.
+///
+/// To write many frames with few syscalls use the `encoder::FrameEncoder`.
 pub trait WriteFrame: Write + Sized {
     fn write_frame<F: Frame>(&mut self, frame: F) -> Result<()> {
         try!(FrameHeader::new(&frame).into_writer(self.by_ref()));
@@ -153,33 +250,92 @@ impl<'a> FrameIter<'a> {
     fn len(&self) -> usize {
         self.buf.len() - self.pos
     }
+
+    /// The number of bytes of the frames yielded so far.
+    ///
+    /// Bytes of a trailing partial frame are not included, they are the first
+    /// bytes to parse once more data is available.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
 }
 
-impl<'a> Iterator for FrameIter<'a> {
-    type Item = Result<FrameKind>;
+impl<'a> FrameIter<'a> {
+    /// Returns the next frame borrowing its payload from the underlying slice.
+    pub fn next_ref(&mut self) -> Option<Result<FrameRef<'a>>> {
+        match self.next_slice() {
+            Some(Ok((header, payload))) => Some(FrameRef::from_slice(header, payload)),
+            Some(Err(e)) => Some(Err(e)),
+            None => None,
+        }
+    }
 
-    fn next(&mut self) -> Option<Result<FrameKind>> {
+    /// Turns the iterator into one yielding borrowed frames.
+    pub fn into_refs(self) -> FrameRefIter<'a> {
+        FrameRefIter { inner: self }
+    }
+
+    /// Splits the next complete frame off the slice, returning its validated
+    /// header and its payload.
+    fn next_slice(&mut self) -> Option<Result<(FrameHeader, &'a [u8])>> {
         if self.len() < 3 {
             return None;
         }
-        let mut buf = &self.buf[self.pos..];
-        let payload_len = BigEndian::read_uint(&buf[..4], 3) as usize;
+        let buf = &self.buf[self.pos..];
+        // reject oversized frames as soon as the length is known
+        let payload_len = BigEndian::read_uint(&buf[..3], 3) as usize;
         if payload_len > self.max_payload {
             return Some(Err(Error::new(ErrorKind::FrameSize,
                                        "payload length exceeds max frame size setting")));
         }
+        if self.len() < HEADER_SIZE {
+            return None;
+        }
+        let header = match FrameHeader::from_reader(&buf[..HEADER_SIZE]) {
+            Ok(header) => header,
+            Err(e) => return Some(Err(e)),
+        };
+        if let Err(e) = validate_header(&header, self.max_payload) {
+            return Some(Err(e));
+        }
         let size = payload_len + HEADER_SIZE;
         if self.len() < size {
             return None;
         }
         self.pos += size;
-        Some(buf.read_frame())
+        // restrict the payload to this frame, a parser must not read into the next one
+        Some(Ok((header, &buf[HEADER_SIZE..size])))
+    }
+}
+
+impl<'a> Iterator for FrameIter<'a> {
+    type Item = Result<FrameKind>;
+
+    fn next(&mut self) -> Option<Result<FrameKind>> {
+        match self.next_slice() {
+            Some(Ok((header, payload))) => Some(FrameKind::from_reader(header, payload)),
+            Some(Err(e)) => Some(Err(e)),
+            None => None,
+        }
+    }
+}
+
+/// Iterate over a slice of bytes yielding Frames which borrow from the slice
+pub struct FrameRefIter<'a> {
+    inner: FrameIter<'a>,
+}
+
+impl<'a> Iterator for FrameRefIter<'a> {
+    type Item = Result<FrameRef<'a>>;
+
+    fn next(&mut self) -> Option<Result<FrameRef<'a>>> {
+        self.inner.next_ref()
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::FrameIter;
+    use super::{FrameHeader, FrameIter, FrameRef};
     use frame::{FrameKind, Frame};
     use error::ErrorKind;
 
@@ -239,4 +395,78 @@ mod test {
         assert_eq!(FrameIter::new(&[0, 0, 210, 1], 100).next().unwrap().err().unwrap().kind(),
                    ErrorKind::FrameSize);
     }
+
+    #[test]
+    fn test_iter_borrowed_frames() {
+        let f = vec![0, 0, 4,     // length
+                     1,           // type headers
+                     4,           // flags
+                     0, 0, 0, 1,  // stream id
+                     0, 1, 2, 3,  // fragment
+                                  // ---
+                     0, 0, 6,     // length
+                     0,           // type data
+                     9,           // flags
+                     0, 0, 0, 1,  // stream id
+                     1,           // padding length
+                     3, 2, 1, 0,  // data
+                     0,           // padding
+                                  // ---
+                     0, 0, 0,     // length
+                     4,           // type settings
+                     1,           // flags
+                     0, 0, 0, 0,  // stream id
+                    ];
+        let mut iter = FrameIter::new(&f, 100).into_refs();
+        let headers = match iter.next().unwrap().unwrap() {
+            FrameRef::Headers(frame) => frame,
+            _ => panic!("Wrong frame"),
+        };
+        assert_eq!(headers.fragment(), [0, 1, 2, 3]);
+        // the fragment points into the buffer
+        assert_eq!(headers.fragment().as_ptr(), f[9..].as_ptr());
+        let data = match iter.next().unwrap().unwrap() {
+            FrameRef::Data(frame) => frame,
+            _ => panic!("Wrong frame"),
+        };
+        assert_eq!(data.payload(), [3, 2, 1, 0]);
+        assert_eq!(data.payload().as_ptr(), f[23..].as_ptr());
+        assert_eq!(data.flow_len(), 6);
+        assert!(data.is_end_stream());
+        match iter.next().unwrap().unwrap() {
+            FrameRef::Control(FrameKind::Settings(frame)) => assert!(frame.is_ack()),
+            _ => panic!("Wrong frame"),
+        };
+        assert!(iter.next().is_none());
+    }
+
+    /// A payload shorter or longer than announced fails instead of
+    /// slicing out of bounds.
+    #[test]
+    fn test_borrowed_payload_len_mismatch() {
+        for &frame_type in &[0, 1, 5, 9] {
+            let header = FrameHeader::from_reader(&[0, 0, 8, frame_type, 0, 0, 0, 0, 1][..])
+                .unwrap();
+            for payload in &[&[0; 4][..], &[0; 12][..]] {
+                let error = FrameRef::from_slice(header.clone(), payload).unwrap_err();
+                assert_eq!(error.kind(), ErrorKind::FrameSize);
+            }
+        }
+    }
+
+    #[test]
+    fn test_borrowed_into_owned() {
+        let f = vec![0, 0, 4,     // length
+                     1,           // type headers
+                     0,           // flags
+                     0, 0, 0, 1,  // stream id
+                     0, 1, 2, 3,  // fragment
+                    ];
+        let borrowed = FrameIter::new(&f, 100).next_ref().unwrap().unwrap().into_owned();
+        let owned = FrameIter::new(&f, 100).next().unwrap().unwrap();
+        match (borrowed, owned) {
+            (FrameKind::Headers(a), FrameKind::Headers(b)) => assert_eq!(a, b),
+            _ => panic!("Wrong frame"),
+        }
+    }
 }
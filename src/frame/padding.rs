@@ -0,0 +1,171 @@
+use std::cmp;
+use std::io::{Read, Write};
+use rand::{self, Rng};
+use frame::{Frame, FrameHeader, FLAG_PADDED};
+use error::{Error, Result};
+
+/// Largest amount of padding a single frame can carry, the pad length is one octet.
+pub const MAX_PADDING: usize = 255;
+
+/// Frames which may be padded according to rfc 6.1, 6.2 and 6.6
+/// (DATA, HEADERS and PUSH_PROMISE).
+pub trait Pad: Frame {
+    /// Add `pad_len` octets of padding, sets the PADDED flag.
+    fn pad(self, pad_len: u8) -> Self;
+}
+
+/// Connection-wide policy deciding how much padding is added to outgoing
+/// DATA, HEADERS and PUSH_PROMISE frames.
+///
+/// Padding hides the exact size of a message from an observer of the
+/// encrypted connection, at the cost of bandwidth.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaddingPolicy {
+    /// Never pad frames.
+    None,
+    /// Always add the given number of octets.
+    Fixed(u8),
+    /// Add a random amount of padding between zero and the given number of octets.
+    Random(u8),
+    /// Pad the payload up to the next multiple of the block size.
+    Block(u8),
+}
+
+impl PaddingPolicy {
+    /// Returns the pad length for a frame with an unpadded payload of `payload_len`,
+    /// or `None` if the frame should not be padded.
+    ///
+    /// The padded payload never exceeds `max_payload`, if not even the pad length
+    /// octet fits the frame is left unpadded.
+    pub fn pad_len(&self, payload_len: usize, max_payload: usize) -> Option<u8> {
+        let room = match max_payload.checked_sub(payload_len + 1) {
+            Some(room) => cmp::min(room, MAX_PADDING),
+            None => return None,
+        };
+        let pad_len = match *self {
+            PaddingPolicy::None => return None,
+            PaddingPolicy::Fixed(n) => n as usize,
+            PaddingPolicy::Random(n) => rand::thread_rng().gen_range(0, n as usize + 1),
+            PaddingPolicy::Block(0) => 0,
+            PaddingPolicy::Block(n) => {
+                let n = n as usize;
+                // the pad length octet counts towards the padded size
+                (n - (payload_len + 1) % n) % n
+            }
+        };
+        Some(cmp::min(pad_len, room) as u8)
+    }
+
+    /// Pads the frame according to the policy.
+    pub fn apply<F: Pad>(&self, frame: F, max_payload: usize) -> F {
+        match self.pad_len(frame.payload_len(), max_payload) {
+            Some(pad_len) => frame.pad(pad_len),
+            None => frame,
+        }
+    }
+}
+
+impl Default for PaddingPolicy {
+    fn default() -> Self {
+        PaddingPolicy::None
+    }
+}
+
+/// Reads the pad length octet of a padded frame, if the PADDED flag is set.
+///
+/// Returns the pad length and the length of the remaining payload without padding.
+pub fn read_pad_len<R: Read>(header: &FrameHeader, mut reader: R) -> Result<(Option<u8>, usize)> {
+    if !header.flags.contains(FLAG_PADDED) {
+        return Ok((None, header.payload_len));
+    }
+    if header.payload_len < 1 {
+        return Err(Error::frame_size("Padded frame is too short to contain the pad length"));
+    }
+    let mut buf = [0; 1];
+    try!(reader.read_exact(&mut buf));
+    let pad_len = buf[0] as usize;
+    if pad_len > header.payload_len - 1 {
+        return Err(Error::protocol(format!("Pad length '{}' exceeds the frame payload length",
+                                           pad_len)));
+    }
+    Ok((Some(buf[0]), header.payload_len - 1 - pad_len))
+}
+
+/// The error for a frame which is too short to contain its mandatory fields.
+///
+/// Without padding this is a frame size error (rfc 4.2), if the padding
+/// consumed the space it is a protocol error (rfc 6.1).
+pub fn too_short(header: &FrameHeader, msg: &'static str) -> Error {
+    if header.flags.contains(FLAG_PADDED) {
+        Error::protocol(msg)
+    } else {
+        Error::frame_size(msg)
+    }
+}
+
+/// Reads and discards `pad_len` octets of padding.
+pub fn skip_padding<R: Read>(pad_len: Option<u8>, mut reader: R) -> Result<()> {
+    if let Some(pad_len) = pad_len {
+        let mut padding = [0; MAX_PADDING];
+        try!(reader.read_exact(&mut padding[..pad_len as usize]));
+    }
+    Ok(())
+}
+
+/// Writes the pad length octet, if the frame is padded.
+pub fn write_pad_len<W: Write>(pad_len: Option<u8>, mut writer: W) -> Result<()> {
+    if let Some(pad_len) = pad_len {
+        try!(writer.write_all(&[pad_len]));
+    }
+    Ok(())
+}
+
+/// Writes `pad_len` octets of padding, which must be zero (rfc 6.1).
+pub fn write_padding<W: Write>(pad_len: Option<u8>, mut writer: W) -> Result<()> {
+    if let Some(pad_len) = pad_len {
+        let padding = [0; MAX_PADDING];
+        try!(writer.write_all(&padding[..pad_len as usize]));
+    }
+    Ok(())
+}
+
+/// Number of payload octets the padding adds to a frame.
+#[inline]
+pub fn padded_len(pad_len: Option<u8>) -> usize {
+    pad_len.map_or(0, |n| n as usize + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::PaddingPolicy;
+
+    #[test]
+    fn test_no_padding() {
+        assert_eq!(PaddingPolicy::None.pad_len(10, 100), None);
+    }
+
+    #[test]
+    fn test_fixed_padding() {
+        assert_eq!(PaddingPolicy::Fixed(8).pad_len(10, 100), Some(8));
+        // limited by max payload
+        assert_eq!(PaddingPolicy::Fixed(8).pad_len(10, 15), Some(4));
+        // no room for the pad length octet
+        assert_eq!(PaddingPolicy::Fixed(8).pad_len(10, 10), None);
+    }
+
+    #[test]
+    fn test_random_padding() {
+        for _ in 0..100 {
+            assert!(PaddingPolicy::Random(16).pad_len(10, 100).unwrap() <= 16);
+        }
+        assert_eq!(PaddingPolicy::Random(0).pad_len(10, 100), Some(0));
+    }
+
+    #[test]
+    fn test_block_padding() {
+        assert_eq!(PaddingPolicy::Block(16).pad_len(10, 100), Some(5));
+        assert_eq!(PaddingPolicy::Block(16).pad_len(15, 100), Some(0));
+        assert_eq!(PaddingPolicy::Block(16).pad_len(16, 100), Some(15));
+        assert_eq!(PaddingPolicy::Block(0).pad_len(16, 100), Some(0));
+    }
+}
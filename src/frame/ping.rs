@@ -0,0 +1,107 @@
+use std::io::{Read, Write};
+use frame::{Frame, FrameHeader, FrameType, Flags, FLAG_ACK};
+use error::{Error, Result};
+
+pub const TYPE_PING: FrameType = 0x6;
+
+pub const PING_PAYLOAD_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PingFrame {
+    data: [u8; PING_PAYLOAD_LENGTH],
+    ack: bool,
+}
+
+impl PingFrame {
+    pub fn new(data: [u8; PING_PAYLOAD_LENGTH]) -> Self {
+        PingFrame {
+            data: data,
+            ack: false,
+        }
+    }
+
+    /// The response to a ping, carrying the same opaque data.
+    pub fn pong(data: [u8; PING_PAYLOAD_LENGTH]) -> Self {
+        PingFrame {
+            data: data,
+            ack: true,
+        }
+    }
+
+    #[inline]
+    pub fn data(&self) -> [u8; PING_PAYLOAD_LENGTH] {
+        self.data
+    }
+
+    #[inline]
+    pub fn is_ack(&self) -> bool {
+        self.ack
+    }
+}
+
+impl Frame for PingFrame {
+    fn from_reader<R: Read>(header: FrameHeader, mut reader: R) -> Result<PingFrame> {
+        if header.stream_id != 0 {
+            return Err(Error::protocol("The stream identifier for a ping frame must be zero"));
+        }
+        if header.payload_len != PING_PAYLOAD_LENGTH {
+            return Err(Error::frame_size(format!("Bad payload length '{:?}'! The payload \
+                                                  length for a ping frame must be 8 octets",
+                                                 header.payload_len)));
+        }
+        let mut data = [0; PING_PAYLOAD_LENGTH];
+        try!(reader.read_exact(&mut data));
+        Ok(PingFrame {
+            data: data,
+            ack: header.flags.contains(FLAG_ACK),
+        })
+    }
+
+    fn into_writer<W: Write>(self, mut writer: W) -> Result<()> {
+        try!(writer.write_all(&self.data));
+        Ok(())
+    }
+
+    fn payload_len(&self) -> usize {
+        PING_PAYLOAD_LENGTH
+    }
+
+    fn frame_type(&self) -> FrameType {
+        TYPE_PING
+    }
+
+    fn flags(&self) -> Flags {
+        if self.ack {
+            FLAG_ACK
+        } else {
+            Flags::empty()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PingFrame;
+    use frame::{ReadFrame, WriteFrame, FrameKind};
+
+    #[test]
+    fn test_ping_frame() {
+        let frame = PingFrame::pong([1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut b = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        let expected = vec![0, 0, 8,    // length
+                            6,          // type
+                            1,          // flags
+                            0, 0, 0, 0, // stream id
+                            1, 2, 3, 4, 5, 6, 7, 8, // opaque data
+                           ];
+        assert_eq!(b, expected);
+        let mut sl = &b[..];
+        let res = match sl.read_frame().unwrap() {
+            FrameKind::Ping(frame) => frame,
+            _ => panic!("Wrong frame type"),
+        };
+        assert_eq!(frame, res);
+        assert!(res.is_ack());
+    }
+}
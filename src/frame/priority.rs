@@ -1,3 +1,4 @@
+use std::cmp;
 use std::io::{Read, Write};
 use byteorder::{ByteOrder, BigEndian};
 use frame::{Frame, FrameHeader, FrameType};
@@ -7,14 +8,14 @@ use error::{Error, Result};
 pub const TYPE_PRIORITY: FrameType = 0x2;
 
 pub const PRIORITY_PAYLOAD_LENGTH: usize = 5;
-const DEFAULT_WEIGHT: u8 = 16;
+const DEFAULT_WEIGHT: u16 = 16;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct PriorityFrame {
     stream_id: StreamId,
     exclusive: bool,
     dependency: StreamId,
-    weight: u8,
+    weight: u16,
 }
 
 impl PriorityFrame {
@@ -27,12 +28,18 @@ impl PriorityFrame {
         }
     }
 
-    fn dependency(mut self, stream_id: StreamId) -> Self {
+    pub fn dependency(mut self, stream_id: StreamId) -> Self {
         self.dependency = stream_id;
         self
     }
 
-    fn exclusive(mut self) -> Self {
+    /// Set the weight, between 1 and 256. Values outside that range are clamped.
+    pub fn weight(mut self, weight: u16) -> Self {
+        self.weight = cmp::max(1, cmp::min(weight, 256));
+        self
+    }
+
+    pub fn exclusive(mut self) -> Self {
         self.exclusive = true;
         self
     }
@@ -56,24 +63,28 @@ impl Frame for PriorityFrame {
         let mut buf = [0; PRIORITY_PAYLOAD_LENGTH];
         try!(reader.read_exact(&mut buf));
         let dep = BigEndian::read_u32(&mut buf);
+        let dependency: StreamId = dep.into();
+        if dependency == header.stream_id {
+            return Err(Error::protocol("A stream cannot depend on itself"));
+        }
         // Add one to the value to obtain a weight between 1 and 256 (section 6.3)
-        let weight = buf[4] + 1;
+        let weight = buf[4] as u16 + 1;
         Ok(PriorityFrame {
             stream_id: header.stream_id,
             exclusive: dep & 0x80000000 != 0,
-            dependency: dep.into(),
+            dependency: dependency,
             weight: weight,
         })
     }
 
     fn into_writer<W: Write>(self, mut writer: W) -> Result<()> {
-        let mut buf = vec![0; PRIORITY_PAYLOAD_LENGTH];
+        let mut buf = [0; PRIORITY_PAYLOAD_LENGTH];
         let mut dep = self.dependency.into();
         if self.exclusive {
             dep = dep | 0x80000000;
         }
         BigEndian::write_u32(&mut buf, dep);
-        buf[4] = self.weight - 1;
+        buf[4] = (self.weight - 1) as u8;
         try!(writer.write_all(&buf));
         Ok(())
     }
@@ -132,6 +143,42 @@ mod test {
         };
     }
 
+    #[test]
+    fn test_max_weight() {
+        let frame = PriorityFrame::new(StreamId(1)).weight(256);
+        let mut b: Vec<u8> = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        assert_eq!(b[13], 255);
+        let mut sl = &b[..];
+        match sl.read_frame().unwrap() {
+            FrameKind::Priority(f) => assert_eq!(f.weight, 256),
+            _ => panic!("Wrong frame type"),
+        };
+    }
+
+    #[test]
+    fn test_weight_out_of_range() {
+        let frame = PriorityFrame::new(StreamId(1)).weight(0);
+        assert_eq!(frame.weight, 1);
+        let mut b: Vec<u8> = Vec::new();
+        b.write_frame(frame).unwrap();
+        assert_eq!(b[13], 0);
+
+        let frame = PriorityFrame::new(StreamId(1)).weight(257);
+        assert_eq!(frame.weight, 256);
+        let mut b: Vec<u8> = Vec::new();
+        b.write_frame(frame).unwrap();
+        assert_eq!(b[13], 255);
+    }
+
+    #[test]
+    fn test_error_self_dependency() {
+        let mut raw = Cursor::new([0, 0, 5 /* length */, 2 /* type */,
+                                   0 /* flags */, 0, 0, 0, 1 /* stream id */, 0, 0, 0,
+                                   1 /* dependency */, 15]);       // weight
+        assert_eq!(raw.read_frame().unwrap_err().kind(), ErrorKind::Protocol);
+    }
+
     #[test]
     fn test_error_zero_stream() {
         let mut raw = Cursor::new([0, 0, 5 /* length */, 2 /* type */,
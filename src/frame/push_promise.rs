@@ -0,0 +1,251 @@
+use std::io::{Read, Write};
+use byteorder::{ByteOrder, BigEndian};
+use StreamId;
+use frame::{Frame, FrameHeader, FrameType, Flags, FLAG_PADDED, FLAG_END_HEADERS};
+use frame::padding::{self, Pad};
+use error::{Error, Result};
+
+pub const TYPE_PUSH_PROMISE: FrameType = 0x5;
+
+// the promised stream id is a 31 bit identifier preceded by a reserved bit
+const PROMISED_ID_LENGTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushPromiseFrame {
+    stream_id: StreamId,
+    promised_stream_id: StreamId,
+    fragment: Vec<u8>,
+    pad_len: Option<u8>,
+    end_headers: bool,
+}
+
+impl PushPromiseFrame {
+    pub fn new(stream_id: StreamId, promised_stream_id: StreamId) -> Self {
+        PushPromiseFrame {
+            stream_id: stream_id,
+            promised_stream_id: promised_stream_id,
+            fragment: Vec::new(),
+            pad_len: None,
+            end_headers: false,
+        }
+    }
+
+    pub fn fragment<T: Into<Vec<u8>>>(mut self, fragment: T) -> Self {
+        self.fragment = fragment.into();
+        self
+    }
+
+    pub fn end_headers(mut self) -> Self {
+        self.end_headers = true;
+        self
+    }
+
+    #[inline]
+    pub fn promised_stream_id(&self) -> StreamId {
+        self.promised_stream_id
+    }
+
+    #[inline]
+    pub fn is_end_headers(&self) -> bool {
+        self.end_headers
+    }
+
+    /// Takes the header block fragment.
+    pub fn into_fragment(self) -> Vec<u8> {
+        self.fragment
+    }
+}
+
+/// A push promise frame borrowing its fragment from the receive buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushPromiseFrameRef<'a> {
+    stream_id: StreamId,
+    promised_stream_id: StreamId,
+    fragment: &'a [u8],
+    pad_len: Option<u8>,
+    end_headers: bool,
+}
+
+impl<'a> PushPromiseFrameRef<'a> {
+    /// Parses the frame from its complete payload.
+    pub fn from_slice(header: FrameHeader,
+                      mut payload: &'a [u8])
+                      -> Result<PushPromiseFrameRef<'a>> {
+        try!(header.check_payload(payload));
+        let (pad_len, promised_stream_id, len) = try!(read_prelude(&header, &mut payload));
+        Ok(PushPromiseFrameRef {
+            stream_id: header.stream_id,
+            promised_stream_id: promised_stream_id,
+            fragment: &payload[..len],
+            pad_len: pad_len,
+            end_headers: header.flags.contains(FLAG_END_HEADERS),
+        })
+    }
+
+    #[inline]
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+
+    #[inline]
+    pub fn promised_stream_id(&self) -> StreamId {
+        self.promised_stream_id
+    }
+
+    #[inline]
+    pub fn fragment(&self) -> &'a [u8] {
+        self.fragment
+    }
+
+    #[inline]
+    pub fn is_end_headers(&self) -> bool {
+        self.end_headers
+    }
+
+    pub fn into_owned(self) -> PushPromiseFrame {
+        PushPromiseFrame {
+            stream_id: self.stream_id,
+            promised_stream_id: self.promised_stream_id,
+            fragment: self.fragment.to_vec(),
+            pad_len: self.pad_len,
+            end_headers: self.end_headers,
+        }
+    }
+}
+
+/// Reads the pad length and promised stream id preceding the fragment.
+///
+/// Returns the pad length, the promised stream id and the length of the fragment.
+fn read_prelude<R: Read>(header: &FrameHeader,
+                         mut reader: R)
+                         -> Result<(Option<u8>, StreamId, usize)> {
+    if header.stream_id == 0 {
+        return Err(Error::protocol("Push promise frame must be associated with a stream, stream \
+                                    id was zero"));
+    }
+    let (pad_len, payload_len) = try!(padding::read_pad_len(header, reader.by_ref()));
+    if payload_len < PROMISED_ID_LENGTH {
+        return Err(padding::too_short(header, "Push promise frame is too short to contain the \
+                                               promised stream id"));
+    }
+    let mut buf = [0; PROMISED_ID_LENGTH];
+    try!(reader.read_exact(&mut buf));
+    Ok((pad_len, BigEndian::read_u32(&buf).into(), payload_len - PROMISED_ID_LENGTH))
+}
+
+impl Pad for PushPromiseFrame {
+    fn pad(mut self, pad_len: u8) -> Self {
+        self.pad_len = Some(pad_len);
+        self
+    }
+}
+
+impl Frame for PushPromiseFrame {
+    fn from_reader<R: Read>(header: FrameHeader, mut reader: R) -> Result<PushPromiseFrame> {
+        let (pad_len, promised_stream_id, len) = try!(read_prelude(&header, reader.by_ref()));
+        let mut fragment = vec![0; len];
+        try!(reader.read_exact(&mut fragment));
+        try!(padding::skip_padding(pad_len, reader.by_ref()));
+        Ok(PushPromiseFrame {
+            stream_id: header.stream_id,
+            promised_stream_id: promised_stream_id,
+            fragment: fragment,
+            pad_len: pad_len,
+            end_headers: header.flags.contains(FLAG_END_HEADERS),
+        })
+    }
+
+    fn into_writer<W: Write>(self, mut writer: W) -> Result<()> {
+        try!(padding::write_pad_len(self.pad_len, writer.by_ref()));
+        let mut buf = [0; PROMISED_ID_LENGTH];
+        BigEndian::write_u32(&mut buf, self.promised_stream_id.into());
+        try!(writer.write_all(&buf));
+        try!(writer.write_all(self.fragment.as_ref()));
+        try!(padding::write_padding(self.pad_len, writer.by_ref()));
+        Ok(())
+    }
+
+    fn payload_len(&self) -> usize {
+        PROMISED_ID_LENGTH + self.fragment.len() + padding::padded_len(self.pad_len)
+    }
+
+    fn frame_type(&self) -> FrameType {
+        TYPE_PUSH_PROMISE
+    }
+
+    fn flags(&self) -> Flags {
+        let mut flags = Flags::empty();
+        if self.end_headers {
+            flags.insert(FLAG_END_HEADERS);
+        }
+        if self.pad_len.is_some() {
+            flags.insert(FLAG_PADDED);
+        }
+        flags
+    }
+
+    fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PushPromiseFrame;
+    use StreamId;
+    use frame::{ReadFrame, WriteFrame, FrameKind};
+    use frame::padding::Pad;
+    use error::ErrorKind;
+
+    #[test]
+    fn test_push_promise_frame() {
+        let frame = PushPromiseFrame::new(StreamId(1), StreamId(2))
+            .fragment(vec![1, 2, 3])
+            .end_headers();
+        let mut b = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        let expected = vec![0, 0, 7,    // length
+                            5,          // type
+                            4,          // flags
+                            0, 0, 0, 1, // stream id
+                            0, 0, 0, 2, // promised stream id
+                            1, 2, 3,    // fragment
+                           ];
+        assert_eq!(b, expected);
+        let mut sl = &b[..];
+        let res = match sl.read_frame().unwrap() {
+            FrameKind::PushPromise(frame) => frame,
+            _ => panic!("Wrong frame type"),
+        };
+        assert_eq!(frame, res);
+    }
+
+    #[test]
+    fn test_padded_push_promise_frame() {
+        let frame = PushPromiseFrame::new(StreamId(1), StreamId(2)).fragment(vec![1]).pad(3);
+        let mut b = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        let expected = vec![0, 0, 9,    // length
+                            5,          // type
+                            8,          // flags
+                            0, 0, 0, 1, // stream id
+                            3,          // padding length
+                            0, 0, 0, 2, // promised stream id
+                            1,          // fragment
+                            0, 0, 0,    // padding
+                           ];
+        assert_eq!(b, expected);
+        let mut sl = &b[..];
+        let res = match sl.read_frame().unwrap() {
+            FrameKind::PushPromise(frame) => frame,
+            _ => panic!("Wrong frame type"),
+        };
+        assert_eq!(frame, res);
+    }
+
+    #[test]
+    fn test_error_missing_promised_id() {
+        let mut b = &vec![0, 0, 2, 5, 0, 0, 0, 0, 1, 0, 0][..];
+        assert_eq!(b.read_frame().unwrap_err().kind(), ErrorKind::FrameSize);
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{Read, Write};
+use byteorder::{ByteOrder, BigEndian};
+use StreamId;
+use frame::{Frame, FrameHeader, FrameType};
+use error::{Error, ErrorKind, Result};
+
+pub const TYPE_RST_STREAM: FrameType = 0x3;
+
+pub const RST_STREAM_PAYLOAD_LENGTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RstStreamFrame {
+    stream_id: StreamId,
+    error: ErrorKind,
+}
+
+impl RstStreamFrame {
+    pub fn new(stream_id: StreamId, error: ErrorKind) -> Self {
+        RstStreamFrame {
+            stream_id: stream_id,
+            error: error,
+        }
+    }
+
+    #[inline]
+    pub fn error(&self) -> ErrorKind {
+        self.error
+    }
+}
+
+impl Frame for RstStreamFrame {
+    fn from_reader<R: Read>(header: FrameHeader, mut reader: R) -> Result<RstStreamFrame> {
+        if header.stream_id == 0 {
+            return Err(Error::protocol("Rst stream frame must be associated with a stream, \
+                                        stream id was zero"));
+        }
+        if header.payload_len != RST_STREAM_PAYLOAD_LENGTH {
+            return Err(Error::frame_size(format!("Bad payload length '{:?}'! The payload \
+                                                  length for a rst stream frame must be 4 \
+                                                  octets",
+                                                 header.payload_len)));
+        }
+        let mut buf = [0; RST_STREAM_PAYLOAD_LENGTH];
+        try!(reader.read_exact(&mut buf));
+        Ok(RstStreamFrame {
+            stream_id: header.stream_id,
+            error: ErrorKind::from_code(BigEndian::read_u32(&buf)),
+        })
+    }
+
+    fn into_writer<W: Write>(self, mut writer: W) -> Result<()> {
+        let mut buf = [0; RST_STREAM_PAYLOAD_LENGTH];
+        BigEndian::write_u32(&mut buf, self.error.code());
+        try!(writer.write_all(&buf));
+        Ok(())
+    }
+
+    fn payload_len(&self) -> usize {
+        RST_STREAM_PAYLOAD_LENGTH
+    }
+
+    fn frame_type(&self) -> FrameType {
+        TYPE_RST_STREAM
+    }
+
+    fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RstStreamFrame;
+    use StreamId;
+    use frame::{ReadFrame, WriteFrame, FrameKind};
+    use error::ErrorKind;
+
+    #[test]
+    fn test_rst_stream_frame() {
+        let frame = RstStreamFrame::new(StreamId(1), ErrorKind::Cancel);
+        let mut b = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        let expected = vec![0, 0, 4,    // length
+                            3,          // type
+                            0,          // flags
+                            0, 0, 0, 1, // stream id
+                            0, 0, 0, 8, // error code
+                           ];
+        assert_eq!(b, expected);
+        let mut sl = &b[..];
+        let res = match sl.read_frame().unwrap() {
+            FrameKind::RstStream(frame) => frame,
+            _ => panic!("Wrong frame type"),
+        };
+        assert_eq!(frame, res);
+    }
+
+    #[test]
+    fn test_unknown_error_code() {
+        let mut b = &vec![0, 0, 4, 3, 0, 0, 0, 0, 1, 0, 0, 1, 0][..];
+        match b.read_frame().unwrap() {
+            FrameKind::RstStream(frame) => assert_eq!(frame.error(), ErrorKind::Internal),
+            _ => panic!("Wrong frame type"),
+        };
+    }
+}
@@ -9,7 +9,7 @@ const MAX_FRAME_SIZE: u32 = 16777215;
 
 pub const TYPE_SETTINGS: FrameType = 0x4;
 // each settings consists of an 2 byte identifier and 4 byte value
-const SETTING_LENGTH: usize = 6;
+pub const SETTING_LENGTH: usize = 6;
 
 /// Settings Parameter according to rfc 6.5.2
 #[derive(Debug, Clone, PartialEq)]
@@ -53,6 +53,27 @@ impl SettingsFrame {
         self.settings
     }
 
+    /// Parses a payload without frame header, as carried by the
+    /// HTTP2-Settings header field of an upgrade request (rfc 3.2.1).
+    pub fn from_payload(mut payload: &[u8]) -> Result<SettingsFrame> {
+        if payload.len() % SETTING_LENGTH != 0 {
+            return Err(Error::new(ErrorKind::FrameSize,
+                                  "Settings payload length must be multiple of 6"));
+        }
+        let mut frame: Self = Default::default();
+        for _ in 0..payload.len() / SETTING_LENGTH {
+            try!(frame.read_setting(&mut payload));
+        }
+        Ok(frame)
+    }
+
+    /// The payload without frame header, see `from_payload`.
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(self.payload_len());
+        self.clone().into_writer(&mut payload).unwrap();
+        payload
+    }
+
     fn read_setting<R: Read>(&mut self, mut reader: R) -> Result<()> {
         let mut buf = [0; 6];
         try!(reader.read_exact(&mut buf));
@@ -269,4 +290,15 @@ mod test {
                        .kind(),
                    ErrorKind::Protocol);
     }
+
+    #[test]
+    fn test_from_payload() {
+        let frame = SettingsFrame::from_payload(&[0, 3, 0, 0, 0, 100, 0, 2, 0, 0, 0, 0]).unwrap();
+        assert!(!frame.is_ack());
+        assert_eq!(frame.clone().settings(),
+                   vec![Setting::MaxConcurrentStreams(100), Setting::EnablePush(false)]);
+        assert_eq!(SettingsFrame::from_payload(&frame.to_payload()).unwrap(), frame);
+        assert_eq!(SettingsFrame::from_payload(&[0, 3, 0, 0]).unwrap_err().kind(),
+                   ErrorKind::FrameSize);
+    }
 }
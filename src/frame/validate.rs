@@ -0,0 +1,253 @@
+//! Validation of frame headers according to rfc sections 4.2 and 6.
+//!
+//! Every length, flag and stream id invariant which can be decided from the
+//! 9 octet frame header alone is checked here, before any payload is read
+//! or allocated. Invariants depending on the payload itself (e.g. the pad
+//! length) are checked by the frame parsers.
+
+use frame::{FrameHeader, FLAG_ACK, FLAG_PADDED, FLAG_PRIORITY};
+use frame::data::TYPE_DATA;
+use frame::headers::TYPE_HEADERS;
+use frame::priority::{TYPE_PRIORITY, PRIORITY_PAYLOAD_LENGTH};
+use frame::rst_stream::{TYPE_RST_STREAM, RST_STREAM_PAYLOAD_LENGTH};
+use frame::settings::{TYPE_SETTINGS, SETTING_LENGTH};
+use frame::push_promise::TYPE_PUSH_PROMISE;
+use frame::ping::{TYPE_PING, PING_PAYLOAD_LENGTH};
+use frame::goaway::TYPE_GOAWAY;
+use frame::window_update::{TYPE_WINDOW_UPDATE, WINDOW_UPDATE_PAYLOAD_LENGTH};
+use frame::continuation::TYPE_CONTINUATION;
+use error::{Error, Result};
+
+/// Checks the frame header against the invariants of its frame type.
+///
+/// Unknown frame types are only checked against `max_payload`, they must be
+/// ignored by the receiver (rfc 4.1).
+pub fn validate_header(header: &FrameHeader, max_payload: usize) -> Result<()> {
+    if header.payload_len > max_payload {
+        return Err(Error::frame_size("payload length exceeds max frame size setting"));
+    }
+    match header.frame_type {
+        TYPE_DATA => {
+            try!(stream_frame(header, "Data"));
+            min_len(header, "Data", padding(header))
+        }
+        TYPE_HEADERS => {
+            try!(stream_frame(header, "Headers"));
+            let mut len = padding(header);
+            if header.flags.contains(FLAG_PRIORITY) {
+                len += PRIORITY_PAYLOAD_LENGTH;
+            }
+            min_len(header, "Headers", len)
+        }
+        TYPE_PRIORITY => {
+            try!(stream_frame(header, "Priority"));
+            exact_len(header, "Priority", PRIORITY_PAYLOAD_LENGTH)
+        }
+        TYPE_RST_STREAM => {
+            try!(stream_frame(header, "Rst stream"));
+            exact_len(header, "Rst stream", RST_STREAM_PAYLOAD_LENGTH)
+        }
+        TYPE_SETTINGS => {
+            try!(connection_frame(header, "Settings"));
+            if header.flags.contains(FLAG_ACK) {
+                return exact_len(header, "Settings ack", 0);
+            }
+            if header.payload_len % SETTING_LENGTH != 0 {
+                return Err(Error::frame_size("Settings Frame payload length must be multiple \
+                                              of 6"));
+            }
+            Ok(())
+        }
+        TYPE_PUSH_PROMISE => {
+            try!(stream_frame(header, "Push promise"));
+            // promised stream id
+            min_len(header, "Push promise", padding(header) + 4)
+        }
+        TYPE_PING => {
+            try!(connection_frame(header, "Ping"));
+            exact_len(header, "Ping", PING_PAYLOAD_LENGTH)
+        }
+        TYPE_GOAWAY => {
+            try!(connection_frame(header, "Goaway"));
+            // last stream id and error code
+            min_len(header, "Goaway", 8)
+        }
+        TYPE_WINDOW_UPDATE => exact_len(header, "Window update", WINDOW_UPDATE_PAYLOAD_LENGTH),
+        TYPE_CONTINUATION => stream_frame(header, "Continuation"),
+        _ => Ok(()),
+    }
+}
+
+/// Length of the pad length field, if the frame is padded.
+#[inline]
+fn padding(header: &FrameHeader) -> usize {
+    if header.flags.contains(FLAG_PADDED) { 1 } else { 0 }
+}
+
+fn stream_frame(header: &FrameHeader, name: &str) -> Result<()> {
+    if header.stream_id == 0 {
+        return Err(Error::protocol(format!("{} frame must be associated with a stream, stream \
+                                            id was zero",
+                                           name)));
+    }
+    Ok(())
+}
+
+fn connection_frame(header: &FrameHeader, name: &str) -> Result<()> {
+    if header.stream_id != 0 {
+        return Err(Error::protocol(format!("The stream identifier for a {} frame must be zero",
+                                           name.to_lowercase())));
+    }
+    Ok(())
+}
+
+fn exact_len(header: &FrameHeader, name: &str, len: usize) -> Result<()> {
+    if header.payload_len != len {
+        return Err(Error::frame_size(format!("Bad payload length '{:?}'! The payload length \
+                                              for a {} frame must be {} octets",
+                                             header.payload_len,
+                                             name.to_lowercase(),
+                                             len)));
+    }
+    Ok(())
+}
+
+fn min_len(header: &FrameHeader, name: &str, len: usize) -> Result<()> {
+    if header.payload_len < len {
+        return Err(Error::frame_size(format!("Bad payload length '{:?}'! The payload length \
+                                              for a {} frame must be at least {} octets",
+                                             header.payload_len,
+                                             name.to_lowercase(),
+                                             len)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use frame::{ReadFrame, FrameIter};
+    use error::ErrorKind;
+
+    const MAX_PAYLOAD: usize = 16384;
+
+    /// Malformed frames, each with the error a receiver must detect.
+    fn malformed_frames() -> Vec<(&'static str, Vec<u8>, ErrorKind)> {
+        vec![
+            ("payload exceeds max frame size",
+             vec![0, 0x40, 1, 0, 0, 0, 0, 0, 1], ErrorKind::FrameSize),
+            // DATA
+            ("data on stream zero",
+             vec![0, 0, 1, 0, 0, 0, 0, 0, 0, 1], ErrorKind::Protocol),
+            ("padded data without pad length",
+             vec![0, 0, 0, 0, 8, 0, 0, 0, 1], ErrorKind::FrameSize),
+            ("data pad length exceeds payload",
+             vec![0, 0, 2, 0, 8, 0, 0, 0, 1, 2, 0], ErrorKind::Protocol),
+            // HEADERS
+            ("headers on stream zero",
+             vec![0, 0, 1, 1, 4, 0, 0, 0, 0, 1], ErrorKind::Protocol),
+            ("headers pad length exceeds payload",
+             vec![0, 0, 3, 1, 8, 0, 0, 0, 1, 3, 1, 2], ErrorKind::Protocol),
+            ("headers pad length equals payload",
+             vec![0, 0, 1, 1, 8, 0, 0, 0, 1, 1], ErrorKind::Protocol),
+            ("headers too short for priority",
+             vec![0, 0, 4, 1, 0x20, 0, 0, 0, 1, 0, 0, 0, 0], ErrorKind::FrameSize),
+            ("headers padding leaves no room for priority",
+             vec![0, 0, 6, 1, 0x28, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0], ErrorKind::Protocol),
+            ("headers depending on itself",
+             vec![0, 0, 5, 1, 0x20, 0, 0, 0, 1, 0, 0, 0, 1, 15], ErrorKind::Protocol),
+            // PRIORITY
+            ("priority on stream zero",
+             vec![0, 0, 5, 2, 0, 0, 0, 0, 0, 0, 0, 0, 1, 15], ErrorKind::Protocol),
+            ("priority too short",
+             vec![0, 0, 4, 2, 0, 0, 0, 0, 1, 0, 0, 0, 0], ErrorKind::FrameSize),
+            ("priority too long",
+             vec![0, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0, 0, 0, 15, 0], ErrorKind::FrameSize),
+            // RST_STREAM
+            ("rst stream on stream zero",
+             vec![0, 0, 4, 3, 0, 0, 0, 0, 0, 0, 0, 0, 8], ErrorKind::Protocol),
+            ("rst stream too short",
+             vec![0, 0, 3, 3, 0, 0, 0, 0, 1, 0, 0, 8], ErrorKind::FrameSize),
+            // SETTINGS
+            ("settings on a stream",
+             vec![0, 0, 0, 4, 0, 0, 0, 0, 1], ErrorKind::Protocol),
+            ("settings ack with payload",
+             vec![0, 0, 6, 4, 1, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1], ErrorKind::FrameSize),
+            ("settings length not a multiple of 6",
+             vec![0, 0, 5, 4, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0], ErrorKind::FrameSize),
+            ("settings invalid enable push",
+             vec![0, 0, 6, 4, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 2], ErrorKind::Protocol),
+            ("settings initial window size too large",
+             vec![0, 0, 6, 4, 0, 0, 0, 0, 0, 0, 4, 0x80, 0, 0, 0], ErrorKind::FlowControl),
+            ("settings max frame size too small",
+             vec![0, 0, 6, 4, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 1], ErrorKind::Protocol),
+            // PUSH_PROMISE
+            ("push promise on stream zero",
+             vec![0, 0, 4, 5, 4, 0, 0, 0, 0, 0, 0, 0, 2], ErrorKind::Protocol),
+            ("push promise without promised stream id",
+             vec![0, 0, 3, 5, 4, 0, 0, 0, 1, 0, 0, 2], ErrorKind::FrameSize),
+            ("push promise padding leaves no room for promised stream id",
+             vec![0, 0, 5, 5, 0xC, 0, 0, 0, 1, 1, 0, 0, 0, 2], ErrorKind::Protocol),
+            // PING
+            ("ping on a stream",
+             vec![0, 0, 8, 6, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0], ErrorKind::Protocol),
+            ("ping too short",
+             vec![0, 0, 7, 6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], ErrorKind::FrameSize),
+            ("ping ack too long",
+             vec![0, 0, 9, 6, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], ErrorKind::FrameSize),
+            // GOAWAY
+            ("goaway on a stream",
+             vec![0, 0, 8, 7, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0], ErrorKind::Protocol),
+            ("goaway too short",
+             vec![0, 0, 7, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], ErrorKind::FrameSize),
+            // WINDOW_UPDATE
+            ("window update too short",
+             vec![0, 0, 3, 8, 0, 0, 0, 0, 0, 0, 0, 1], ErrorKind::FrameSize),
+            ("window update too long",
+             vec![0, 0, 5, 8, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0], ErrorKind::FrameSize),
+            ("window update with zero increment",
+             vec![0, 0, 4, 8, 0, 0, 0, 0, 1, 0, 0, 0, 0], ErrorKind::Protocol),
+            ("window update with reserved bit only",
+             vec![0, 0, 4, 8, 0, 0, 0, 0, 0, 0x80, 0, 0, 0], ErrorKind::Protocol),
+            // CONTINUATION
+            ("continuation on stream zero",
+             vec![0, 0, 1, 9, 4, 0, 0, 0, 0, 1], ErrorKind::Protocol),
+        ]
+    }
+
+    #[test]
+    fn test_read_malformed_frames() {
+        for (name, raw, kind) in malformed_frames() {
+            let mut sl = &raw[..];
+            match sl.read_frame_checked(MAX_PAYLOAD) {
+                Ok(frame) => panic!("{}: accepted malformed frame {:?}", name, frame),
+                Err(e) => assert_eq!(e.kind(), kind, "{}: {}", name, e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_malformed_frames() {
+        for (name, raw, kind) in malformed_frames() {
+            match FrameIter::new(&raw, MAX_PAYLOAD).next() {
+                Some(Ok(frame)) => panic!("{}: accepted malformed frame {:?}", name, frame),
+                Some(Err(e)) => assert_eq!(e.kind(), kind, "{}: {}", name, e),
+                None => panic!("{}: no frame", name),
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_rejects_before_payload() {
+        // a settings frame on a stream is rejected as soon as the header is complete
+        let raw = [0, 0, 6, 4, 0, 0, 0, 0, 1];
+        let err = FrameIter::new(&raw, MAX_PAYLOAD).next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        // only the length is available, the iterator must not read past the slice
+        assert!(FrameIter::new(&[0, 0, 1], MAX_PAYLOAD).next().is_none());
+        assert!(FrameIter::new(&[0, 0, 1, 0, 0, 0, 0, 0], MAX_PAYLOAD).next().is_none());
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::{Read, Write};
+use byteorder::{ByteOrder, BigEndian};
+use StreamId;
+use frame::{Frame, FrameHeader, FrameType};
+use error::{Error, Result};
+
+pub const TYPE_WINDOW_UPDATE: FrameType = 0x8;
+
+pub const WINDOW_UPDATE_PAYLOAD_LENGTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowUpdateFrame {
+    stream_id: StreamId,
+    increment: u32,
+}
+
+impl WindowUpdateFrame {
+    /// A window update for the stream, or for the whole connection if the
+    /// stream id is zero.
+    pub fn new(stream_id: StreamId, increment: u32) -> Self {
+        WindowUpdateFrame {
+            stream_id: stream_id,
+            increment: increment & 0x7FFFFFFF,
+        }
+    }
+
+    #[inline]
+    pub fn increment(&self) -> u32 {
+        self.increment
+    }
+}
+
+impl Frame for WindowUpdateFrame {
+    fn from_reader<R: Read>(header: FrameHeader, mut reader: R) -> Result<WindowUpdateFrame> {
+        if header.payload_len != WINDOW_UPDATE_PAYLOAD_LENGTH {
+            return Err(Error::frame_size(format!("Bad payload length '{:?}'! The payload \
+                                                  length for a window update frame must be 4 \
+                                                  octets",
+                                                 header.payload_len)));
+        }
+        let mut buf = [0; WINDOW_UPDATE_PAYLOAD_LENGTH];
+        try!(reader.read_exact(&mut buf));
+        let increment = BigEndian::read_u32(&buf) & 0x7FFFFFFF;
+        if increment == 0 {
+            return Err(Error::protocol("Window update frame with an increment of zero"));
+        }
+        Ok(WindowUpdateFrame {
+            stream_id: header.stream_id,
+            increment: increment,
+        })
+    }
+
+    fn into_writer<W: Write>(self, mut writer: W) -> Result<()> {
+        let mut buf = [0; WINDOW_UPDATE_PAYLOAD_LENGTH];
+        BigEndian::write_u32(&mut buf, self.increment);
+        try!(writer.write_all(&buf));
+        Ok(())
+    }
+
+    fn payload_len(&self) -> usize {
+        WINDOW_UPDATE_PAYLOAD_LENGTH
+    }
+
+    fn frame_type(&self) -> FrameType {
+        TYPE_WINDOW_UPDATE
+    }
+
+    fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WindowUpdateFrame;
+    use StreamId;
+    use frame::{ReadFrame, WriteFrame, FrameKind};
+
+    #[test]
+    fn test_window_update_frame() {
+        let frame = WindowUpdateFrame::new(StreamId(1), 1024);
+        let mut b = Vec::new();
+        b.write_frame(frame.clone()).unwrap();
+        let expected = vec![0, 0, 4,    // length
+                            8,          // type
+                            0,          // flags
+                            0, 0, 0, 1, // stream id
+                            0, 0, 4, 0, // increment
+                           ];
+        assert_eq!(b, expected);
+        let mut sl = &b[..];
+        let res = match sl.read_frame().unwrap() {
+            FrameKind::WindowUpdate(frame) => frame,
+            _ => panic!("Wrong frame type"),
+        };
+        assert_eq!(frame, res);
+    }
+}
@@ -0,0 +1,165 @@
+//! Entry points for the fuzz targets in `fuzz/`.
+//!
+//! Each function takes arbitrary bytes and panics only if an invariant of
+//! the parsers or the connection is broken, errors for malformed input are
+//! expected. Run them with `cargo fuzz run <target>` from the repository root.
+
+use std::io::{Read, Write};
+use buffer::FrameReader;
+use connection::PREFACE;
+use frame::FrameIter;
+use hpack;
+use message::{Request, Response};
+use mock::MockStream;
+use pool::BufferPool;
+use server::{self, ServerConnection};
+
+/// Largest payload accepted, the default SETTINGS_MAX_FRAME_SIZE.
+const MAX_PAYLOAD: usize = 16384;
+
+/// Upper bound of `ready` calls per input, against livelocks.
+const MAX_ROUNDS: usize = 4096;
+
+/// Splits off the first byte as the size of the chunks the rest of the input
+/// is delivered in.
+fn chunked(data: &[u8]) -> (usize, &[u8]) {
+    match data.split_first() {
+        Some((&n, rest)) => (n as usize + 1, rest),
+        None => (1, data),
+    }
+}
+
+/// Parses frames from a slice, owned and borrowing frames must agree.
+pub fn frame_iter(data: &[u8]) {
+    let mut owned = FrameIter::new(data, MAX_PAYLOAD);
+    let mut refs = FrameIter::new(data, MAX_PAYLOAD).into_refs();
+    loop {
+        match (owned.next(), refs.next()) {
+            (Some(Ok(a)), Some(Ok(b))) => {
+                assert_eq!(format!("{:?}", a), format!("{:?}", b.into_owned()));
+            }
+            (Some(Err(a)), Some(Err(b))) => {
+                assert_eq!(a.kind(), b.kind());
+                return;
+            }
+            (None, None) => return,
+            (a, b) => panic!("owned and borrowing frames disagree: {:?} {:?}", a, b),
+        }
+    }
+}
+
+/// Reads frames delivered in chunks, they must be the frames parsed from the
+/// whole input at once.
+pub fn frame_reader(data: &[u8]) {
+    let (chunk, data) = chunked(data);
+    let (mut tx, rx) = MockStream::new();
+    tx.hold();
+    tx.write_all(data).unwrap();
+    let mut reader = FrameReader::new(rx, MAX_PAYLOAD);
+    let mut expected = FrameIter::new(data, MAX_PAYLOAD);
+    loop {
+        if tx.deliver_some(chunk) == 0 {
+            tx.close();
+        }
+        if reader.fill().is_err() {
+            return;
+        }
+        while let Some(frame) = reader.next_frame() {
+            match (frame, expected.next()) {
+                (Ok(a), Some(Ok(b))) => assert_eq!(format!("{:?}", a), format!("{:?}", b)),
+                (Err(a), Some(Err(b))) => {
+                    assert_eq!(a.kind(), b.kind());
+                    return;
+                }
+                (a, b) => panic!("chunked and whole input disagree: {:?} {:?}", a, b),
+            }
+        }
+        if reader.is_eof() {
+            assert!(expected.next().is_none());
+            return;
+        }
+    }
+}
+
+/// Decodes a header block, the decoded headers must stay within the limit
+/// and survive a round trip through a fresh encoder and decoder.
+pub fn hpack_decoder(data: &[u8]) {
+    let max = hpack::DEFAULT_MAX_HEADER_LIST_SIZE;
+    let headers = match hpack::Decoder::new().decode(data, max) {
+        Ok(headers) => headers,
+        Err(_) => return,
+    };
+    assert!(hpack::header_list_size(&headers) <= max);
+    let mut block = Vec::new();
+    hpack::Encoder::new().encode(&headers, &mut block);
+    assert_eq!(hpack::Decoder::new().decode(&block, max).unwrap(), headers);
+}
+
+/// Runs a server connection on input following the preface, everything the
+/// server sends must be valid frames, and neither a header block waiting for
+/// CONTINUATION frames nor a decoded header list exceeds its limit.
+pub fn server_connection(data: &[u8]) {
+    let (chunk, data) = chunked(data);
+    let (local, mut peer) = MockStream::new();
+    let mut conn = ServerConnection::new(local, server::default_settings(), &BufferPool::new())
+        .unwrap();
+    let max = conn.connection().max_header_list();
+    let mut service = |req: Request| {
+        assert!(hpack::header_list_size(&req.headers) <= max);
+        assert!(hpack::header_list_size(&req.trailers) <= max);
+        Response::new(200).body(req.body)
+    };
+    peer.hold();
+    peer.write_all(PREFACE).unwrap();
+    peer.write_all(data).unwrap();
+    let mut output = Vec::new();
+    for _ in 0..MAX_ROUNDS {
+        if peer.deliver_some(chunk) == 0 {
+            peer.close();
+        }
+        let _ = conn.ready(&mut service);
+        let _ = peer.read_to_end(&mut output);
+        let buffered = conn.connection().header_block_len();
+        assert!(buffered <= conn.connection().max_header_block());
+        if conn.is_closed() {
+            break;
+        }
+    }
+    for frame in FrameIter::new(&output, MAX_PAYLOAD) {
+        if let Err(e) = frame {
+            panic!("server sent an invalid frame: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use connection::PREFACE;
+    use super::{frame_iter, frame_reader, hpack_decoder, server_connection};
+
+    /// SETTINGS, a GET request on stream 1 with DATA, PING and a truncated frame
+    fn inputs() -> Vec<Vec<u8>> {
+        let frames = vec![0, 0, 0, 4, 0, 0, 0, 0, 0,
+                          0, 0, 3, 1, 4, 0, 0, 0, 1, 0x82, 0x86, 0x84,
+                          0, 0, 2, 0, 1, 0, 0, 0, 1, b'h', b'i',
+                          0, 0, 8, 6, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8,
+                          0, 0, 4, 8, 0];
+        let mut inputs = vec![vec![], vec![0], PREFACE.to_vec(), frames.clone()];
+        for chunk in 0..20 {
+            let mut input = vec![chunk];
+            input.extend(&frames);
+            inputs.push(input);
+        }
+        inputs
+    }
+
+    #[test]
+    fn test_targets() {
+        for input in inputs() {
+            frame_iter(&input);
+            frame_reader(&input);
+            hpack_decoder(&input);
+            server_connection(&input);
+        }
+    }
+}
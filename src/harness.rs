@@ -0,0 +1,759 @@
+//! Scripted peers for frame-level tests.
+//!
+//! A `Script` is a list of frames to send to a server or client and of frames
+//! expected back, run over the in-memory transport of `mock` or against a
+//! server listening on a socket:
+//!
+//! ```ignore
+//! Script::new()
+//!     .handshake()
+//!     .headers(2, &[(":method", "GET")], true)
+//!     .expect(Expect::GoAway(ErrorKind::Protocol))
+//!     .expect(Expect::Closed)
+//!     .run_server(service);
+//! ```
+//!
+//! While waiting for an expected frame, other frames are skipped, except
+//! GOAWAY and RST_STREAM which fail the script unless expected. A failing
+//! script panics with a transcript of all frames exchanged.
+
+use std::fmt;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::ops::Deref;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use {Settings, StreamId};
+use buffer::FrameReader;
+use client::{self, Client};
+use connection::{PREFACE, Transport};
+use error::{ErrorKind, Result};
+use frame::{Frame, FrameKind, WriteFrame};
+use frame::continuation::ContinuationFrame;
+use frame::headers::HeadersFrame;
+use frame::push_promise::PushPromiseFrame;
+use frame::settings::SettingsFrame;
+use hpack::{self, Header};
+use message::Request;
+use mock::MockStream;
+use pool::BufferPool;
+use server::{self, Server, Service, ServerConnection, ShutdownHandle};
+
+/// Rounds of driving the target without output before an expectation fails.
+const MAX_IDLE_ROUNDS: usize = 3;
+
+/// How long a round waits for a remote target, in milliseconds.
+const REMOTE_ROUND_MS: u64 = 10;
+
+/// Rounds without output before an expectation on a remote target fails.
+const REMOTE_IDLE_ROUNDS: usize = 200;
+
+/// A server or client driven by a script.
+pub trait Target {
+    /// Handles everything the script sent so far.
+    fn drive(&mut self) -> Result<()>;
+    fn is_closed(&self) -> bool;
+    /// Servers expect the client preface before the SETTINGS frame.
+    fn expects_preface(&self) -> bool;
+
+    /// Rounds of driving without output after which expectations fail.
+    fn max_idle_rounds(&self) -> usize {
+        MAX_IDLE_ROUNDS
+    }
+}
+
+/// A server connection with the service answering its requests.
+pub struct ServerTarget<V> {
+    pub conn: ServerConnection<MockStream>,
+    pub service: V,
+}
+
+impl<V: Service> Target for ServerTarget<V> {
+    fn drive(&mut self) -> Result<()> {
+        self.conn.ready(&mut self.service)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.conn.is_closed()
+    }
+
+    fn expects_preface(&self) -> bool {
+        true
+    }
+}
+
+impl Target for Client<MockStream> {
+    fn drive(&mut self) -> Result<()> {
+        self.ready()
+    }
+
+    fn is_closed(&self) -> bool {
+        Client::is_closed(self)
+    }
+
+    fn expects_preface(&self) -> bool {
+        false
+    }
+}
+
+/// A server or client running on its own, reached over a socket.
+///
+/// Driving it just waits for its frames, it is closed once the peer reads
+/// the end of the stream.
+pub struct Remote {
+    server: bool,
+}
+
+impl Target for Remote {
+    fn drive(&mut self) -> Result<()> {
+        thread::sleep(Duration::from_millis(REMOTE_ROUND_MS));
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    fn expects_preface(&self) -> bool {
+        self.server
+    }
+
+    fn max_idle_rounds(&self) -> usize {
+        REMOTE_IDLE_ROUNDS
+    }
+}
+
+/// A server on an unused port, shut down and joined once dropped.
+///
+/// Dereferences to the address to connect to.
+pub struct TestServer {
+    addr: SocketAddr,
+    handle: ShutdownHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Runs a server answering with `service` on a thread of its own.
+    pub fn start<V: Service + Send + 'static>(service: V) -> TestServer {
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let server = Server::bind(&"127.0.0.1:0".parse().unwrap(), service).unwrap();
+            let handle = server.shutdown_handle().unwrap();
+            tx.send((server.local_addr().unwrap(), handle)).unwrap();
+            server.run().unwrap();
+        });
+        let (addr, handle) = rx.recv().unwrap();
+        TestServer {
+            addr: addr,
+            handle: handle,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Deref for TestServer {
+    type Target = SocketAddr;
+
+    fn deref(&self) -> &SocketAddr {
+        &self.addr
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // the server stopped already if it failed
+        let _ = self.handle.shutdown(Duration::from_millis(0));
+        let result = self.thread.take().unwrap().join();
+        if !thread::panicking() {
+            result.unwrap();
+        }
+    }
+}
+
+/// A frame, or header block, the script waits for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expect {
+    Settings,
+    SettingsAck,
+    PingAck([u8; 8]),
+    /// A PING from the target, which waits for the acknowledgement.
+    Ping([u8; 8]),
+    /// A complete header block on the stream.
+    Headers(u32),
+    /// A header block on the stream containing the field, the block of a
+    /// PUSH_PROMISE belongs to the promised stream.
+    Field(u32, &'static str, &'static str),
+    /// A PUSH_PROMISE on the stream promising the second stream.
+    PushPromise(u32, u32),
+    /// A DATA frame on the stream with exactly this payload.
+    Data(u32, Vec<u8>),
+    /// A DATA or HEADERS frame on the stream with END_STREAM set.
+    EndStream(u32),
+    WindowUpdate(u32),
+    Reset(u32, ErrorKind),
+    GoAway(ErrorKind),
+    /// GOAWAY naming the last stream processed.
+    LastStreamId(u32),
+    /// The target closed the connection.
+    Closed,
+}
+
+/// A frame received by the peer, HEADERS and CONTINUATION frames completing
+/// a header block carry the decoded block.
+struct Incoming {
+    frame: FrameKind,
+    block: Option<(StreamId, Vec<Header>, bool)>,
+}
+
+impl Incoming {
+    fn matches(&self, expect: &Expect) -> bool {
+        match (expect, &self.frame) {
+            (&Expect::Settings, &FrameKind::Settings(ref f)) => !f.is_ack(),
+            (&Expect::SettingsAck, &FrameKind::Settings(ref f)) => f.is_ack(),
+            (&Expect::PingAck(data), &FrameKind::Ping(ref f)) => f.is_ack() && f.data() == data,
+            (&Expect::Ping(data), &FrameKind::Ping(ref f)) => !f.is_ack() && f.data() == data,
+            (&Expect::Data(id, ref data), &FrameKind::Data(ref f)) => {
+                f.stream_id() == id && f.payload() == &data[..]
+            }
+            (&Expect::EndStream(id), &FrameKind::Data(ref f)) => {
+                f.stream_id() == id && f.is_end_stream()
+            }
+            (&Expect::PushPromise(id, promised), &FrameKind::PushPromise(ref f)) => {
+                f.stream_id() == id && f.promised_stream_id() == promised
+            }
+            (&Expect::WindowUpdate(id), &FrameKind::WindowUpdate(ref f)) => f.stream_id() == id,
+            (&Expect::Reset(id, kind), &FrameKind::RstStream(ref f)) => {
+                f.stream_id() == id && f.error() == kind
+            }
+            (&Expect::GoAway(kind), &FrameKind::GoAway(ref f)) => f.error() == kind,
+            (&Expect::LastStreamId(id), &FrameKind::GoAway(ref f)) => f.last_stream_id() == id,
+            _ => self.matches_block(expect),
+        }
+    }
+
+    fn matches_block(&self, expect: &Expect) -> bool {
+        let (id, ref headers, end_stream) = match self.block {
+            Some(ref block) => block.clone(),
+            None => return false,
+        };
+        match *expect {
+            Expect::Headers(n) => id == n,
+            Expect::Field(n, name, value) => {
+                id == n &&
+                headers.iter().any(|&(ref k, ref v)| k == name.as_bytes() && v == value.as_bytes())
+            }
+            Expect::EndStream(n) => id == n && end_stream,
+            _ => false,
+        }
+    }
+
+    /// Frames which end streams or the connection are never skipped.
+    fn is_fatal(&self) -> bool {
+        match self.frame {
+            FrameKind::GoAway(_) | FrameKind::RstStream(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Incoming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.block {
+            Some((id, ref headers, end_stream)) => {
+                let fields: Vec<String> = headers.iter()
+                    .map(|&(ref k, ref v)| {
+                        format!("{}: {}", String::from_utf8_lossy(k), String::from_utf8_lossy(v))
+                    })
+                    .collect();
+                write!(f, "header block on {:?} {:?} end_stream={}", id, fields, end_stream)
+            }
+            None => write!(f, "{:?}", self.frame),
+        }
+    }
+}
+
+enum Step<T> {
+    // sends the preface, unless only servers expect it and the target isn't one
+    Preface(bool),
+    Send(String, Vec<u8>),
+    Expect(Expect),
+    // bytes sent outside of frames, e.g. an HTTP/1.1 response
+    ExpectRaw(Vec<u8>),
+    Refute(Expect),
+    Act(Box<FnMut(&mut T)>),
+    Close,
+}
+
+/// The transport of the peer.
+trait PeerStream: Transport {
+    /// Ends the stream towards the target.
+    fn close(&self);
+}
+
+impl PeerStream for MockStream {
+    fn close(&self) {
+        MockStream::close(self)
+    }
+}
+
+impl PeerStream for TcpStream {
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Write);
+    }
+}
+
+/// The peer's end of the transport.
+struct Peer<S> {
+    stream: S,
+    reader: FrameReader<S>,
+    decoder: hpack::Decoder,
+    // header block waiting for CONTINUATION frames
+    block: Option<(StreamId, bool, Vec<u8>)>,
+    // the target is a client, its preface comes first
+    read_preface: bool,
+    // the target closed the transport
+    closed: bool,
+    // frames skipped while waiting for the last expectation
+    skipped: Vec<Incoming>,
+    transcript: Vec<String>,
+}
+
+impl<S: PeerStream> Peer<S> {
+    fn new(stream: S, read_preface: bool) -> io::Result<Peer<S>> {
+        let max_payload = Settings::default().max_frame_size as usize;
+        Ok(Peer {
+            reader: FrameReader::new(try!(stream.try_clone()), max_payload),
+            stream: stream,
+            decoder: hpack::Decoder::new(),
+            block: None,
+            read_preface: read_preface,
+            closed: false,
+            skipped: Vec::new(),
+            transcript: Vec::new(),
+        })
+    }
+
+    fn fail(&self, msg: String) -> ! {
+        panic!("{}\ntranscript:\n{}", msg, self.transcript.join("\n"))
+    }
+
+    fn send(&mut self, label: &str, bytes: &[u8]) {
+        self.transcript.push(format!("-> {}", label));
+        if let Err(e) = self.stream.write_all(bytes) {
+            self.fail(format!("failed to send {}: {}", label, e));
+        }
+    }
+
+    fn fill(&mut self) {
+        match self.reader.fill() {
+            Ok(_) => {}
+            // the target closed with data of ours unread
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => self.closed = true,
+            Err(e) => self.fail(format!("failed to read: {}", e)),
+        }
+        if self.reader.is_eof() {
+            self.closed = true;
+        }
+    }
+
+    /// Parses the next frame the target sent.
+    fn next(&mut self) -> Option<Incoming> {
+        self.fill();
+        if self.read_preface {
+            match self.reader.read_preface(PREFACE) {
+                Ok(true) => {
+                    self.read_preface = false;
+                    self.transcript.push("<- preface".to_owned());
+                }
+                Ok(false) => return None,
+                Err(e) => self.fail(format!("invalid preface: {}", e)),
+            }
+        }
+        let frame = match self.reader.next_frame() {
+            Some(Ok(frame)) => frame,
+            Some(Err(e)) => self.fail(format!("invalid frame: {}", e)),
+            None => return None,
+        };
+        let block = match frame {
+            FrameKind::Headers(ref f) => {
+                let block = (f.stream_id(), f.is_end_stream(), f.clone().into_fragment());
+                self.block = Some(block);
+                f.is_end_headers()
+            }
+            FrameKind::PushPromise(ref f) => {
+                let block = (f.promised_stream_id(), false, f.clone().into_fragment());
+                self.block = Some(block);
+                f.is_end_headers()
+            }
+            FrameKind::Continuation(ref f) => {
+                match self.block {
+                    Some((_, _, ref mut fragment)) => fragment.extend(f.clone().into_fragment()),
+                    None => self.fail("CONTINUATION without HEADERS".to_owned()),
+                }
+                f.is_end_headers()
+            }
+            _ => false,
+        };
+        let block = if block {
+            let (id, end_stream, fragment) = self.block.take().unwrap();
+            match self.decoder.decode(&fragment, ::std::usize::MAX) {
+                Ok(headers) => Some((id, headers, end_stream)),
+                Err(e) => self.fail(format!("invalid header block: {}", e)),
+            }
+        } else {
+            None
+        };
+        let incoming = Incoming {
+            frame: frame,
+            block: block,
+        };
+        self.transcript.push(format!("<- {}", incoming));
+        Some(incoming)
+    }
+
+    fn expect<T: Target>(&mut self, target: &mut T, expect: &Expect) {
+        let mut idle = 0;
+        self.skipped.clear();
+        loop {
+            let mut received = false;
+            while let Some(incoming) = self.next() {
+                received = true;
+                if incoming.matches(expect) {
+                    return;
+                }
+                if incoming.is_fatal() {
+                    self.fail(format!("expected {:?}, got {}", expect, incoming));
+                }
+                self.skipped.push(incoming);
+            }
+            if *expect == Expect::Closed && (self.closed || target.is_closed()) {
+                self.transcript.push("<- closed".to_owned());
+                return;
+            }
+            if self.closed {
+                self.fail(format!("connection closed while waiting for {:?}", expect));
+            }
+            if received {
+                idle = 0;
+            } else if idle == target.max_idle_rounds() {
+                self.fail(format!("timed out waiting for {:?}", expect));
+            } else {
+                idle += 1;
+            }
+            if let Err(e) = target.drive() {
+                self.transcript.push(format!("!! {}", e));
+            }
+        }
+    }
+
+    fn expect_raw<T: Target>(&mut self, target: &mut T, bytes: &[u8]) {
+        let printable = String::from_utf8_lossy(bytes).into_owned();
+        let mut idle = 0;
+        loop {
+            self.fill();
+            match self.reader.read_preface(bytes) {
+                Ok(true) => {
+                    self.transcript.push(format!("<- raw {:?}", printable));
+                    return;
+                }
+                Ok(false) => {}
+                Err(_) => {
+                    let got = String::from_utf8_lossy(self.reader.buffered()).into_owned();
+                    self.fail(format!("expected raw {:?}, got {:?}", printable, got));
+                }
+            }
+            if self.closed {
+                self.fail(format!("connection closed while waiting for raw {:?}", printable));
+            }
+            if idle == target.max_idle_rounds() {
+                self.fail(format!("timed out waiting for raw {:?}", printable));
+            }
+            idle += 1;
+            if let Err(e) = target.drive() {
+                self.transcript.push(format!("!! {}", e));
+            }
+        }
+    }
+
+    fn refute(&self, expect: &Expect) {
+        if let Some(incoming) = self.skipped.iter().find(|i| i.matches(expect)) {
+            self.fail(format!("unexpected {}", incoming));
+        }
+    }
+}
+
+/// A sequence of frames sent to and expected from a target.
+pub struct Script<T> {
+    steps: Vec<Step<T>>,
+    encoder: hpack::Encoder,
+}
+
+impl<T: Target> Script<T> {
+    pub fn new() -> Script<T> {
+        Script {
+            steps: Vec::new(),
+            encoder: hpack::Encoder::new(),
+        }
+    }
+
+    /// Sends the client connection preface.
+    pub fn preface(mut self) -> Self {
+        self.steps.push(Step::Preface(true));
+        self
+    }
+
+    /// Exchanges SETTINGS with the target, preceded by the preface if the
+    /// target is a server.
+    pub fn handshake(mut self) -> Self {
+        self.steps.push(Step::Preface(false));
+        self.send(SettingsFrame::default())
+            .expect(Expect::Settings)
+            .send(SettingsFrame::ack())
+            .expect(Expect::SettingsAck)
+    }
+
+    pub fn send<F: Frame + fmt::Debug>(mut self, frame: F) -> Self {
+        let label = format!("{:?}", frame);
+        let mut bytes = Vec::new();
+        bytes.write_frame(frame).unwrap();
+        self.steps.push(Step::Send(label, bytes));
+        self
+    }
+
+    /// Sends bytes as they are, for frames the frame types refuse to build.
+    pub fn send_raw(mut self, bytes: &[u8]) -> Self {
+        self.steps.push(Step::Send(format!("raw {:?}", bytes), bytes.to_vec()));
+        self
+    }
+
+    /// Sends a header block in a single HEADERS frame.
+    pub fn headers(self, stream_id: u32, fields: &[(&str, &str)], end_stream: bool) -> Self {
+        self.header_block(stream_id, fields, end_stream, None)
+    }
+
+    /// Sends a header block in a HEADERS frame followed by CONTINUATION
+    /// frames carrying at most `max` octets of the block each.
+    pub fn header_block(mut self,
+                        stream_id: u32,
+                        fields: &[(&str, &str)],
+                        end_stream: bool,
+                        max: Option<usize>)
+                        -> Self {
+        let headers: Vec<Header> = fields.iter()
+            .map(|&(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect();
+        let mut block = Vec::new();
+        self.encoder.encode(&headers, &mut block);
+        let id = StreamId::from(stream_id);
+        let max = max.unwrap_or(block.len());
+        let mut chunks: Vec<&[u8]> = block.chunks(::std::cmp::max(max, 1)).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        let label = format!("header block on {:?} {:?} end_stream={}", id, fields, end_stream);
+        let mut bytes = Vec::new();
+        let mut frame = HeadersFrame::new(id).fragment(chunks[0]);
+        if end_stream {
+            frame = frame.end_stream();
+        }
+        if chunks.len() == 1 {
+            frame = frame.end_headers();
+        }
+        bytes.write_frame(frame).unwrap();
+        for (i, chunk) in chunks.iter().enumerate().skip(1) {
+            let mut frame = ContinuationFrame::new(id).fragment(*chunk);
+            if i == chunks.len() - 1 {
+                frame = frame.end_headers();
+            }
+            bytes.write_frame(frame).unwrap();
+        }
+        self.steps.push(Step::Send(label, bytes));
+        self
+    }
+
+    /// Sends a PUSH_PROMISE with the promised request in a single frame.
+    pub fn push_promise(mut self, stream_id: u32, promised: u32, fields: &[(&str, &str)])
+                        -> Self {
+        let headers: Vec<Header> = fields.iter()
+            .map(|&(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect();
+        let mut block = Vec::new();
+        self.encoder.encode(&headers, &mut block);
+        let frame = PushPromiseFrame::new(StreamId::from(stream_id), StreamId::from(promised))
+            .fragment(block)
+            .end_headers();
+        let label = format!("push promise on {} of {} {:?}", stream_id, promised, fields);
+        let mut bytes = Vec::new();
+        bytes.write_frame(frame).unwrap();
+        self.steps.push(Step::Send(label, bytes));
+        self
+    }
+
+    pub fn expect(mut self, expect: Expect) -> Self {
+        self.steps.push(Step::Expect(expect));
+        self
+    }
+
+    /// Expects the target to send `bytes` as they are, before any frames.
+    pub fn expect_raw(mut self, bytes: &[u8]) -> Self {
+        self.steps.push(Step::ExpectRaw(bytes.to_vec()));
+        self
+    }
+
+    /// Fails if a frame matching `expect` was skipped while waiting for the
+    /// previous expectation.
+    pub fn refute(mut self, expect: Expect) -> Self {
+        self.steps.push(Step::Refute(expect));
+        self
+    }
+
+    /// Expects GOAWAY with the error code, followed by the connection closing.
+    pub fn connection_error(self, kind: ErrorKind) -> Self {
+        self.expect(Expect::GoAway(kind)).expect(Expect::Closed)
+    }
+
+    /// Expects RST_STREAM with the error code.
+    pub fn stream_error(self, stream_id: u32, kind: ErrorKind) -> Self {
+        self.expect(Expect::Reset(stream_id, kind))
+    }
+
+    /// Runs the closure on the target, e.g. to send a request.
+    pub fn act<F: FnMut(&mut T) + 'static>(mut self, f: F) -> Self {
+        self.steps.push(Step::Act(Box::new(f)));
+        self
+    }
+
+    /// Closes the peer's end of the transport.
+    pub fn close(mut self) -> Self {
+        self.steps.push(Step::Close);
+        self
+    }
+
+    fn run<S: PeerStream>(self, target: &mut T, peer: &mut Peer<S>) {
+        for step in self.steps {
+            match step {
+                Step::Preface(always) => {
+                    if always || target.expects_preface() {
+                        peer.send("preface", PREFACE);
+                    }
+                }
+                Step::Send(label, bytes) => peer.send(&label, &bytes),
+                Step::Expect(expect) => peer.expect(target, &expect),
+                Step::ExpectRaw(bytes) => peer.expect_raw(target, &bytes),
+                Step::Refute(expect) => peer.refute(&expect),
+                Step::Act(mut f) => f(target),
+                Step::Close => {
+                    peer.transcript.push("-> close".to_owned());
+                    PeerStream::close(&peer.stream);
+                }
+            }
+        }
+        let _ = target.drive();
+    }
+}
+
+impl<V: Service> Script<ServerTarget<V>> {
+    /// Runs the script against a server connection answering with `service`.
+    pub fn run_server(self, service: V) -> ServerTarget<V> {
+        let (local, remote) = MockStream::new();
+        let conn = ServerConnection::new(local, server::default_settings(), &BufferPool::new())
+            .unwrap();
+        let mut target = ServerTarget {
+            conn: conn,
+            service: service,
+        };
+        let mut peer = Peer::new(remote, false).unwrap();
+        self.run(&mut target, &mut peer);
+        target
+    }
+}
+
+impl Script<Client<MockStream>> {
+    /// Runs the script against a client.
+    pub fn run_client(self) -> Client<MockStream> {
+        self.run_client_with(client::default_settings())
+    }
+
+    /// Runs the script against a client announcing `settings`.
+    pub fn run_client_with(self, settings: Settings) -> Client<MockStream> {
+        let (local, remote) = MockStream::new();
+        let mut target = Client::new(local, settings, &BufferPool::new()).unwrap();
+        let mut peer = Peer::new(remote, true).unwrap();
+        self.run(&mut target, &mut peer);
+        target
+    }
+
+    /// Runs the script against a client starting with an upgrade `request`.
+    pub fn run_client_upgrade(self, request: Request) -> Client<MockStream> {
+        let (local, remote) = MockStream::new();
+        let settings = client::default_settings();
+        let mut target = Client::upgrade(local, settings, &BufferPool::new(), request).unwrap();
+        let mut peer = Peer::new(remote, true).unwrap();
+        self.run(&mut target, &mut peer);
+        target
+    }
+}
+
+impl Script<Remote> {
+    /// Runs the script over a new connection to the server at `addr`.
+    pub fn run_remote(self, addr: &SocketAddr) {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut peer = Peer::new(stream, false).unwrap();
+        self.run(&mut Remote { server: true }, &mut peer);
+    }
+
+    /// Runs the script as the server of the next client connecting to the
+    /// `listener`.
+    pub fn run_accept(self, listener: &TcpListener) {
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nodelay(true).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut peer = Peer::new(stream, true).unwrap();
+        self.run(&mut Remote { server: false }, &mut peer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use error::ErrorKind;
+    use frame::ping::PingFrame;
+    use message::{Request, Response};
+    use super::{Script, Expect};
+
+    fn hello(req: Request) -> Response {
+        match req.path() {
+            Some(b"/") => Response::new(200).body(&b"hello"[..]),
+            _ => Response::new(404),
+        }
+    }
+
+    #[test]
+    fn test_handshake_and_ping() {
+        Script::new()
+            .handshake()
+            .send(PingFrame::new([1, 2, 3, 4, 5, 6, 7, 8]))
+            .expect(Expect::PingAck([1, 2, 3, 4, 5, 6, 7, 8]))
+            .run_server(hello);
+    }
+
+    #[test]
+    fn test_peer_closes() {
+        Script::new()
+            .handshake()
+            .close()
+            .expect(Expect::Closed)
+            .run_server(hello);
+    }
+
+    #[test]
+    #[should_panic(expected = "timed out waiting for Reset")]
+    fn test_unmet_expectation() {
+        Script::new()
+            .handshake()
+            .expect(Expect::Reset(1, ErrorKind::Protocol))
+            .run_server(hello);
+    }
+}
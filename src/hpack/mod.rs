@@ -0,0 +1,449 @@
+//! HPACK header compression (rfc 7541).
+//!
+//! Every connection holds one `Encoder` for the header blocks it sends and one
+//! `Decoder` for the header blocks it receives. Both keep a dynamic table, so
+//! all header blocks of a connection must pass through them in order.
+
+mod huffman;
+mod table;
+
+use error::{Error, ErrorKind, Result};
+use self::table::{Table, Match};
+
+/// A header field, name and value.
+pub type Header = (Vec<u8>, Vec<u8>);
+
+/// Default of `SETTINGS_HEADER_TABLE_SIZE`.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Limit of a decoded header list unless `SETTINGS_MAX_HEADER_LIST_SIZE`
+/// was announced.
+pub const DEFAULT_MAX_HEADER_LIST_SIZE: usize = 64 * 1024;
+
+/// Size of a header list as `SETTINGS_MAX_HEADER_LIST_SIZE` counts it, the
+/// octets of names and values plus 32 per field (rfc 7540 6.5.2).
+pub fn header_list_size(headers: &[Header]) -> usize {
+    headers.iter().map(|&(ref name, ref value)| field_size(name, value)).sum()
+}
+
+fn field_size(name: &[u8], value: &[u8]) -> usize {
+    name.len() + value.len() + 32
+}
+
+/// Headers which are never added to a table, so they can't be probed by
+/// an attacker sharing the connection (rfc 7541 7.1.3).
+const SENSITIVE: &'static [&'static [u8]] = &[b"authorization", b"proxy-authorization"];
+
+fn compression_error(msg: &'static str) -> Error {
+    Error::new(ErrorKind::Compression, msg)
+}
+
+fn list_too_large() -> Error {
+    Error::new(ErrorKind::EnhanceYourCalm, "header list too large")
+}
+
+/// Appends `value` as integer with an `n` bit prefix, `flags` are the high
+/// bits of the first octet (rfc 7541 5.1).
+fn encode_int(value: usize, n: u8, flags: u8, dst: &mut Vec<u8>) {
+    let max = (1 << n) - 1;
+    if value < max {
+        dst.push(flags | value as u8);
+        return;
+    }
+    dst.push(flags | max as u8);
+    let mut value = value - max;
+    while value >= 128 {
+        dst.push((value % 128) as u8 | 0x80);
+        value /= 128;
+    }
+    dst.push(value as u8);
+}
+
+/// Decodes an integer with an `n` bit prefix, returns the value and the
+/// number of octets read.
+fn decode_int(buf: &[u8], n: u8) -> Result<(usize, usize)> {
+    let max = (1 << n) - 1;
+    let first = match buf.first() {
+        Some(&b) => (b & max as u8) as usize,
+        None => return Err(compression_error("truncated integer")),
+    };
+    if first < max {
+        return Ok((first, 1));
+    }
+    let mut value = max;
+    let mut shift = 0;
+    for (i, &b) in buf[1..].iter().enumerate() {
+        // more than 4 continuation octets exceed any sane size
+        if shift > 28 {
+            return Err(compression_error("integer overflow"));
+        }
+        value += ((b & 0x7f) as usize) << shift;
+        shift += 7;
+        if b & 0x80 == 0 {
+            return Ok((value, i + 2));
+        }
+    }
+    Err(compression_error("truncated integer"))
+}
+
+/// The `Encoder` compresses header lists into header blocks.
+#[derive(Debug)]
+pub struct Encoder {
+    table: Table,
+    // smallest and final table size to signal with the next block
+    size_update: Option<(usize, usize)>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            table: Table::new(DEFAULT_TABLE_SIZE),
+            size_update: None,
+        }
+    }
+
+    /// Applies the peer's `SETTINGS_HEADER_TABLE_SIZE`, the encoder uses at
+    /// most the default table size.
+    pub fn set_max_table_size(&mut self, max_size: usize) {
+        let size = ::std::cmp::min(max_size, DEFAULT_TABLE_SIZE);
+        if size == self.table.max_size() && self.size_update.is_none() {
+            return;
+        }
+        let min = match self.size_update {
+            Some((min, _)) => ::std::cmp::min(min, size),
+            None => size,
+        };
+        self.size_update = Some((min, size));
+        self.table.set_max_size(size);
+    }
+
+    /// Appends the header block of `headers` to `dst`.
+    pub fn encode(&mut self, headers: &[Header], dst: &mut Vec<u8>) {
+        if let Some((min, size)) = self.size_update.take() {
+            if min < size {
+                encode_int(min, 5, 0x20, dst);
+            }
+            encode_int(size, 5, 0x20, dst);
+        }
+        for &(ref name, ref value) in headers {
+            let sensitive = SENSITIVE.contains(&&name[..]);
+            match self.table.find(name, value) {
+                Match::Full(index) if !sensitive => encode_int(index, 7, 0x80, dst),
+                Match::Full(index) | Match::Name(index) => {
+                    if sensitive {
+                        encode_int(index, 4, 0x10, dst);
+                    } else {
+                        encode_int(index, 6, 0x40, dst);
+                        self.table.insert(name.clone(), value.clone());
+                    }
+                    encode_string(value, dst);
+                }
+                Match::None => {
+                    if sensitive {
+                        dst.push(0x10);
+                    } else {
+                        dst.push(0x40);
+                        self.table.insert(name.clone(), value.clone());
+                    }
+                    encode_string(name, dst);
+                    encode_string(value, dst);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Encoder::new()
+    }
+}
+
+/// Appends a string literal, Huffman encoded if that is shorter.
+fn encode_string(s: &[u8], dst: &mut Vec<u8>) {
+    let len = huffman::encoded_len(s);
+    if len < s.len() {
+        encode_int(len, 7, 0x80, dst);
+        huffman::encode(s, dst);
+    } else {
+        encode_int(s.len(), 7, 0, dst);
+        dst.extend_from_slice(s);
+    }
+}
+
+/// The `Decoder` decompresses header blocks into header lists.
+///
+/// Every error is a connection error, the dynamic table can't be trusted
+/// after a failed block. It is of type `COMPRESSION_ERROR` unless the header
+/// list exceeds its limit.
+pub struct Decoder {
+    table: Table,
+    huffman: huffman::Decoder,
+    max_table_size: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            table: Table::new(DEFAULT_TABLE_SIZE),
+            huffman: huffman::Decoder::new(),
+            max_table_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    /// Sets the limit for table size updates, i.e. our acknowledged
+    /// `SETTINGS_HEADER_TABLE_SIZE`.
+    pub fn set_max_table_size(&mut self, max_size: usize) {
+        self.max_table_size = max_size;
+        if self.table.max_size() > max_size {
+            self.table.set_max_size(max_size);
+        }
+    }
+
+    /// Decodes a complete header block into a header list of at most
+    /// `max_list_size`, counted like `header_list_size`.
+    ///
+    /// Indexed fields are copied out of the table, the limit keeps a small
+    /// block from expanding into a huge list.
+    pub fn decode(&mut self, mut block: &[u8], max_list_size: usize) -> Result<Vec<Header>> {
+        let mut headers = Vec::new();
+        let mut list_size = 0;
+        while let Some(&b) = block.first() {
+            let n = if b & 0x80 == 0x80 {
+                // indexed header field
+                let (index, n) = try!(decode_int(block, 7));
+                let (name, value) = try!(self.get(index));
+                list_size += field_size(name, value);
+                if list_size > max_list_size {
+                    return Err(list_too_large());
+                }
+                headers.push((name.to_vec(), value.to_vec()));
+                n
+            } else if b & 0xc0 == 0x40 {
+                // literal with incremental indexing
+                let (header, n) = try!(self.decode_literal(block, 6));
+                self.table.insert(header.0.clone(), header.1.clone());
+                list_size += field_size(&header.0, &header.1);
+                headers.push(header);
+                n
+            } else if b & 0xe0 == 0x20 {
+                // dynamic table size update, only allowed at the beginning
+                if !headers.is_empty() {
+                    return Err(compression_error("table size update after header field"));
+                }
+                let (size, n) = try!(decode_int(block, 5));
+                if size > self.max_table_size {
+                    return Err(compression_error("table size update exceeds the limit"));
+                }
+                self.table.set_max_size(size);
+                n
+            } else {
+                // literal without indexing or never indexed
+                let (header, n) = try!(self.decode_literal(block, 4));
+                list_size += field_size(&header.0, &header.1);
+                headers.push(header);
+                n
+            };
+            if list_size > max_list_size {
+                return Err(list_too_large());
+            }
+            block = &block[n..];
+        }
+        Ok(headers)
+    }
+
+    fn get(&self, index: usize) -> Result<(&[u8], &[u8])> {
+        self.table.get(index).ok_or_else(|| compression_error("invalid table index"))
+    }
+
+    /// Decodes a literal header field with a name index of `n` bits.
+    fn decode_literal(&self, block: &[u8], n: u8) -> Result<(Header, usize)> {
+        let (index, mut pos) = try!(decode_int(block, n));
+        let name = if index == 0 {
+            let (name, len) = try!(self.decode_string(&block[pos..]));
+            pos += len;
+            name
+        } else {
+            try!(self.get(index)).0.to_vec()
+        };
+        let (value, len) = try!(self.decode_string(&block[pos..]));
+        Ok(((name, value), pos + len))
+    }
+
+    fn decode_string(&self, buf: &[u8]) -> Result<(Vec<u8>, usize)> {
+        let huffman = buf.first().map_or(false, |&b| b & 0x80 == 0x80);
+        let (len, n) = try!(decode_int(buf, 7));
+        if buf.len() - n < len {
+            return Err(compression_error("truncated string literal"));
+        }
+        let raw = &buf[n..n + len];
+        let s = if huffman {
+            let mut s = Vec::with_capacity(len * 8 / 5);
+            try!(self.huffman.decode(raw, &mut s));
+            s
+        } else {
+            raw.to_vec()
+        };
+        Ok((s, n + len))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Encoder, Decoder, Header, encode_int, decode_int};
+    use super::DEFAULT_MAX_HEADER_LIST_SIZE as MAX_LIST;
+    use error::ErrorKind;
+
+    fn headers(list: &[(&str, &str)]) -> Vec<Header> {
+        list.iter().map(|&(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec())).collect()
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len() / 2).map(|i| u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_integer() {
+        // rfc 7541 C.1
+        let mut buf = Vec::new();
+        encode_int(10, 5, 0, &mut buf);
+        assert_eq!(buf, [10]);
+        buf.clear();
+        encode_int(1337, 5, 0, &mut buf);
+        assert_eq!(buf, [31, 154, 10]);
+        assert_eq!(decode_int(&buf, 5).unwrap(), (1337, 3));
+        assert_eq!(decode_int(&[31, 154], 5).unwrap_err().kind(), ErrorKind::Compression);
+        assert_eq!(decode_int(&[31, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f], 5)
+                       .unwrap_err()
+                       .kind(),
+                   ErrorKind::Compression);
+    }
+
+    #[test]
+    fn test_decode_requests_without_huffman() {
+        // rfc 7541 C.3
+        let mut decoder = Decoder::new();
+        let first = hex("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d");
+        assert_eq!(decoder.decode(&first, MAX_LIST).unwrap(),
+                   headers(&[(":method", "GET"),
+                             (":scheme", "http"),
+                             (":path", "/"),
+                             (":authority", "www.example.com")]));
+        let second = hex("8286 84be 5808 6e6f 2d63 6163 6865");
+        assert_eq!(decoder.decode(&second, MAX_LIST).unwrap(),
+                   headers(&[(":method", "GET"),
+                             (":scheme", "http"),
+                             (":path", "/"),
+                             (":authority", "www.example.com"),
+                             ("cache-control", "no-cache")]));
+        let third = hex("8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 \
+                         65");
+        assert_eq!(decoder.decode(&third, MAX_LIST).unwrap(),
+                   headers(&[(":method", "GET"),
+                             (":scheme", "https"),
+                             (":path", "/index.html"),
+                             (":authority", "www.example.com"),
+                             ("custom-key", "custom-value")]));
+        assert_eq!(decoder.table.size(), 164);
+    }
+
+    #[test]
+    fn test_encode_requests_with_huffman() {
+        // rfc 7541 C.4
+        let mut encoder = Encoder::new();
+        let mut buf = Vec::new();
+        encoder.encode(&headers(&[(":method", "GET"),
+                                  (":scheme", "http"),
+                                  (":path", "/"),
+                                  (":authority", "www.example.com")]),
+                       &mut buf);
+        assert_eq!(buf, hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"));
+        buf.clear();
+        encoder.encode(&headers(&[(":method", "GET"),
+                                  (":scheme", "http"),
+                                  (":path", "/"),
+                                  (":authority", "www.example.com"),
+                                  ("cache-control", "no-cache")]),
+                       &mut buf);
+        assert_eq!(buf, hex("8286 84be 5886 a8eb 1064 9cbf"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        let list = headers(&[(":status", "200"),
+                             ("content-type", "text/html"),
+                             ("authorization", "secret"),
+                             ("x-long", "v".repeat(5000).as_str())]);
+        for _ in 0..3 {
+            let mut buf = Vec::new();
+            encoder.encode(&list, &mut buf);
+            assert_eq!(decoder.decode(&buf, MAX_LIST).unwrap(), list);
+        }
+        // sensitive fields are never indexed
+        let mut buf = Vec::new();
+        encoder.encode(&headers(&[("authorization", "secret")]), &mut buf);
+        assert_eq!(buf[0] & 0xf0, 0x10);
+    }
+
+    #[test]
+    fn test_table_size_update() {
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        encoder.set_max_table_size(0);
+        encoder.set_max_table_size(100);
+        let mut buf = Vec::new();
+        encoder.encode(&headers(&[("x", "y")]), &mut buf);
+        // the minimum and the final size are signalled
+        assert_eq!(buf[..3], [0x20, 0x3f, 0x45]);
+        assert_eq!(decoder.decode(&buf, MAX_LIST).unwrap(), headers(&[("x", "y")]));
+        assert_eq!(decoder.table.max_size(), 100);
+
+        decoder.set_max_table_size(50);
+        assert_eq!(decoder.decode(&[0x3f, 0x45], MAX_LIST).unwrap_err().kind(),
+                   ErrorKind::Compression);
+        // size updates must come first
+        assert_eq!(decoder.decode(&[0x82, 0x20], MAX_LIST).unwrap_err().kind(),
+                   ErrorKind::Compression);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut decoder = Decoder::new();
+        // index 0 and beyond the tables
+        assert_eq!(decoder.decode(&[0x80], MAX_LIST).unwrap_err().kind(), ErrorKind::Compression);
+        assert_eq!(decoder.decode(&[0xbe], MAX_LIST).unwrap_err().kind(), ErrorKind::Compression);
+        // string longer than the block
+        assert_eq!(decoder.decode(&[0x40, 0x05, b'a'], MAX_LIST).unwrap_err().kind(),
+                   ErrorKind::Compression);
+        // missing value
+        assert_eq!(decoder.decode(&[0x44], MAX_LIST).unwrap_err().kind(), ErrorKind::Compression);
+    }
+
+    /// One large table entry referenced over and over must not expand a
+    /// small block beyond the limit.
+    #[test]
+    fn test_indexed_repeat_limit() {
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        let value = String::from_utf8(vec![b'a'; 4000]).unwrap();
+        let mut buf = Vec::new();
+        encoder.encode(&headers(&[("x", &value)]), &mut buf);
+        assert_eq!(decoder.decode(&buf, MAX_LIST).unwrap().len(), 1);
+        // 1-octet references to the entry, 4033 octets each once decoded
+        let block = vec![0xbe; 16 * 1024];
+        assert_eq!(decoder.decode(&block, MAX_LIST).unwrap_err().kind(),
+                   ErrorKind::EnhanceYourCalm);
+        assert_eq!(decoder.decode(&block[..16], MAX_LIST).unwrap().len(), 16);
+        assert_eq!(decoder.decode(&block[..16], 16 * 4033 - 1).unwrap_err().kind(),
+                   ErrorKind::EnhanceYourCalm);
+    }
+}
@@ -0,0 +1,220 @@
+//! Static and dynamic header tables (rfc 7541 2.3).
+
+use std::collections::VecDeque;
+
+/// Overhead added to the length of name and value of every entry.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// The static table of rfc 7541 Appendix A, index 1 is the first entry.
+pub static STATIC_TABLE: [(&'static [u8], &'static [u8]); 61] = [
+    (b":authority", b""),
+    (b":method", b"GET"),
+    (b":method", b"POST"),
+    (b":path", b"/"),
+    (b":path", b"/index.html"),
+    (b":scheme", b"http"),
+    (b":scheme", b"https"),
+    (b":status", b"200"),
+    (b":status", b"204"),
+    (b":status", b"206"),
+    (b":status", b"304"),
+    (b":status", b"400"),
+    (b":status", b"404"),
+    (b":status", b"500"),
+    (b"accept-charset", b""),
+    (b"accept-encoding", b"gzip, deflate"),
+    (b"accept-language", b""),
+    (b"accept-ranges", b""),
+    (b"accept", b""),
+    (b"access-control-allow-origin", b""),
+    (b"age", b""),
+    (b"allow", b""),
+    (b"authorization", b""),
+    (b"cache-control", b""),
+    (b"content-disposition", b""),
+    (b"content-encoding", b""),
+    (b"content-language", b""),
+    (b"content-length", b""),
+    (b"content-location", b""),
+    (b"content-range", b""),
+    (b"content-type", b""),
+    (b"cookie", b""),
+    (b"date", b""),
+    (b"etag", b""),
+    (b"expect", b""),
+    (b"expires", b""),
+    (b"from", b""),
+    (b"host", b""),
+    (b"if-match", b""),
+    (b"if-modified-since", b""),
+    (b"if-none-match", b""),
+    (b"if-range", b""),
+    (b"if-unmodified-since", b""),
+    (b"last-modified", b""),
+    (b"link", b""),
+    (b"location", b""),
+    (b"max-forwards", b""),
+    (b"proxy-authenticate", b""),
+    (b"proxy-authorization", b""),
+    (b"range", b""),
+    (b"referer", b""),
+    (b"refresh", b""),
+    (b"retry-after", b""),
+    (b"server", b""),
+    (b"set-cookie", b""),
+    (b"strict-transport-security", b""),
+    (b"transfer-encoding", b""),
+    (b"user-agent", b""),
+    (b"vary", b""),
+    (b"via", b""),
+    (b"www-authenticate", b""),
+];
+
+/// Result of looking up a header field in the tables.
+#[derive(Debug, PartialEq)]
+pub enum Match {
+    /// Name and value are in the table at the index.
+    Full(usize),
+    /// Only the name is in the table at the index.
+    Name(usize),
+    None,
+}
+
+/// The dynamic table, indexed after the static table with the newest entry
+/// first.
+#[derive(Debug)]
+pub struct Table {
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Table {
+    pub fn new(max_size: usize) -> Table {
+        Table {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size: max_size,
+        }
+    }
+
+    /// The sum of the sizes of all entries.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    #[inline]
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Changes the maximum size, evicting entries which don't fit anymore.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(0);
+    }
+
+    /// Returns the entry at `index` of the combined index space, 1 based.
+    pub fn get(&self, index: usize) -> Option<(&[u8], &[u8])> {
+        if index == 0 {
+            return None;
+        }
+        if index <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[index - 1];
+            return Some((name, value));
+        }
+        self.entries
+            .get(index - STATIC_TABLE.len() - 1)
+            .map(|&(ref name, ref value)| (&name[..], &value[..]))
+    }
+
+    /// Adds an entry, evicting the oldest entries to make room. An entry
+    /// larger than the maximum size empties the table (rfc 7541 4.4).
+    pub fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    /// Searches both tables, preferring full matches.
+    pub fn find(&self, name: &[u8], value: &[u8]) -> Match {
+        let mut result = Match::None;
+        let dynamic = self.entries.iter().map(|&(ref n, ref v)| (&n[..], &v[..]));
+        for (i, (n, v)) in STATIC_TABLE.iter().cloned().chain(dynamic).enumerate() {
+            if n == name {
+                if v == value {
+                    return Match::Full(i + 1);
+                }
+                if result == Match::None {
+                    result = Match::Name(i + 1);
+                }
+            }
+        }
+        result
+    }
+
+    /// Evicts entries until `additional` bytes fit.
+    fn evict(&mut self, additional: usize) {
+        while self.size + additional > self.max_size {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Table, Match};
+
+    #[test]
+    fn test_index_space() {
+        let mut table = Table::new(4096);
+        assert!(table.get(0).is_none());
+        assert_eq!(table.get(2), Some((&b":method"[..], &b"GET"[..])));
+        assert_eq!(table.get(61), Some((&b"www-authenticate"[..], &b""[..])));
+        assert!(table.get(62).is_none());
+        table.insert(b"a".to_vec(), b"1".to_vec());
+        table.insert(b"b".to_vec(), b"2".to_vec());
+        assert_eq!(table.get(62), Some((&b"b"[..], &b"2"[..])));
+        assert_eq!(table.get(63), Some((&b"a"[..], &b"1"[..])));
+        assert_eq!(table.size(), 2 * 34);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut table = Table::new(70);
+        table.insert(b"a".to_vec(), b"1".to_vec());
+        table.insert(b"b".to_vec(), b"2".to_vec());
+        table.insert(b"c".to_vec(), b"3".to_vec());
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(63), Some((&b"b"[..], &b"2"[..])));
+        table.set_max_size(40);
+        assert_eq!(table.len(), 1);
+        // too large for the table, empties it
+        table.insert(vec![b'x'; 20], vec![]);
+        assert_eq!(table.len(), 0);
+        assert_eq!(table.size(), 0);
+    }
+
+    #[test]
+    fn test_find() {
+        let mut table = Table::new(4096);
+        assert_eq!(table.find(b":method", b"POST"), Match::Full(3));
+        assert_eq!(table.find(b":method", b"PUT"), Match::Name(2));
+        assert_eq!(table.find(b"x-custom", b"1"), Match::None);
+        table.insert(b"x-custom".to_vec(), b"1".to_vec());
+        assert_eq!(table.find(b"x-custom", b"1"), Match::Full(62));
+        assert_eq!(table.find(b"x-custom", b"2"), Match::Name(62));
+    }
+}
@@ -4,20 +4,42 @@ extern crate log;
 extern crate bitflags;
 extern crate byteorder;
 extern crate mio;
+extern crate rand;
+#[cfg(feature = "http")]
+extern crate http;
+#[cfg(test)]
+extern crate quickcheck;
 
-// #[cfg(test)]
-// mod mock;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
+#[cfg(any(test, feature = "testing"))]
+pub mod harness;
+#[cfg(test)]
+mod conformance;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
 
+#[cfg(feature = "http")]
+mod compat;
 mod error;
+mod body;
 mod connection;
 mod frame;
-mod server;
-// mod client;
+mod hpack;
+mod stream;
+mod upgrade;
 pub mod buffer;
+pub mod client;
+pub mod client_pool;
+pub mod message;
+pub mod pool;
+pub mod server;
 
 use frame::settings::{Setting, SettingsFrame};
+pub use connection::Transport;
+pub use error::{Error, ErrorKind, Result};
 
-#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
 pub struct StreamId(u32);
 
 impl PartialEq<u32> for StreamId {
@@ -38,6 +60,7 @@ impl From<u32> for StreamId {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct Settings {
     pub header_table_size: u32,
     pub enable_push: bool,
@@ -48,13 +71,28 @@ pub struct Settings {
 }
 
 impl Settings {
+    /// The SETTINGS frame announcing these settings.
+    fn to_frame(&self) -> SettingsFrame {
+        let mut frame = SettingsFrame::default();
+        frame.add_setting(Setting::HeaderTableSize(self.header_table_size));
+        frame.add_setting(Setting::EnablePush(self.enable_push));
+        if let Some(val) = self.max_concurrent_streams {
+            frame.add_setting(Setting::MaxConcurrentStreams(val));
+        }
+        frame.add_setting(Setting::InitialWindowSize(self.initial_window_size));
+        frame.add_setting(Setting::MaxFrameSize(self.max_frame_size));
+        if let Some(val) = self.max_header_list_size {
+            frame.add_setting(Setting::MaxHeaderListSize(val));
+        }
+        frame
+    }
+
     fn update(&mut self, frame: SettingsFrame) {
         for setting in frame.settings() {
             match setting {
                 Setting::HeaderTableSize(val) => self.header_table_size = val,
                 Setting::EnablePush(val) => self.enable_push = val,
                 Setting::MaxConcurrentStreams(val) => self.max_concurrent_streams = Some(val),
-                // TODO update streams with new window size
                 Setting::InitialWindowSize(val) => self.initial_window_size = val,
                 Setting::MaxFrameSize(val) => self.max_frame_size = val,
                 Setting::MaxHeaderListSize(val) => self.max_header_list_size = Some(val),
@@ -69,13 +107,14 @@ impl Default for Settings {
             header_table_size: 4096,
             enable_push: true,
             max_concurrent_streams: None,
-            initial_window_size: 65536,
+            initial_window_size: 65535,
             max_frame_size: 16384,
             max_header_list_size: None,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct WindowSize(i32);
 
 impl WindowSize {
@@ -86,8 +125,20 @@ impl WindowSize {
         self.0 as usize
     }
 
-    fn set(&mut self, n: i32) {
-        self.0 = n;
+    /// Adds a WINDOW_UPDATE increment or a change of the initial window size,
+    /// the window must not exceed 2^31-1 octets (rfc 6.9.1).
+    fn increase(&mut self, n: i64) -> Result<()> {
+        let size = self.0 as i64 + n;
+        if size > i32::max_value() as i64 {
+            return Err(Error::new(ErrorKind::FlowControl, "flow control window exceeds 2^31-1"));
+        }
+        self.0 = size as i32;
+        Ok(())
+    }
+
+    /// Subtracts sent or received flow-controlled octets.
+    fn decrease(&mut self, n: usize) {
+        self.0 -= n as i32;
     }
 }
 
@@ -0,0 +1,491 @@
+//! Requests and responses as seen by applications.
+//!
+//! Received header blocks are checked against the rules of rfc 8.1.2 before
+//! they become a `Request` or `Response`, violations make the message
+//! malformed and are answered with a stream error of type PROTOCOL_ERROR
+//! (rfc 8.1.2.6).
+
+use std::str;
+use error::{Error, Result};
+use hpack::Header;
+
+pub use body::{Body, BodySender, Responder};
+#[cfg(feature = "http")]
+pub use compat::Trailers;
+
+/// Header fields only meaningful for HTTP/1 connections (rfc 8.1.2.2).
+const CONNECTION_HEADERS: [&'static str; 5] = ["connection",
+                                               "keep-alive",
+                                               "proxy-connection",
+                                               "transfer-encoding",
+                                               "upgrade"];
+
+const REQUEST_PSEUDO_HEADERS: [&'static str; 4] = [":method", ":scheme", ":authority", ":path"];
+
+fn find<'a>(headers: &'a [Header], name: &str) -> Option<&'a [u8]> {
+    headers.iter()
+        .find(|&&(ref n, _)| n == name.as_bytes())
+        .map(|&(_, ref v)| &v[..])
+}
+
+fn malformed(msg: String) -> Error {
+    Error::protocol(format!("malformed message: {}", msg))
+}
+
+fn is_pseudo(name: &[u8]) -> bool {
+    name.first() == Some(&b':')
+}
+
+/// Checks the regular header fields of any header block.
+fn check_field(name: &[u8], value: &[u8]) -> Result<()> {
+    let printable = String::from_utf8_lossy(name);
+    if name.is_empty() {
+        return Err(malformed("empty header field name".to_owned()));
+    }
+    if name.iter().any(|b| b.is_ascii_uppercase()) {
+        return Err(malformed(format!("uppercase header field name '{}'", printable)));
+    }
+    if CONNECTION_HEADERS.iter().any(|h| h.as_bytes() == name) {
+        return Err(malformed(format!("connection-specific header field '{}'", printable)));
+    }
+    if name == b"te" && value != b"trailers" {
+        return Err(malformed("te header field with a value other than 'trailers'".to_owned()));
+    }
+    Ok(())
+}
+
+/// Checks a header block whose pseudo-header fields must be among `allowed`,
+/// each at most once and before all regular fields.
+fn check_block(headers: &[Header], allowed: &[&str]) -> Result<()> {
+    let mut regular = false;
+    for (i, &(ref name, ref value)) in headers.iter().enumerate() {
+        if !is_pseudo(name) {
+            regular = true;
+            try!(check_field(name, value));
+            continue;
+        }
+        let printable = String::from_utf8_lossy(name);
+        if regular {
+            return Err(malformed(format!("pseudo-header field '{}' after regular fields",
+                                         printable)));
+        }
+        if !allowed.iter().any(|p| p.as_bytes() == &name[..]) {
+            return Err(malformed(format!("invalid pseudo-header field '{}'", printable)));
+        }
+        if headers[..i].iter().any(|&(ref n, _)| n == name) {
+            return Err(malformed(format!("duplicate pseudo-header field '{}'", printable)));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the header block of a request (rfc 8.1.2.1, 8.1.2.3, 8.3).
+pub fn check_request(headers: &[Header]) -> Result<()> {
+    try!(check_block(headers, &REQUEST_PSEUDO_HEADERS));
+    let method = match find(headers, ":method") {
+        Some(method) => method,
+        None => return Err(malformed("missing :method".to_owned())),
+    };
+    if method == b"CONNECT" {
+        // only the authority to connect to (rfc 8.3)
+        if find(headers, ":authority").is_none() {
+            return Err(malformed("CONNECT without :authority".to_owned()));
+        }
+        if find(headers, ":scheme").is_some() || find(headers, ":path").is_some() {
+            return Err(malformed("CONNECT with :scheme or :path".to_owned()));
+        }
+        return content_length(headers).map(|_| ());
+    }
+    if find(headers, ":scheme").is_none() {
+        return Err(malformed("missing :scheme".to_owned()));
+    }
+    match find(headers, ":path") {
+        Some(path) if !path.is_empty() => {}
+        Some(_) => return Err(malformed("empty :path".to_owned())),
+        None => return Err(malformed("missing :path".to_owned())),
+    }
+    content_length(headers).map(|_| ())
+}
+
+/// Checks the header block of a response (rfc 8.1.2.1, 8.1.2.4).
+pub fn check_response(headers: &[Header]) -> Result<()> {
+    try!(check_block(headers, &[":status"]));
+    try!(status(headers));
+    content_length(headers).map(|_| ())
+}
+
+/// Checks a trailing header block, which must not contain pseudo-header
+/// fields (rfc 8.1).
+pub fn check_trailers(headers: &[Header]) -> Result<()> {
+    check_block(headers, &[])
+}
+
+fn status(headers: &[Header]) -> Result<u16> {
+    let value = match find(headers, ":status") {
+        Some(value) => value,
+        None => return Err(malformed("missing :status".to_owned())),
+    };
+    // three digits, `parse` would take a sign as well
+    if value.len() != 3 || !value.iter().all(|b| b.is_ascii_digit()) {
+        return Err(malformed("invalid :status".to_owned()));
+    }
+    let status = value.iter().fold(0, |n, &b| n * 10 + (b - b'0') as u16);
+    if status < 100 || status > 599 {
+        return Err(malformed("invalid :status".to_owned()));
+    }
+    Ok(status)
+}
+
+/// The value of the content-length header fields, all of which must agree.
+pub fn content_length(headers: &[Header]) -> Result<Option<u64>> {
+    let mut length = None;
+    for &(ref name, ref value) in headers {
+        if name != b"content-length" {
+            continue;
+        }
+        let digits = !value.is_empty() && value.iter().all(|b| b.is_ascii_digit());
+        let parsed = if digits {
+            str::from_utf8(value).ok().and_then(|s| s.parse::<u64>().ok())
+        } else {
+            None
+        };
+        match (parsed, length) {
+            (None, _) => return Err(malformed("invalid content-length".to_owned())),
+            (Some(n), Some(m)) if n != m => {
+                return Err(malformed("conflicting content-length values".to_owned()))
+            }
+            (n, _) => length = n,
+        }
+    }
+    Ok(length)
+}
+
+/// Tracks the length of a message body against its content-length
+/// (rfc 8.1.2.6).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyLength {
+    expected: Option<u64>,
+    received: u64,
+}
+
+impl BodyLength {
+    pub fn new(expected: Option<u64>) -> BodyLength {
+        BodyLength {
+            expected: expected,
+            received: 0,
+        }
+    }
+
+    /// Adds received DATA, fails once the body exceeds the content-length.
+    pub fn add(&mut self, len: usize) -> Result<()> {
+        self.received += len as u64;
+        match self.expected {
+            Some(n) if self.received > n => {
+                Err(malformed(format!("body exceeds content-length of {}", n)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The body is complete, fails if it is shorter than the content-length.
+    pub fn end(&self) -> Result<()> {
+        match self.expected {
+            Some(n) if self.received != n => {
+                Err(malformed(format!("body of {} octets with content-length {}",
+                                      self.received,
+                                      n)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A request, the pseudo-header fields are part of `headers`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Request {
+    pub headers: Vec<Header>,
+    pub body: Vec<u8>,
+    /// header fields sent after the body
+    pub trailers: Vec<Header>,
+}
+
+impl Request {
+    pub fn new(method: &str, scheme: &str, authority: &str, path: &str) -> Request {
+        Request::default()
+            .header(":method", method)
+            .header(":scheme", scheme)
+            .header(":authority", authority)
+            .header(":path", path)
+    }
+
+    /// Builds a request from a received header block.
+    pub fn from_headers(headers: Vec<Header>) -> Result<Request> {
+        try!(check_request(&headers));
+        Ok(Request {
+            headers: headers,
+            body: Vec::new(),
+            trailers: Vec::new(),
+        })
+    }
+
+    pub fn header<N, V>(mut self, name: N, value: V) -> Self
+        where N: Into<Vec<u8>>,
+              V: Into<Vec<u8>>
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn body<T: Into<Vec<u8>>>(mut self, body: T) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn trailer<N, V>(mut self, name: N, value: V) -> Self
+        where N: Into<Vec<u8>>,
+              V: Into<Vec<u8>>
+    {
+        self.trailers.push((name.into(), value.into()));
+        self
+    }
+
+    /// The value of the first header field called `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        find(&self.headers, name)
+    }
+
+    /// The value of the first trailer field called `name`.
+    pub fn get_trailer(&self, name: &str) -> Option<&[u8]> {
+        find(&self.trailers, name)
+    }
+
+    pub fn method(&self) -> Option<&[u8]> {
+        self.get(":method")
+    }
+
+    pub fn path(&self) -> Option<&[u8]> {
+        self.get(":path")
+    }
+
+    /// Returns true if sending the request again has the same effect as
+    /// sending it once (rfc 7231 4.2.2).
+    pub fn is_idempotent(&self) -> bool {
+        match self.method() {
+            Some(b"GET") | Some(b"HEAD") | Some(b"OPTIONS") | Some(b"TRACE") | Some(b"PUT") |
+            Some(b"DELETE") => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the request asks for 100 (Continue) before its body
+    /// is sent (rfc 7231 5.1.1).
+    pub fn expects_continue(&self) -> bool {
+        self.get("expect").map_or(false, |v| v.eq_ignore_ascii_case(b"100-continue"))
+    }
+}
+
+/// A response, `headers` does not contain the `:status` pseudo-header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<Header>,
+    pub body: Vec<u8>,
+    /// header fields sent after the body
+    pub trailers: Vec<Header>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status: status,
+            headers: Vec::new(),
+            body: Vec::new(),
+            trailers: Vec::new(),
+        }
+    }
+
+    pub fn header<N, V>(mut self, name: N, value: V) -> Self
+        where N: Into<Vec<u8>>,
+              V: Into<Vec<u8>>
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn body<T: Into<Vec<u8>>>(mut self, body: T) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn trailer<N, V>(mut self, name: N, value: V) -> Self
+        where N: Into<Vec<u8>>,
+              V: Into<Vec<u8>>
+    {
+        self.trailers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        find(&self.headers, name)
+    }
+
+    pub fn get_trailer(&self, name: &str) -> Option<&[u8]> {
+        find(&self.trailers, name)
+    }
+
+    /// Returns true for 1xx responses, which precede the final response.
+    pub fn is_informational(&self) -> bool {
+        self.status >= 100 && self.status < 200
+    }
+
+    /// The header block sent for this response, starting with `:status`.
+    pub fn header_block(&self) -> Vec<Header> {
+        let mut block = Vec::with_capacity(self.headers.len() + 1);
+        block.push((b":status".to_vec(), self.status.to_string().into_bytes()));
+        block.extend(self.headers.iter().cloned());
+        block
+    }
+
+    /// Builds a response from a received header block.
+    pub fn from_headers(headers: Vec<Header>) -> Result<Response> {
+        try!(check_response(&headers));
+        let status = try!(status(&headers));
+        Ok(Response {
+            status: status,
+            headers: headers.into_iter().filter(|&(ref n, _)| !is_pseudo(n)).collect(),
+            body: Vec::new(),
+            trailers: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use error::ErrorKind;
+    use hpack::Header;
+    use super::{Request, Response, BodyLength, check_request, check_trailers, content_length};
+
+    fn block(fields: &[(&str, &str)]) -> Vec<Header> {
+        fields.iter().map(|&(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec())).collect()
+    }
+
+    fn get(extra: &[(&str, &str)]) -> Vec<Header> {
+        let mut headers = block(&[(":method", "GET"), (":scheme", "http"), (":path", "/")]);
+        headers.extend(block(extra));
+        headers
+    }
+
+    #[test]
+    fn test_request() {
+        let req = Request::new("GET", "http", "example.com", "/").header("accept", "*/*");
+        assert_eq!(req.method(), Some(&b"GET"[..]));
+        assert_eq!(req.path(), Some(&b"/"[..]));
+        assert_eq!(req.get("accept"), Some(&b"*/*"[..]));
+        assert_eq!(req.get("cookie"), None);
+        assert_eq!(Request::from_headers(req.headers.clone()).unwrap(), req);
+        assert!(!req.expects_continue());
+        assert!(req.is_idempotent());
+        assert!(!Request::new("POST", "http", "localhost", "/").is_idempotent());
+        assert!(req.header("expect", "100-Continue").expects_continue());
+    }
+
+    #[test]
+    fn test_response_headers() {
+        let res = Response::new(404).header("server", "deuter");
+        let block = res.header_block();
+        assert_eq!(block[0], (b":status".to_vec(), b"404".to_vec()));
+        assert_eq!(Response::from_headers(block).unwrap(), res);
+        assert!(Response::from_headers(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_malformed_requests() {
+        let cases = vec![
+            ("uppercase name", get(&[("Accept", "*/*")])),
+            ("pseudo-header after regular field", {
+                let mut headers = block(&[(":method", "GET"), (":scheme", "http")]);
+                headers.extend(block(&[("accept", "*/*"), (":path", "/")]));
+                headers
+            }),
+            ("unknown pseudo-header", get(&[(":foo", "bar")])),
+            ("response pseudo-header", block(&[(":status", "200"), (":method", "GET"),
+                                              (":scheme", "http"), (":path", "/")])),
+            ("duplicate pseudo-header", block(&[(":method", "GET"), (":scheme", "http"),
+                                               (":path", "/"), (":path", "/")])),
+            ("missing :method", block(&[(":scheme", "http"), (":path", "/")])),
+            ("missing :scheme", block(&[(":method", "GET"), (":path", "/")])),
+            ("missing :path", block(&[(":method", "GET"), (":scheme", "http")])),
+            ("empty :path", block(&[(":method", "GET"), (":scheme", "http"), (":path", "")])),
+            ("connection header", get(&[("connection", "keep-alive")])),
+            ("keep-alive header", get(&[("keep-alive", "timeout=5")])),
+            ("transfer-encoding header", get(&[("transfer-encoding", "chunked")])),
+            ("te other than trailers", get(&[("te", "gzip")])),
+            ("invalid content-length", get(&[("content-length", "x")])),
+            ("signed content-length", get(&[("content-length", "+5")])),
+            ("empty content-length", get(&[("content-length", "")])),
+            ("conflicting content-length", get(&[("content-length", "1"),
+                                                 ("content-length", "2")])),
+            ("CONNECT with :path", block(&[(":method", "CONNECT"), (":authority", "a:443"),
+                                          (":path", "/")])),
+        ];
+        for (name, headers) in cases {
+            match check_request(&headers) {
+                Ok(()) => panic!("{}: accepted {:?}", name, headers),
+                Err(e) => assert_eq!(e.kind(), ErrorKind::Protocol, "{}", name),
+            }
+        }
+    }
+
+    #[test]
+    fn test_valid_requests() {
+        check_request(&get(&[("te", "trailers"), ("content-length", "0")])).unwrap();
+        check_request(&block(&[(":method", "CONNECT"), (":authority", "a:443")])).unwrap();
+        assert_eq!(content_length(&get(&[("content-length", "5"), ("content-length", "5")]))
+                       .unwrap(),
+                   Some(5));
+    }
+
+    #[test]
+    fn test_malformed_responses() {
+        let cases = vec![
+            block(&[]),
+            block(&[(":status", "2000")]),
+            block(&[(":status", "abc")]),
+            block(&[(":status", "+99")]),
+            block(&[(":status", "099")]),
+            block(&[(":status", "000")]),
+            block(&[(":status", "600")]),
+            block(&[(":status", "200"), ("content-length", "+5")]),
+            block(&[(":status", "200"), (":path", "/")]),
+            block(&[("server", "x"), (":status", "200")]),
+            block(&[(":status", "200"), ("Server", "x")]),
+        ];
+        for headers in cases {
+            assert!(Response::from_headers(headers.clone()).is_err(), "{:?}", headers);
+        }
+    }
+
+    #[test]
+    fn test_trailers() {
+        check_trailers(&block(&[("grpc-status", "0")])).unwrap();
+        assert!(check_trailers(&block(&[(":status", "200")])).is_err());
+        assert!(check_trailers(&block(&[(":path", "/")])).is_err());
+
+        let res = Response::new(200).trailer("grpc-status", "0");
+        assert_eq!(res.get_trailer("grpc-status"), Some(&b"0"[..]));
+        assert_eq!(res.get("grpc-status"), None);
+    }
+
+    #[test]
+    fn test_body_length() {
+        let mut len = BodyLength::new(Some(3));
+        len.add(2).unwrap();
+        assert!(len.end().is_err());
+        len.add(1).unwrap();
+        len.end().unwrap();
+        assert!(len.add(1).is_err());
+        let mut unknown = BodyLength::new(None);
+        unknown.add(100).unwrap();
+        unknown.end().unwrap();
+    }
+}
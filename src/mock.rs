@@ -1,51 +1,258 @@
-use std::io::{Read, Write, Cursor, Result};
-use std::rc::Rc;
+//! Deterministic in-memory transport for tests.
+//!
+//! `MockStream::new` creates the two connected ends of a duplex byte stream.
+//! Like a non-blocking socket, reading from an empty stream fails with
+//! `WouldBlock`, and an end can be configured to misbehave the way real
+//! sockets do: short reads and writes, spurious `WouldBlock`s, a full send
+//! buffer, data held back until the test delivers it, and abrupt EOF.
+//!
+//! Nothing depends on timing, a test decides when data moves.
+//!
+//! ```
+//! use std::io::{Read, Write, ErrorKind};
+//! use deuter::mock::MockStream;
+//!
+//! let (mut client, mut server) = MockStream::new();
+//! let mut buf = [0; 8];
+//! assert_eq!(server.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+//!
+//! client.set_write_chunk(2);
+//! assert_eq!(client.write(&[1, 2, 3]).unwrap(), 2);
+//!
+//! client.hold();
+//! client.write_all(&[3]).unwrap();
+//! assert_eq!(server.read(&mut buf).unwrap(), 2);
+//! assert_eq!(server.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+//! client.deliver();
+//! assert_eq!(server.read(&mut buf).unwrap(), 1);
+//!
+//! client.close();
+//! assert_eq!(server.read(&mut buf).unwrap(), 0);
+//! ```
+
 use std::cell::RefCell;
-use connection::Connection;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write, ErrorKind};
+use std::rc::Rc;
+
+/// One direction of the duplex stream.
+#[derive(Debug, Default)]
+struct Pipe {
+    // bytes visible to the reading end
+    buf: VecDeque<u8>,
+    // bytes written while held, not yet visible
+    held: VecDeque<u8>,
+    holding: bool,
+    // maximum of buffered bytes, like a socket's send buffer
+    capacity: Option<usize>,
+    // the writing end closed, reads return EOF once `buf` is drained
+    closed: bool,
+    // the connection was reset, both ends fail
+    reset: bool,
+}
+
+impl Pipe {
+    fn buffered(&self) -> usize {
+        self.buf.len() + self.held.len()
+    }
+}
+
+/// Faults injected on the operations of one end.
+#[derive(Debug, Clone, Copy, Default)]
+struct Faults {
+    read_chunk: Option<usize>,
+    write_chunk: Option<usize>,
+    blocked_reads: usize,
+    blocked_writes: usize,
+}
 
-#[derive(Debug)]
+/// One end of an in-memory duplex stream.
+///
+/// Clones refer to the same end, like `try_clone` of a socket.
+#[derive(Debug, Clone)]
 pub struct MockStream {
-    rx: Rc<RefCell<Cursor<Vec<u8>>>>,
-    tx: Rc<RefCell<Cursor<Vec<u8>>>>,
+    rx: Rc<RefCell<Pipe>>,
+    tx: Rc<RefCell<Pipe>>,
+    faults: Rc<RefCell<Faults>>,
 }
 
 impl MockStream {
+    /// Creates a pair of connected ends.
     pub fn new() -> (MockStream, MockStream) {
-        let rx = Rc::new(RefCell::new(Cursor::new(Vec::new())));
-        let tx = Rc::new(RefCell::new(Cursor::new(Vec::new())));
+        let a = Rc::new(RefCell::new(Pipe::default()));
+        let b = Rc::new(RefCell::new(Pipe::default()));
         (MockStream {
-            rx: rx.clone(),
-            tx: tx.clone(),
+            rx: a.clone(),
+            tx: b.clone(),
+            faults: Rc::default(),
         },
          MockStream {
-            rx: tx.clone(),
-            tx: rx.clone(),
+            rx: b,
+            tx: a,
+            faults: Rc::default(),
         })
     }
-}
 
-impl Write for MockStream {
-    fn write<'a>(&mut self, buf: &'a [u8]) -> Result<usize> {
-        self.tx.borrow_mut().get_mut().write(buf)
+    /// Returns at most `n` bytes per read.
+    pub fn set_read_chunk(&self, n: usize) {
+        self.faults.borrow_mut().read_chunk = Some(cmp::max(n, 1));
+    }
+
+    /// Accepts at most `n` bytes per write.
+    pub fn set_write_chunk(&self, n: usize) {
+        self.faults.borrow_mut().write_chunk = Some(cmp::max(n, 1));
+    }
+
+    /// Fails the next `n` reads with `WouldBlock`, even if data is available.
+    pub fn block_reads(&self, n: usize) {
+        self.faults.borrow_mut().blocked_reads = n;
+    }
+
+    /// Fails the next `n` writes with `WouldBlock`.
+    pub fn block_writes(&self, n: usize) {
+        self.faults.borrow_mut().blocked_writes = n;
+    }
+
+    /// Limits the bytes written but not yet read by the peer, further writes
+    /// are short or fail with `WouldBlock`.
+    pub fn set_send_capacity(&self, n: usize) {
+        self.tx.borrow_mut().capacity = Some(n);
+    }
+
+    /// Holds back written bytes from the peer until `deliver` is called.
+    pub fn hold(&self) {
+        self.tx.borrow_mut().holding = true;
+    }
+
+    /// Makes up to `n` held bytes visible to the peer, returns the number of
+    /// bytes delivered. Bytes written afterwards are still held.
+    pub fn deliver_some(&self, n: usize) -> usize {
+        let mut pipe = self.tx.borrow_mut();
+        let n = cmp::min(n, pipe.held.len());
+        let bytes: Vec<u8> = pipe.held.drain(..n).collect();
+        pipe.buf.extend(bytes);
+        n
     }
 
-    fn flush(&mut self) -> Result<()> {
-        self.tx.borrow_mut().get_mut().flush()
+    /// Delivers all held bytes and stops holding.
+    pub fn deliver(&self) {
+        let mut pipe = self.tx.borrow_mut();
+        pipe.holding = false;
+        let bytes: Vec<u8> = pipe.held.drain(..).collect();
+        pipe.buf.extend(bytes);
     }
+
+    /// Shuts down the write direction, the peer reads EOF once it consumed
+    /// the delivered bytes. Held bytes are discarded.
+    pub fn close(&self) {
+        let mut pipe = self.tx.borrow_mut();
+        pipe.held.clear();
+        pipe.closed = true;
+    }
+
+    /// Aborts the connection, discarding buffered data in both directions.
+    /// All further operations on both ends fail with `ConnectionReset`.
+    pub fn reset(&self) {
+        for pipe in &[&self.rx, &self.tx] {
+            let mut pipe = pipe.borrow_mut();
+            pipe.buf.clear();
+            pipe.held.clear();
+            pipe.reset = true;
+        }
+    }
+
+    /// Number of bytes the peer can read right now.
+    pub fn readable_by_peer(&self) -> usize {
+        self.tx.borrow().buf.len()
+    }
+
+    /// Number of bytes this end can read right now.
+    pub fn readable(&self) -> usize {
+        self.rx.borrow().buf.len()
+    }
+
+    /// Returns true if the peer closed or reset the stream.
+    pub fn is_closed(&self) -> bool {
+        let pipe = self.rx.borrow();
+        pipe.closed || pipe.reset
+    }
+}
+
+fn reset_error() -> io::Error {
+    io::Error::new(ErrorKind::ConnectionReset, "connection reset by peer")
+}
+
+fn would_block() -> io::Error {
+    io::Error::new(ErrorKind::WouldBlock, "operation would block")
 }
 
 impl Read for MockStream {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        self.rx.borrow_mut().read(buf)
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.rx.borrow_mut();
+        if pipe.reset {
+            return Err(reset_error());
+        }
+        let mut faults = self.faults.borrow_mut();
+        if faults.blocked_reads > 0 {
+            faults.blocked_reads -= 1;
+            return Err(would_block());
+        }
+        if pipe.buf.is_empty() {
+            if pipe.closed || buf.is_empty() {
+                return Ok(0);
+            }
+            return Err(would_block());
+        }
+        let len = cmp::min(cmp::min(buf.len(), pipe.buf.len()),
+                           faults.read_chunk.unwrap_or(usize::max_value()));
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
     }
 }
 
-impl Connection for MockStream {}
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.tx.borrow_mut();
+        if pipe.reset {
+            return Err(reset_error());
+        }
+        if pipe.closed {
+            return Err(io::Error::new(ErrorKind::BrokenPipe, "stream closed"));
+        }
+        let mut faults = self.faults.borrow_mut();
+        if faults.blocked_writes > 0 {
+            faults.blocked_writes -= 1;
+            return Err(would_block());
+        }
+        let room = match pipe.capacity {
+            Some(capacity) => capacity.saturating_sub(pipe.buffered()),
+            None => usize::max_value(),
+        };
+        if room == 0 && !buf.is_empty() {
+            return Err(would_block());
+        }
+        let len = cmp::min(cmp::min(buf.len(), room),
+                           faults.write_chunk.unwrap_or(usize::max_value()));
+        if pipe.holding {
+            pipe.held.extend(&buf[..len]);
+        } else {
+            pipe.buf.extend(&buf[..len]);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod test {
     use super::MockStream;
-    use std::io::{Read, Write};
+    use std::io::{Read, Write, ErrorKind};
 
     #[test]
     fn test_mem_writer() {
@@ -81,4 +288,86 @@ mod test {
         let b: &[_] = &[1, 2, 3, 4];
         assert_eq!(buf, b);
     }
+
+    #[test]
+    fn test_would_block_when_empty() {
+        let (mut server, mut client) = MockStream::new();
+        let mut buf = [0; 4];
+        assert_eq!(client.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+        server.write_all(&[1]).unwrap();
+        assert_eq!(client.read(&mut buf).unwrap(), 1);
+        assert_eq!(client.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_short_reads_and_writes() {
+        let (mut server, mut client) = MockStream::new();
+        server.set_write_chunk(3);
+        client.set_read_chunk(2);
+        assert_eq!(server.write(&[1, 2, 3, 4, 5]).unwrap(), 3);
+        let mut buf = [0; 8];
+        assert_eq!(client.read(&mut buf).unwrap(), 2);
+        assert_eq!(client.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 3);
+    }
+
+    #[test]
+    fn test_injected_would_block() {
+        let (mut server, mut client) = MockStream::new();
+        server.block_writes(1);
+        assert_eq!(server.write(&[1]).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(server.write(&[1]).unwrap(), 1);
+        client.block_reads(2);
+        let mut buf = [0; 1];
+        assert_eq!(client.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(client.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(client.read(&mut buf).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_send_capacity() {
+        let (mut server, mut client) = MockStream::new();
+        server.set_send_capacity(4);
+        assert_eq!(server.write(&[1; 6]).unwrap(), 4);
+        assert_eq!(server.write(&[1]).unwrap_err().kind(), ErrorKind::WouldBlock);
+        let mut buf = [0; 3];
+        assert_eq!(client.read(&mut buf).unwrap(), 3);
+        assert_eq!(server.write(&[1; 6]).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_delayed_delivery() {
+        let (mut server, mut client) = MockStream::new();
+        server.hold();
+        server.write_all(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(client.readable(), 0);
+        assert_eq!(server.deliver_some(3), 3);
+        let mut buf = [0; 8];
+        assert_eq!(client.read(&mut buf).unwrap(), 3);
+        assert_eq!(client.read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+        server.deliver();
+        server.write_all(&[5]).unwrap();
+        assert_eq!(client.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf[..2], [4, 5]);
+    }
+
+    #[test]
+    fn test_close_and_reset() {
+        let (mut server, mut client) = MockStream::new();
+        server.write_all(&[1, 2]).unwrap();
+        server.close();
+        assert!(client.is_closed());
+        let mut buf = [0; 8];
+        assert_eq!(client.read(&mut buf).unwrap(), 2);
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+        assert_eq!(server.write(&[1]).unwrap_err().kind(), ErrorKind::BrokenPipe);
+        // the other direction is still open
+        client.write_all(&[3]).unwrap();
+        assert_eq!(server.read(&mut buf).unwrap(), 1);
+
+        client.write_all(&[4]).unwrap();
+        client.reset();
+        assert_eq!(server.read(&mut buf).unwrap_err().kind(), ErrorKind::ConnectionReset);
+        assert_eq!(client.write(&[1]).unwrap_err().kind(), ErrorKind::ConnectionReset);
+    }
 }
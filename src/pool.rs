@@ -0,0 +1,201 @@
+//! Pool of fixed-size buffers shared by the connections of a server.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Size of the pooled chunks, holds a default sized frame.
+pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+/// Number of idle chunks kept for reuse.
+pub const DEFAULT_MAX_IDLE: usize = 1024;
+
+/// Snapshot of the pool utilisation.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PoolStats {
+    /// Size of a chunk in bytes.
+    pub chunk_size: usize,
+    /// Chunks currently held by readers and writers.
+    pub in_use: usize,
+    /// Chunks kept in the pool for reuse.
+    pub idle: usize,
+    /// Total number of chunks handed out.
+    pub acquired: u64,
+    /// Number of chunks handed out which were served from the idle list.
+    pub reused: u64,
+}
+
+impl PoolStats {
+    /// Chunks allocated by the pool, in use or idle.
+    pub fn allocated(&self) -> usize {
+        self.in_use + self.idle
+    }
+
+    /// The share of allocated chunks currently in use, from 0 to 1.
+    pub fn utilisation(&self) -> f64 {
+        match self.allocated() {
+            0 => 0.0,
+            n => self.in_use as f64 / n as f64,
+        }
+    }
+
+    /// The share of acquisitions served without allocating, from 0 to 1.
+    pub fn hit_rate(&self) -> f64 {
+        match self.acquired {
+            0 => 0.0,
+            n => self.reused as f64 / n as f64,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pool {
+    idle: Vec<Vec<u8>>,
+    max_idle: usize,
+    stats: PoolStats,
+}
+
+/// The `BufferPool` hands out fixed-size chunks to the readers and writers of
+/// all connections of an event loop.
+///
+/// Buffers hold a chunk only while they have data, an idle connection returns
+/// its chunks to the pool, so the memory held by the server depends on the
+/// number of active rather than open connections. Buffers may grow a chunk
+/// beyond the chunk size for large frames, such chunks are freed instead of
+/// being kept when they are released.
+///
+/// The pool is cheap to clone, all clones share the same chunks. It is meant
+/// for single threaded event loops and not `Send`.
+///
+/// ```
+/// use deuter::pool::BufferPool;
+///
+/// let pool = BufferPool::with_chunk_size(1024, 16);
+/// let chunk = pool.acquire();
+/// assert!(chunk.capacity() >= 1024);
+/// assert_eq!(pool.stats().in_use, 1);
+/// pool.release(chunk);
+/// assert_eq!(pool.stats().in_use, 0);
+/// assert_eq!(pool.stats().idle, 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    inner: Rc<RefCell<Pool>>,
+}
+
+impl BufferPool {
+    pub fn new() -> BufferPool {
+        Self::with_chunk_size(DEFAULT_CHUNK_SIZE, DEFAULT_MAX_IDLE)
+    }
+
+    /// Creates a pool of `chunk_size` chunks keeping at most `max_idle` idle
+    /// chunks around.
+    pub fn with_chunk_size(chunk_size: usize, max_idle: usize) -> BufferPool {
+        BufferPool {
+            inner: Rc::new(RefCell::new(Pool {
+                idle: Vec::new(),
+                max_idle: max_idle,
+                stats: PoolStats { chunk_size: chunk_size, ..PoolStats::default() },
+            })),
+        }
+    }
+
+    #[inline]
+    pub fn chunk_size(&self) -> usize {
+        self.inner.borrow().stats.chunk_size
+    }
+
+    /// Takes an empty chunk with a capacity of at least the chunk size.
+    pub fn acquire(&self) -> Vec<u8> {
+        let mut pool = self.inner.borrow_mut();
+        pool.stats.acquired += 1;
+        pool.stats.in_use += 1;
+        match pool.idle.pop() {
+            Some(chunk) => {
+                pool.stats.idle -= 1;
+                pool.stats.reused += 1;
+                chunk
+            }
+            None => Vec::with_capacity(pool.stats.chunk_size),
+        }
+    }
+
+    /// Returns a chunk taken with `acquire`.
+    pub fn release(&self, mut chunk: Vec<u8>) {
+        let mut pool = self.inner.borrow_mut();
+        pool.stats.in_use -= 1;
+        let chunk_size = pool.stats.chunk_size;
+        // keep neither chunks grown for large frames nor foreign small buffers
+        if pool.idle.len() < pool.max_idle && chunk.capacity() >= chunk_size &&
+           chunk.capacity() <= 2 * chunk_size {
+            chunk.clear();
+            pool.idle.push(chunk);
+            pool.stats.idle += 1;
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.inner.borrow().stats
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        BufferPool::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BufferPool;
+
+    #[test]
+    fn test_reuse_chunks() {
+        let pool = BufferPool::with_chunk_size(64, 4);
+        let a = pool.acquire();
+        let b = pool.acquire();
+        let ptr = a.as_ptr();
+        pool.release(a);
+        let c = pool.acquire();
+        assert_eq!(c.as_ptr(), ptr);
+        let stats = pool.stats();
+        assert_eq!(stats.in_use, 2);
+        assert_eq!(stats.idle, 0);
+        assert_eq!(stats.acquired, 3);
+        assert_eq!(stats.reused, 1);
+        assert_eq!(stats.utilisation(), 1.0);
+        pool.release(b);
+        pool.release(c);
+        assert_eq!(pool.stats().utilisation(), 0.0);
+        assert_eq!(pool.stats().allocated(), 2);
+    }
+
+    #[test]
+    fn test_max_idle() {
+        let pool = BufferPool::with_chunk_size(64, 1);
+        let a = pool.acquire();
+        let b = pool.acquire();
+        pool.release(a);
+        pool.release(b);
+        assert_eq!(pool.stats().idle, 1);
+        assert_eq!(pool.stats().allocated(), 1);
+    }
+
+    #[test]
+    fn test_drop_grown_chunks() {
+        let pool = BufferPool::with_chunk_size(64, 4);
+        let mut a = pool.acquire();
+        a.resize(1024, 0);
+        pool.release(a);
+        assert_eq!(pool.stats().idle, 0);
+        assert_eq!(pool.stats().in_use, 0);
+    }
+
+    #[test]
+    fn test_shared_between_clones() {
+        let pool = BufferPool::with_chunk_size(64, 4);
+        let other = pool.clone();
+        other.release(pool.acquire());
+        assert_eq!(pool.stats().idle, 1);
+        assert_eq!(other.acquire().capacity(), 64);
+        assert_eq!(pool.stats().hit_rate(), 0.5);
+    }
+}
@@ -1,125 +1,819 @@
-use mio::{Handler, Token, EventLoop, EventSet, PollOpt};
-use mio::tcp::TcpListener;
-use mio::util::Slab;
+use std::collections::HashMap;
+use std::io;
 use std::net::SocketAddr;
-use connection::Connection;
-use error::Result;
+use std::time::Duration;
+use mio::{Handler, Sender, Token, EventLoop, EventSet, PollOpt};
+use mio::tcp::{TcpListener, TcpStream};
+use mio::util::Slab;
+use {Settings, StreamId};
+use body::Bodies;
+use connection::{Connection, Event, Role, Transport};
+use message::{self, Body, BodyLength, Request, Responder, Response};
+use pool::{BufferPool, PoolStats};
+use error::{Error, ErrorKind, Result};
+use hpack::Header;
+
+#[cfg(feature = "http")]
+pub use compat::HttpService;
 
 const SERVER: Token = Token(0);
 
-struct Server {
-    listener: TcpListener,
-    connections: Slab<Connection>,
+/// Connections a server serves at once.
+const MAX_CONNECTIONS: usize = 1024;
+
+/// The PING payload measuring the round trip of a shutdown announcement.
+const SHUTDOWN_PING: [u8; 8] = *b"shutdown";
+
+/// Answers requests.
+pub trait Service {
+    fn call(&mut self, request: Request) -> Response;
+
+    /// Called once the headers of a request arrived. A service streaming the
+    /// request body takes `body`, answers through `responder` and returns
+    /// `None`. By default the request is handed back to be buffered and
+    /// answered by `call`.
+    ///
+    /// A request with `expect: 100-continue` gets 100 (Continue) unless the
+    /// service responded already, e.g. with 417 (Expectation Failed).
+    /// Associated resources are pushed through `Responder::push`.
+    fn start(&mut self, request: Request, body: Body, responder: Responder) -> Option<Request> {
+        let _ = (body, responder);
+        Some(request)
+    }
+
+    /// Called on every `ready` of a connection, after its events are handled,
+    /// to continue streamed bodies.
+    fn poll(&mut self) {}
 }
 
-impl Server {
-    fn new(listener: TcpListener) -> Self {
-        let slab = Slab::new_starting_at(Token(1), 1024);
-        Server {
-            listener: listener,
-            connections: slab,
+impl<F: FnMut(Request) -> Response> Service for F {
+    fn call(&mut self, request: Request) -> Response {
+        self(request)
+    }
+}
+
+/// The settings a server announces, servers never accept pushes.
+pub fn default_settings() -> Settings {
+    Settings { enable_push: false, ..Settings::default() }
+}
+
+/// A request waiting for the end of its body.
+struct Pending {
+    // `None` if the service streams the body
+    request: Option<Request>,
+    length: BodyLength,
+}
+
+/// Progress of a graceful shutdown.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shutdown {
+    Running,
+    // GOAWAY announced the shutdown, waiting for the PING to return
+    Draining,
+    // the final GOAWAY is sent, waiting for the remaining streams
+    Closing,
+}
+
+/// The server side of a single connection, collects requests and sends the
+/// responses of a `Service`.
+pub struct ServerConnection<S> {
+    conn: Connection<S>,
+    requests: HashMap<StreamId, Pending>,
+    bodies: Bodies,
+    shutdown: Shutdown,
+}
+
+impl<S: Transport> ServerConnection<S> {
+    pub fn new(socket: S, settings: Settings, pool: &BufferPool)
+               -> io::Result<ServerConnection<S>> {
+        Ok(ServerConnection {
+            conn: try!(Connection::new(socket, Role::Server, settings, pool)),
+            requests: HashMap::new(),
+            bodies: Bodies::new(),
+            shutdown: Shutdown::Running,
+        })
+    }
+
+    pub fn socket(&self) -> &S {
+        self.conn.socket()
+    }
+
+    pub fn connection(&mut self) -> &mut Connection<S> {
+        &mut self.conn
+    }
+
+    /// Reads available frames, answers completed requests and writes as much
+    /// as the transport takes.
+    pub fn ready<V: Service>(&mut self, service: &mut V) -> Result<()> {
+        let read = self.conn.read();
+        while let Some(event) = self.conn.poll() {
+            self.handle_event(event, service);
+        }
+        self.bodies.sync(&mut self.conn);
+        service.poll();
+        self.bodies.sync(&mut self.conn);
+        if self.shutdown == Shutdown::Closing && self.conn.active_streams() == 0 {
+            self.conn.close();
+        }
+        if self.conn.is_closed() {
+            self.bodies.reset_all(ErrorKind::Cancel);
         }
+        try!(self.conn.write());
+        read
     }
 
-    pub fn run(addr: SocketAddr) -> Result<()> {
-        let listener = try!(TcpListener::bind(&addr));
-        let mut event_loop = try!(EventLoop::new());
-        try!(event_loop.register(&listener, SERVER, EventSet::readable(), PollOpt::edge()));
-        let mut server = Self::new(listener);
-        event_loop.run(&mut server);
-        Ok(())
+    pub fn is_closed(&self) -> bool {
+        self.conn.is_closed()
+    }
+
+    /// Starts a graceful shutdown: GOAWAY with the highest stream id tells
+    /// the client to stop opening streams, once a PING made the round trip
+    /// the final GOAWAY names the last stream processed. The connection
+    /// closes when its remaining streams are complete. Call `ready` to send.
+    pub fn shutdown(&mut self) {
+        if self.shutdown == Shutdown::Running {
+            self.conn.drain();
+            self.conn.ping(SHUTDOWN_PING);
+            self.shutdown = Shutdown::Draining;
+        }
+    }
+
+    /// Closes the connection whatever streams are still in flight, after a
+    /// final GOAWAY unless that went out already.
+    pub fn close(&mut self) {
+        if self.shutdown != Shutdown::Closing {
+            self.conn.go_away(ErrorKind::No);
+            self.shutdown = Shutdown::Closing;
+        }
+        self.conn.close();
     }
 
-    fn accept_new(&mut self, event_loop: &mut EventLoop<Server>) {
-        match self.listener.accept() {
-            Ok(Some((socket, addr))) => {
-                info!("New Connection from {}", addr);
-                let token = self.connections
-                    .insert_with(|token| Connection::new(socket, token))
-                    .unwrap();
-                event_loop.register(&self.connections[token].socket,
-                              token,
-                              EventSet::readable(), // TODO hup?
-                              PollOpt::edge())
-                    .unwrap();
+    fn handle_event<V: Service>(&mut self, event: Event, service: &mut V) {
+        let (id, result) = match event {
+            Event::Headers { stream_id, headers, end_stream } => {
+                let result = self.recv_headers(stream_id, headers, end_stream, service);
+                (stream_id, result.map(|_| end_stream))
+            }
+            Event::Data { stream_id, data, end_stream } => {
+                (stream_id, self.recv_data(stream_id, data, end_stream).map(|_| end_stream))
             }
-            Ok(None) => {}
+            Event::Reset { stream_id, error } => {
+                self.requests.remove(&stream_id);
+                self.bodies.reset(stream_id, error);
+                return;
+            }
+            Event::Pong(SHUTDOWN_PING) if self.shutdown == Shutdown::Draining => {
+                // streams the client opened before it saw the first GOAWAY
+                // have arrived by now
+                self.conn.go_away(ErrorKind::No);
+                self.shutdown = Shutdown::Closing;
+                return;
+            }
+            _ => return,
+        };
+        match result {
+            Ok(true) => self.respond(id, service),
+            Ok(false) => {}
             Err(e) => {
-                // TODO handle
-                event_loop.shutdown();
+                // malformed requests are stream errors (rfc 8.1.2.6)
+                debug!("malformed request on {:?}: {}", id, e);
+                self.requests.remove(&id);
+                self.bodies.reset(id, ErrorKind::Protocol);
+                self.conn.reset(id, ErrorKind::Protocol);
+            }
+        }
+    }
+
+    fn recv_headers<V: Service>(&mut self,
+                                id: StreamId,
+                                headers: Vec<Header>,
+                                end_stream: bool,
+                                service: &mut V)
+                                -> Result<()> {
+        if let Some(pending) = self.requests.get_mut(&id) {
+            if !end_stream {
+                return Err(Error::protocol("trailers without END_STREAM"));
+            }
+            try!(message::check_trailers(&headers));
+            try!(pending.length.end());
+            match pending.request {
+                Some(ref mut request) => request.trailers = headers,
+                None => self.bodies.recv_trailers(id, headers),
+            }
+            return Ok(());
+        }
+        let length = BodyLength::new(try!(message::content_length(&headers)));
+        let request = try!(Request::from_headers(headers));
+        if end_stream {
+            try!(length.end());
+        }
+        let expects_continue = request.expects_continue() && !end_stream;
+        let body = self.bodies.receive(id);
+        self.bodies.recv_data(id, Vec::new(), end_stream);
+        let capacity = self.conn.send_capacity(id);
+        let push_room = self.conn.push_capacity(id);
+        let responder = self.bodies.respond(id, capacity, push_room);
+        let request = service.start(request, body, responder);
+        if request.is_some() {
+            self.bodies.forget(id);
+            if expects_continue {
+                // the body is buffered for `call` anyway
+                try!(self.conn.send_headers(id, &Response::new(100).header_block(), false));
+            }
+        } else if expects_continue {
+            self.bodies.expect_continue(id);
+        }
+        self.requests.insert(id, Pending { request: request, length: length });
+        Ok(())
+    }
+
+    fn recv_data(&mut self, id: StreamId, data: Vec<u8>, end_stream: bool) -> Result<()> {
+        let pending = match self.requests.get_mut(&id) {
+            Some(pending) => pending,
+            None => {
+                self.conn.release_capacity(id, data.len());
+                return Ok(());
             }
+        };
+        if pending.request.is_some() {
+            // buffered bodies are consumed right away
+            self.conn.release_capacity(id, data.len());
+        }
+        try!(pending.length.add(data.len()));
+        if end_stream {
+            try!(pending.length.end());
+        }
+        match pending.request {
+            Some(ref mut request) => request.body.extend(data),
+            None => self.bodies.recv_data(id, data, end_stream),
+        }
+        Ok(())
+    }
+
+    fn respond<V: Service>(&mut self, id: StreamId, service: &mut V) {
+        let request = match self.requests.remove(&id) {
+            Some(Pending { request: Some(request), .. }) => request,
+            _ => return,
+        };
+        let response = service.call(request);
+        if let Err(e) = message::check_trailers(&response.trailers) {
+            warn!("invalid trailers in response on {:?}: {}", id, e);
+            self.conn.reset(id, ErrorKind::Internal);
+            return;
+        }
+        let result = self.conn.send_message(id,
+                                            &response.header_block(),
+                                            &response.body,
+                                            &response.trailers);
+        if let Err(e) = result {
+            debug!("failed to respond on {:?}: {}", id, e);
         }
     }
 }
 
-impl Handler for Server {
-    type Timeout = ();
-    type Message = ();
+/// Shuts a running server down from another thread, see
+/// `Server::shutdown_handle`.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    sender: Sender<Duration>,
+}
 
-    fn ready(&mut self, event_loop: &mut EventLoop<Server>, token: Token, events: EventSet) {
-        match token {
-            SERVER => self.accept_new(event_loop),
-            _ => {
-                if events.is_readable() {
-                    self.connections[token].read()
-                }
-                if events.is_writable() {
-                    self.connections[token].write()
+impl ShutdownHandle {
+    /// Stops accepting connections and shuts every connection down
+    /// gracefully, see `ServerConnection::shutdown`. Connections still open
+    /// after `grace` are closed, `run` returns once all are.
+    pub fn shutdown(&self, grace: Duration) -> Result<()> {
+        self.sender
+            .send(grace)
+            .map_err(|_| Error::new(ErrorKind::Internal, "server is not running"))
+    }
+}
+
+pub struct Server<V: Service> {
+    listener: TcpListener,
+    connections: Slab<ServerConnection<TcpStream>>,
+    pool: BufferPool,
+    service: V,
+    // taken by `run`
+    event_loop: Option<EventLoop<Server<V>>>,
+    shutting_down: bool,
+}
+
+impl<V: Service> Server<V> {
+    pub fn bind(addr: &SocketAddr, service: V) -> Result<Server<V>> {
+        let listener = try!(TcpListener::bind(addr));
+        Ok(Server {
+            listener: listener,
+            connections: Slab::new_starting_at(Token(1), MAX_CONNECTIONS),
+            pool: BufferPool::new(),
+            service: service,
+            event_loop: Some(try!(EventLoop::new())),
+            shutting_down: false,
+        })
+    }
+
+    /// A handle to shut the server down once it runs.
+    pub fn shutdown_handle(&self) -> Option<ShutdownHandle> {
+        self.event_loop.as_ref().map(|event_loop| ShutdownHandle { sender: event_loop.channel() })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(try!(self.listener.local_addr()))
+    }
+
+    /// Utilisation of the read and write buffers shared by all connections.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    pub fn run(mut self) -> Result<()> {
+        let mut event_loop = match self.event_loop.take() {
+            Some(event_loop) => event_loop,
+            None => return Err(Error::new(ErrorKind::Internal, "server ran already")),
+        };
+        try!(event_loop.register(&self.listener, SERVER, EventSet::readable(), PollOpt::edge()));
+        try!(event_loop.run(&mut self));
+        Ok(())
+    }
+
+    fn accept_new(&mut self, event_loop: &mut EventLoop<Server<V>>) {
+        loop {
+            match self.listener.accept() {
+                Ok(Some((socket, addr))) => {
+                    info!("New Connection from {}", addr);
+                    let conn = match ServerConnection::new(socket, default_settings(), &self.pool) {
+                        Ok(conn) => conn,
+                        Err(e) => {
+                            warn!("Failed to set up connection from {}: {}", addr, e);
+                            continue;
+                        }
+                    };
+                    let token = match self.connections.insert(conn) {
+                        Ok(token) => token,
+                        Err(_) => {
+                            warn!("Connection limit reached, dropping {}", addr);
+                            continue;
+                        }
+                    };
+                    event_loop.register(self.connections[token].socket(),
+                                  token,
+                                  EventSet::readable() | EventSet::writable() | EventSet::hup(),
+                                  PollOpt::edge())
+                        .unwrap();
+                    // the client's preface may have arrived already
+                    self.ready_connection(event_loop, token);
                 }
-                if events.is_hup() {}
-                if events.is_error() {}
-                if self.connections[token].is_closed() {
-                    event_loop.deregister(&self.connections[token].socket);
-                    let _ = self.connections.remove(token);
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("Failed to accept: {}", e);
+                    event_loop.shutdown();
+                    return;
                 }
             }
         }
     }
 
-    fn timeout(&mut self, event_loop: &mut EventLoop<Server>, timeout: Self::Timeout) {}
+    fn ready_connection(&mut self, event_loop: &mut EventLoop<Server<V>>, token: Token) {
+        if let Err(e) = self.connections[token].ready(&mut self.service) {
+            debug!("connection {:?}: {}", token, e);
+        }
+        if self.connections[token].is_closed() {
+            let _ = event_loop.deregister(self.connections[token].socket());
+            let _ = self.connections.remove(token);
+        }
+        if self.shutting_down && self.connections.is_empty() {
+            event_loop.shutdown();
+        }
+    }
+
+    fn tokens(&self) -> Vec<Token> {
+        (1..MAX_CONNECTIONS + 1).map(Token).filter(|&t| self.connections.contains(t)).collect()
+    }
+
+    fn shutdown(&mut self, event_loop: &mut EventLoop<Server<V>>, grace: Duration) {
+        if self.shutting_down {
+            return;
+        }
+        info!("Shutting down, {} connections open", self.connections.count());
+        self.shutting_down = true;
+        let _ = event_loop.deregister(&self.listener);
+        if self.connections.is_empty() {
+            return event_loop.shutdown();
+        }
+        let ms = grace.as_secs() * 1000 + grace.subsec_nanos() as u64 / 1000000;
+        if event_loop.timeout_ms((), ms).is_err() {
+            warn!("Failed to set the shutdown deadline");
+        }
+        for token in self.tokens() {
+            self.connections[token].shutdown();
+            self.ready_connection(event_loop, token);
+        }
+    }
+}
+
+impl<V: Service> Handler for Server<V> {
+    // the shutdown deadline
+    type Timeout = ();
+    // the grace period of a shutdown
+    type Message = Duration;
+
+    fn ready(&mut self, event_loop: &mut EventLoop<Server<V>>, token: Token, _: EventSet) {
+        match token {
+            SERVER => self.accept_new(event_loop),
+            _ => self.ready_connection(event_loop, token),
+        }
+    }
+
+    fn notify(&mut self, event_loop: &mut EventLoop<Server<V>>, grace: Duration) {
+        self.shutdown(event_loop, grace);
+    }
+
+    fn timeout(&mut self, event_loop: &mut EventLoop<Server<V>>, _: ()) {
+        for token in self.tokens() {
+            info!("Closing {:?} at the shutdown deadline", token);
+            self.connections[token].close();
+            self.ready_connection(event_loop, token);
+        }
+        event_loop.shutdown();
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::io::{BufRead, BufReader, Read, Write};
+    use std::io::Write;
     use std::net::TcpStream;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use client::Client;
+    use error::ErrorKind;
+    use frame::data::DataFrame;
+    use frame::ping::PingFrame;
+    use frame::settings::{Setting, SettingsFrame};
+    use harness::{Script, Expect, ServerTarget, TestServer};
+    use message::{Body, BodySender, Request, Responder, Response};
+    use upgrade;
+    use StreamId;
+    use super::{Server, Service};
     extern crate env_logger;
 
-    const HOST: &'static str = "127.0.0.1:60254";
-
-    fn start_server() {
-        use std::thread;
-        use std::time::Duration;
-        use std::sync::{Once, ONCE_INIT};
+    #[test]
+    fn test_server() {
+        let _ = env_logger::init();
+        let server = TestServer::start(|_: Request| Response::new(200));
 
-        static INIT: Once = ONCE_INIT;
+        let mut sock = TcpStream::connect(*server).unwrap();
+        sock.write_all(b"hello world\n").unwrap();
+        sock.write_all(b"this is a line\n").unwrap();
+    }
 
-        INIT.call_once(|| {
-            thread::spawn(|| {
-                info!("running server");
-                super::Server::run(HOST.parse().unwrap()).unwrap();
-            });
-            thread::sleep(Duration::from_millis(1000));
+    /// A client which never acknowledges the PING is closed at the deadline.
+    #[test]
+    fn test_shutdown() {
+        let _ = env_logger::init();
+        let (tx, rx) = mpsc::channel();
+        let server = thread::spawn(move || {
+            let service = |_: Request| Response::new(200);
+            let server = Server::bind(&"127.0.0.1:0".parse().unwrap(), service).unwrap();
+            tx.send((server.local_addr().unwrap(), server.shutdown_handle().unwrap())).unwrap();
+            server.run().unwrap();
         });
-        println!("running");
+        let (addr, handle) = rx.recv().unwrap();
+
+        let _sock = TcpStream::connect(addr).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        handle.shutdown(Duration::from_millis(200)).unwrap();
+        server.join().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert!(TcpStream::connect(addr).is_err());
     }
 
     #[test]
-    fn test_server() {
+    fn test_upgrade() {
         let _ = env_logger::init();
-        start_server();
+        let service = |req: Request| Response::new(200).body(req.path().unwrap().to_vec());
+        let server = TestServer::start(service);
+
+        let request = Request::new("GET", "http", "localhost", "/first");
+        let (client, response) = Client::connect_upgrade(&server, request).unwrap();
+        assert_eq!(response, Response::new(200).body(&b"/first"[..]));
+        assert!(!client.is_declined());
+        assert!(!client.is_closed());
+    }
+
+
+    /// Streams request bodies back as they arrive, once `consume` is set.
+    struct Echo {
+        consume: bool,
+        body: Option<Body>,
+        sender: Option<BodySender>,
+    }
+
+    impl Service for Echo {
+        fn call(&mut self, _: Request) -> Response {
+            unreachable!()
+        }
+
+        fn start(&mut self, _: Request, body: Body, responder: Responder) -> Option<Request> {
+            self.body = Some(body);
+            self.sender = Some(responder.send(Response::new(200)));
+            None
+        }
+
+        fn poll(&mut self) {
+            if !self.consume {
+                return;
+            }
+            if let Some(ref mut body) = self.body {
+                while let Some(chunk) = body.poll_chunk() {
+                    self.sender.as_mut().unwrap().write_all(&chunk).unwrap();
+                }
+                if body.is_end() {
+                    self.sender.take().map(|sender| sender.finish());
+                }
+            }
+        }
+    }
+
+    /// Sends 103 (Early Hints) before answering, refuses expectations.
+    struct Hints;
+
+    impl Service for Hints {
+        fn call(&mut self, _: Request) -> Response {
+            unreachable!()
+        }
 
-        let mut sock = BufReader::new(TcpStream::connect(HOST).unwrap());
-        let mut recv = String::new();
+        fn start(&mut self, req: Request, _: Body, mut responder: Responder)
+                 -> Option<Request> {
+            if req.expects_continue() {
+                responder.respond(Response::new(417)).unwrap();
+                return None;
+            }
+            let hints = Response::new(103).header("link", "</style.css>; rel=preload");
+            responder.inform(hints).unwrap();
+            assert!(responder.inform(Response::new(200)).is_err());
+            responder.respond(Response::new(200)).unwrap();
+            None
+        }
+    }
 
-        sock.get_mut().write_all(b"hello world\n").unwrap();
+    /// Pushes a stylesheet with every page, records whether it could.
+    struct Pusher {
+        pushed: Vec<bool>,
+    }
 
-        // assert_eq!(recv, "hello world\n");
+    impl Service for Pusher {
+        fn call(&mut self, _: Request) -> Response {
+            unreachable!()
+        }
 
-        recv.clear();
+        fn start(&mut self, _: Request, _: Body, mut responder: Responder) -> Option<Request> {
+            let post = Request::new("POST", "http", "localhost", "/form");
+            assert!(responder.push(post).is_err());
+            let css = Request::new("GET", "http", "localhost", "/style.css");
+            match responder.push(css) {
+                Ok(pushed) => {
+                    pushed.respond(Response::new(200).body(&b"css"[..])).unwrap();
+                    self.pushed.push(true);
+                }
+                Err(_) => self.pushed.push(false),
+            }
+            responder.respond(Response::new(200).body(&b"html"[..])).unwrap();
+            None
+        }
+    }
 
-        sock.get_mut().write_all(b"this is a line\n").unwrap();
+    fn hello(req: Request) -> Response {
+        match req.path() {
+            Some(b"/") => Response::new(200).body(&b"hello"[..]),
+            _ => Response::new(404),
+        }
+    }
 
-        // assert_eq!(recv, "this is a line\n")
+    fn get(path: &'static str) -> [(&'static str, &'static str); 4] {
+        [(":method", "GET"), (":scheme", "http"), (":authority", "localhost"), (":path", path)]
+    }
+
+    fn post_continue() -> [(&'static str, &'static str); 5] {
+        [(":method", "POST"),
+         (":scheme", "http"),
+         (":authority", "localhost"),
+         (":path", "/"),
+         ("expect", "100-continue")]
+    }
+
+    #[test]
+    fn test_request() {
+        Script::new()
+            .handshake()
+            .headers(1, &get("/"), true)
+            .expect(Expect::Field(1, ":status", "200"))
+            .expect(Expect::Data(1, b"hello".to_vec()))
+            .headers(3, &get("/missing"), true)
+            .expect(Expect::Field(3, ":status", "404"))
+            .run_server(hello);
+    }
+
+    #[test]
+    fn test_trailers() {
+        let echo = |req: Request| {
+            let mut res = Response::new(200).body(req.body);
+            res.trailers = req.trailers;
+            res
+        };
+        Script::new()
+            .handshake()
+            .headers(1, &get("/"), false)
+            .send(DataFrame::new(StreamId(1)).data(&b"body"[..]))
+            .headers(1, &[("checksum", "1")], true)
+            .expect(Expect::Field(1, ":status", "200"))
+            .expect(Expect::Data(1, b"body".to_vec()))
+            .expect(Expect::Field(1, "checksum", "1"))
+            .run_server(echo);
+    }
+
+    #[test]
+    fn test_streaming_request() {
+        let echo = Echo { consume: true, body: None, sender: None };
+        Script::new()
+            .handshake()
+            .headers(1, &get("/"), false)
+            .expect(Expect::Field(1, ":status", "200"))
+            .send(DataFrame::new(StreamId(1)).data(&b"ab"[..]))
+            .expect(Expect::Data(1, b"ab".to_vec()))
+            .send(DataFrame::new(StreamId(1)).data(&b"c"[..]).end_stream())
+            .expect(Expect::EndStream(1))
+            .run_server(echo);
+    }
+
+    /// Data of a streamed body is only given back to the flow control window
+    /// once it is consumed.
+    #[test]
+    fn test_streaming_backpressure() {
+        let echo = Echo { consume: false, body: None, sender: None };
+        let chunk = vec![0; 16384];
+        Script::new()
+            .handshake()
+            .headers(1, &get("/"), false)
+            .send(DataFrame::new(StreamId(1)).data(&chunk[..]))
+            .send(DataFrame::new(StreamId(1)).data(&chunk[..]))
+            .send(PingFrame::new([1; 8]))
+            .expect(Expect::PingAck([1; 8]))
+            .send(PingFrame::new([2; 8]))
+            .expect(Expect::PingAck([2; 8]))
+            .refute(Expect::WindowUpdate(1))
+            .act(|target: &mut ServerTarget<Echo>| {
+                assert_eq!(target.service.body.as_ref().unwrap().buffered(), 32768);
+                target.service.consume = true;
+            })
+            .expect(Expect::WindowUpdate(1))
+            .run_server(echo);
+    }
+
+    #[test]
+    fn test_early_hints() {
+        Script::new()
+            .handshake()
+            .headers(1, &get("/"), true)
+            .expect(Expect::Field(1, ":status", "103"))
+            .refute(Expect::Field(1, ":status", "200"))
+            .expect(Expect::Field(1, ":status", "200"))
+            .run_server(Hints);
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        Script::new()
+            .handshake()
+            .headers(1, &post_continue(), false)
+            .expect(Expect::Field(1, ":status", "100"))
+            .send(DataFrame::new(StreamId(1)).data(&b"body"[..]).end_stream())
+            .expect(Expect::Field(1, ":status", "200"))
+            .run_server(hello);
+        // a streaming service may refuse before the body is sent
+        Script::new()
+            .handshake()
+            .headers(1, &post_continue(), false)
+            .expect(Expect::Field(1, ":status", "417"))
+            .refute(Expect::Field(1, ":status", "100"))
+            .run_server(Hints);
+    }
+
+    #[test]
+    fn test_server_push() {
+        let target = Script::new()
+            .handshake()
+            .headers(1, &get("/"), true)
+            .expect(Expect::PushPromise(1, 2))
+            .expect(Expect::Field(1, ":status", "200"))
+            .expect(Expect::Data(1, b"html".to_vec()))
+            .expect(Expect::Field(2, ":status", "200"))
+            .expect(Expect::Data(2, b"css".to_vec()))
+            .run_server(Pusher { pushed: Vec::new() });
+        assert_eq!(target.service.pushed, vec![true]);
+
+        let mut settings = SettingsFrame::default();
+        settings.add_setting(Setting::EnablePush(false));
+        let target = Script::new()
+            .handshake()
+            .send(settings)
+            .expect(Expect::SettingsAck)
+            .headers(1, &get("/"), true)
+            .expect(Expect::Data(1, b"html".to_vec()))
+            .refute(Expect::PushPromise(1, 2))
+            .run_server(Pusher { pushed: Vec::new() });
+        assert_eq!(target.service.pushed, vec![false]);
+
+        // pushed streams count towards the client's limit, the first one is
+        // kept open by its flow control window
+        let mut settings = SettingsFrame::default();
+        settings.add_setting(Setting::MaxConcurrentStreams(1));
+        settings.add_setting(Setting::InitialWindowSize(0));
+        let target = Script::new()
+            .handshake()
+            .send(settings)
+            .expect(Expect::SettingsAck)
+            .headers(1, &get("/"), false)
+            .expect(Expect::PushPromise(1, 2))
+            .headers(3, &get("/"), false)
+            .expect(Expect::Field(3, ":status", "200"))
+            .refute(Expect::PushPromise(3, 4))
+            .run_server(Pusher { pushed: Vec::new() });
+        assert_eq!(target.service.pushed, vec![true, false]);
+    }
+
+    /// The final GOAWAY follows the PING round trip, streams opened before
+    /// it are still answered.
+    #[test]
+    fn test_graceful_shutdown() {
+        Script::new()
+            .handshake()
+            .headers(1, &get("/"), false)
+            .act(|target: &mut ServerTarget<fn(Request) -> Response>| {
+                target.conn.shutdown();
+            })
+            .expect(Expect::LastStreamId(0x7fffffff))
+            .expect(Expect::Ping(*b"shutdown"))
+            .headers(3, &get("/"), false)
+            .send(PingFrame::pong(*b"shutdown"))
+            .expect(Expect::LastStreamId(3))
+            .headers(5, &get("/"), true)
+            .send(DataFrame::new(StreamId(1)).end_stream())
+            .expect(Expect::Field(1, ":status", "200"))
+            .refute(Expect::Headers(5))
+            .send(DataFrame::new(StreamId(3)).end_stream())
+            .expect(Expect::Field(3, ":status", "200"))
+            .expect(Expect::Closed)
+            .run_server(hello as fn(Request) -> Response);
+    }
+
+    #[test]
+    fn test_h2c_upgrade() {
+        Script::new()
+            .send_raw(b"GET / HTTP/1.1\r\n\
+                        Host: localhost\r\n\
+                        Connection: Upgrade, HTTP2-Settings\r\n\
+                        Upgrade: h2c\r\n\
+                        HTTP2-Settings: AAMAAABk\r\n\r\n")
+            .expect_raw(upgrade::SWITCHING_PROTOCOLS)
+            .expect(Expect::Settings)
+            .expect(Expect::Field(1, ":status", "200"))
+            .expect(Expect::Data(1, b"hello".to_vec()))
+            .preface()
+            .send(SettingsFrame::default())
+            .expect(Expect::SettingsAck)
+            .send(DataFrame::new(StreamId(1)).data(&b"late"[..]))
+            .stream_error(1, ErrorKind::StreamClosed)
+            .headers(3, &get("/"), true)
+            .expect(Expect::Field(3, ":status", "200"))
+            .run_server(hello);
+        // the body of the upgrade request continues on stream 1
+        let echo = Echo { consume: true, body: None, sender: None };
+        Script::new()
+            .send_raw(b"POST / HTTP/1.1\r\n\
+                        Host: localhost\r\n\
+                        Connection: Upgrade, HTTP2-Settings\r\n\
+                        Upgrade: h2c\r\n\
+                        HTTP2-Settings: \r\n\
+                        Content-Length: 4\r\n\r\n\
+                        body")
+            .expect_raw(upgrade::SWITCHING_PROTOCOLS)
+            .expect(Expect::Settings)
+            .expect(Expect::Field(1, ":status", "200"))
+            .expect(Expect::Data(1, b"body".to_vec()))
+            .preface()
+            .send(SettingsFrame::default())
+            .expect(Expect::SettingsAck)
+            .run_server(echo);
+    }
+
+    #[test]
+    fn test_h2c_declined() {
+        Script::new()
+            .send_raw(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .expect_raw(upgrade::VERSION_NOT_SUPPORTED)
+            .expect(Expect::Closed)
+            .run_server(hello);
     }
 }
@@ -0,0 +1,206 @@
+//! Stream states and flow control windows (rfc 5.1, 6.9).
+
+use std::collections::VecDeque;
+use {StreamId, WindowSize};
+use hpack::Header;
+
+/// The stream states of rfc 5.1, idle streams are not stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    ReservedLocal,
+    ReservedRemote,
+    Open,
+    HalfClosedLocal,
+    HalfClosedRemote,
+    Closed,
+}
+
+#[derive(Debug)]
+pub struct Stream {
+    id: StreamId,
+    state: State,
+    /// octets we may send on the stream
+    pub send_window: WindowSize,
+    /// octets the peer may send on the stream
+    pub recv_window: WindowSize,
+    /// consumed octets not given back to the peer yet
+    pub recv_released: usize,
+    // data waiting for the flow control window
+    send_buf: VecDeque<u8>,
+    // END_STREAM is sent with the last buffered octet
+    send_end: bool,
+    // trailers are sent with END_STREAM once the buffer is empty
+    send_trailers: Option<Vec<Header>>,
+}
+
+impl Stream {
+    pub fn new(id: StreamId, state: State, send_window: i32, recv_window: i32) -> Stream {
+        Stream {
+            id: id,
+            state: state,
+            send_window: WindowSize(send_window),
+            recv_window: WindowSize(recv_window),
+            recv_released: 0,
+            send_buf: VecDeque::new(),
+            send_end: false,
+            send_trailers: None,
+        }
+    }
+
+    #[inline]
+    pub fn id(&self) -> StreamId {
+        self.id
+    }
+
+    #[inline]
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Returns true if the peer may still send frames carrying data.
+    pub fn is_recv_open(&self) -> bool {
+        match self.state {
+            State::Open | State::HalfClosedLocal => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if we may still send frames carrying data.
+    pub fn is_send_open(&self) -> bool {
+        match self.state {
+            State::Open | State::HalfClosedRemote => true,
+            _ => false,
+        }
+    }
+
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.state == State::Closed
+    }
+
+    /// The peer sent a frame with END_STREAM.
+    pub fn recv_end(&mut self) {
+        self.state = match self.state {
+            State::Open => State::HalfClosedRemote,
+            _ => State::Closed,
+        };
+    }
+
+    /// We sent a frame with END_STREAM.
+    pub fn send_end(&mut self) {
+        self.state = match self.state {
+            State::Open => State::HalfClosedLocal,
+            _ => State::Closed,
+        };
+    }
+
+    /// Response headers on a reserved stream open it for one direction.
+    pub fn recv_reserved_headers(&mut self) {
+        if self.state == State::ReservedRemote {
+            self.state = State::HalfClosedLocal;
+        }
+    }
+
+    pub fn send_reserved_headers(&mut self) {
+        if self.state == State::ReservedLocal {
+            self.state = State::HalfClosedRemote;
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.state = State::Closed;
+        self.send_buf.clear();
+        self.send_end = false;
+        self.send_trailers = None;
+    }
+
+    /// Queues data for sending, END_STREAM follows the data if `end_stream`.
+    pub fn queue_data(&mut self, data: &[u8], end_stream: bool) {
+        self.send_buf.extend(data);
+        self.send_end |= end_stream;
+    }
+
+    /// Number of queued octets.
+    #[inline]
+    pub fn queued(&self) -> usize {
+        self.send_buf.len()
+    }
+
+    /// Queues trailers, they end the stream after the queued data.
+    pub fn queue_trailers(&mut self, trailers: Vec<Header>) {
+        self.send_trailers = Some(trailers);
+    }
+
+    /// Returns true if queued data, trailers or END_STREAM wait to be sent.
+    pub fn has_queued(&self) -> bool {
+        !self.send_buf.is_empty() || self.send_end || self.send_trailers.is_some()
+    }
+
+    /// Takes the queued trailers once all queued data is sent.
+    pub fn take_trailers(&mut self) -> Option<Vec<Header>> {
+        if self.send_buf.is_empty() {
+            self.send_trailers.take()
+        } else {
+            None
+        }
+    }
+
+    /// Takes up to `max` queued octets, returns them and whether END_STREAM
+    /// is to be sent with them.
+    pub fn take_queued(&mut self, max: usize) -> (Vec<u8>, bool) {
+        let n = ::std::cmp::min(max, self.send_buf.len());
+        let data: Vec<u8> = self.send_buf.drain(..n).collect();
+        let end = self.send_end && self.send_buf.is_empty();
+        if end {
+            self.send_end = false;
+        }
+        (data, end)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Stream, State};
+    use StreamId;
+
+    #[test]
+    fn test_transitions() {
+        let mut stream = Stream::new(StreamId(1), State::Open, 10, 10);
+        assert!(stream.is_recv_open() && stream.is_send_open());
+        stream.recv_end();
+        assert_eq!(stream.state(), State::HalfClosedRemote);
+        assert!(!stream.is_recv_open() && stream.is_send_open());
+        stream.send_end();
+        assert!(stream.is_closed());
+
+        let mut pushed = Stream::new(StreamId(2), State::ReservedRemote, 10, 10);
+        assert!(!pushed.is_recv_open());
+        pushed.recv_reserved_headers();
+        assert_eq!(pushed.state(), State::HalfClosedLocal);
+        pushed.recv_end();
+        assert!(pushed.is_closed());
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut stream = Stream::new(StreamId(1), State::Open, 10, 10);
+        stream.queue_data(&[1, 2, 3], true);
+        assert_eq!(stream.take_queued(2), (vec![1, 2], false));
+        assert!(stream.has_queued());
+        assert_eq!(stream.take_queued(2), (vec![3], true));
+        assert!(!stream.has_queued());
+    }
+
+    #[test]
+    fn test_queue_trailers() {
+        let mut stream = Stream::new(StreamId(1), State::Open, 10, 10);
+        let trailers = vec![(b"grpc-status".to_vec(), b"0".to_vec())];
+        stream.queue_data(&[1, 2], false);
+        stream.queue_trailers(trailers.clone());
+        assert_eq!(stream.take_trailers(), None);
+        assert_eq!(stream.take_queued(5), (vec![1, 2], false));
+        assert!(stream.has_queued());
+        assert_eq!(stream.take_trailers(), Some(trailers));
+        assert!(!stream.has_queued());
+    }
+}
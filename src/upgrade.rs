@@ -0,0 +1,564 @@
+//! Starting HTTP/2 over cleartext TCP with an HTTP/1.1 upgrade (rfc 3.2).
+//!
+//! A client may send an HTTP/1.1 request with `Upgrade: h2c` and its
+//! SETTINGS payload in the `HTTP2-Settings` header field instead of the
+//! preface. The server answers with 101 Switching Protocols and continues
+//! the request as stream 1. A server which declines answers over HTTP/1.1.
+//!
+//! Only as much of HTTP/1.1 is understood as the upgrade needs: request
+//! bodies delimited by `Content-Length`, and response bodies which are
+//! chunked, delimited by `Content-Length` or by the end of the connection.
+
+use std::cmp;
+use std::str;
+use error::{Error, ErrorKind, Result};
+use frame::settings::SettingsFrame;
+use message::{Request, Response};
+
+/// Limit of an HTTP/1.1 message head.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+/// Limit of an upgrade request body, it is read before switching protocols.
+const MAX_BODY_SIZE: usize = 4 * 1024;
+
+/// Header fields of HTTP/1.1 messages which don't carry over to HTTP/2, the
+/// host of a request becomes the `:authority`.
+const HOP_BY_HOP_HEADERS: [&'static str; 7] = ["connection",
+                                               "keep-alive",
+                                               "proxy-connection",
+                                               "transfer-encoding",
+                                               "upgrade",
+                                               "http2-settings",
+                                               "host"];
+
+const BASE64URL: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
+                                   abcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// The answer to an upgrade request, HTTP/2 frames follow right after it.
+pub const SWITCHING_PROTOCOLS: &'static [u8] = b"HTTP/1.1 101 Switching Protocols\r\n\
+                                                 connection: Upgrade\r\n\
+                                                 upgrade: h2c\r\n\r\n";
+
+/// The answer to HTTP/1.1 requests which don't upgrade to h2c.
+pub const VERSION_NOT_SUPPORTED: &'static [u8] = b"HTTP/1.1 505 HTTP Version Not Supported\r\n\
+                                                   connection: close\r\n\
+                                                   content-length: 0\r\n\r\n";
+
+/// The server's answer to an upgrade request.
+#[derive(Debug)]
+pub enum Reply {
+    /// 101 Switching Protocols, HTTP/2 starts after this many octets.
+    Switched(usize),
+    /// The server declined and answered over HTTP/1.1.
+    Declined(Response),
+}
+
+/// An HTTP/1.1 request upgrading to h2c.
+#[derive(Debug)]
+pub struct Upgrade {
+    /// The request to continue as stream 1.
+    pub request: Request,
+    /// The client's settings from the HTTP2-Settings header field.
+    pub settings: SettingsFrame,
+}
+
+/// Returns true if the buffered bytes can't be the start of the client
+/// preface. The PRI method is reserved for the preface (rfc 11.6), anything
+/// else is taken for an HTTP/1.1 request.
+pub fn is_http1(buf: &[u8]) -> bool {
+    let len = cmp::min(buf.len(), 4);
+    buf[..len] != b"PRI "[..len]
+}
+
+fn declined(msg: &str) -> Error {
+    Error::new(ErrorKind::Http11Required, format!("no h2c upgrade: {}", msg))
+}
+
+/// Parses an HTTP/1.1 request upgrading to h2c from the start of `buf`.
+///
+/// Returns the upgrade and the number of octets it took, or None while the
+/// request is incomplete. Any other HTTP/1.1 request fails with
+/// HTTP_1_1_REQUIRED, it can't be served over HTTP/2.
+pub fn parse_request(buf: &[u8]) -> Result<Option<(Upgrade, usize)>> {
+    let head_len = match find_head(buf) {
+        Some(len) => len,
+        None if buf.len() >= MAX_HEAD_SIZE => return Err(declined("request head too large")),
+        None => return Ok(None),
+    };
+    let head = try!(str::from_utf8(&buf[..head_len - 4])
+        .map_err(|_| declined("request head is not UTF-8")));
+    let mut lines = head.split("\r\n");
+    let line = lines.next().unwrap_or("");
+    let parts: Vec<&str> = line.split(' ').collect();
+    if parts.len() != 3 || parts[0].is_empty() || parts[1].is_empty() {
+        return Err(declined("invalid request line"));
+    }
+    if parts[2] != "HTTP/1.1" {
+        return Err(declined("not an HTTP/1.1 request"));
+    }
+    let fields = try!(parse_fields(lines).map_err(declined));
+
+    if !has_token(&fields, "upgrade", "h2c") {
+        return Err(declined("missing upgrade to h2c"));
+    }
+    if !has_token(&fields, "connection", "upgrade") ||
+       !has_token(&fields, "connection", "http2-settings") {
+        return Err(declined("connection header field must name upgrade and HTTP2-Settings"));
+    }
+    // exactly one HTTP2-Settings header field (rfc 3.2.1)
+    let mut values = fields.iter().filter(|&&(ref n, _)| n == "http2-settings");
+    let settings = match (values.next(), values.next()) {
+        (Some(&(_, ref value)), None) => try!(decode_settings(value)),
+        _ => return Err(declined("expected exactly one HTTP2-Settings header field")),
+    };
+
+    if fields.iter().any(|&(ref n, _)| n == "transfer-encoding") {
+        return Err(declined("transfer codings are not supported"));
+    }
+    let body_len = match fields.iter().find(|&&(ref n, _)| n == "content-length") {
+        Some(&(_, ref value)) => {
+            match value.parse::<usize>() {
+                Ok(len) if len <= MAX_BODY_SIZE => len,
+                Ok(_) => return Err(declined("request body too large")),
+                Err(_) => return Err(declined("invalid content-length")),
+            }
+        }
+        None => 0,
+    };
+    if buf.len() < head_len + body_len {
+        return Ok(None);
+    }
+
+    let mut headers = vec![(b":method".to_vec(), parts[0].as_bytes().to_vec()),
+                           (b":scheme".to_vec(), b"http".to_vec())];
+    if let Some(&(_, ref host)) = fields.iter().find(|&&(ref n, _)| n == "host") {
+        headers.push((b":authority".to_vec(), host.as_bytes().to_vec()));
+    }
+    headers.push((b":path".to_vec(), parts[1].as_bytes().to_vec()));
+    for (name, value) in fields {
+        if !HOP_BY_HOP_HEADERS.contains(&&name[..]) {
+            headers.push((name.into_bytes(), value.into_bytes()));
+        }
+    }
+    let request = try!(Request::from_headers(headers)
+            .map_err(|e| declined(&e.to_string())))
+        .body(&buf[head_len..head_len + body_len]);
+    let upgrade = Upgrade {
+        request: request,
+        settings: settings,
+    };
+    Ok(Some((upgrade, head_len + body_len)))
+}
+
+/// Writes `request` as an HTTP/1.1 request upgrading to h2c, which
+/// announces `settings` in the HTTP2-Settings header field (rfc 3.2.1).
+pub fn encode_request(request: &Request, settings: &SettingsFrame) -> Result<Vec<u8>> {
+    let (method, path) = match (request.method(), request.path()) {
+        (Some(method), Some(path)) => (method, path),
+        _ => return Err(Error::protocol("upgrade request without :method or :path")),
+    };
+    if !request.trailers.is_empty() {
+        return Err(Error::protocol("an upgrade request can't have trailers"));
+    }
+    let mut buf = Vec::new();
+    buf.extend_from_slice(method);
+    buf.push(b' ');
+    buf.extend_from_slice(path);
+    buf.extend_from_slice(b" HTTP/1.1\r\n");
+    if let Some(authority) = request.get(":authority") {
+        encode_field(&mut buf, b"host", authority);
+    }
+    for &(ref name, ref value) in &request.headers {
+        let hop_by_hop = HOP_BY_HOP_HEADERS.iter().any(|h| h.as_bytes() == &name[..]);
+        if !hop_by_hop && name.first() != Some(&b':') && name != b"content-length" {
+            encode_field(&mut buf, name, value);
+        }
+    }
+    if !request.body.is_empty() {
+        encode_field(&mut buf, b"content-length", request.body.len().to_string().as_bytes());
+    }
+    encode_field(&mut buf, b"connection", b"Upgrade, HTTP2-Settings");
+    encode_field(&mut buf, b"upgrade", b"h2c");
+    let payload = encode_base64url(&settings.to_payload());
+    encode_field(&mut buf, b"http2-settings", payload.as_bytes());
+    buf.extend_from_slice(b"\r\n");
+    buf.extend_from_slice(&request.body);
+    Ok(buf)
+}
+
+fn encode_field(buf: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    buf.extend_from_slice(name);
+    buf.extend_from_slice(b": ");
+    buf.extend_from_slice(value);
+    buf.extend_from_slice(b"\r\n");
+}
+
+/// Parses the server's answer to an upgrade request from the start of `buf`,
+/// interim 1xx responses other than 101 are skipped. Responses to HEAD
+/// requests have no body, a body delimited by the end of the connection is
+/// complete once `eof` is set.
+///
+/// Returns None while the answer is incomplete.
+pub fn parse_response(buf: &[u8], eof: bool, head: bool) -> Result<Option<Reply>> {
+    let mut start = 0;
+    loop {
+        let rest = &buf[start..];
+        let head_len = match find_head(rest) {
+            Some(len) => len,
+            None if rest.len() >= MAX_HEAD_SIZE => {
+                return Err(Error::protocol("response head too large"))
+            }
+            None if eof => return Err(Error::protocol("connection closed before the response")),
+            None => return Ok(None),
+        };
+        let (status, fields) = try!(parse_status_head(&rest[..head_len - 4]));
+        if status == 101 {
+            if !has_token(&fields, "upgrade", "h2c") {
+                return Err(Error::protocol("switched to a protocol other than h2c"));
+            }
+            return Ok(Some(Reply::Switched(start + head_len)));
+        }
+        start += head_len;
+        if status < 200 {
+            continue;
+        }
+
+        let body = &buf[start..];
+        let chunked = has_token(&fields, "transfer-encoding", "chunked");
+        let length = fields.iter().find(|&&(ref n, _)| n == "content-length");
+        let complete = if head || status == 204 || status == 304 {
+            Some(Vec::new())
+        } else if chunked {
+            try!(decode_chunked(body))
+        } else if let Some(&(_, ref value)) = length {
+            let len = try!(value.parse::<usize>()
+                .map_err(|_| Error::protocol("invalid content-length")));
+            if body.len() >= len {
+                Some(body[..len].to_vec())
+            } else {
+                None
+            }
+        } else if eof {
+            Some(body.to_vec())
+        } else {
+            None
+        };
+        let body = match complete {
+            Some(body) => body,
+            None if eof => return Err(Error::protocol("connection closed before the body ended")),
+            None => return Ok(None),
+        };
+        let mut response = Response::new(status).body(body);
+        for (name, value) in fields {
+            if !HOP_BY_HOP_HEADERS.contains(&&name[..]) {
+                response = response.header(name, value);
+            }
+        }
+        return Ok(Some(Reply::Declined(response)));
+    }
+}
+
+/// The length of the message head at the start of `buf`, including the
+/// empty line which ends it.
+fn find_head(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|pos| pos + 4)
+}
+
+/// Parses the status line and header fields of a response head.
+fn parse_status_head(head: &[u8]) -> Result<(u16, Vec<(String, String)>)> {
+    let head = try!(str::from_utf8(head)
+        .map_err(|_| Error::protocol("response head is not UTF-8")));
+    let mut lines = head.split("\r\n");
+    let line = lines.next().unwrap_or("");
+    let mut parts = line.splitn(3, ' ');
+    let status = match (parts.next(), parts.next()) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/1.") && status.len() == 3 => {
+            status.parse::<u16>().ok()
+        }
+        _ => None,
+    };
+    match status {
+        Some(status) if status >= 100 => {
+            Ok((status, try!(parse_fields(lines).map_err(Error::protocol))))
+        }
+        _ => Err(Error::protocol("invalid status line")),
+    }
+}
+
+/// Decodes a chunked body (rfc 7230 4.1), once its last chunk and the
+/// trailer section arrived. Trailer fields are dropped.
+fn decode_chunked(buf: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut body = Vec::new();
+    let mut pos = 0;
+    loop {
+        let line_len = match buf[pos..].windows(2).position(|w| w == b"\r\n") {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let size = str::from_utf8(&buf[pos..pos + line_len])
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok());
+        let size = match size {
+            Some(size) => size,
+            None => return Err(Error::protocol("invalid chunk size")),
+        };
+        pos += line_len + 2;
+        if size == 0 {
+            // the trailer section ends with an empty line
+            loop {
+                match buf[pos..].windows(2).position(|w| w == b"\r\n") {
+                    Some(0) => return Ok(Some(body)),
+                    Some(len) => pos += len + 2,
+                    None => return Ok(None),
+                }
+            }
+        }
+        if buf.len() - pos < size.saturating_add(2) {
+            return Ok(None);
+        }
+        if &buf[pos + size..pos + size + 2] != b"\r\n" {
+            return Err(Error::protocol("chunk not followed by CRLF"));
+        }
+        body.extend_from_slice(&buf[pos..pos + size]);
+        pos += size + 2;
+    }
+}
+
+/// Splits header lines into lowercase names and trimmed values.
+fn parse_fields<'a, I>(lines: I) -> ::std::result::Result<Vec<(String, String)>, &'static str>
+    where I: Iterator<Item = &'a str>
+{
+    let mut fields = Vec::new();
+    for line in lines {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            return Err("obsolete line folding");
+        }
+        let colon = match line.find(':') {
+            Some(colon) if colon > 0 => colon,
+            _ => return Err("invalid header line"),
+        };
+        let name = &line[..colon];
+        if name.ends_with(' ') || name.ends_with('\t') {
+            return Err("whitespace before colon");
+        }
+        fields.push((name.to_ascii_lowercase(), line[colon + 1..].trim().to_owned()));
+    }
+    Ok(fields)
+}
+
+/// Returns true if a comma separated `name` field lists `token`.
+fn has_token(fields: &[(String, String)], name: &str, token: &str) -> bool {
+    fields.iter()
+        .filter(|&&(ref n, _)| n == name)
+        .flat_map(|&(_, ref value)| value.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+fn decode_settings(value: &str) -> Result<SettingsFrame> {
+    match decode_base64url(value) {
+        Some(payload) => {
+            SettingsFrame::from_payload(&payload).map_err(|e| declined(&e.to_string()))
+        }
+        None => Err(declined("HTTP2-Settings is not base64url")),
+    }
+}
+
+/// Encodes base64url without padding (rfc 4648 5), as HTTP2-Settings wants it.
+fn encode_base64url(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 4 + 2) / 3);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().fold(0u32, |acc, &b| acc << 8 | b as u32);
+        let bits = bits << (8 * (3 - chunk.len()));
+        for i in 0..chunk.len() + 1 {
+            out.push(BASE64URL[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    out
+}
+
+/// Decodes base64url with or without trailing padding (rfc 4648 5).
+fn decode_base64url(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim_right_matches('=');
+    let mut out = Vec::with_capacity(value.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in value.bytes() {
+        let digit = match BASE64URL.iter().position(|&d| d == c) {
+            Some(digit) => digit as u32,
+            None => return None,
+        };
+        acc = (acc << 6 | digit) & 0xfff;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    // a single character left over can't encode an octet
+    if bits >= 6 {
+        return None;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Reply, decode_base64url, encode_base64url, encode_request, is_http1,
+                parse_request, parse_response};
+    use error::ErrorKind;
+    use frame::settings::{Setting, SettingsFrame};
+    use message::{Request, Response};
+
+    const UPGRADE: &'static [u8] = b"POST /upload HTTP/1.1\r\n\
+                                     Host: example.com\r\n\
+                                     Connection: Upgrade, HTTP2-Settings\r\n\
+                                     Upgrade: h2c\r\n\
+                                     HTTP2-Settings: AAMAAABkAAIAAAAA\r\n\
+                                     Content-Length: 5\r\n\
+                                     Accept: */*\r\n\r\n\
+                                     hello";
+
+    #[test]
+    fn test_is_http1() {
+        assert!(!is_http1(b""));
+        assert!(!is_http1(b"PR"));
+        assert!(!is_http1(b"PRI * HTTP/2.0"));
+        assert!(is_http1(b"PRIX"));
+        assert!(is_http1(b"GET / HTTP/1.1"));
+    }
+
+    #[test]
+    fn test_base64url() {
+        assert_eq!(decode_base64url("AAMAAABkAAIAAAAA").unwrap(),
+                   vec![0, 3, 0, 0, 0, 100, 0, 2, 0, 0, 0, 0]);
+        assert_eq!(decode_base64url("").unwrap(), Vec::<u8>::new());
+        assert_eq!(decode_base64url("-_8").unwrap(), vec![0xfb, 0xff]);
+        assert_eq!(decode_base64url("-_8=").unwrap(), vec![0xfb, 0xff]);
+        assert!(decode_base64url("+/8").is_none());
+        assert!(decode_base64url("AAMAA").is_none());
+        for len in 0..8 {
+            let data: Vec<u8> = (0..len).map(|i| 0xf8 + i as u8).collect();
+            assert_eq!(decode_base64url(&encode_base64url(&data)).unwrap(), data);
+        }
+        assert_eq!(encode_base64url(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn test_parse_request() {
+        for len in 0..UPGRADE.len() {
+            assert!(parse_request(&UPGRADE[..len]).unwrap().is_none());
+        }
+        let (upgrade, len) = parse_request(UPGRADE).unwrap().unwrap();
+        assert_eq!(len, UPGRADE.len());
+        let request = upgrade.request;
+        assert_eq!(request.method(), Some(&b"POST"[..]));
+        assert_eq!(request.get(":scheme"), Some(&b"http"[..]));
+        assert_eq!(request.get(":authority"), Some(&b"example.com"[..]));
+        assert_eq!(request.path(), Some(&b"/upload"[..]));
+        assert_eq!(request.get("accept"), Some(&b"*/*"[..]));
+        assert_eq!(request.get("content-length"), Some(&b"5"[..]));
+        assert!(request.get("upgrade").is_none());
+        assert!(request.get("http2-settings").is_none());
+        assert!(request.get("host").is_none());
+        assert_eq!(request.body, b"hello");
+        assert_eq!(upgrade.settings.settings(),
+                   vec![Setting::MaxConcurrentStreams(100), Setting::EnablePush(false)]);
+    }
+
+    #[test]
+    fn test_encode_request() {
+        let mut settings = SettingsFrame::default();
+        settings.add_setting(Setting::MaxConcurrentStreams(100));
+        settings.add_setting(Setting::EnablePush(false));
+        let request = Request::new("POST", "http", "example.com", "/upload")
+            .header("accept", "*/*")
+            .header("content-length", "7")
+            .body(&b"hello"[..]);
+        let encoded = encode_request(&request, &settings).unwrap();
+        assert_eq!(&encoded[..],
+                   &b"POST /upload HTTP/1.1\r\n\
+                      host: example.com\r\n\
+                      accept: */*\r\n\
+                      content-length: 5\r\n\
+                      connection: Upgrade, HTTP2-Settings\r\n\
+                      upgrade: h2c\r\n\
+                      http2-settings: AAMAAABkAAIAAAAA\r\n\r\n\
+                      hello"[..]);
+        let (upgrade, _) = parse_request(&encoded).unwrap().unwrap();
+        assert_eq!(upgrade.settings, settings);
+        assert_eq!(upgrade.request.headers[..5], request.headers[..5]);
+        assert_eq!(upgrade.request.body, request.body);
+
+        let trailers = Request::new("GET", "http", "example.com", "/").trailer("a", "b");
+        assert!(encode_request(&trailers, &settings).is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let switched = b"HTTP/1.1 100 Continue\r\n\r\n\
+                         HTTP/1.1 101 Switching Protocols\r\n\
+                         Connection: Upgrade\r\n\
+                         Upgrade: h2c\r\n\r\n\
+                         frames";
+        for len in 0..switched.len() - 6 {
+            assert!(parse_response(&switched[..len], false, false).unwrap().is_none());
+        }
+        match parse_response(switched, false, false).unwrap() {
+            Some(Reply::Switched(len)) => assert_eq!(&switched[len..], b"frames"),
+            reply => panic!("unexpected {:?}", reply),
+        }
+        let other = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n";
+        assert!(parse_response(other, false, false).is_err());
+
+        let declined = |buf: &[u8], eof: bool, head: bool| {
+            match parse_response(buf, eof, head).unwrap() {
+                Some(Reply::Declined(response)) => Some(response),
+                None => None,
+                reply => panic!("unexpected {:?}", reply),
+            }
+        };
+        let length = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello";
+        assert!(declined(&length[..length.len() - 1], false, false).is_none());
+        assert_eq!(declined(length, false, false).unwrap(),
+                   Response::new(200).header("content-length", "5").body(&b"hello"[..]));
+        assert_eq!(declined(&length[..length.len() - 5], false, true).unwrap().body, b"");
+        assert!(parse_response(&length[..length.len() - 1], true, false).is_err());
+
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                        3;ext=1\r\nhel\r\n2\r\nlo\r\n0\r\nx: y\r\n\r\n";
+        for len in 0..chunked.len() {
+            assert!(declined(&chunked[..len], false, false).is_none());
+        }
+        assert_eq!(declined(chunked, false, false).unwrap(),
+                   Response::new(200).body(&b"hello"[..]));
+
+        let close = b"HTTP/1.0 404 Not Found\r\n\r\nmissing";
+        assert!(declined(close, false, false).is_none());
+        assert_eq!(declined(close, true, false).unwrap(), Response::new(404).body(&b"missing"[..]));
+
+        assert!(parse_response(b"HTTP/2 200\r\n\r\n", false, false).is_err());
+        assert!(parse_response(b"HTTP/1.1 2000 OK\r\n\r\n", false, false).is_err());
+    }
+
+    #[test]
+    fn test_declined() {
+        let requests: [&[u8]; 6] = [b"GET / HTTP/1.1\r\nHost: a\r\n\r\n",
+                                    b"GET / HTTP/1.0\r\nUpgrade: h2c\r\nHTTP2-Settings: \r\n\
+                                      Connection: upgrade, http2-settings\r\n\r\n",
+                                    b"GET / HTTP/1.1\r\nUpgrade: h2c\r\nHTTP2-Settings: \r\n\
+                                      Connection: upgrade\r\n\r\n",
+                                    b"GET / HTTP/1.1\r\nUpgrade: h2c\r\n\
+                                      Connection: upgrade, http2-settings\r\n\r\n",
+                                    b"GET / HTTP/1.1\r\nUpgrade: h2c\r\nHTTP2-Settings: AAM\r\n\
+                                      Connection: upgrade, http2-settings\r\n\r\n",
+                                    b"POST / HTTP/1.1\r\nUpgrade: h2c\r\nHTTP2-Settings: \r\n\
+                                      Connection: upgrade, http2-settings\r\n\
+                                      Transfer-Encoding: chunked\r\n\r\n"];
+        for request in requests.iter() {
+            assert_eq!(parse_request(request).unwrap_err().kind(),
+                       ErrorKind::Http11Required);
+        }
+        let head = vec![b'a'; 8 * 1024];
+        assert_eq!(parse_request(&head).unwrap_err().kind(), ErrorKind::Http11Required);
+    }
+}